@@ -0,0 +1,745 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use minimal_storage::{
+    provider::{Directory, StorageProvider},
+    serialize_fast::MinimalSerdeFast,
+    serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal},
+};
+use osm_tag_compression::{
+    compressed_data::{flattened_id, CompressedOsmData},
+    field::Field,
+};
+use osmpbfreader::{OsmId, Ref};
+use tree::{
+    geo::distance_meters,
+    open_tree_sparse_in,
+    point_range::StoredBinaryTree,
+    sparse::SparseValue,
+};
+
+use crate::{
+    place::AddressPoint,
+    text::{fold, fuzzy_eq, is_street_type, stable_hash, variants, words},
+};
+
+const WORD_SATURATION: usize = 8_000;
+const STREET_SATURATION: usize = 4_000;
+
+/// How many of the streets sharing the most words with a query are scored.
+const MAX_CANDIDATE_STREETS: usize = 32;
+
+/// Something that can be found by searching for its street: an addressed object, a
+/// named road, or a range of house numbers along an `addr:interpolation` way.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AddressEntry {
+    /// Flattened OSM id, see `flattened_id`
+    pub id: u64,
+    pub point: (i32, i32),
+    pub street: String,
+    pub housenumber: Option<String>,
+    pub city: Option<String>,
+    pub postcode: Option<String>,
+    pub interpolation: Option<Interpolation>,
+}
+
+impl AddressEntry {
+    /// An entry for the object if it has a house number and a street, or if it's a named
+    /// road. Interpolation ways are left for `PendingInterpolation`.
+    pub fn from_data(data: &CompressedOsmData) -> Option<Self> {
+        let fields = data.fields()?;
+
+        if fields.get_tag("addr:interpolation").is_some() {
+            return None;
+        }
+
+        if let Some((point, place)) = AddressPoint::from_data(data) {
+            if let (Some(street), Some(housenumber)) = (place.street, place.housenumber) {
+                return Some(AddressEntry {
+                    id: place.id,
+                    point,
+                    street,
+                    housenumber: Some(housenumber),
+                    city: place.city,
+                    postcode: place.postcode,
+                    interpolation: None,
+                });
+            }
+        }
+
+        fields.get_tag("highway")?;
+        let street = fields.get_tag("name")?.into_owned();
+
+        //the middle of a road's bbox is often off the road, so use its middle node
+        let point = match data {
+            CompressedOsmData::Way { children, .. } if !children.is_empty() => {
+                children[children.len() / 2]
+            }
+            _ => data.bbox().center(),
+        };
+
+        Some(AddressEntry {
+            id: flattened_id(&data.osm_id()),
+            point,
+            street,
+            housenumber: None,
+            city: None,
+            postcode: None,
+            interpolation: None,
+        })
+    }
+
+    /// Which street the entry is grouped under in the index.
+    pub fn street_key(&self) -> u64 {
+        street_key(&self.street)
+    }
+}
+
+fn street_key(street: &str) -> u64 {
+    stable_hash(&words(street).join(" "))
+}
+
+/// House numbers along an `addr:interpolation` way, from `first` at the way's first node
+/// to `last` at its last node.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Interpolation {
+    pub first: u32,
+    pub last: u32,
+    pub step: u32,
+    pub line: Vec<(i32, i32)>,
+}
+
+impl Interpolation {
+    /// `kind` is the value of `addr:interpolation`. Only numeric house numbers can be
+    /// interpolated.
+    pub fn new(kind: &str, first: &str, last: &str, line: Vec<(i32, i32)>) -> Option<Self> {
+        let step = match kind {
+            "even" | "odd" => 2,
+            "all" => 1,
+            other => other.parse().ok().filter(|s| *s > 0)?,
+        };
+
+        let first: u32 = first.trim().parse().ok()?;
+        let last: u32 = last.trim().parse().ok()?;
+
+        if first == last || line.len() < 2 {
+            return None;
+        }
+
+        Some(Interpolation {
+            first,
+            last,
+            step,
+            line,
+        })
+    }
+
+    pub fn contains(&self, number: u32) -> bool {
+        let (min, max) = (self.first.min(self.last), self.first.max(self.last));
+        (min..=max).contains(&number) && number.abs_diff(self.first).is_multiple_of(self.step)
+    }
+
+    /// Where `number` is along the line, assuming the house numbers are evenly spread.
+    pub fn point_of(&self, number: u32) -> (i32, i32) {
+        let fraction = number.abs_diff(self.first) as f64 / self.last.abs_diff(self.first) as f64;
+
+        let lengths: Vec<f64> = self
+            .line
+            .windows(2)
+            .map(|w| distance_meters(w[0], w[1]))
+            .collect();
+        let mut remaining = lengths.iter().sum::<f64>() * fraction;
+
+        for (segment, length) in self.line.windows(2).zip(lengths) {
+            if remaining <= length && length > 0.0 {
+                let t = remaining / length;
+                let lerp = |a: i32, b: i32| a + ((b - a) as f64 * t).round() as i32;
+                return (
+                    lerp(segment[0].0, segment[1].0),
+                    lerp(segment[0].1, segment[1].1),
+                );
+            }
+            remaining -= length;
+        }
+
+        *self.line.last().unwrap()
+    }
+}
+
+/// An `addr:interpolation` way, waiting for the addresses at its ends to be indexed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PendingInterpolation {
+    pub way: u64,
+    pub kind: String,
+    /// The OSM ids of the way's first and last nodes
+    pub ends: [u64; 2],
+    pub line: Vec<(i32, i32)>,
+    /// The way's own `addr:street`, if it has one
+    pub street: Option<String>,
+}
+
+impl PendingInterpolation {
+    /// `nodes` are the way's node ids, in order.
+    pub fn from_way(nodes: &[u64], data: &CompressedOsmData) -> Option<Self> {
+        let CompressedOsmData::Way {
+            id, tags, children, ..
+        } = data
+        else {
+            return None;
+        };
+
+        let kind = tags.get_tag("addr:interpolation")?.into_owned();
+
+        Some(PendingInterpolation {
+            way: id.0 as u64,
+            kind,
+            ends: [*nodes.first()?, *nodes.last()?],
+            line: children.clone(),
+            street: AddressPoint::from_data(data).and_then(|(_, p)| p.street),
+        })
+    }
+}
+
+/// A `type=associatedStreet` relation: a street name, and the houses on it, which may not
+/// have an `addr:street` of their own.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AssociatedStreet {
+    pub street: String,
+    pub houses: Vec<OsmId>,
+}
+
+impl AssociatedStreet {
+    pub fn from_relation<'a>(
+        fields: impl Iterator<Item = &'a Field>,
+        refs: &[Ref],
+    ) -> Option<Self> {
+        let tags: Vec<_> = fields.filter_map(|f| f.as_tag()).collect();
+        let tag = |key: &str| -> Option<&str> {
+            tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_ref())
+        };
+
+        if tag("type") != Some("associatedStreet") {
+            return None;
+        }
+
+        Some(AssociatedStreet {
+            street: tag("name")?.to_string(),
+            houses: refs
+                .iter()
+                .filter(|r| matches!(r.role.as_str(), "house" | "address"))
+                .map(|r| r.member)
+                .collect(),
+        })
+    }
+}
+
+/// A search result. `place.city` is filled in from the administrative area the result is
+/// in, if the query named it and the object itself doesn't say.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AddressMatch {
+    pub place: AddressPoint,
+    pub point: (i32, i32),
+    /// Whether the position was interpolated from an `addr:interpolation` way
+    pub interpolated: bool,
+    pub score: f64,
+}
+
+/// What was typed into a search: the house number, if there's something that looks like
+/// one, and the rest of the words.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AddressQuery {
+    pub housenumber: Option<String>,
+    pub words: Vec<String>,
+}
+
+impl AddressQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut words = words(query);
+
+        let housenumber = words
+            .iter()
+            .position(|w| looks_like_housenumber(w))
+            .map(|i| words.remove(i));
+
+        AddressQuery { housenumber, words }
+    }
+}
+
+/// `12`, `12a`, or `1234bc`, but not a 5-digit postcode.
+fn looks_like_housenumber(word: &str) -> bool {
+    let digits = word.chars().take_while(|c| c.is_ascii_digit()).count();
+    let rest = &word[digits..];
+
+    (1..=4).contains(&digits) && rest.len() <= 2 && rest.chars().all(|c| c.is_alphabetic())
+}
+
+/// House numbers as they're compared: folded, without spaces.
+fn compact(text: &str) -> String {
+    fold(text).split_whitespace().collect()
+}
+
+/// Streets, grouped by their normalized name, and an index from the words of those
+/// names (and their one-deletion variants, for typos) to the streets they're in.
+pub struct AddressIndex {
+    words: StoredBinaryTree<WORD_SATURATION, u64, u64>,
+    streets: StoredBinaryTree<STREET_SATURATION, u64, AddressEntry>,
+}
+
+impl AddressIndex {
+    pub fn open(folder: PathBuf) -> Self {
+        Self::open_in(&Directory::new(folder))
+    }
+
+    /// Like `open`, but with the index's trees in `files`, which could be in memory
+    pub fn open_in(files: &dyn StorageProvider) -> Self {
+        let mut words =
+            open_tree_sparse_in::<1, WORD_SATURATION, u64, u64>(files, "words", 0..=u64::MAX);
+        let mut streets = open_tree_sparse_in::<1, STREET_SATURATION, u64, AddressEntry>(
+            files,
+            "streets",
+            0..=u64::MAX,
+        );
+
+        words.expand_to_depth(5);
+        streets.expand_to_depth(5);
+
+        AddressIndex { words, streets }
+    }
+
+    pub fn add(&self, entry: AddressEntry) {
+        let key = entry.street_key();
+
+        if self.streets.find_items_in_box(&(key..=key)).next().is_none() {
+            let mut seen = Vec::new();
+            for word in words(&entry.street) {
+                for variant in variants(&word) {
+                    if !seen.contains(&variant) {
+                        self.words.insert(stable_hash(&variant), key);
+                        seen.push(variant);
+                    }
+                }
+            }
+        }
+
+        self.streets.insert(key, entry);
+    }
+
+    /// Every entry on the street with exactly this (normalized) name.
+    pub fn entries_on(&self, street: &str) -> Vec<AddressEntry> {
+        all_at(&self.streets, street_key(street))
+    }
+
+    /// The best matches for `query`, best first. `area_names` lists the administrative
+    /// areas a point is in, smallest last, for entries which don't have a city.
+    pub fn search(
+        &self,
+        query: &AddressQuery,
+        limit: usize,
+        area_names: impl Fn((i32, i32)) -> Vec<String>,
+    ) -> Vec<AddressMatch> {
+        //streets sharing the most (fuzzily-matched) words with the query. Words like
+        // `street` are in too many streets to look up, unless they're all there is
+        let distinctive = query.words.iter().any(|w| !is_street_type(w));
+
+        let mut hits: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, word) in query.words.iter().enumerate() {
+            if distinctive && is_street_type(word) {
+                continue;
+            }
+
+            for variant in variants(word) {
+                for street in all_at(&self.words, stable_hash(&variant)) {
+                    let words = hits.entry(street).or_default();
+                    if !words.contains(&i) {
+                        words.push(i);
+                    }
+                }
+            }
+        }
+
+        let mut candidates: Vec<(u64, usize)> =
+            hits.into_iter().map(|(k, w)| (k, w.len())).collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        candidates.truncate(MAX_CANDIDATE_STREETS);
+
+        //the same address can be in the index more than once, e.g. a road split into
+        // several ways, so keep the best of each
+        let mut best: HashMap<String, AddressMatch> = HashMap::new();
+        for (street, _) in candidates {
+            for entry in all_at(&self.streets, street) {
+                let Some(m) = score(query, entry, &area_names) else {
+                    continue;
+                };
+
+                let display = m.place.display();
+                match best.get(&display) {
+                    Some(existing) if existing.score >= m.score => {}
+                    _ => {
+                        best.insert(display, m);
+                    }
+                }
+            }
+        }
+
+        let mut matches: Vec<_> = best.into_values().collect();
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.place.display().cmp(&b.place.display()))
+        });
+        matches.truncate(limit);
+
+        matches
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.words.flush()?;
+        self.streets.flush()
+    }
+}
+
+/// Every value at `key`. Common words and street names have more values than fit in one
+/// node, and those are spread over several, which `get_all_owned` doesn't look through.
+fn all_at<const SATURATION: usize, V: SparseValue>(
+    tree: &StoredBinaryTree<SATURATION, u64, V>,
+    key: u64,
+) -> Vec<V> {
+    tree.find_items_in_box(&(key..=key)).collect()
+}
+
+/// How well `entry` matches, or `None` if it doesn't. Each part of the address which
+/// matches adds to the score, so more complete matches rank higher, and each word of the
+/// query which matches nothing takes away from it.
+fn score(
+    query: &AddressQuery,
+    entry: AddressEntry,
+    area_names: &impl Fn((i32, i32)) -> Vec<String>,
+) -> Option<AddressMatch> {
+    let street_words = words(&entry.street);
+    let city_words = entry.city.as_deref().map(words).unwrap_or_default();
+    let postcode_words = entry.postcode.as_deref().map(words).unwrap_or_default();
+
+    let mut areas: Option<Vec<(String, Vec<String>)>> = None;
+    let mut matched_area = None;
+
+    let mut street_matched = vec![false; street_words.len()];
+    let mut place_hits = 0;
+    let mut misses = 0;
+
+    for word in query.words.iter() {
+        if let Some(i) = street_words.iter().position(|s| fuzzy_eq(word, s)) {
+            street_matched[i] = true;
+            continue;
+        }
+
+        if city_words
+            .iter()
+            .chain(postcode_words.iter())
+            .any(|c| fuzzy_eq(word, c))
+        {
+            place_hits += 1;
+            continue;
+        }
+
+        if entry.city.is_none() {
+            let areas = areas.get_or_insert_with(|| {
+                area_names(entry.point)
+                    .into_iter()
+                    .map(|name| {
+                        let w = words(&name);
+                        (name, w)
+                    })
+                    .collect()
+            });
+
+            let area = areas
+                .iter()
+                .rev()
+                .find(|(_, area_words)| area_words.iter().any(|a| fuzzy_eq(word, a)));
+            if let Some((name, _)) = area {
+                matched_area.get_or_insert_with(|| name.clone());
+                place_hits += 1;
+                continue;
+            }
+        }
+
+        misses += 1;
+    }
+
+    let coverage =
+        street_matched.iter().filter(|m| **m).count() as f64 / street_words.len().max(1) as f64;
+    let named = street_words
+        .iter()
+        .zip(street_matched.iter())
+        .any(|(w, m)| *m && !is_street_type(w));
+    if coverage < 0.5 || (!named && street_words.iter().any(|w| !is_street_type(w))) {
+        return None;
+    }
+
+    let is_street = entry.housenumber.is_none() && entry.interpolation.is_none();
+
+    let (number_score, housenumber, point, interpolated) = match &query.housenumber {
+        Some(wanted) => {
+            if entry.housenumber.as_deref().map(compact).as_ref() == Some(wanted) {
+                (1.0, entry.housenumber.clone(), entry.point, false)
+            } else if let Some(interpolation) = &entry.interpolation {
+                let number = wanted.parse().ok().filter(|n| interpolation.contains(*n))?;
+                (
+                    0.8,
+                    Some(wanted.clone()),
+                    interpolation.point_of(number),
+                    true,
+                )
+            } else if is_street {
+                //the street is better than nothing if the house isn't mapped
+                (0.3, None, entry.point, false)
+            } else {
+                return None;
+            }
+        }
+        None if is_street => (0.5, None, entry.point, false),
+        None if entry.interpolation.is_some() => return None,
+        None => (0.0, entry.housenumber.clone(), entry.point, false),
+    };
+
+    let place = AddressPoint {
+        id: entry.id,
+        building: false,
+        housenumber,
+        street: Some(entry.street),
+        city: entry.city.or(matched_area),
+        postcode: entry.postcode,
+    };
+
+    let completeness = [&place.housenumber, &place.city, &place.postcode]
+        .iter()
+        .filter(|p| p.is_some())
+        .count() as f64
+        / 3.0;
+
+    Some(AddressMatch {
+        score: 2.0 * coverage + number_score + 0.5 * place_hits as f64 - 0.5 * misses as f64
+            + 0.1 * completeness,
+        place,
+        point,
+        interpolated,
+    })
+}
+
+//header byte layout:
+//bits 0-3: whether each of housenumber, city, postcode, and interpolation is present
+impl SerializeMinimal for AddressEntry {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        let parts = [&self.housenumber, &self.city, &self.postcode];
+
+        let mut header = (self.interpolation.is_some() as u8) << 3;
+        for (i, part) in parts.iter().enumerate() {
+            if part.is_some() {
+                header |= 1 << i;
+            }
+        }
+
+        write_to.write_all(&[header])?;
+        self.id.minimally_serialize(write_to, ())?;
+        self.point.0.minimally_serialize(write_to, ())?;
+        self.point.1.minimally_serialize(write_to, ())?;
+        self.street.minimally_serialize(write_to, 0.into())?;
+
+        for part in parts.into_iter().flatten() {
+            part.minimally_serialize(write_to, 0.into())?;
+        }
+
+        if let Some(interpolation) = &self.interpolation {
+            interpolation.first.minimally_serialize(write_to, ())?;
+            interpolation.last.minimally_serialize(write_to, ())?;
+            interpolation.step.minimally_serialize(write_to, ())?;
+
+            interpolation.line.len().minimally_serialize(write_to, ())?;
+            for point in interpolation.line.iter() {
+                point.0.minimally_serialize(write_to, ())?;
+                point.1.minimally_serialize(write_to, ())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DeserializeFromMinimal for AddressEntry {
+    type ExternalData<'d> = ();
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        let header = from.read_one()?;
+        let id = u64::deserialize_minimal(from, ())?;
+        let point = (
+            i32::deserialize_minimal(from, ())?,
+            i32::deserialize_minimal(from, ())?,
+        );
+        let street = String::deserialize_minimal(from, None)?;
+
+        let mut part = |i: usize| -> std::io::Result<Option<String>> {
+            if header & (1 << i) == 0 {
+                return Ok(None);
+            }
+            String::deserialize_minimal(from, None).map(Some)
+        };
+
+        let housenumber = part(0)?;
+        let city = part(1)?;
+        let postcode = part(2)?;
+
+        let interpolation = if header & (1 << 3) != 0 {
+            let first = u32::deserialize_minimal(from, ())?;
+            let last = u32::deserialize_minimal(from, ())?;
+            let step = u32::deserialize_minimal(from, ())?;
+
+            let len = usize::deserialize_minimal(from, ())?;
+            let mut line = Vec::with_capacity(len);
+            for _ in 0..len {
+                line.push((
+                    i32::deserialize_minimal(from, ())?,
+                    i32::deserialize_minimal(from, ())?,
+                ));
+            }
+
+            Some(Interpolation {
+                first,
+                last,
+                step,
+                line,
+            })
+        } else {
+            None
+        };
+
+        Ok(AddressEntry {
+            id,
+            point,
+            street,
+            housenumber,
+            city,
+            postcode,
+            interpolation,
+        })
+    }
+}
+
+impl MinimalSerdeFast for AddressEntry {
+    fn fast_minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        external_data: (),
+    ) -> std::io::Result<()> {
+        //entries are mostly strings, which have no faster form
+        self.minimally_serialize(write_to, external_data)
+    }
+
+    fn fast_deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        Self::deserialize_minimal(from, external_data)
+    }
+
+    fn fast_seek_after<R: std::io::Read>(from: &mut R) -> std::io::Result<()> {
+        Self::deserialize_minimal(from, ()).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use minimal_storage::provider::MemoryDirectory;
+
+    use super::*;
+
+    #[test]
+    pub fn parse_query() {
+        let q = AddressQuery::parse("12 Main St, Springfield");
+        assert_eq!(q.housenumber.as_deref(), Some("12"));
+        assert_eq!(q.words, vec!["main", "street", "springfield"]);
+
+        let q = AddressQuery::parse("Hauptstraße 3a, 10115 Berlin");
+        assert_eq!(q.housenumber.as_deref(), Some("3a"));
+        assert_eq!(q.words, vec!["hauptstrasse", "10115", "berlin"]);
+    }
+
+    #[test]
+    pub fn interpolation() {
+        let line = vec![(0, 0), (100, 0), (100, 100)];
+        let i = Interpolation::new("even", "2", "10", line.clone()).unwrap();
+
+        assert!(i.contains(6));
+        assert!(!i.contains(7));
+        assert!(!i.contains(12));
+        assert_eq!(i.point_of(2), (0, 0));
+        assert_eq!(i.point_of(6), (100, 0));
+        assert_eq!(i.point_of(10), (100, 100));
+
+        assert!(Interpolation::new("alphabetic", "2a", "2f", line.clone()).is_none());
+        assert!(Interpolation::new("odd", "3", "3", line).is_none());
+    }
+
+    #[test]
+    pub fn crowded_keys() {
+        let index = AddressIndex::open_in(&MemoryDirectory::new());
+        let entry = |street: String, housenumber: Option<String>| AddressEntry {
+            id: 1,
+            point: (0, 0),
+            street,
+            housenumber,
+            city: None,
+            postcode: None,
+            interpolation: None,
+        };
+
+        //more houses on one street than fit in a node of the street tree, and more
+        // streets with one word than fit in a node of the word tree
+        let houses = STREET_SATURATION + 500;
+        let streets = WORD_SATURATION + 500;
+        for number in 0..houses {
+            index.add(entry("Main Street".into(), Some(number.to_string())));
+        }
+        for number in 1..streets {
+            index.add(entry(format!("Main Street {number}"), None));
+        }
+
+        let mut numbers: Vec<usize> = index
+            .entries_on("Main Street")
+            .into_iter()
+            .map(|e| e.housenumber.unwrap().parse().unwrap())
+            .collect();
+        numbers.sort();
+        assert_eq!(numbers, (0..houses).collect::<Vec<_>>());
+
+        assert_eq!(all_at(&index.words, stable_hash("main")).len(), streets);
+    }
+
+    #[test]
+    pub fn roundtrip() {
+        let entry = AddressEntry {
+            id: 42,
+            point: (-5, 7),
+            street: "Main St".into(),
+            housenumber: None,
+            city: Some("Springfield".into()),
+            postcode: None,
+            interpolation: Interpolation::new("odd", "1", "9", vec![(1, 2), (3, 4)]),
+        };
+
+        let mut bytes = Vec::new();
+        entry.minimally_serialize(&mut bytes, ()).unwrap();
+
+        assert_eq!(
+            AddressEntry::deserialize_minimal(&mut &bytes[..], ()).unwrap(),
+            entry
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use minimal_storage::serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal};
+use osm_tag_compression::field::Field;
+use tree::bbox::BoundingBox;
+
+use crate::polygon::Polygon;
+
+/// A `boundary=administrative` relation, with its member ways assembled into a polygon.
+/// Stored in the admin tree under the polygon's bounding box.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AdminArea {
+    pub boundary: AdminBoundary,
+    pub polygon: Polygon,
+}
+
+/// What's known about an administrative boundary from its relation's tags.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AdminBoundary {
+    pub relation: u64,
+    /// `admin_level`: 2 for countries, and higher numbers for smaller areas
+    pub admin_level: u8,
+    pub name: String,
+}
+
+impl AdminBoundary {
+    /// `None` unless the relation is an administrative boundary with a valid level.
+    pub fn from_fields<'a>(relation: u64, fields: impl Iterator<Item = &'a Field>) -> Option<Self> {
+        let tags: Vec<_> = fields.filter_map(|f| f.as_tag()).collect();
+        let tag = |key: &str| -> Option<&str> {
+            tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_ref())
+        };
+
+        if tag("boundary") != Some("administrative") {
+            return None;
+        }
+
+        let admin_level = tag("admin_level")?.parse::<f64>().ok()?;
+        if !(1.0..=u8::MAX as f64).contains(&admin_level) {
+            return None;
+        }
+
+        Some(AdminBoundary {
+            relation,
+            admin_level: admin_level as u8,
+            name: tag("name").unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl SerializeMinimal for AdminArea {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        self.boundary.relation.minimally_serialize(write_to, ())?;
+        write_to.write_all(&[self.boundary.admin_level])?;
+        self.boundary.name.minimally_serialize(write_to, 0.into())?;
+
+        //points are stored relative to the bbox, the same as ways are
+        let bbox = self.polygon.bbox();
+
+        self.polygon.rings.len().minimally_serialize(write_to, ())?;
+        for ring in self.polygon.rings.iter() {
+            ring.len().minimally_serialize(write_to, ())?;
+            for point in ring.iter() {
+                i32::abs_diff(*bbox.x(), point.0).minimally_serialize(write_to, ())?;
+                i32::abs_diff(*bbox.y(), point.1).minimally_serialize(write_to, ())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DeserializeFromMinimal for AdminArea {
+    type ExternalData<'d> = &'d BoundingBox<i32>;
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        bbox: &'d BoundingBox<i32>,
+    ) -> Result<Self, std::io::Error> {
+        let relation = u64::deserialize_minimal(from, ())?;
+        let admin_level = from.read_one()?;
+        let name = String::deserialize_minimal(from, None)?;
+
+        let ring_count = usize::deserialize_minimal(from, ())?;
+        let mut rings = Vec::with_capacity(ring_count);
+
+        for _ in 0..ring_count {
+            let len = usize::deserialize_minimal(from, ())?;
+            let mut ring = Vec::with_capacity(len);
+
+            for _ in 0..len {
+                let x_off = u32::deserialize_minimal(from, ())?;
+                let y_off = u32::deserialize_minimal(from, ())?;
+
+                ring.push((
+                    bbox.x().wrapping_add_unsigned(x_off),
+                    bbox.y().wrapping_add_unsigned(y_off),
+                ));
+            }
+
+            rings.push(ring);
+        }
+
+        Ok(AdminArea {
+            boundary: AdminBoundary {
+                relation,
+                admin_level,
+                name,
+            },
+            polygon: Polygon { rings },
+        })
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+
+use minimal_storage::provider::{Directory, StorageProvider};
+use osm_tag_compression::compressed_data::{flattened_id, CompressedOsmData};
+use osmpbfreader::{NodeId, OsmId, WayId};
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    dense::structure::StoredTree,
+    geo::degrees_to_decimicro,
+    open_tree_dense_in,
+};
+
+use crate::{
+    address::{
+        AddressEntry, AddressIndex, AddressMatch, AddressQuery, AssociatedStreet, Interpolation,
+        PendingInterpolation,
+    },
+    admin::{AdminArea, AdminBoundary},
+    name::{NameIndex, NameMatch},
+    place::AddressPoint,
+};
+
+pub const AREA_SATURATION: usize = 2_000;
+const PLACE_SATURATION: usize = 8_000;
+
+/// How far from the queried point `reverse_geocode` looks for a building or address.
+pub const DEFAULT_PLACE_RADIUS_METERS: f64 = 100.0;
+
+/// How many results `search_address` returns.
+pub const MAX_ADDRESS_RESULTS: usize = 10;
+
+/// How many results `search_names` returns.
+pub const MAX_NAME_RESULTS: usize = 10;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct NearbyPlace {
+    pub place: AddressPoint,
+    pub point: (i32, i32),
+    pub distance_meters: f64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReverseGeocode {
+    /// Every administrative area containing the point, from the largest (lowest
+    /// `admin_level`) to the smallest
+    pub areas: Vec<AdminBoundary>,
+    pub nearest: Option<NearbyPlace>,
+}
+
+/// Spatial indexes of administrative areas, keyed by the bbox of their polygon, and of
+/// buildings and addresses, keyed by their centre; and indexes of addresses by street
+/// and of everything by name, for searching.
+pub struct Geocoder {
+    areas: StoredTree<2, AREA_SATURATION, BoundingBox<i32>, AdminArea>,
+    places: StoredTree<2, PLACE_SATURATION, BoundingBox<i32>, AddressPoint>,
+    addresses: AddressIndex,
+    names: NameIndex,
+}
+
+impl Geocoder {
+    pub fn open(folder: PathBuf) -> Self {
+        Self::open_in(&Directory::new(folder))
+    }
+
+    /// Like `open`, but with the geocoder's trees in `files`, which could be in memory
+    pub fn open_in(files: &dyn StorageProvider) -> Self {
+        let mut areas = open_tree_dense_in::<2, AREA_SATURATION, BoundingBox<i32>, AdminArea>(
+            &*files.subdirectory("areas"),
+            EARTH_BBOX,
+        );
+        let mut places = open_tree_dense_in::<2, PLACE_SATURATION, BoundingBox<i32>, AddressPoint>(
+            &*files.subdirectory("places"),
+            EARTH_BBOX,
+        );
+
+        areas.expand_to_depth(5);
+        places.expand_to_depth(5);
+
+        let addresses = AddressIndex::open_in(&*files.subdirectory("addresses"));
+        let names = NameIndex::open_in(files, "names");
+
+        Geocoder {
+            areas,
+            places,
+            addresses,
+            names,
+        }
+    }
+
+    /// Index the object if it's a building, has an address, or has a name.
+    pub fn add_place(&self, data: &CompressedOsmData) {
+        self.names.add(data);
+
+        if let Some(((x, y), place)) = AddressPoint::from_data(data) {
+            self.places.insert(&BoundingBox::from_point(x, y), place);
+        }
+
+        if let Some(entry) = AddressEntry::from_data(data) {
+            self.addresses.add(entry);
+        }
+    }
+
+    /// The place with the flattened id `id`, if it was indexed with its centre in `bbox`.
+    pub fn place_with_id(&self, bbox: &BoundingBox<i32>, id: u64) -> Option<AddressPoint> {
+        self.places
+            .find_entries_in_box(bbox)
+            .map(|(_, place)| place)
+            .find(|place| place.id == id)
+    }
+
+    /// Index the house numbers along an interpolation way. This should happen after all
+    /// the nodes are added, since the numbers come from the addresses of the way's ends.
+    /// Returns whether the ends had numbers which could be interpolated.
+    pub fn add_interpolation(&self, pending: PendingInterpolation) -> bool {
+        let (Some(first_point), Some(last_point)) = (pending.line.first(), pending.line.last())
+        else {
+            return false;
+        };
+
+        let end = |point: &(i32, i32), node: u64| {
+            self.place_with_id(
+                &BoundingBox::from_point(point.0, point.1),
+                flattened_id(&OsmId::Node(NodeId(node as i64))),
+            )
+        };
+        let (Some(first), Some(last)) = (
+            end(first_point, pending.ends[0]),
+            end(last_point, pending.ends[1]),
+        ) else {
+            return false;
+        };
+
+        let (Some(first_number), Some(last_number)) = (&first.housenumber, &last.housenumber)
+        else {
+            return false;
+        };
+        let Some(interpolation) =
+            Interpolation::new(&pending.kind, first_number, last_number, pending.line)
+        else {
+            return false;
+        };
+        let Some(street) = pending.street.or(first.street).or(last.street) else {
+            return false;
+        };
+
+        self.addresses.add(AddressEntry {
+            id: flattened_id(&OsmId::Way(WayId(pending.way as i64))),
+            point: interpolation.point_of(interpolation.first),
+            street,
+            housenumber: None,
+            city: first.city.or(last.city),
+            postcode: first.postcode.or(last.postcode),
+            interpolation: Some(interpolation),
+        });
+
+        true
+    }
+
+    /// Index the houses of an associated street relation which don't have an
+    /// `addr:street` of their own. `houses` are the bboxes and flattened ids of the
+    /// relation's house members.
+    pub fn add_associated_street(
+        &self,
+        relation: &AssociatedStreet,
+        houses: impl Iterator<Item = (BoundingBox<i32>, u64)>,
+    ) {
+        for (bbox, id) in houses {
+            let Some(place) = self.place_with_id(&bbox, id) else {
+                continue;
+            };
+            let (None, Some(housenumber)) = (&place.street, place.housenumber) else {
+                continue;
+            };
+
+            self.addresses.add(AddressEntry {
+                id,
+                point: bbox.center(),
+                street: relation.street.clone(),
+                housenumber: Some(housenumber),
+                city: place.city,
+                postcode: place.postcode,
+                interpolation: None,
+            });
+        }
+    }
+
+    pub fn add_admin_area(&self, area: AdminArea) {
+        let bbox = area.polygon.bbox();
+        self.areas.insert(&bbox, area);
+    }
+
+    /// Which administrative areas `(lon, lat)` (in degrees) is in, and the closest
+    /// building or address within `DEFAULT_PLACE_RADIUS_METERS`.
+    pub fn reverse_geocode(&self, lon: f64, lat: f64) -> ReverseGeocode {
+        self.reverse_geocode_within(degrees_to_decimicro(lon, lat), DEFAULT_PLACE_RADIUS_METERS)
+    }
+
+    pub fn reverse_geocode_within(&self, point: (i32, i32), radius_meters: f64) -> ReverseGeocode {
+        ReverseGeocode {
+            areas: self.areas_containing(point),
+            nearest: self.nearest_place(point, radius_meters),
+        }
+    }
+
+    pub fn areas_containing(&self, point: (i32, i32)) -> Vec<AdminBoundary> {
+        let query = BoundingBox::from_point(point.0, point.1);
+
+        //the bbox overlap is only a coarse filter: most areas whose bbox contains the
+        // point won't contain the point itself
+        let mut areas: Vec<_> = self
+            .areas
+            .find_entries_touching_box(&query, usize::MAX)
+            .filter(|(_, area)| area.polygon.contains(point))
+            .map(|(_, area)| area.boundary)
+            .collect();
+
+        areas.sort_by_key(|a| a.admin_level);
+
+        areas
+    }
+
+    pub fn nearest_place(&self, point: (i32, i32), radius_meters: f64) -> Option<NearbyPlace> {
+        self.places
+            .nearest(point, 1, |_, _| true)
+            .find(|(.., d)| *d <= radius_meters)
+            .map(|(bbox, place, distance_meters)| NearbyPlace {
+                place,
+                point: (*bbox.x(), *bbox.y()),
+                distance_meters,
+            })
+    }
+
+    /// Find addresses and streets matching a query like `12 Main St, Springfield`. Typos
+    /// of one letter in longer words are allowed, and results matching more of the query
+    /// come first.
+    pub fn search_address(&self, query: &str) -> Vec<AddressMatch> {
+        self.addresses
+            .search(&AddressQuery::parse(query), MAX_ADDRESS_RESULTS, |point| {
+                self.areas_containing(point)
+                    .into_iter()
+                    .map(|a| a.name)
+                    .collect()
+            })
+    }
+
+    /// Find objects whose name, or one of whose names' words, starts with `prefix`, for
+    /// autocompletion. More important places come first. See `NameIndex::search`.
+    pub fn search_names(
+        &self,
+        prefix: &str,
+        lang: Option<&str>,
+        bbox_hint: Option<&BoundingBox<i32>>,
+    ) -> Vec<NameMatch> {
+        self.names.search(prefix, lang, bbox_hint, MAX_NAME_RESULTS)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.areas.flush()?;
+        self.places.flush()?;
+        self.addresses.flush()?;
+        self.names.flush()
+    }
+}
@@ -0,0 +1,20 @@
+//! Offline geocoding. Administrative boundaries are assembled into polygons while a
+//! map is compressed, and buildings and addresses are indexed by position, so that a
+//! point can be turned into the areas and address it's in. Addresses and named roads
+//! are also indexed by street, so that typed-in addresses can be found, and everything
+//! with a name is indexed by it, for autocompletion.
+
+pub mod address;
+pub mod admin;
+pub mod geocoder;
+pub mod name;
+pub mod place;
+pub mod polygon;
+pub mod text;
+
+pub use address::AddressMatch;
+pub use geocoder::{Geocoder, NearbyPlace, ReverseGeocode};
+pub use name::NameMatch;
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,374 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use minimal_storage::{
+    provider::{Directory, StorageProvider},
+    serialize_fast::MinimalSerdeFast,
+    serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal},
+};
+use osm_tag_compression::{
+    compressed_data::{flattened_id, CompressedOsmData},
+    field::Field,
+};
+use osm_tags_to_fields::fields::AnyOsmField;
+use tree::{bbox::BoundingBox, open_tree_sparse_in, point_range::StoredBinaryTree};
+
+use crate::text::normalize;
+
+const NAME_SATURATION: usize = 8_000;
+
+/// Names are also indexed from the start of each of their first few words, so that
+/// `bahnhof` finds `Berlin Hauptbahnhof`.
+const MAX_WORD_STARTS: usize = 4;
+
+/// A name of an object, stored under the first 8 bytes of the normalized name (or of
+/// one of its words). Since the name tree splits its keys in halves, it's a trie over
+/// those bytes, and all the names starting with a prefix are one range of keys.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NameEntry {
+    /// Flattened OSM id, see `flattened_id`
+    pub id: u64,
+    pub point: (i32, i32),
+    /// How important the object is, from 0 to 100. See `popularity`
+    pub rank: u8,
+    /// Which tag the name came from: `name`, `name:xx`, `alt_name` or `old_name`
+    pub key: String,
+    pub name: String,
+}
+
+impl NameEntry {
+    /// The language of a `name:xx` name.
+    pub fn lang(&self) -> Option<&str> {
+        self.key.strip_prefix("name:")
+    }
+}
+
+/// Which of an object's tags are names, and what they say.
+pub fn names_of<'a>(fields: impl Iterator<Item = &'a Field>) -> Vec<(String, String)> {
+    let mut names = Vec::new();
+
+    for field in fields {
+        match field {
+            Field::Field(AnyOsmField::NameLocalizedString(n)) => {
+                names.extend(n.value().iter().map(|(lang, v)| {
+                    (format!("name:{}", String::from_utf8_lossy(lang)), v.clone())
+                }))
+            }
+            _ => {
+                let Some((k, v)) = field.as_tag() else {
+                    continue;
+                };
+
+                let is_name = match k.as_ref() {
+                    "name" | "alt_name" | "old_name" => true,
+                    k => k.strip_prefix("name:").is_some_and(is_language_code),
+                };
+
+                if is_name && !v.is_empty() {
+                    names.push((k.into_owned(), v.into_owned()));
+                }
+            }
+        }
+    }
+
+    //plain `name` first, so that it's the one kept when a translation is the same
+    names.sort_by_key(|(k, _)| k != "name");
+
+    names
+}
+
+/// `de`, `zh-Hans`, or `sr-Latn`, but not `etymology` from `name:etymology`.
+fn is_language_code(code: &str) -> bool {
+    let (lang, script) = code.split_once('-').unwrap_or((code, ""));
+
+    (2..=3).contains(&lang.len())
+        && lang.chars().all(|c| c.is_ascii_lowercase())
+        && script.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// How important an object is likely to be to someone searching for it: countries
+/// above cities above villages above everything else.
+pub fn popularity<'a>(fields: impl Iterator<Item = &'a Field>) -> u8 {
+    let mut rank = 5;
+
+    for (k, v) in fields.filter_map(Field::as_tag) {
+        let r = match (k.as_ref(), v.as_ref()) {
+            ("place", "country") => 100,
+            ("place", "state") => 90,
+            ("place", "region" | "province") => 85,
+            ("place", "city") => 80,
+            ("place", "county") => 70,
+            ("place", "town") => 60,
+            ("place", "island") => 50,
+            ("place", "borough" | "municipality") => 50,
+            ("place", "suburb") => 45,
+            ("place", "village") => 40,
+            ("place", "quarter") => 35,
+            ("place", "hamlet" | "neighbourhood") => 25,
+            ("place", "locality" | "islet") => 20,
+            ("place", _) => 15,
+            ("admin_level", level) => match level.parse::<f64>() {
+                Ok(level) if (1.0..=12.0).contains(&level) => 110 - 6 * level as u8,
+                _ => 0,
+            },
+            ("highway", _) => 10,
+            _ => 0,
+        };
+
+        rank = rank.max(r);
+    }
+
+    rank
+}
+
+/// The key which names starting with `normalized` are stored under: its first 8 bytes,
+/// padded with `fill`.
+fn prefix_key(normalized: &str, fill: u8) -> u64 {
+    let mut bytes = [fill; 8];
+    for (b, n) in bytes.iter_mut().zip(normalized.bytes()) {
+        *b = n;
+    }
+    u64::from_be_bytes(bytes)
+}
+
+/// The name, and the rest of it from the start of each of its next few words.
+fn word_starts(normalized: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(normalized)
+        .chain(
+            normalized
+                .match_indices(' ')
+                .map(move |(i, _)| &normalized[i + 1..]),
+        )
+        .take(MAX_WORD_STARTS)
+}
+
+/// A search result.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NameMatch {
+    pub entry: NameEntry,
+    pub score: f64,
+}
+
+pub struct NameIndex {
+    names: StoredBinaryTree<NAME_SATURATION, u64, NameEntry>,
+}
+
+impl NameIndex {
+    pub fn open(folder: PathBuf) -> Self {
+        let directory = Directory::new(folder.parent().unwrap_or(Path::new("")));
+        let name = folder.file_name().and_then(|name| name.to_str()).unwrap();
+
+        Self::open_in(&directory, name)
+    }
+
+    /// Like `open`, but with the index in the file named `name` in `files`
+    pub fn open_in(files: &dyn StorageProvider, name: &str) -> Self {
+        let mut names =
+            open_tree_sparse_in::<1, NAME_SATURATION, u64, NameEntry>(files, name, 0..=u64::MAX);
+        names.expand_to_depth(5);
+
+        NameIndex { names }
+    }
+
+    /// Index every name the object has.
+    pub fn add(&self, data: &CompressedOsmData) {
+        let Some(fields) = data.fields() else {
+            return;
+        };
+
+        let names = names_of(fields.iter());
+        if names.is_empty() {
+            return;
+        }
+
+        let id = flattened_id(&data.osm_id());
+        let point = data.bbox().center();
+        let rank = popularity(fields.iter());
+
+        //translations are often the same as the name; there's no need to store those
+        // twice, but ones which only differ in accents are kept, to be shown in their
+        // own language
+        let mut seen: Vec<(String, String)> = Vec::new();
+        for (key, name) in names {
+            let normalized = normalize(&name);
+
+            for start in word_starts(&normalized) {
+                if seen.iter().any(|(s, n)| s == start && *n == name) {
+                    continue;
+                }
+                seen.push((start.to_string(), name.clone()));
+
+                self.names.insert(
+                    prefix_key(start, 0),
+                    NameEntry {
+                        id,
+                        point,
+                        rank,
+                        key: key.clone(),
+                        name: name.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// The best `limit` names starting with `prefix`, or with a word starting with it.
+    /// If `lang` is given, names in other languages are left out, and names in it come
+    /// first. Objects in `bbox_hint` come before ones outside it.
+    pub fn search(
+        &self,
+        prefix: &str,
+        lang: Option<&str>,
+        bbox_hint: Option<&BoundingBox<i32>>,
+        limit: usize,
+    ) -> Vec<NameMatch> {
+        let prefix = normalize(prefix);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+
+        let range = prefix_key(&prefix, 0)..=prefix_key(&prefix, u8::MAX);
+
+        let mut best: HashMap<u64, NameMatch> = HashMap::new();
+        for (_, entry) in self.names.find_entries_in_box(&range) {
+            if let (Some(wanted), Some(entry_lang)) = (lang, entry.lang()) {
+                if wanted != entry_lang {
+                    continue;
+                }
+            }
+
+            let normalized = normalize(&entry.name);
+            let Some(start) = word_starts(&normalized).position(|s| s.starts_with(&prefix)) else {
+                //only the first 8 bytes were compared by the key
+                continue;
+            };
+
+            let mut score = entry.rank as f64;
+            if normalized == prefix {
+                score += 50.0;
+            }
+            if start == 0 {
+                score += 20.0;
+            }
+            match (entry.key.as_str(), entry.lang()) {
+                (_, Some(_)) if entry.lang() == lang => score += 10.0,
+                ("name", _) => score += 5.0,
+                (_, None) => score -= 10.0,
+                _ => {}
+            }
+            if bbox_hint
+                .is_some_and(|b| b.contains(&BoundingBox::from_point(entry.point.0, entry.point.1)))
+            {
+                score += 30.0;
+            }
+
+            match best.get(&entry.id) {
+                Some(existing) if existing.score >= score => {}
+                _ => {
+                    best.insert(entry.id, NameMatch { entry, score });
+                }
+            }
+        }
+
+        let mut matches: Vec<_> = best.into_values().collect();
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.entry.name.len().cmp(&b.entry.name.len()))
+                .then_with(|| a.entry.name.cmp(&b.entry.name))
+        });
+        matches.truncate(limit);
+
+        matches
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.names.flush()
+    }
+}
+
+impl SerializeMinimal for NameEntry {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        self.id.minimally_serialize(write_to, ())?;
+        self.point.0.minimally_serialize(write_to, ())?;
+        self.point.1.minimally_serialize(write_to, ())?;
+        write_to.write_all(&[self.rank])?;
+        self.key.minimally_serialize(write_to, 0.into())?;
+        self.name.minimally_serialize(write_to, 0.into())
+    }
+}
+
+impl DeserializeFromMinimal for NameEntry {
+    type ExternalData<'d> = ();
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        Ok(NameEntry {
+            id: u64::deserialize_minimal(from, ())?,
+            point: (
+                i32::deserialize_minimal(from, ())?,
+                i32::deserialize_minimal(from, ())?,
+            ),
+            rank: from.read_one()?,
+            key: String::deserialize_minimal(from, None)?,
+            name: String::deserialize_minimal(from, None)?,
+        })
+    }
+}
+
+impl MinimalSerdeFast for NameEntry {
+    fn fast_minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        external_data: (),
+    ) -> std::io::Result<()> {
+        //entries are mostly strings, which have no faster form
+        self.minimally_serialize(write_to, external_data)
+    }
+
+    fn fast_deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        Self::deserialize_minimal(from, external_data)
+    }
+
+    fn fast_seek_after<R: std::io::Read>(from: &mut R) -> std::io::Result<()> {
+        Self::deserialize_minimal(from, ()).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn keys_sort_by_prefix() {
+        let lo = prefix_key("ber", 0);
+        let hi = prefix_key("ber", u8::MAX);
+
+        for inside in ["ber", "berlin", "bernau", "berliner strasse"] {
+            assert!((lo..=hi).contains(&prefix_key(inside, 0)), "{inside}");
+        }
+        for outside in ["be", "bea", "bfr", "zurich", "берлин"] {
+            assert!(!(lo..=hi).contains(&prefix_key(outside, 0)), "{outside}");
+        }
+
+        assert_eq!(
+            word_starts("berlin hauptbahnhof ost").collect::<Vec<_>>(),
+            vec!["berlin hauptbahnhof ost", "hauptbahnhof ost", "ost"]
+        );
+        assert!(is_language_code("de") && is_language_code("sr-Latn"));
+        assert!(!is_language_code("etymology") && !is_language_code("DE"));
+    }
+}
@@ -0,0 +1,139 @@
+use minimal_storage::serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal};
+use osm_tag_compression::{
+    compressed_data::{flattened_id, CompressedOsmData},
+    field::Field,
+};
+use osm_tags_to_fields::fields::AnyOsmField;
+use tree::bbox::BoundingBox;
+
+/// A building or an addressed object, stored in the place tree at its centre point.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct AddressPoint {
+    /// Flattened OSM id, see `flattened_id`
+    pub id: u64,
+    pub building: bool,
+    pub housenumber: Option<String>,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub postcode: Option<String>,
+}
+
+impl AddressPoint {
+    /// The object's centre and address, if it's a building or has an address.
+    pub fn from_data(data: &CompressedOsmData) -> Option<((i32, i32), Self)> {
+        let fields = data.fields()?;
+
+        let mut point = AddressPoint {
+            id: flattened_id(&data.osm_id()),
+            ..Default::default()
+        };
+
+        for field in fields.iter() {
+            match field {
+                Field::Field(AnyOsmField::AddrAddress(address)) => {
+                    let address = address.value();
+                    let text = |v: Option<&osm_value_atom::LiteralValue>| v.map(|v| v.to_string());
+
+                    point.housenumber = text(address.number());
+                    point.street = text(address.street());
+                    point.city = text(address.city());
+                    point.postcode = text(address.postcode());
+                }
+                _ => {
+                    if let Some((k, v)) = field.as_tag() {
+                        if k == "building" && v != "no" {
+                            point.building = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        if !point.building && point.housenumber.is_none() && point.street.is_none() {
+            return None;
+        }
+
+        Some((data.bbox().center(), point))
+    }
+
+    /// A single line, like `12 Main St, Springfield 12345`.
+    pub fn display(&self) -> String {
+        let first = [&self.housenumber, &self.street]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let second = [&self.city, &self.postcode]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        [first, second]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+//header byte layout:
+//bit 0: building
+//bits 1-4: whether each of housenumber, street, city, and postcode is present
+impl SerializeMinimal for AddressPoint {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        let parts = [&self.housenumber, &self.street, &self.city, &self.postcode];
+
+        let mut header = self.building as u8;
+        for (i, part) in parts.iter().enumerate() {
+            if part.is_some() {
+                header |= 1 << (i + 1);
+            }
+        }
+
+        write_to.write_all(&[header])?;
+        self.id.minimally_serialize(write_to, ())?;
+
+        for part in parts.into_iter().flatten() {
+            part.minimally_serialize(write_to, 0.into())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DeserializeFromMinimal for AddressPoint {
+    type ExternalData<'d> = &'d BoundingBox<i32>;
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: &'d BoundingBox<i32>,
+    ) -> Result<Self, std::io::Error> {
+        let header = from.read_one()?;
+        let id = u64::deserialize_minimal(from, ())?;
+
+        let mut part = |i: usize| -> std::io::Result<Option<String>> {
+            if header & (1 << (i + 1)) == 0 {
+                return Ok(None);
+            }
+            String::deserialize_minimal(from, None).map(Some)
+        };
+
+        Ok(AddressPoint {
+            id,
+            building: header & 1 != 0,
+            housenumber: part(0)?,
+            street: part(1)?,
+            city: part(2)?,
+            postcode: part(3)?,
+        })
+    }
+}
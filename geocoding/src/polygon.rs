@@ -0,0 +1,87 @@
+pub use tree::shapes::{Polygon, Ring};
+
+/// Build a polygon from the outer and inner member ways of a multipolygon-style
+/// relation. Returns `None` if the ways can't be joined into closed rings.
+pub fn polygon_from_ways(
+    outer: Vec<Vec<(i32, i32)>>,
+    inner: Vec<Vec<(i32, i32)>>,
+) -> Option<Polygon> {
+    //outer and inner rings are joined separately, since an inner ring may touch
+    // the outer one and would otherwise be merged into it
+    let mut rings = assemble_rings(outer)?;
+
+    if rings.is_empty() {
+        return None;
+    }
+
+    rings.extend(assemble_rings(inner)?);
+
+    Some(Polygon { rings })
+}
+
+/// Join ways end-to-end into closed rings. The ways may be in any order, and any
+/// direction. Returns `None` if some ways can't be closed into a ring.
+pub fn assemble_rings(mut ways: Vec<Vec<(i32, i32)>>) -> Option<Vec<Ring>> {
+    ways.retain(|w| w.len() >= 2);
+
+    let mut rings = Vec::new();
+
+    while let Some(mut ring) = ways.pop() {
+        while ring.first() != ring.last() {
+            let end = *ring.last().unwrap();
+
+            let next = ways
+                .iter()
+                .position(|w| w.first() == Some(&end) || w.last() == Some(&end))?;
+
+            let mut way = ways.swap_remove(next);
+            if way[0] != end {
+                way.reverse();
+            }
+
+            ring.extend_from_slice(&way[1..]);
+        }
+
+        //a ring needs at least 3 distinct points to have an area
+        if ring.len() >= 4 {
+            rings.push(ring);
+        }
+    }
+
+    Some(rings)
+}
+
+#[cfg(test)]
+mod test {
+    use tree::bbox::BoundingBox;
+
+    use super::*;
+
+    #[test]
+    pub fn joins_ways_in_any_direction() {
+        let ways = vec![
+            vec![(0, 0), (10, 0)],
+            vec![(10, 10), (0, 10), (0, 0)],
+            vec![(10, 10), (10, 0)],
+        ];
+
+        let rings = assemble_rings(ways).unwrap();
+        assert_eq!(rings.len(), 1);
+        assert_eq!(rings[0].len(), 5);
+        assert_eq!(rings[0].first(), rings[0].last());
+
+        assert!(assemble_rings(vec![vec![(0, 0), (10, 0)], vec![(10, 0), (10, 10)]]).is_none());
+    }
+
+    #[test]
+    pub fn holes() {
+        let square = |min, max| vec![(min, min), (max, min), (max, max), (min, max), (min, min)];
+
+        let polygon = polygon_from_ways(vec![square(0, 100)], vec![square(40, 60)]).unwrap();
+
+        assert!(polygon.contains((10, 10)));
+        assert!(!polygon.contains((50, 50)));
+        assert!(!polygon.contains((150, 50)));
+        assert_eq!(polygon.bbox(), BoundingBox::new(0, 0, 100, 100));
+    }
+}
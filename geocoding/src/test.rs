@@ -0,0 +1,346 @@
+use std::path::PathBuf;
+
+use osm_tag_compression::compressed_data::{flattened_id, CompressedOsmData, Fields, NodeFields};
+use osmpbfreader::{NodeId, Tags, WayId};
+use tree::{bbox::BoundingBox, geo::degrees_to_decimicro};
+
+use crate::{
+    address::{AssociatedStreet, PendingInterpolation},
+    admin::{AdminArea, AdminBoundary},
+    polygon::polygon_from_ways,
+    Geocoder,
+};
+
+fn open_test_geocoder(name: &str) -> Geocoder {
+    let path = PathBuf::from(".test").join(name);
+    let _ = std::fs::remove_dir_all(&path);
+
+    Geocoder::open(path)
+}
+
+fn square(min: (f64, f64), max: (f64, f64)) -> Vec<(i32, i32)> {
+    [
+        (min.0, min.1),
+        (max.0, min.1),
+        (max.0, max.1),
+        (min.0, max.1),
+        (min.0, min.1),
+    ]
+    .into_iter()
+    .map(|(lon, lat)| degrees_to_decimicro(lon, lat))
+    .collect()
+}
+
+fn area(relation: u64, admin_level: u8, name: &str, outer: Vec<(i32, i32)>) -> AdminArea {
+    AdminArea {
+        boundary: AdminBoundary {
+            relation,
+            admin_level,
+            name: name.to_string(),
+        },
+        polygon: polygon_from_ways(vec![outer], vec![]).unwrap(),
+    }
+}
+
+fn building(id: i64, center: (f64, f64), tags: &[(&str, &str)]) -> CompressedOsmData {
+    let children = square(
+        (center.0 - 0.0001, center.1 - 0.0001),
+        (center.0 + 0.0001, center.1 + 0.0001),
+    );
+    let (min, max) = (children[0], children[2]);
+
+    let mut t = Tags::new();
+    for (k, v) in tags {
+        t.insert((*k).into(), (*v).into());
+    }
+
+    CompressedOsmData::Way {
+        bbox: BoundingBox::new(min.0, min.1, max.0, max.1),
+        id: WayId(id),
+        tags: Fields::from_tags(t),
+        children,
+    }
+}
+
+#[test]
+pub fn boundary_hierarchy() {
+    let mut geocoder = open_test_geocoder("boundary_hierarchy");
+
+    geocoder.add_admin_area(area(1, 2, "Country", square((-1.0, -1.0), (1.0, 1.0))));
+    geocoder.add_admin_area(area(2, 8, "Town", square((0.1, 0.1), (0.2, 0.2))));
+    geocoder.add_admin_area(area(3, 8, "Elsewhere", square((0.3, 0.1), (0.4, 0.2))));
+    //an L shape, whose bbox covers the point but whose area doesn't
+    geocoder.add_admin_area(area(
+        4,
+        6,
+        "County",
+        [
+            (0.0, 0.0),
+            (0.5, 0.0),
+            (0.5, 0.05),
+            (0.05, 0.05),
+            (0.05, 0.5),
+            (0.0, 0.5),
+            (0.0, 0.0),
+        ]
+        .into_iter()
+        .map(|(lon, lat)| degrees_to_decimicro(lon, lat))
+        .collect(),
+    ));
+    geocoder.flush().unwrap();
+
+    let names = |r: crate::ReverseGeocode| r.areas.into_iter().map(|a| a.name).collect::<Vec<_>>();
+
+    assert_eq!(
+        names(geocoder.reverse_geocode(0.15, 0.15)),
+        vec!["Country", "Town"]
+    );
+    assert_eq!(
+        names(geocoder.reverse_geocode(0.01, 0.3)),
+        vec!["Country", "County"]
+    );
+    assert!(names(geocoder.reverse_geocode(5.0, 5.0)).is_empty());
+}
+
+#[test]
+pub fn nearest_address() {
+    let geocoder = open_test_geocoder("nearest_address");
+
+    geocoder.add_place(&building(
+        10,
+        (0.15, 0.15),
+        &[
+            ("building", "house"),
+            ("addr:housenumber", "12"),
+            ("addr:street", "Main St"),
+            ("addr:city", "Springfield"),
+        ],
+    ));
+    geocoder.add_place(&building(11, (0.1508, 0.15), &[("building", "yes")]));
+    geocoder.add_place(&building(12, (0.1502, 0.15), &[("amenity", "bench")]));
+
+    let nearest = geocoder.reverse_geocode(0.1501, 0.15).nearest.unwrap();
+    assert_eq!(nearest.place.display(), "12 Main St, Springfield");
+    assert!(nearest.place.building);
+    assert!(
+        (nearest.distance_meters - 11.1).abs() < 0.5,
+        "{}",
+        nearest.distance_meters
+    );
+
+    let nearest = geocoder.reverse_geocode(0.1507, 0.15).nearest.unwrap();
+    assert_eq!(nearest.place.housenumber, None);
+
+    assert!(geocoder.reverse_geocode(0.16, 0.15).nearest.is_none());
+}
+
+fn node(id: i64, at: (f64, f64), tags: &[(&str, &str)]) -> CompressedOsmData {
+    let (x, y) = degrees_to_decimicro(at.0, at.1);
+
+    let mut t = Tags::new();
+    for (k, v) in tags {
+        t.insert((*k).into(), (*v).into());
+    }
+
+    CompressedOsmData::Node {
+        id: NodeId(id),
+        tags: NodeFields::Multiple(Fields::from_tags(t)),
+        point: BoundingBox::from_point(x, y),
+    }
+}
+
+fn road(id: i64, from: (f64, f64), to: (f64, f64), tags: &[(&str, &str)]) -> CompressedOsmData {
+    let children = vec![
+        degrees_to_decimicro(from.0, from.1),
+        degrees_to_decimicro((from.0 + to.0) / 2.0, (from.1 + to.1) / 2.0),
+        degrees_to_decimicro(to.0, to.1),
+    ];
+
+    let mut bbox = BoundingBox::from_point(children[0].0, children[0].1);
+    for (x, y) in children.iter() {
+        bbox.extend_with_point(*x, *y);
+    }
+
+    let mut t = Tags::new();
+    for (k, v) in tags {
+        t.insert((*k).into(), (*v).into());
+    }
+
+    CompressedOsmData::Way {
+        bbox,
+        id: WayId(id),
+        tags: Fields::from_tags(t),
+        children,
+    }
+}
+
+#[test]
+pub fn address_search() {
+    let mut geocoder = open_test_geocoder("address_search");
+
+    geocoder.add_admin_area(area(1, 8, "Springfield", square((0.1, 0.1), (0.2, 0.2))));
+    geocoder.add_place(&road(
+        100,
+        (0.14, 0.1505),
+        (0.16, 0.1505),
+        &[("highway", "residential"), ("name", "Main Street")],
+    ));
+    for (id, center, number, street, city) in [
+        (10, (0.15, 0.15), "12", "Main St", "Springfield"),
+        (11, (0.1502, 0.15), "14", "Main St", "Springfield"),
+        (12, (0.3, 0.3), "12", "Elm Street", "Shelbyville"),
+    ] {
+        geocoder.add_place(&building(
+            id,
+            center,
+            &[
+                ("building", "house"),
+                ("addr:housenumber", number),
+                ("addr:street", street),
+                ("addr:city", city),
+                ("addr:postcode", "12345"),
+            ],
+        ));
+    }
+    geocoder.flush().unwrap();
+
+    let first = |query: &str| {
+        let results = geocoder.search_address(query);
+        assert!(!results.is_empty(), "nothing found for {query}");
+        results[0].clone()
+    };
+
+    let exact = first("12 Main St, Springfield");
+    assert_eq!(exact.place.display(), "12 Main St, Springfield 12345");
+    assert_eq!(exact.point, degrees_to_decimicro(0.15, 0.15));
+
+    assert_eq!(first("12 Mian Stret, Sprngfield").place, exact.place);
+    assert_eq!(first("Main St 12 12345").place, exact.place);
+
+    //without a house number, the road itself, in the area the query named
+    assert_eq!(
+        first("Main Street, Springfield").place.display(),
+        "Main Street, Springfield"
+    );
+    //a house which isn't mapped falls back to the street
+    assert_eq!(first("99 Main St").place.display(), "Main Street");
+
+    assert_eq!(
+        first("12 Elm St").place.display(),
+        "12 Elm Street, Shelbyville 12345"
+    );
+    assert!(geocoder.search_address("12 Oak St").is_empty());
+}
+
+#[test]
+pub fn interpolation_and_associated_street() {
+    let mut geocoder = open_test_geocoder("interpolation_and_associated_street");
+
+    let (start, end) = ((0.15, 0.16), (0.152, 0.16));
+    for (id, at, number) in [(1, start, "2"), (2, end, "10")] {
+        geocoder.add_place(&node(
+            id,
+            at,
+            &[("addr:housenumber", number), ("addr:street", "Oak Road")],
+        ));
+    }
+
+    let way = road(200, start, end, &[("addr:interpolation", "even")]);
+    geocoder.add_place(&way);
+    let pending = PendingInterpolation::from_way(&[1, 3, 2], &way).unwrap();
+    assert!(geocoder.add_interpolation(pending));
+
+    let house = building(
+        20,
+        (0.2, 0.2),
+        &[("building", "house"), ("addr:housenumber", "5")],
+    );
+    geocoder.add_place(&house);
+    geocoder.add_associated_street(
+        &AssociatedStreet {
+            street: "Birch Lane".into(),
+            houses: vec![house.osm_id()],
+        },
+        [(*house.bbox(), flattened_id(&house.osm_id()))].into_iter(),
+    );
+    geocoder.flush().unwrap();
+
+    let results = geocoder.search_address("6 Oak Rd");
+    assert!(results[0].interpolated);
+    assert_eq!(results[0].place.display(), "6 Oak Road");
+    assert_eq!(results[0].point, degrees_to_decimicro(0.151, 0.16));
+
+    //odd numbers aren't in an even interpolation, so only the ends' street matches
+    assert!(geocoder
+        .search_address("7 Oak Rd")
+        .iter()
+        .all(|r| !r.interpolated));
+
+    let results = geocoder.search_address("5 Birch Ln");
+    assert_eq!(results[0].place.display(), "5 Birch Lane");
+    assert_eq!(results[0].point, house.bbox().center());
+}
+
+#[test]
+pub fn name_search() {
+    let mut geocoder = open_test_geocoder("name_search");
+
+    let place = |id, at, tags: &[(&str, &str)]| geocoder.add_place(&node(id, at, tags));
+
+    place(1, (13.4, 52.5), &[("place", "city"), ("name", "Berlin"), ("name:ru", "Берлин")]);
+    place(2, (13.6, 52.7), &[("place", "town"), ("name", "Bernau bei Berlin")]);
+    place(3, (8.5, 47.4), &[("place", "city"), ("name", "Zürich"), ("name:en", "Zurich")]);
+    place(4, (37.6, 55.8), &[("place", "city"), ("name", "Москва"), ("name:en", "Moscow")]);
+    place(5, (37.0, 55.0), &[("place", "village"), ("name", "Ёлкино")]);
+    place(
+        6,
+        (20.5, 54.7),
+        &[("place", "city"), ("name", "Калининград"), ("old_name", "Königsberg")],
+    );
+    place(7, (-89.6, 39.8), &[("place", "town"), ("name", "Springfield")]);
+    place(8, (-93.3, 37.2), &[("place", "town"), ("name", "Springfield")]);
+
+    geocoder.add_place(&road(
+        100,
+        (13.41, 52.51),
+        (13.42, 52.51),
+        &[("highway", "residential"), ("name", "Berliner Straße")],
+    ));
+    geocoder.flush().unwrap();
+
+    let names = |prefix: &str, lang: Option<&str>| {
+        geocoder
+            .search_names(prefix, lang, None)
+            .into_iter()
+            .map(|m| m.entry.name)
+            .collect::<Vec<_>>()
+    };
+
+    //cities before towns before streets, and whole-name prefixes before word prefixes
+    assert_eq!(
+        names("ber", None),
+        vec!["Berlin", "Bernau bei Berlin", "Berliner Straße"]
+    );
+    assert_eq!(names("berlin", None)[0], "Berlin");
+    assert_eq!(names("strasse", None), vec!["Berliner Straße"]);
+
+    assert_eq!(names("ZÜR", None), vec!["Zürich"]);
+    assert_eq!(names("zur", Some("en")), vec!["Zurich"]);
+
+    assert_eq!(names("МОСК", None), vec!["Москва"]);
+    assert_eq!(names("mosc", None), vec!["Moscow"]);
+    assert_eq!(names("елк", None), vec!["Ёлкино"]);
+    assert_eq!(names("königs", None), vec!["Königsberg"]);
+
+    assert_eq!(names("бер", Some("ru")), vec!["Берлин"]);
+    assert!(names("бер", Some("de")).is_empty());
+
+    let (x, y) = degrees_to_decimicro(-93.3, 37.2);
+    let hint = BoundingBox::new(x - 1_000_000, y - 1_000_000, x + 1_000_000, y + 1_000_000);
+    let hinted = geocoder.search_names("springf", None, Some(&hint));
+    assert_eq!(hinted.len(), 2);
+    assert_eq!(
+        hinted[0].entry.id,
+        flattened_id(&osmpbfreader::OsmId::Node(NodeId(8)))
+    );
+}
@@ -0,0 +1,207 @@
+/// Lowercase, remove accents, and turn punctuation into spaces, so that names can be
+/// compared regardless of how they were typed.
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for ch in text.chars().flat_map(char::to_lowercase) {
+        match fold_char(ch) {
+            Some(s) => folded.push_str(s),
+            None if ch.is_alphanumeric() => folded.push(ch),
+            None => folded.push(' '),
+        }
+    }
+
+    folded
+}
+
+/// The ASCII spelling of accented lowercase Latin letters, and the plain forms of
+/// Cyrillic letters with diacritics.
+fn fold_char(ch: char) -> Option<&'static str> {
+    Some(match ch {
+        //combining marks, from text which was decomposed before it was tagged
+        '\u{300}'..='\u{36f}' => "",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĵ' => "j",
+        'ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' | 'ț' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        'ё' => "е",
+        'й' => "и",
+        'ї' => "і",
+        'ў' => "у",
+        'ѓ' => "г",
+        'ќ' => "к",
+        '\'' | '’' => "",
+        _ => return None,
+    })
+}
+
+/// `fold`, with runs of spaces collapsed into one.
+pub fn normalize(text: &str) -> String {
+    fold(text).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Common abbreviations in street names, and what they're short for.
+const ABBREVIATIONS: [(&str, &str); 22] = [
+    ("st", "street"),
+    ("str", "strasse"),
+    ("ave", "avenue"),
+    ("av", "avenue"),
+    ("rd", "road"),
+    ("dr", "drive"),
+    ("blvd", "boulevard"),
+    ("ln", "lane"),
+    ("ct", "court"),
+    ("pl", "place"),
+    ("sq", "square"),
+    ("hwy", "highway"),
+    ("pkwy", "parkway"),
+    ("cres", "crescent"),
+    ("ter", "terrace"),
+    ("n", "north"),
+    ("s", "south"),
+    ("e", "east"),
+    ("w", "west"),
+    ("ne", "northeast"),
+    ("nw", "northwest"),
+    ("mt", "mount"),
+];
+
+/// Fold `text`, split it into words, and expand abbreviations. `Hauptstr.` and
+/// `hauptstrasse` aren't split into the same words; fuzzy matching catches those.
+pub fn words(text: &str) -> Vec<String> {
+    fold(text)
+        .split_whitespace()
+        .map(|w| {
+            ABBREVIATIONS
+                .iter()
+                .find(|(short, _)| *short == w)
+                .map(|(_, long)| long.to_string())
+                .unwrap_or_else(|| w.to_string())
+        })
+        .collect()
+}
+
+/// Whether the word is one of the kinds of road (or directions) which so many street
+/// names have that it doesn't say which street is meant.
+pub fn is_street_type(word: &str) -> bool {
+    ABBREVIATIONS.iter().any(|(_, long)| *long == word)
+}
+
+/// The word, and (for words long enough that a typo is likely) every way of deleting
+/// one character from it. Two words which share a variant are at most about one edit
+/// apart.
+pub fn variants(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut variants = vec![word.to_string()];
+
+    if chars.len() >= 4 {
+        for skip in 0..chars.len() {
+            let v: String = chars
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != skip)
+                .map(|(_, c)| c)
+                .collect();
+            if !variants.contains(&v) {
+                variants.push(v);
+            }
+        }
+    }
+
+    variants
+}
+
+/// Whether two folded words are the same, allowing one typo in longer words.
+pub fn fuzzy_eq(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    if a.len().min(b.len()) < 4 || a.len().abs_diff(b.len()) > 1 {
+        return false;
+    }
+
+    //length of the common prefix and suffix; one edit apart if they leave at most one
+    // character in each word
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let left = a.len().max(b.len()) - prefix.min(a.len().min(b.len()));
+    left <= suffix + 1 || (a.len() == b.len() && transposed(&a, &b))
+}
+
+fn transposed(a: &[char], b: &[char]) -> bool {
+    let diffs: Vec<usize> = (0..a.len()).filter(|i| a[*i] != b[*i]).collect();
+    matches!(diffs.as_slice(), [i, j] if *j == i + 1 && a[*i] == b[*j] && a[*j] == b[*i])
+}
+
+/// A hash which is the same on every run and platform, for keying stored indexes.
+pub fn stable_hash(text: &str) -> u64 {
+    //FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn folding() {
+        assert_eq!(fold("Rue de l'Église"), "rue de leglise");
+        assert_eq!(words("Große Str."), vec!["grosse", "strasse"]);
+        assert_eq!(
+            words("12 N. Main St"),
+            vec!["12", "north", "main", "street"]
+        );
+
+        assert_eq!(normalize("ЁЛКИНО"), "елкино");
+        assert_eq!(normalize("Zu\u{308}rich  Hbf"), "zurich hbf");
+        assert_eq!(normalize("Новый Свет"), normalize("новыи свет"));
+    }
+
+    #[test]
+    pub fn fuzzy() {
+        assert!(fuzzy_eq("springfield", "springfeild"));
+        assert!(fuzzy_eq("springfield", "sprngfield"));
+        assert!(fuzzy_eq("springfield", "springfieldd"));
+        assert!(fuzzy_eq("main", "mian"));
+        assert!(fuzzy_eq("main", "maine"));
+        assert!(!fuzzy_eq("main", "mayne"));
+        assert!(!fuzzy_eq("springfield", "shelbyville"));
+
+        let shared = |a: &str, b: &str| variants(a).iter().any(|v| variants(b).contains(v));
+        assert!(shared("springfield", "springfeild"));
+        assert!(shared("main", "man"));
+        assert!(!shared("main", "elm"));
+    }
+}
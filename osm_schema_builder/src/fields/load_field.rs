@@ -303,11 +303,24 @@ impl FieldData {
             "##)
         };
 
+        let datatype = self.datatype();
+        let tag_value_code = self.tag_value_code();
+
         format!(
             r##"
-            
+
             {single_osm_field_code}
         {stateful_osm_field_code}
+            impl {name} {{
+                pub fn value(&self) -> &{datatype} {{
+                    &self.0
+                }}
+
+                pub fn tag_value(&self) -> Option<std::borrow::Cow<'_, str>> {{
+                    {tag_value_code}
+                }}
+            }}
+
             impl crate::fields::OsmField for {name} {{
         const FIELD_ID: u16 = {id};
         }}
@@ -331,6 +344,30 @@ impl FieldData {
         )
     }
 
+    /// Code which turns the field back into the value of its single OSM tag. Fields
+    /// which are built from more than one tag have no single value, and give `None`.
+    fn tag_value_code(&self) -> &'static str {
+        match self {
+            FieldData::Text { .. } => "Some(self.0.as_str().into())",
+            FieldData::Number { .. } => "Some(self.0.to_string().into())",
+            FieldData::UnitNumber { .. } => "Some(format!(\"{}{}\", self.0.0, self.0.1).into())",
+            FieldData::Colour { .. } => "Some(self.0.to_osm_string().into())",
+            FieldData::Date { .. } => r#"Some(match self.0 {
+                    (y, 0xff, _) => format!("{y:04}"),
+                    (y, m, 0xff) => format!("{y:04}-{m:02}"),
+                    (y, m, d) => format!("{y:04}-{m:02}-{d:02}"),
+                }.into())"#,
+            FieldData::Combo { .. } => "Some(self.0.as_str().into())",
+            FieldData::SemiCombo { .. } => "Some(self.0.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(\";\").into())",
+            FieldData::Checkbox { .. } => "Some(if self.0 { \"yes\" } else { \"no\" }.into())",
+            FieldData::LocalizedString { .. }
+            | FieldData::MultiYesCombo { .. }
+            | FieldData::DirectionalCombo { .. }
+            | FieldData::Address { .. }
+            | FieldData::Access {} => "None",
+        }
+    }
+
     pub fn is_single(&self) -> bool {
         if let AbstractKeyCount::One = self.key_count() {
             true
@@ -537,8 +574,9 @@ impl FieldData {
         };
 
         let end = match self {
+            //without any of their tags, these would still be a field, with nothing in it
             FieldData::LocalizedString { .. } |
-            FieldData::MultiYesCombo { .. } => format!("Some(({name}(state)).into())"),
+            FieldData::MultiYesCombo { .. } => format!("(state != Default::default()).then(|| ({name}(state)).into())"),
             FieldData::DirectionalCombo { .. } => format!("Some(({name}(state?)).into())"),
             FieldData::Access { .. } => "todo!()".to_string(),
             FieldData::Address { .. } => format!("state.to_option().map(|x| crate::fields::AnyOsmField::from({name}(x)))"),
@@ -678,18 +716,34 @@ fn generate_values_enum(root_key: &str, options: &[String]) -> String {
 
     s.push('}');
 
+    //map each variant back to the tag value it was parsed from
+    let as_str_arms = options
+        .iter()
+        .map(|x| format!("Self::{} => {x:?},", slugify(x, RustStruct)))
+        .collect::<String>();
+
+    s += &format!("
+    impl {root_key}Value {{
+        pub fn as_str(&self) -> &'static str {{
+            match *self {{ {as_str_arms} }}
+        }}
+    }}
+    ");
+
     let enum_count = options.len();
 
     assert!(enum_count < 256);
 
+    //the byte with the nibble in it is written as it is, since it's read back as it is.
+    //as a varint, it'd take two bytes whenever the field id leaves its top bit set
     let (ser_code, deser_code) = match enum_count {
         //Smallest: this can fit into the nibble of extra data we get, without adding any more bytes!
         0..16 => {
-            ("let mut external_data = external_data; external_data.copy_from(*self as u8); external_data.into_inner().minimally_serialize(write_to, ())", "let _from = from; Ok(unsafe { std::mem::transmute( external_data.into_inner_masked() ) })")
+            ("let mut external_data = external_data; external_data.copy_from(*self as u8); write_to.write_all(&[external_data.into_inner()])", "let _from = from; Ok(unsafe { std::mem::transmute( external_data.into_inner_masked() ) })")
         },
         //larger: this can fit into one u8. we assert during build that there aren't more than 256 enum variants
         0..256 => {
-            ("external_data.into_inner().minimally_serialize(write_to, ())?; (*self as u8).minimally_serialize(write_to, ())", "let _ = external_data; Ok(unsafe { std::mem::transmute(u8::deserialize_minimal(from, ())?) })")
+            ("write_to.write_all(&[external_data.into_inner()])?; (*self as u8).minimally_serialize(write_to, ())", "let _ = external_data; Ok(unsafe { std::mem::transmute(u8::deserialize_minimal(from, ())?) })")
         },
         _ => unreachable!()
     };
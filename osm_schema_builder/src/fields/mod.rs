@@ -7,6 +7,7 @@ use std::{
 
 use load_field::{load_field, Field, FieldData};
 use mod_tree::ModuleTree;
+use sha2::{Digest, Sha256};
 
 use crate::util::{slugify, SlugificationMethod};
 
@@ -34,9 +35,18 @@ pub fn make_fields(write_to: &mut impl Write) -> std::io::Result<()> {
     let field_types = make_field_structs("id-tagging-schema-data/fields".into())?;
 
     let mut field_id = 0;
-    let field_types = write_field_structs(write_to, field_types, &mut field_id)?;
+    let mut schema = Sha256::new();
+    let field_types = write_field_structs(write_to, field_types, &mut field_id, &mut schema)?;
 
     writeln!(write_to, "pub const MAX_FIELD_ID: usize = {field_id};")?;
+    //stored in every map, so that one whose fields were numbered or encoded differently
+    //isn't misread
+    let schema: [u8; 32] = schema.finalize().into();
+    writeln!(
+        write_to,
+        "pub const SCHEMA_HASH: u64 = {:#018x};",
+        u64::from_le_bytes(schema[..8].try_into().unwrap())
+    )?;
 
     write!(
         write_to,
@@ -117,6 +127,35 @@ pub fn make_fields(write_to: &mut impl Write) -> std::io::Result<()> {
 
     write!(write_to, "}}}}}}")?;
 
+    write!(
+        write_to,
+        r##"
+
+    impl AnyOsmField {{
+        pub fn key(&self) -> &'static str {{
+            match self {{
+    "##
+    )?;
+
+    for (enum_name, FieldReferenceData { key, .. }) in field_types.iter() {
+        write!(write_to, "AnyOsmField::{enum_name}(_) => {key:?},\n")?
+    }
+
+    write!(
+        write_to,
+        r##"}}}}
+
+        pub fn tag_value(&self) -> Option<std::borrow::Cow<'_, str>> {{
+            match self {{
+    "##
+    )?;
+
+    for (enum_name, _) in field_types.iter() {
+        write!(write_to, "AnyOsmField::{enum_name}(f) => f.tag_value(),\n")?
+    }
+
+    write!(write_to, "}}}}}}")?;
+
     field_types.iter().for_each(
         |(
             enum_name,
@@ -214,20 +253,22 @@ pub fn make_fields(write_to: &mut impl Write) -> std::io::Result<()> {
 struct FieldReferenceData {
     fully_qualified_struct_name: String,
     is_single_field: bool,
+    key: String,
 }
 
 fn write_field_structs(
     write_to: &mut impl Write,
     fields: ModuleTree<String, Field>,
     field_id_counter: &mut usize,
+    schema: &mut Sha256,
 ) -> std::io::Result<BTreeMap<String, FieldReferenceData>> {
     let mut result_types = BTreeMap::new();
 
     if let Some(field) = fields.value {
         let enum_name = field.data.enum_name();
 
-        write!(
-            write_to,
+        //a field's struct and impls are everything which decides how it's encoded
+        let code = format!(
             "#[derive(Clone, Debug)]\npub struct {}({});\n\n{}\n{}",
             field.name,
             field.data.datatype(),
@@ -235,8 +276,9 @@ fn write_field_structs(
             field
                 .data
                 .traitimpl(&&field.name, *field_id_counter, &enum_name)
-        )
-        .unwrap();
+        );
+        schema.update(code.as_bytes());
+        write_to.write_all(code.as_bytes()).unwrap();
 
         *field_id_counter += 1;
 
@@ -245,6 +287,7 @@ fn write_field_structs(
                 enum_name,
                 FieldReferenceData {
                     is_single_field: field.data.is_single(),
+                    key: field.data.key().cloned().unwrap_or_default(),
                     fully_qualified_struct_name: format!(
                         "{}::{}",
                         field.module.join("::"),
@@ -257,7 +300,7 @@ fn write_field_structs(
 
     for (mod_name, fields) in fields.children.into_iter() {
         write!(write_to, "\npub mod {mod_name} {{\n")?;
-        result_types.extend(write_field_structs(write_to, fields, field_id_counter)?);
+        result_types.extend(write_field_structs(write_to, fields, field_id_counter, schema)?);
         write!(write_to, "}}\n")?;
     }
 
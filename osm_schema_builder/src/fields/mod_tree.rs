@@ -1,15 +1,16 @@
-use std::{collections::HashMap, hash::Hash};
+use std::collections::BTreeMap;
 
 pub struct ModuleTree<K, V> {
     pub value: Option<V>,
-    pub children: HashMap<K, ModuleTree<K, V>>
+    //ordered, so that fields are numbered the same every time the schema is built
+    pub children: BTreeMap<K, ModuleTree<K, V>>
 }
 
-impl<K:Hash+Eq,V> ModuleTree<K,V> {
+impl<K:Ord,V> ModuleTree<K,V> {
     pub fn new() -> Self {
         Self {
             value: None,
-            children: HashMap::new(),
+            children: BTreeMap::new(),
         }
     }
 }
\ No newline at end of file
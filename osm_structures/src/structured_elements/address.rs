@@ -5,7 +5,7 @@ use osm_value_atom::LiteralValue;
 
 use crate::auxil::string_prefix_view::StrAsciiPrefixView;
 
-use super::insert_with_byte;
+use super::{get_with_byte, insert_with_byte};
 
 const MAX_TAG_LENGTH_PLUS_TWO: usize = 20;
 
@@ -39,6 +39,26 @@ impl OsmAddress {
             && self.extra.is_none()
     }
 
+    pub fn number(&self) -> Option<&LiteralValue> {
+        self.number.as_ref()
+    }
+
+    pub fn street(&self) -> Option<&LiteralValue> {
+        self.street.as_ref()
+    }
+
+    pub fn city(&self) -> Option<&LiteralValue> {
+        self.city.as_ref()
+    }
+
+    pub fn state(&self) -> Option<&LiteralValue> {
+        self.state.as_ref()
+    }
+
+    pub fn postcode(&self) -> Option<&LiteralValue> {
+        self.extra.as_ref()?.postcode.as_ref()
+    }
+
     pub fn is_karlsruhe_minimal(&self) -> bool {
         self.state.is_none()
             && self.number.is_some()
@@ -265,10 +285,91 @@ impl DeserializeFromMinimal for OsmAddress {
     type ExternalData<'d> = &'d Pool<LiteralValue>;
 
     fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
-        _from: &'a mut R,
-        _external_data: Self::ExternalData<'d>,
+        from: &'a mut R,
+        pool: Self::ExternalData<'d>,
     ) -> Result<Self, std::io::Error> {
-        todo!()
+        let first_byte = u8::deserialize_minimal(from, ())?;
+
+        //the niche'd version: just a small house number and a street
+        if first_byte & 0b1000_0000 != 0 {
+            let number = (first_byte & 0b11_1111) as usize + 1;
+            let street = get_with_byte(from, pool, 1, 0)?;
+
+            return Ok(OsmAddress {
+                number: Some(LiteralValue::from(number.to_string())),
+                street,
+                city: None,
+                state: None,
+                prefix: None,
+                province: None,
+                extra: None,
+            });
+        }
+
+        //the header bytes all come before any of the values
+        let second_byte = if first_byte & 1 != 0 {
+            u8::deserialize_minimal(from, ())?
+        } else {
+            0
+        };
+        let third_byte = if second_byte & (1 << 1) != 0 {
+            u8::deserialize_minimal(from, ())?
+        } else {
+            0
+        };
+
+        let number = get_with_byte(from, pool, first_byte, 6)?;
+        let street = get_with_byte(from, pool, first_byte, 5)?;
+        let city = get_with_byte(from, pool, first_byte, 4)?;
+        let state = get_with_byte(from, pool, first_byte, 3)?;
+        let province = get_with_byte(from, pool, first_byte, 2)?;
+        let prefix = get_with_byte(from, pool, first_byte, 1)?;
+
+        let extra = if first_byte & 1 != 0 {
+            let housename = get_with_byte(from, pool, second_byte, 7)?;
+            let unit = get_with_byte(from, pool, second_byte, 6)?;
+            let floor = get_with_byte(from, pool, second_byte, 5)?;
+            let postbox = get_with_byte(from, pool, second_byte, 4)?;
+            let full = get_with_byte(from, pool, second_byte, 3)?;
+            let postcode = get_with_byte(from, pool, second_byte, 2)?;
+
+            let even_more_extra = if second_byte & (1 << 1) != 0 {
+                Some(OsmAddressEvenMoreExtra {
+                    hamlet: get_with_byte(from, pool, third_byte, 7)?,
+                    suburb: get_with_byte(from, pool, third_byte, 6)?,
+                    subdistrict: get_with_byte(from, pool, third_byte, 5)?,
+                    county: get_with_byte(from, pool, third_byte, 4)?,
+                    door: get_with_byte(from, pool, third_byte, 3)?,
+                    flats: get_with_byte(from, pool, third_byte, 2)?,
+                    block: get_with_byte(from, pool, third_byte, 1)?,
+                    block_number: get_with_byte(from, pool, third_byte, 0)?,
+                })
+            } else {
+                None
+            };
+
+            Some(OsmAddressExtra {
+                housename,
+                unit,
+                floor,
+                postbox,
+                full,
+                postcode,
+                even_more_extra,
+            })
+        } else {
+            None
+        };
+
+        Ok(OsmAddress {
+            number,
+            street,
+            city,
+            state,
+            prefix,
+            province,
+            extra,
+        })
     }
 }
 
@@ -30,7 +30,37 @@ pub enum StandardColour {
     RedWhite = 15,
 }
 
+const VALUES: [u8; 6] = [0x00, 0x33, 0x66, 0x99, 0xcc, 0xff];
+
 impl OsmColour {
+    pub fn to_osm_string(&self) -> String {
+        match self {
+            Self::StandardColour(c) => match c {
+                StandardColour::Black => "black",
+                StandardColour::Brown => "brown",
+                StandardColour::Yellow => "yellow",
+                StandardColour::Green => "green",
+                StandardColour::GrayWithA => "gray",
+                StandardColour::GreyWithE => "grey",
+                StandardColour::White => "white",
+                StandardColour::Blue => "blue",
+                StandardColour::Orange => "orange",
+                StandardColour::Silver => "silver",
+                StandardColour::Purple => "purple",
+                StandardColour::DarkGreen => "darkgreen",
+                StandardColour::Beige => "beige",
+                StandardColour::Maroon => "maroon",
+                StandardColour::Red => "red",
+                StandardColour::RedWhite => "red/white",
+            }
+            .to_string(),
+            Self::Hex(r, g, b) => format!(
+                "#{:02x}{:02x}{:02x}",
+                VALUES[*r as usize], VALUES[*g as usize], VALUES[*b as usize]
+            ),
+        }
+    }
+
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "black" => Some(Self::StandardColour(StandardColour::Black)),
@@ -54,8 +84,6 @@ impl OsmColour {
                     let g = u8::from_str_radix(&s[3..5], 16).ok()?;
                     let b = u8::from_str_radix(&s[5..7], 16).ok()?;
 
-                    const VALUES: [u8; 6] = [0x00, 0x33, 0x66, 0x99, 0xcc, 0xff];
-
                     let ri = VALUES.iter().position(|x| *x == r)? as u8;
                     let gi = VALUES.iter().position(|x| *x == g)? as u8;
                     let bi = VALUES.iter().position(|x| *x == b)? as u8;
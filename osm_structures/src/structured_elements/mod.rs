@@ -1,4 +1,4 @@
-use minimal_storage::{pooled_storage::Pool, varint::ToVarint};
+use minimal_storage::{pooled_storage::{Pool, PooledId}, serialize_min::DeserializeFromMinimal, varint::ToVarint};
 use osm_value_atom::LiteralValue;
 
 pub mod address;
@@ -22,4 +22,27 @@ pub(self) fn insert_with_byte(
         }
         None => Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// The reverse of `insert_with_byte`: read the value's id if its bit is set, and get the
+/// value from the pool.
+#[inline]
+pub(self) fn get_with_byte<R: std::io::Read>(
+    from: &mut R,
+    pool: &Pool<LiteralValue>,
+    byte: u8,
+    byte_index: u8,
+) -> std::io::Result<Option<LiteralValue>> {
+    if (byte >> byte_index) & 1 == 0 {
+        return Ok(None);
+    }
+
+    let id = PooledId::deserialize_minimal(from, ())?;
+    match pool.get(id, ())? {
+        Some(value) => Ok(Some(LiteralValue::clone(&value))),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("pooled value {id} doesn't exist"),
+        )),
+    }
+}
@@ -1,14 +1,15 @@
 use debug_logs::debug_print;
-use node::{osm_node_to_compressed_node, serialize_node, NodeFields};
+use node::{osm_node_to_compressed_node, serialize_node};
+pub use node::{NodeFields, NodeSingleInlined};
 use osm_value_atom::LiteralValue;
 use osmpbfreader::{NodeId, OsmId, OsmObj, Ref, RelationId, WayId};
 use relation::{osm_relation_to_compressed_node, serialize_relation};
-use way::{deserialize_way, get_points, osm_way_to_compressed_node, serialize_way};
+use way::{deserialize_way, get_fields, get_id, get_points, osm_way_to_compressed_node, serialize_way};
 
 use tree::{bbox::BoundingBox, point_range::StoredBinaryTree};
 
 use minimal_storage::{
-    pooled_storage::Pool,
+    pooled_storage::{Pool, PooledId},
     serialize_min::{DeserializeFromMinimal, SerializeMinimal},
     varint::{from_varint, ToVarint},
 };
@@ -18,6 +19,42 @@ use crate::field::Field;
 #[derive(Clone, Debug)]
 pub struct Fields(Vec<Field>);
 
+impl Fields {
+    /// Parse tags into the typed fields they make up. Tags which aren't part of any
+    /// field are kept as they are.
+    pub fn from_tags(tags: osmpbfreader::Tags) -> Self {
+        let (fields, tags) = osm_tags_to_fields::fields::parse_tags_to_fields(tags);
+
+        let mut combined_fields = Vec::with_capacity(fields.len() + tags.len());
+
+        for t in fields {
+            combined_fields.push(Field::Field(t));
+        }
+        for (k, v) in tags.iter() {
+            combined_fields.push((k, v).into());
+        }
+
+        Fields(combined_fields)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Field> {
+        self.0.iter()
+    }
+
+    pub fn get_tag(&self, key: &str) -> Option<std::borrow::Cow<'_, str>> {
+        self.iter()
+            .filter_map(|f| f.as_tag())
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+impl FromIterator<Field> for Fields {
+    fn from_iter<T: IntoIterator<Item = Field>>(iter: T) -> Self {
+        Fields(iter.into_iter().collect())
+    }
+}
+
 mod node;
 mod relation;
 mod way;
@@ -52,6 +89,17 @@ impl CompressedOsmData {
         }
     }
 
+    /// The object's tags. Nodes which only have a single inlined tag don't have any
+    /// `Fields`.
+    pub fn fields(&self) -> Option<&Fields> {
+        match self {
+            CompressedOsmData::Node { tags: NodeFields::Multiple(f), .. } => Some(f),
+            CompressedOsmData::Node { .. } => None,
+            CompressedOsmData::Way { tags, .. } => Some(tags),
+            CompressedOsmData::Relation { tags, .. } => Some(tags),
+        }
+    }
+
     pub fn osm_id(&self) -> OsmId {
         match self {
             CompressedOsmData::Node { id, .. } => OsmId::Node(*id),
@@ -128,7 +176,7 @@ fn insert_bbox<const C: usize>(
 }
 
 impl DeserializeFromMinimal for CompressedOsmData {
-    type ExternalData<'a> = (OsmObjectType, &'a BoundingBox<i32>, &'a mut Pool<LiteralValue>);
+    type ExternalData<'a> = (OsmObjectType, &'a BoundingBox<i32>, &'a Pool<LiteralValue>);
 
     fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
         from: &'a mut R,
@@ -178,9 +226,11 @@ impl SerializeMinimal for CompressedOsmData {
         }
     }
 }
+/// An object as it's stored in the geography tree.
 #[derive(Clone, Debug)]
 pub struct UncompressedOsmData(Vec<u8>);
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum OsmObjectType {
     Node,
     Way,
@@ -194,7 +244,7 @@ impl UncompressedOsmData {
 
         UncompressedOsmData(blob)
     }
-    pub fn compress(self, bbox: &BoundingBox<i32>, pool: &mut Pool<LiteralValue>) -> std::io::Result<CompressedOsmData> {
+    pub fn compress(self, bbox: &BoundingBox<i32>, pool: &Pool<LiteralValue>) -> std::io::Result<CompressedOsmData> {
         let osm_type = self.determine_type().unwrap();
         CompressedOsmData::deserialize_minimal(&mut &self.0[..], (osm_type, bbox, pool))
     }
@@ -237,6 +287,13 @@ impl UncompressedOsmData {
         }
     }
 
+    pub fn decompress_way_id(&self) -> Option<std::io::Result<WayId>> {
+        match self.determine_type() {
+            Some(OsmObjectType::Way) => Some(get_id(&mut &self.0[..])),
+            _ => None,
+        }
+    }
+
     pub fn decompress_way_points(
         &self,
         bbox: &BoundingBox<i32>,
@@ -246,6 +303,91 @@ impl UncompressedOsmData {
             _ => None,
         }
     }
+
+    pub fn decompress_node_id(&self) -> Option<std::io::Result<NodeId>> {
+        match self.determine_type() {
+            Some(OsmObjectType::Node) => Some(node::get_id(&mut &self.0[..])),
+            _ => None,
+        }
+    }
+
+    pub fn decompress_relation_id(&self) -> Option<std::io::Result<RelationId>> {
+        match self.determine_type() {
+            Some(OsmObjectType::Relation) => Some(relation::get_id(&mut &self.0[..])),
+            _ => None,
+        }
+    }
+
+    pub fn decompress_relation_members(&self) -> Option<std::io::Result<Vec<Ref>>> {
+        match self.determine_type() {
+            Some(OsmObjectType::Relation) => Some(relation::get_members(&mut &self.0[..])),
+            _ => None,
+        }
+    }
+
+    /// The tag inlined into a node's header, without reading past it. `None` if this
+    /// isn't a node, or if its tags are stored separately; `Some(None)` if it has no tags.
+    pub fn decompress_node_inlined_tag(&self) -> Option<Option<NodeSingleInlined>> {
+        NodeSingleInlined::from_header(*self.0.first()?)
+    }
+
+    /// The ids of a node's or relation's fields in the field pool. Ways' fields are
+    /// stored in the way itself, so ways don't have any.
+    pub fn decompress_pooled_field_ids(&self) -> std::io::Result<Vec<PooledId>> {
+        self.split_field_ids().map(|(_, ids, _)| ids)
+    }
+
+    /// A node's or relation's fields, read from the field pool one at a time so that a
+    /// caller can stop at any of them.
+    pub fn decompress_pooled_fields<'a>(
+        &self,
+        fields: &'a Pool<Field>,
+        values: &'a Pool<LiteralValue>,
+    ) -> std::io::Result<impl Iterator<Item = std::io::Result<Field>> + 'a> {
+        let ids = self.decompress_pooled_field_ids()?;
+
+        Ok(ids.into_iter().map(move |id| Field::from_pool(id, fields, values)))
+    }
+
+    /// The same object, but with its fields at the given ids in the field pool instead,
+    /// such as after the pool has been compacted. It must have as many as before.
+    pub fn with_pooled_field_ids(&self, new_ids: &[PooledId]) -> std::io::Result<Self> {
+        let (before, ids, after) = self.split_field_ids()?;
+
+        if ids.len() != new_ids.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} field ids were given for {} fields", new_ids.len(), ids.len()),
+            ));
+        }
+
+        let mut blob = before.to_vec();
+        for id in new_ids {
+            id.write_varint(&mut blob)?;
+        }
+        blob.extend_from_slice(after);
+
+        Ok(UncompressedOsmData(blob))
+    }
+
+    fn split_field_ids(&self) -> std::io::Result<(&[u8], Vec<PooledId>, &[u8])> {
+        match self.determine_type() {
+            Some(OsmObjectType::Node) => node::split_field_ids(&self.0),
+            Some(OsmObjectType::Relation) => relation::split_field_ids(&self.0),
+            _ => Ok((&self.0, Vec::new(), &[])),
+        }
+    }
+
+    /// A way's fields, decoded lazily so that a caller can stop at any of them.
+    pub fn decompress_way_fields<'a>(
+        &'a self,
+        pool: &'a Pool<LiteralValue>,
+    ) -> Option<std::io::Result<impl Iterator<Item = std::io::Result<Field>> + 'a>> {
+        match self.determine_type() {
+            Some(OsmObjectType::Way) => Some(get_fields(&self.0[..], pool)),
+            _ => None,
+        }
+    }
 }
 
 impl SerializeMinimal for UncompressedOsmData {
@@ -3,7 +3,11 @@ use osmpbfreader::NodeId;
 
 use crate::{field::Field, removable::remove_non_stored_tags};
 
-use minimal_storage::{pooled_storage::Pool, serialize_min::SerializeMinimal, varint::ToVarint};
+use minimal_storage::{
+    pooled_storage::{Pool, PooledId},
+    serialize_min::{DeserializeFromMinimal, ReadExtReadOne},
+    varint::ToVarint,
+};
 
 use tree::bbox::BoundingBox;
 
@@ -25,7 +29,7 @@ pub fn serialize_node<W: std::io::Write>(
 ) -> Result<(), std::io::Error> {
     match tags {
         NodeFields::Single(s) => write_node_only_single_inlined_tags(write_to, s, id),
-        NodeFields::Multiple(f) => write_node_with_uninlined_tags(write_to, external_data, f),
+        NodeFields::Multiple(f) => write_node_with_uninlined_tags(write_to, external_data, f, id),
     }    
 }
 
@@ -48,6 +52,7 @@ pub fn write_node_only_single_inlined_tags<W: std::io::Write>(
 
     //NodeNoTags layout:
     // header (1 byte): as above
+    // id: varint
     // num_parents (ONLY IF header is MORE parents): varint parent count
     // parent(s): [parent count] iterations of either a varint, or an n-byte-as-per-header int.
 
@@ -65,6 +70,7 @@ fn write_node_with_uninlined_tags<W: std::io::Write>(
     write_to: &mut W,
     (literals, values): &(Pool<Field>, Pool<LiteralValue>),
     tags: &Fields,
+    id: &NodeId,
 ) -> std::io::Result<()> {
     //header layout:
     //1: node
@@ -77,6 +83,7 @@ fn write_node_with_uninlined_tags<W: std::io::Write>(
 
     //NodeBitflagTags layout:
     // header (1 byte): as above
+    // id: varint
     // num_parents (ONLY IF header is MORE parents): varint parent count
     // parent(s): [parent count] iterations of either a varint, or an n-byte-as-per-header int.
     // num_tags (ONLY IF header is MORE tags): varint uninlined tag count
@@ -84,14 +91,18 @@ fn write_node_with_uninlined_tags<W: std::io::Write>(
 
     let Fields(fields) = tags;
 
-    let mut typ = 0b10_00_0_000u8;
+    let mut typ = 0b11_00_0_000u8;
 
+    //the header's written as it is, not as a varint, so that its first bits are the
+    // object's first bits, like every other object's header
     if fields.len() < 0b1111 {
         typ |= fields.len() as u8;
-        typ.minimally_serialize(write_to, ())?;
+        write_to.write_all(&[typ])?;
+        id.0.write_varint(write_to)?;
     } else {
         typ |= 0b1111;
-        typ.minimally_serialize(write_to, ())?;
+        write_to.write_all(&[typ])?;
+        id.0.write_varint(write_to)?;
         fields.len().write_varint(write_to)?;
     }
 
@@ -122,6 +133,43 @@ pub enum NodeSingleInlined {
     NeedleleavedTree = 7,
 }
 
+impl NodeSingleInlined {
+    /// The inlined tag from a node's header byte, as written by
+    /// `write_node_only_single_inlined_tags`. `None` if the node's tags aren't inlined,
+    /// `Some(None)` if it has no tags.
+    pub fn from_header(header: u8) -> Option<Option<Self>> {
+        let is_node = header >> 7 == 1;
+        let has_uninlined_tags = (header >> 6) & 1 == 1;
+        if !is_node || has_uninlined_tags {
+            return None;
+        }
+
+        Some(match header & 0b111 {
+            1 => Some(NodeSingleInlined::Tree),
+            2 => Some(NodeSingleInlined::PowerTower),
+            3 => Some(NodeSingleInlined::PowerPole),
+            4 => Some(NodeSingleInlined::BroadleavedTree),
+            5 => Some(NodeSingleInlined::Bench),
+            6 => Some(NodeSingleInlined::Hydrant),
+            7 => Some(NodeSingleInlined::NeedleleavedTree),
+            _ => None,
+        })
+    }
+
+    /// The tags which this stands for. See `inline_node_tags`.
+    pub fn tags(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            NodeSingleInlined::Tree => &[("natural", "tree")],
+            NodeSingleInlined::PowerTower => &[("power", "tower")],
+            NodeSingleInlined::PowerPole => &[("power", "pole")],
+            NodeSingleInlined::BroadleavedTree => &[("natural", "tree"), ("leaf_type", "broadleaved")],
+            NodeSingleInlined::Bench => &[("amenity", "bench")],
+            NodeSingleInlined::Hydrant => &[("emergency", "fire_hydrant")],
+            NodeSingleInlined::NeedleleavedTree => &[("natural", "tree"), ("leaf_type", "needleleaved")],
+        }
+    }
+}
+
 pub fn inline_node_tags(mut tags: osmpbfreader::Tags) -> NodeFields {
     remove_non_stored_tags(&mut tags);
 
@@ -153,18 +201,7 @@ pub fn inline_node_tags(mut tags: osmpbfreader::Tags) -> NodeFields {
         }
     }
 
-    let (fields, tags) = osm_tags_to_fields::fields::parse_tags_to_fields(tags);
-
-    let mut combined_fields = Vec::with_capacity(fields.len() + tags.len());
-
-    for t in fields {
-        combined_fields.push(Field::Field(t));
-    }
-    for (k,v) in tags.iter() {
-        combined_fields.push((k, v).into());
-    }
-
-    NodeFields::Multiple(Fields(combined_fields))
+    NodeFields::Multiple(Fields::from_tags(tags))
 }
 
 fn tags_has_exactly(tags: &osmpbfreader::Tags, expected: &[(&str, &str)]) -> bool {
@@ -180,3 +217,43 @@ fn tags_has_exactly(tags: &osmpbfreader::Tags, expected: &[(&str, &str)]) -> boo
 
     return true;
 }
+
+/// The id of a node with either layout, which is written straight after its header.
+pub fn get_id(from: &mut impl std::io::Read) -> std::io::Result<NodeId> {
+    let header = from.read_one()?;
+
+    if header >> 7 != 1 {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    Ok(NodeId(DeserializeFromMinimal::deserialize_minimal(from, ())?))
+}
+
+/// A node's bytes, split around the ids of its fields in the field pool: everything up
+/// to the first id (including the count), the ids, and whatever's after them. Nodes with
+/// only inlined tags don't have any ids.
+pub fn split_field_ids(bytes: &[u8]) -> std::io::Result<(&[u8], Vec<PooledId>, &[u8])> {
+    let mut from = bytes;
+    let header = from.read_one()?;
+
+    if header >> 7 != 1 {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+    if (header >> 6) & 1 == 0 {
+        return Ok((bytes, Vec::new(), &[]));
+    }
+
+    let _id = u64::deserialize_minimal(&mut from, ())?;
+
+    let count = match header & 0b1111 {
+        0b1111 => usize::deserialize_minimal(&mut from, ())?,
+        count => count as usize,
+    };
+    let before = &bytes[..bytes.len() - from.len()];
+
+    let ids = (0..count)
+        .map(|_| PooledId::deserialize_minimal(&mut from, ()))
+        .collect::<std::io::Result<_>>()?;
+
+    Ok((before, ids, from))
+}
@@ -1,8 +1,8 @@
-use minimal_storage::{packed_string_serialization::is_final::IterIsFinal, pooled_storage::Pool, serialize_min::SerializeMinimal};
+use minimal_storage::{packed_string_serialization::is_final::IterIsFinal, pooled_storage::{Pool, PooledId}, serialize_min::{DeserializeFromMinimal, SerializeMinimal}};
 use osm_value_atom::LiteralValue;
 use osmpbfreader::{OsmObj, Ref, Relation, RelationId};
 
-use crate::{compressed_data::flattened_id, field::Field, removable::remove_non_stored_tags};
+use crate::{compressed_data::{flattened_id, unflattened_id}, field::Field, removable::remove_non_stored_tags};
 
 use tree::{bbox::BoundingBox, point_range::StoredBinaryTree};
 
@@ -20,18 +20,7 @@ pub fn osm_relation_to_compressed_node<const C: usize>(mut relation: Relation, b
 
     remove_non_stored_tags(&mut relation.tags);
 
-    let (fields, tags) = osm_tags_to_fields::fields::parse_tags_to_fields(relation.tags);
-
-    let mut combined_fields = Vec::with_capacity(fields.len() + tags.len());
-
-    for t in fields {
-        combined_fields.push(Field::Field(t));
-    }
-    for (k,v) in tags.iter() {
-        combined_fields.push((k, v).into());
-    }
-
-    Ok(CompressedOsmData::Relation { bbox, tags: Fields(combined_fields), id: relation.id, refs: relation.refs })
+    Ok(CompressedOsmData::Relation { bbox, tags: Fields::from_tags(relation.tags), id: relation.id, refs: relation.refs })
 }
 
 pub fn serialize_relation<W: std::io::Write>(
@@ -77,4 +66,56 @@ pub fn serialize_relation<W: std::io::Write>(
     
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn get_id(from: &mut impl std::io::Read) -> std::io::Result<RelationId> {
+    let header = u8::deserialize_minimal(from, ())?;
+
+    if header != 0b00_00_0000u8 {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    Ok(RelationId(DeserializeFromMinimal::deserialize_minimal(from, ())?))
+}
+
+/// The relation's members. The ids of its fields are skipped over; they're read by
+/// `split_field_ids`.
+pub fn get_members(from: &mut impl std::io::Read) -> std::io::Result<Vec<Ref>> {
+    get_id(from)?;
+
+    let fields_count = usize::deserialize_minimal(from, ())?;
+    for _ in 0..fields_count {
+        u64::deserialize_minimal(from, ())?;
+    }
+
+    let children_count = usize::deserialize_minimal(from, ())?;
+
+    let mut roles = Vec::with_capacity(children_count);
+    for _ in 0..children_count {
+        roles.push(String::deserialize_minimal(from, None)?);
+    }
+
+    roles
+        .into_iter()
+        .map(|role| {
+            let member = unflattened_id(u64::deserialize_minimal(from, ())?);
+            Ok(Ref { member, role: role.into() })
+        })
+        .collect()
+}
+
+/// A relation's bytes, split around the ids of its fields in the field pool, like
+/// `node::split_field_ids`.
+pub fn split_field_ids(bytes: &[u8]) -> std::io::Result<(&[u8], Vec<PooledId>, &[u8])> {
+    let mut from = bytes;
+    get_id(&mut from)?;
+
+    let fields_count = usize::deserialize_minimal(&mut from, ())?;
+    let before = &bytes[..bytes.len() - from.len()];
+
+    let ids = (0..fields_count)
+        .map(|_| PooledId::deserialize_minimal(&mut from, ()))
+        .collect::<std::io::Result<_>>()?;
+
+    Ok((before, ids, from))
+}
@@ -35,20 +35,9 @@ pub fn osm_way_to_compressed_node<const C: usize>(
 
     remove_non_stored_tags(&mut way.tags);
 
-    let (fields, tags) = osm_tags_to_fields::fields::parse_tags_to_fields(way.tags);
-
-    let mut combined_fields = Vec::with_capacity(fields.len() + tags.len());
-
-    for t in fields {
-        combined_fields.push(Field::Field(t));
-    }
-    for (k, v) in tags.iter() {
-        combined_fields.push((k, v).into());
-    }
-
     Ok(CompressedOsmData::Way {
         bbox,
-        tags: super::Fields(combined_fields),
+        tags: Fields::from_tags(way.tags),
         id: way.id,
         children,
     })
@@ -97,7 +86,7 @@ pub fn serialize_way<W: std::io::Write>(
 pub fn deserialize_way(
     from: &mut impl std::io::Read,
     bbox: &BoundingBox<i32>,
-    pool: &mut Pool<LiteralValue>,
+    pool: &Pool<LiteralValue>,
 ) -> std::io::Result<(WayId, Vec<(i32, i32)>, Vec<Field>)> {
     let header = u8::deserialize_minimal(from, ())?;
 
@@ -129,12 +118,22 @@ pub fn deserialize_way(
     let mut fields = Vec::with_capacity(fields_count);
 
     for _ in 0..fields_count {
-        fields.push(DeserializeFromMinimal::deserialize_minimal(from, &mut *pool)?)
+        fields.push(DeserializeFromMinimal::deserialize_minimal(from, pool)?)
     }
 
     Ok((id, points, fields))
 }
 
+pub fn get_id(from: &mut impl std::io::Read) -> std::io::Result<WayId> {
+    let header = u8::deserialize_minimal(from, ())?;
+
+    if header != 0b01_00_0000u8 {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    Ok(WayId(DeserializeFromMinimal::deserialize_minimal(from, ())?))
+}
+
 pub fn get_points(
     from: &mut impl std::io::Read,
     bbox: &BoundingBox<i32>,
@@ -166,3 +165,27 @@ pub fn get_points(
 
     Ok(vec)
 }
+
+/// The way's fields, decoded one at a time as the iterator is advanced. The nodes'
+/// positions are skipped over without being decoded into points.
+pub fn get_fields<'a>(
+    mut from: impl std::io::Read + 'a,
+    pool: &'a Pool<LiteralValue>,
+) -> std::io::Result<impl Iterator<Item = std::io::Result<Field>> + 'a> {
+    let header = u8::deserialize_minimal(&mut from, ())?;
+
+    if header != 0b0100_0000u8 {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    let _id = WayId(DeserializeFromMinimal::deserialize_minimal(&mut from, ())?);
+
+    let points_count = usize::deserialize_minimal(&mut from, ())?;
+    for _ in 0..points_count * 2 {
+        u32::deserialize_minimal(&mut from, ())?;
+    }
+
+    let fields_count = usize::deserialize_minimal(&mut from, ())?;
+
+    Ok((0..fields_count).map(move |_| Field::deserialize_minimal(&mut from, pool)))
+}
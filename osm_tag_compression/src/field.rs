@@ -1,8 +1,8 @@
-use std::io::Write;
+use std::{borrow::Cow, io::Write};
 
 use minimal_storage::{
     bit_sections::{BitSection, Byte},
-    pooled_storage::Pool,
+    pooled_storage::{Pool, PooledId},
     serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal},
     varint::ToVarint,
 };
@@ -17,6 +17,38 @@ pub enum Field {
     Field(AnyOsmField),
 }
 
+impl Field {
+    pub fn key(&self) -> Cow<'_, str> {
+        match self {
+            Field::Other(k, _) => k.to_string().into(),
+            Field::Field(f) => f.key().into(),
+        }
+    }
+
+    /// The `(key, value)` OSM tag that this field was made from. Fields which combine
+    /// several tags (like addresses or localized names) don't have a single tag, so
+    /// they give `None`.
+    pub fn as_tag(&self) -> Option<(Cow<'_, str>, Cow<'_, str>)> {
+        match self {
+            Field::Other(k, v) => Some((k.to_string().into(), v.to_string().into())),
+            Field::Field(f) => Some((f.key().into(), f.tag_value()?)),
+        }
+    }
+
+    /// The field with the given id in the field pool. Typed fields can't be read past
+    /// without being decoded, so its bytes are found with the pool's offset tables.
+    pub fn from_pool(id: PooledId, fields: &Pool<Field>, values: &Pool<LiteralValue>) -> std::io::Result<Self> {
+        let Some(blob) = fields.serialized_value(id)? else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("pooled field {id} doesn't exist"),
+            ));
+        };
+
+        Field::deserialize_minimal(&mut &blob[..], values)
+    }
+}
+
 impl<A: AsRef<str>, B: Into<LiteralValue>> From<(A, B)> for Field {
     fn from(value: (A, B)) -> Self {
         Field::Other(value.0.as_ref().into(), value.1.into())
@@ -24,7 +56,7 @@ impl<A: AsRef<str>, B: Into<LiteralValue>> From<(A, B)> for Field {
 }
 
 impl DeserializeFromMinimal for Field {
-    type ExternalData<'d> = &'d mut Pool<LiteralValue>;
+    type ExternalData<'d> = &'d Pool<LiteralValue>;
 
     fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
         from: &'a mut R,
@@ -38,14 +70,22 @@ impl DeserializeFromMinimal for Field {
             let k_id = u64::deserialize_minimal(from, ())?;
             let v_id = u64::deserialize_minimal(from, ())?;
 
-            let k = pool.get(k_id, ())?.unwrap().into_owned();
-            let v = pool.get(v_id, ())?.unwrap().into_owned();
+            let get = |id| match pool.get(id, ())? {
+                Some(value) => Ok(LiteralValue::clone(&value)),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("pooled value {id} doesn't exist"),
+                )),
+            };
+            let k = get(k_id)?;
+            let v = get(v_id)?;
 
             return Ok(Field::Other(k, v));
         } else {
             let head = u16::from_be_bytes([head, from.read_one()?]);
 
-            let head = BitSection::<1, 16, u16>::from(head);
+            //the leading bit is the one which marks this as a typed field
+            let head = BitSection::<0, 16, u16>::from(head).reduce_extent::<1, 16>();
 
             return Ok(Field::Field(AnyOsmField::deserialize_minimal(from, (pool, head))?));
         }
@@ -0,0 +1,338 @@
+//! Tag filters which are evaluated on objects' fields as they're decoded, so that a
+//! query can stop reading an object at the first field which rules it out.
+
+use minimal_storage::pooled_storage::Pool;
+use osm_value_atom::LiteralValue;
+
+use crate::{
+    compressed_data::{CompressedOsmData, NodeFields, NodeSingleInlined, OsmObjectType, UncompressedOsmData},
+    field::Field,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Test {
+    Exists,
+    OneOf(Vec<(String, LiteralValue)>),
+    Compare(Comparison, isize),
+}
+
+/// A test on the value of a single key. An object without the key never matches.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Predicate {
+    key: String,
+    /// The key as it's stored in a `Field::Other`, so it can be compared without
+    /// formatting every stored key.
+    key_literal: LiteralValue,
+    test: Test,
+}
+
+impl Predicate {
+    fn new(key: &str, test: Test) -> Self {
+        Predicate {
+            key: key.to_string(),
+            key_literal: key.into(),
+            test,
+        }
+    }
+
+    pub fn exists(key: &str) -> Self {
+        Self::new(key, Test::Exists)
+    }
+
+    pub fn equals(key: &str, value: &str) -> Self {
+        Self::one_of(key, [value])
+    }
+
+    pub fn one_of<'a>(key: &str, values: impl IntoIterator<Item = &'a str>) -> Self {
+        let values = values.into_iter().map(|v| (v.to_string(), v.into())).collect();
+        Self::new(key, Test::OneOf(values))
+    }
+
+    /// The value, read as a number with `LiteralValue::as_number`, compared to `than`.
+    /// Values which aren't numbers don't match.
+    pub fn compare(key: &str, comparison: Comparison, than: isize) -> Self {
+        Self::new(key, Test::Compare(comparison, than))
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether this applies to the field, and if so whether the field passes.
+    fn evaluate(&self, field: &Field) -> Option<bool> {
+        match field {
+            Field::Other(k, v) => (*k == self.key_literal).then(|| self.test_literal(v)),
+            Field::Field(f) => {
+                if f.key() != self.key {
+                    return None;
+                }
+                match (&self.test, f.tag_value()) {
+                    (Test::Exists, _) => Some(true),
+                    (_, Some(v)) => Some(self.test_str(&v)),
+                    //a combined field, like an address, has no single value to test
+                    (_, None) => None,
+                }
+            }
+        }
+    }
+
+    fn test_literal(&self, value: &LiteralValue) -> bool {
+        match &self.test {
+            Test::Exists => true,
+            Test::OneOf(values) => values.iter().any(|(_, v)| v == value),
+            Test::Compare(c, than) => value.as_number().is_some_and(|n| compare(*c, n, *than)),
+        }
+    }
+
+    fn test_str(&self, value: &str) -> bool {
+        match &self.test {
+            Test::Exists => true,
+            Test::OneOf(values) => values.iter().any(|(v, _)| v == value),
+            Test::Compare(c, than) => value.parse().is_ok_and(|n| compare(*c, n, *than)),
+        }
+    }
+}
+
+fn compare(comparison: Comparison, value: isize, than: isize) -> bool {
+    match comparison {
+        Comparison::Less => value < than,
+        Comparison::LessOrEqual => value <= than,
+        Comparison::Greater => value > than,
+        Comparison::GreaterOrEqual => value >= than,
+    }
+}
+
+/// Objects whose type is one of `types` (or any type, if there are none) and whose
+/// tags pass all of the predicates.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TagFilter {
+    types: Vec<OsmObjectType>,
+    predicates: Vec<Predicate>,
+}
+
+impl TagFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, predicate: Predicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+
+    pub fn of_type(mut self, typ: OsmObjectType) -> Self {
+        self.types.push(typ);
+        self
+    }
+
+    pub fn predicates(&self) -> &[Predicate] {
+        &self.predicates
+    }
+
+    fn allows_type(&self, typ: OsmObjectType) -> bool {
+        self.types.is_empty() || self.types.contains(&typ)
+    }
+
+    pub fn matches(&self, data: &CompressedOsmData) -> bool {
+        let typ = match data {
+            CompressedOsmData::Node { .. } => OsmObjectType::Node,
+            CompressedOsmData::Way { .. } => OsmObjectType::Way,
+            CompressedOsmData::Relation { .. } => OsmObjectType::Relation,
+        };
+        if !self.allows_type(typ) {
+            return false;
+        }
+
+        match data {
+            CompressedOsmData::Node {
+                tags: NodeFields::Single(tag),
+                ..
+            } => self.matches_inlined(tag),
+            _ => {
+                let fields = data.fields().into_iter().flat_map(|f| f.iter());
+                self.matches_fields(fields.map(|f| Ok::<_, std::io::Error>(f.clone())))
+                    .unwrap()
+            }
+        }
+    }
+
+    /// Evaluate the filter on a stored object, reading no more of it than needed. Nodes
+    /// with one inlined tag are decided from their header alone, and other nodes' and
+    /// relations' fields are read from the field pool one at a time.
+    pub fn matches_stored(
+        &self,
+        data: &UncompressedOsmData,
+        pools: &(Pool<Field>, Pool<LiteralValue>),
+    ) -> std::io::Result<bool> {
+        let Some(typ) = data.determine_type() else {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        };
+        if !self.allows_type(typ) {
+            return Ok(false);
+        }
+
+        if let Some(tag) = data.decompress_node_inlined_tag() {
+            return Ok(self.matches_inlined(&tag));
+        }
+
+        let (fields, values) = pools;
+        match data.decompress_way_fields(values) {
+            Some(way_fields) => self.matches_fields(way_fields?),
+            None => self.matches_fields(data.decompress_pooled_fields(fields, values)?),
+        }
+    }
+
+    fn matches_inlined(&self, tag: &Option<NodeSingleInlined>) -> bool {
+        let tags = tag.map(|t| t.tags()).unwrap_or_default();
+
+        let fields = tags.iter().map(|t| Ok::<_, std::io::Error>(Field::from(*t)));
+        self.matches_fields(fields).unwrap()
+    }
+
+    /// Stops at the first field which fails a predicate, or as soon as every predicate
+    /// has passed.
+    fn matches_fields<E>(&self, fields: impl Iterator<Item = Result<Field, E>>) -> Result<bool, E> {
+        let mut passed = vec![false; self.predicates.len()];
+        let mut left = self.predicates.len();
+
+        for field in fields {
+            if left == 0 {
+                break;
+            }
+            let field = field?;
+
+            for (predicate, passed) in self.predicates.iter().zip(passed.iter_mut()) {
+                match predicate.evaluate(&field) {
+                    Some(false) => return Ok(false),
+                    Some(true) if !*passed => {
+                        *passed = true;
+                        left -= 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(left == 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use minimal_storage::provider::{MemoryDirectory, StorageProvider};
+    use osmpbfreader::{NodeId, Ref, RelationId, Tags, WayId};
+    use tree::bbox::BoundingBox;
+
+    use crate::compressed_data::Fields;
+
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter().map(|(k, v)| ((*k).into(), (*v).into())).collect()
+    }
+
+    fn way(fields: Fields) -> CompressedOsmData {
+        CompressedOsmData::Way {
+            bbox: BoundingBox::new(0, 0, 10, 10),
+            id: WayId(1),
+            tags: fields,
+            children: vec![(0, 0), (10, 10)],
+        }
+    }
+
+    #[test]
+    pub fn filters() {
+        let files = MemoryDirectory::new();
+        let pools = (
+            Pool::new(files.open("literals").unwrap()).unwrap(),
+            Pool::new(files.open("values").unwrap()).unwrap(),
+        );
+
+        let road = way(Fields::from_tags(tags(&[("highway", "residential"), ("lanes", "2"), ("name", "Main Street")])));
+        let bench = CompressedOsmData::Node {
+            id: NodeId(2),
+            tags: NodeFields::Single(Some(NodeSingleInlined::Bench)),
+            point: BoundingBox::from_point(5, 5),
+        };
+
+        let residential = TagFilter::new().with(Predicate::one_of("highway", ["primary", "residential"]));
+        let wide = TagFilter::new().with(Predicate::compare("lanes", Comparison::GreaterOrEqual, 3));
+        let named = TagFilter::new()
+            .with(Predicate::exists("highway"))
+            .with(Predicate::equals("name", "Main Street"));
+        let benches = TagFilter::new()
+            .of_type(OsmObjectType::Node)
+            .with(Predicate::equals("amenity", "bench"));
+
+        assert!(residential.matches(&road));
+        assert!(!wide.matches(&road));
+        assert!(named.matches(&road));
+        assert!(!benches.matches(&road));
+        assert!(benches.matches(&bench));
+        assert!(!residential.matches(&bench));
+
+        let stored_road = UncompressedOsmData::new(&road, &pools);
+        let stored_bench = UncompressedOsmData::new(&bench, &pools);
+
+        assert!(residential.matches_stored(&stored_road, &pools).unwrap());
+        assert!(!wide.matches_stored(&stored_road, &pools).unwrap());
+        assert!(named.matches_stored(&stored_road, &pools).unwrap());
+        assert!(!benches.matches_stored(&stored_road, &pools).unwrap());
+        assert!(benches.matches_stored(&stored_bench, &pools).unwrap());
+    }
+
+    #[test]
+    pub fn pooled_fields() {
+        let files = MemoryDirectory::new();
+        let pools = (
+            Pool::new(files.open("literals").unwrap()).unwrap(),
+            Pool::new(files.open("values").unwrap()).unwrap(),
+        );
+
+        let cafe = CompressedOsmData::Node {
+            id: NodeId(3),
+            tags: NodeFields::Multiple(Fields::from_tags(tags(&[
+                ("amenity", "cafe"),
+                ("name", "Corner Cafe"),
+                ("opening_hours", "Mo-Fr 08:00-18:00"),
+            ]))),
+            point: BoundingBox::from_point(5, 5),
+        };
+        let route = CompressedOsmData::Relation {
+            bbox: BoundingBox::new(0, 0, 10, 10),
+            id: RelationId(4),
+            refs: vec![Ref {
+                member: WayId(1).into(),
+                role: "".into(),
+            }],
+            tags: Fields::from_tags(tags(&[("type", "route"), ("route", "bus"), ("ref", "42")])),
+        };
+        let stored_cafe = UncompressedOsmData::new(&cafe, &pools);
+        let stored_route = UncompressedOsmData::new(&route, &pools);
+
+        let cafes = TagFilter::new()
+            .of_type(OsmObjectType::Node)
+            .with(Predicate::equals("amenity", "cafe"))
+            .with(Predicate::exists("name"));
+        let bus_routes = TagFilter::new()
+            .with(Predicate::equals("route", "bus"))
+            .with(Predicate::compare("ref", Comparison::Less, 100));
+        let restaurants = TagFilter::new().with(Predicate::equals("amenity", "restaurant"));
+
+        for (filter, object, stored) in [(&cafes, &cafe, &stored_cafe), (&bus_routes, &route, &stored_route)] {
+            assert!(filter.matches(object));
+            assert!(filter.matches_stored(stored, &pools).unwrap());
+            assert!(!restaurants.matches_stored(stored, &pools).unwrap());
+        }
+        assert!(!cafes.matches_stored(&stored_route, &pools).unwrap());
+        assert!(!bus_routes.matches_stored(&stored_cafe, &pools).unwrap());
+    }
+}
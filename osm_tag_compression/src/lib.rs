@@ -1,3 +1,5 @@
 pub mod field;
+pub mod filter;
+pub mod manifest;
 pub mod removable;
 pub mod compressed_data;
\ No newline at end of file
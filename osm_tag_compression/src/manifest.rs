@@ -0,0 +1,179 @@
+use std::{fmt::Display, io};
+
+use minimal_storage::provider::StorageProvider;
+use osm_tags_to_fields::fields::SCHEMA_HASH;
+
+/// The version of how a map's objects and values are encoded: `UncompressedOsmData`'s
+/// headers, `LiteralValue`'s header nibbles, `DeltaFriendlyU32Offset`, and the order of
+/// `DeltaBoundingBox32` keys in the map's trees. It has to go up
+/// whenever any of them changes, along with a step in the `upgrade` command which
+/// rewrites a map from the version before. How fields are encoded is generated from the
+/// tagging schema, so it's covered by `SCHEMA_HASH` instead.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Starts every map's manifest
+pub const MANIFEST_MAGIC: [u8; 8] = *b"TMAPMANI";
+const MANIFEST_NAME: &str = "manifest";
+/// The magic number, the format version and the schema hash
+const MANIFEST_SIZE: usize = 8 + 4 + 8;
+
+/// Which encoding a map was written in, kept in the map's `manifest` file so that a
+/// build which encodes differently refuses the map instead of misreading it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// A hash of the generated fields' ids and encodings
+    pub schema_hash: u64,
+}
+
+impl Manifest {
+    /// The manifest of a map written by this build
+    pub fn current() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            schema_hash: SCHEMA_HASH,
+        }
+    }
+
+    /// The map's manifest, or `None` if it hasn't got one, which it won't if it was
+    /// written before maps were versioned
+    pub fn read(files: &dyn StorageProvider) -> io::Result<Option<Self>> {
+        //never made, so that checking a map doesn't change it
+        let Some(file) = files.open_existing(MANIFEST_NAME)? else {
+            return Ok(None);
+        };
+        if file.is_empty()? {
+            return Ok(None);
+        }
+
+        let mut bytes = [0; MANIFEST_SIZE];
+        file.read_exact_at(&mut bytes, 0)
+            .ok()
+            .filter(|_| bytes.starts_with(&MANIFEST_MAGIC))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the map's manifest is damaged"))?;
+
+        Ok(Some(Self {
+            format_version: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            schema_hash: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+        }))
+    }
+
+    pub fn write(&self, files: &dyn StorageProvider) -> io::Result<()> {
+        let mut bytes = MANIFEST_MAGIC.to_vec();
+        bytes.extend_from_slice(&self.format_version.to_le_bytes());
+        bytes.extend_from_slice(&self.schema_hash.to_le_bytes());
+
+        let file = files.create(MANIFEST_NAME)?;
+        file.write_all_at(&bytes, 0)?;
+        file.sync_data()
+    }
+
+    /// Check that the map was written in the encoding which this build reads. If it
+    /// wasn't, the error is `InvalidData`, with a `FormatMismatch` inside it.
+    pub fn check(files: &dyn StorageProvider) -> io::Result<()> {
+        match Self::read(files)? {
+            Some(manifest) if manifest == Self::current() => Ok(()),
+            found => Err(io::Error::new(io::ErrorKind::InvalidData, FormatMismatch { found })),
+        }
+    }
+
+    /// Like `check`, but a map which hasn't had anything written to it yet is given this
+    /// build's manifest
+    pub fn check_or_create(files: &dyn StorageProvider) -> io::Result<()> {
+        let mut is_new = Self::read(files)?.is_none();
+        for name in ["literals", "values", "geography/structure"] {
+            is_new &= files.open(name)?.is_empty()?;
+        }
+
+        if is_new {
+            return Self::current().write(files);
+        }
+        Self::check(files)
+    }
+}
+
+/// A map was written in a different encoding than this build reads
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FormatMismatch {
+    /// The map's manifest, if it has one
+    pub found: Option<Manifest>,
+}
+
+impl Display for FormatMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.found {
+            None => write!(
+                f,
+                "the map has no manifest, so it was written before maps were versioned; run `upgrade` on it"
+            ),
+            Some(found) if found.format_version > FORMAT_VERSION => write!(
+                f,
+                "the map is in format version {}, which is newer than this build's version {FORMAT_VERSION}",
+                found.format_version
+            ),
+            Some(found) if found.format_version < FORMAT_VERSION => write!(
+                f,
+                "the map is in format version {}, and this build reads version {FORMAT_VERSION}; run `upgrade` on it",
+                found.format_version
+            ),
+            Some(found) => write!(
+                f,
+                "the map's fields were generated from a different tagging schema ({:#018x}, where this build's is {SCHEMA_HASH:#018x}); it has to be compressed again",
+                found.schema_hash
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatMismatch {}
+
+#[cfg(test)]
+mod test {
+    use minimal_storage::provider::{Directory, MemoryDirectory};
+
+    use super::*;
+
+    #[test]
+    pub fn check() {
+        let files = MemoryDirectory::new();
+        Manifest::check_or_create(&files).unwrap();
+        assert_eq!(Manifest::read(&files).unwrap(), Some(Manifest::current()));
+        Manifest::check(&files).unwrap();
+
+        //written before maps were versioned
+        let unversioned = MemoryDirectory::new();
+        unversioned.open("values").unwrap().write_all_at(b"values", 0).unwrap();
+        let error = Manifest::check_or_create(&unversioned).unwrap_err();
+        assert_eq!(
+            error.get_ref().unwrap().downcast_ref::<FormatMismatch>(),
+            Some(&FormatMismatch { found: None })
+        );
+
+        let older = Manifest {
+            format_version: FORMAT_VERSION - 1,
+            ..Manifest::current()
+        };
+        older.write(&files).unwrap();
+        let error = Manifest::check(&files).unwrap_err().to_string();
+        assert!(error.contains("run `upgrade`"), "{error}");
+
+        files.create(MANIFEST_NAME).unwrap().write_all_at(b"TMAP", 0).unwrap();
+        assert!(Manifest::read(&files).is_err());
+    }
+
+    #[test]
+    pub fn checking_makes_no_manifest() {
+        let folder = std::env::temp_dir().join("manifest_checking_makes_no_manifest");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let files = Directory::new(&folder);
+        assert_eq!(Manifest::read(&files).unwrap(), None);
+        assert!(Manifest::check(&files).is_err());
+        assert!(!folder.join(MANIFEST_NAME).exists());
+
+        //nor a folder to keep it in
+        assert_eq!(Manifest::read(&Directory::new(folder.join("missing"))).unwrap(), None);
+        assert!(!folder.join("missing").exists());
+    }
+}
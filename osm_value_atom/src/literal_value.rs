@@ -41,6 +41,45 @@ impl LiteralValue {
     }
 }
 
+impl std::fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LiteralValue::Specificvalue(v) => f.write_str(match v {
+                LiteralValueSpecificValue::BoolYes => "yes",
+                LiteralValueSpecificValue::BoolNo => "no",
+                LiteralValueSpecificValue::Blank => "",
+            }),
+            LiteralValue::UInt(num) => write!(f, "{num}"),
+            LiteralValue::IInt(num) => write!(f, "{num}"),
+            LiteralValue::TinyUNumber(num) => write!(f, "{num}"),
+            LiteralValue::TinyINumber(num) => write!(f, "{num}"),
+            LiteralValue::Date(y, m, d) => write!(f, "{y:04}-{m:02}-{d:02}"),
+            LiteralValue::Time(h, m) => write!(f, "{h:02}:{m:02}"),
+            LiteralValue::ListWithSep(sep, items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, "{}", *sep as char)?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                Ok(())
+            }
+            LiteralValue::TwoUpperLatinAbbrev(a, b) => write!(f, "{}{}", *a as char, *b as char),
+            LiteralValue::SplitSemiList(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(";")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                Ok(())
+            }
+            LiteralValue::String(s) => f.write_str(s),
+            LiteralValue::Ref(id) => write!(f, "#{id}"),
+        }
+    }
+}
+
 impl<T: AsRef<str>> From<T> for LiteralValue {
     fn from(value: T) -> Self {
         let value = value.as_ref();
@@ -129,12 +168,11 @@ impl SerializeMinimal for LiteralValue {
         buf.push(0u8);
 
         let header_byte = match self {
-            LiteralValue::Specificvalue(v) => 0b0000 & {
-                match v {
-                    LiteralValueSpecificValue::BoolYes => 0b10,
-                    LiteralValueSpecificValue::BoolNo => 0b01,
-                    LiteralValueSpecificValue::Blank => 0b00,
-                }
+            //variant 0b0000, so only the lower nibble is set
+            LiteralValue::Specificvalue(v) => match v {
+                LiteralValueSpecificValue::BoolYes => 0b10,
+                LiteralValueSpecificValue::BoolNo => 0b01,
+                LiteralValueSpecificValue::Blank => 0b00,
             },
             LiteralValue::UInt(num) => {
                 num.write_varint(&mut buf)?;
@@ -158,7 +196,7 @@ impl SerializeMinimal for LiteralValue {
             LiteralValue::String(s) => {
                 //the string's serializer handles the process of writing the header byte.
                 //this prevents excessive buffer usage
-                return s.as_str().minimally_serialize(&mut buf,  0b1100_0000u8.into());
+                return s.as_str().minimally_serialize(write_to,  0b1100_0000u8.into());
             },
 
             LiteralValue::Ref(_) => panic!("Unable to serialize a reference!"),
@@ -0,0 +1,75 @@
+use osmpbfreader::OsmId;
+use tree::bbox::BoundingBox;
+
+/// An object as a query sees it: decoded tags and positions, rather than the stored form.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Element {
+    pub id: OsmId,
+    pub tags: Vec<(String, String)>,
+    /// A node's position, or a way's points in order. Empty for relations.
+    pub geometry: Vec<(i32, i32)>,
+    pub bbox: BoundingBox<i32>,
+    /// A relation's members. Empty for nodes and ways.
+    pub members: Vec<Member>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Member {
+    pub id: OsmId,
+    pub role: String,
+}
+
+impl Element {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn point(&self) -> Option<(i32, i32)> {
+        match self.id {
+            OsmId::Node(_) => self.geometry.first().copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Where a query's elements come from.
+///
+/// Ways don't keep their nodes' ids, so a way's nodes are the nodes which lie on its
+/// points. Nodes without tags aren't stored, so they can't be found this way.
+pub trait Source {
+    /// Every element whose bbox overlaps `bbox`.
+    fn elements_in(&mut self, bbox: &BoundingBox<i32>) -> Vec<Element>;
+
+    fn element(&mut self, id: OsmId) -> Option<Element>;
+
+    /// A way's nodes, or a relation's members.
+    fn children(&mut self, element: &Element) -> Vec<Element> {
+        match element.id {
+            OsmId::Node(_) => Vec::new(),
+            OsmId::Way(_) => self
+                .elements_in(&element.bbox)
+                .into_iter()
+                .filter(|e| e.point().is_some_and(|p| element.geometry.contains(&p)))
+                .collect(),
+            OsmId::Relation(_) => element
+                .members
+                .iter()
+                .filter_map(|m| self.element(m.id))
+                .collect(),
+        }
+    }
+
+    /// The ways which a node is on, and the relations which an element is a member of.
+    fn parents(&mut self, element: &Element) -> Vec<Element> {
+        let point = element.point();
+
+        self.elements_in(&element.bbox)
+            .into_iter()
+            .filter(|e| match e.id {
+                OsmId::Node(_) => false,
+                OsmId::Way(_) => point.is_some_and(|p| e.geometry.contains(&p)),
+                OsmId::Relation(_) => e.members.iter().any(|m| m.id == element.id),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, HashMap};
+
+use osm_tag_compression::compressed_data::flattened_id;
+use osmpbfreader::{NodeId, OsmId, RelationId, WayId};
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    geo::distance_meters,
+    shapes::{Circle, Corridor},
+    tree_traits::MultidimensionalQuery,
+};
+
+use crate::{
+    element::{Element, Source},
+    parse::{Filter, Format, Query, Recurse, Statement, TagTest, Types, Verbosity},
+};
+
+/// Elements by their flattened id, so that they're ordered nodes first, then ways, then
+/// relations, each by id, as Overpass sorts them.
+type Set = BTreeMap<u64, Element>;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum OutItem {
+    Element(Element, Verbosity),
+    Count {
+        nodes: usize,
+        ways: usize,
+        relations: usize,
+    },
+}
+
+/// Everything written by a query's `out` statements, in order.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Output {
+    pub format: Format,
+    pub items: Vec<OutItem>,
+}
+
+pub fn run(query: &Query, source: &mut impl Source) -> Output {
+    let mut interpreter = Interpreter {
+        source,
+        global_bbox: query.bbox.unwrap_or(EARTH_BBOX),
+        sets: HashMap::new(),
+        output: Output {
+            format: query.format,
+            items: Vec::new(),
+        },
+    };
+
+    for statement in query.statements.iter() {
+        interpreter.statement(statement);
+    }
+
+    interpreter.output
+}
+
+struct Interpreter<'s, S: Source> {
+    source: &'s mut S,
+    global_bbox: BoundingBox<i32>,
+    sets: HashMap<String, Set>,
+    output: Output,
+}
+
+impl<S: Source> Interpreter<'_, S> {
+    /// Run a statement, storing its result in the set it's assigned to, and returning it
+    /// for any union or difference it's part of.
+    fn statement(&mut self, statement: &Statement) -> Set {
+        let (result, into) = match statement {
+            Statement::Select {
+                types,
+                input,
+                filters,
+                into,
+            } => (self.select(types, input.as_deref(), filters), into),
+            Statement::Union { statements, into } => {
+                let mut union = Set::new();
+                for s in statements {
+                    union.extend(self.statement(s));
+                }
+                (union, into)
+            }
+            Statement::Difference { from, minus, into } => {
+                let mut from = self.statement(from);
+                for id in self.statement(minus).keys() {
+                    from.remove(id);
+                }
+                (from, into)
+            }
+            Statement::Recurse {
+                input,
+                recurse,
+                into,
+            } => (self.recurse(input, *recurse), into),
+            Statement::Item { input, into } => (self.set(input), into),
+            Statement::Out { input, verbosity } => {
+                let set = self.set(input);
+                self.out(set, *verbosity);
+                return Set::new();
+            }
+        };
+
+        self.sets.insert(into.clone(), result.clone());
+
+        result
+    }
+
+    fn set(&self, name: &str) -> Set {
+        //like Overpass, a set which was never assigned is empty
+        self.sets.get(name).cloned().unwrap_or_default()
+    }
+
+    fn select(&mut self, types: &Types, input: Option<&str>, filters: &[Filter]) -> Set {
+        let candidates = if let Some(input) = input {
+            self.set(input).into_values().collect()
+        } else if let Some(ids) = filters.iter().find_map(|f| match f {
+            Filter::Ids(ids) => Some(ids),
+            _ => None,
+        }) {
+            ids.iter()
+                .flat_map(|id| {
+                    let ids = [
+                        types.nodes.then_some(OsmId::Node(NodeId(*id))),
+                        types.ways.then_some(OsmId::Way(WayId(*id))),
+                        types.relations.then_some(OsmId::Relation(RelationId(*id))),
+                    ];
+                    ids.into_iter().flatten()
+                })
+                .filter_map(|id| self.source.element(id))
+                .collect()
+        } else {
+            let area = filters
+                .iter()
+                .filter_map(|f| match f {
+                    Filter::Bbox(bbox) => Some(*bbox),
+                    Filter::Around {
+                        center,
+                        radius_meters,
+                    } => Some(bounds(&Circle::new(*center, *radius_meters))),
+                    _ => None,
+                })
+                .fold(self.global_bbox, intersection);
+
+            self.source.elements_in(&area)
+        };
+
+        candidates
+            .into_iter()
+            .filter(|e| types.allows(&e.id) && filters.iter().all(|f| passes(e, f)))
+            .map(|e| (flattened_id(&e.id), e))
+            .collect()
+    }
+
+    fn recurse(&mut self, input: &str, recurse: Recurse) -> Set {
+        let input = self.set(input);
+
+        let step = |this: &mut Self, set: &Set| -> Set {
+            let mut found = Set::new();
+            for element in set.values() {
+                let next = match recurse {
+                    Recurse::Down | Recurse::DownAll => this.source.children(element),
+                    Recurse::Up | Recurse::UpAll => this.source.parents(element),
+                };
+                found.extend(next.into_iter().map(|e| (flattened_id(&e.id), e)));
+            }
+            found
+        };
+
+        let mut result = step(self, &input);
+
+        match recurse {
+            //the nodes of relations' member ways are part of a single step down
+            Recurse::Down => {
+                let member_ways: Set = result
+                    .iter()
+                    .filter(|(_, e)| matches!(e.id, OsmId::Way(_)))
+                    .filter(|(id, _)| !input.contains_key(id))
+                    .map(|(id, e)| (*id, e.clone()))
+                    .collect();
+                let nodes = step(self, &member_ways);
+                result.extend(nodes);
+            }
+            //as are the relations of the ways found going up
+            Recurse::Up => {
+                let ways: Set = result
+                    .iter()
+                    .filter(|(_, e)| matches!(e.id, OsmId::Way(_)))
+                    .map(|(id, e)| (*id, e.clone()))
+                    .collect();
+                let relations = step(self, &ways)
+                    .into_iter()
+                    .filter(|(_, e)| matches!(e.id, OsmId::Relation(_)));
+                result.extend(relations);
+            }
+            Recurse::DownAll | Recurse::UpAll => {
+                let mut frontier = result.clone();
+                while !frontier.is_empty() {
+                    frontier = step(self, &frontier);
+                    frontier.retain(|id, _| !result.contains_key(id));
+                    result.extend(frontier.clone());
+                }
+            }
+        }
+
+        result
+    }
+
+    fn out(&mut self, set: Set, verbosity: Verbosity) {
+        if verbosity == Verbosity::Count {
+            let count = |f: fn(&OsmId) -> bool| set.values().filter(|e| f(&e.id)).count();
+
+            self.output.items.push(OutItem::Count {
+                nodes: count(|id| matches!(id, OsmId::Node(_))),
+                ways: count(|id| matches!(id, OsmId::Way(_))),
+                relations: count(|id| matches!(id, OsmId::Relation(_))),
+            });
+            return;
+        }
+
+        self.output
+            .items
+            .extend(set.into_values().map(|e| OutItem::Element(e, verbosity)));
+    }
+}
+
+fn passes(element: &Element, filter: &Filter) -> bool {
+    match filter {
+        Filter::Tag(test) => passes_tag_test(element, test),
+        Filter::Bbox(bbox) => match element.point() {
+            Some((x, y)) => bbox.contains(&BoundingBox::from_point(x, y)),
+            None => bbox.overlaps(&element.bbox),
+        },
+        Filter::Around {
+            center,
+            radius_meters,
+        } => match (&element.id, element.point()) {
+            (_, Some(point)) => distance_meters(point, *center) <= *radius_meters,
+            (OsmId::Way(_), None) => {
+                Corridor::new(element.geometry.clone(), *radius_meters).distance_meters(*center)
+                    <= *radius_meters
+            }
+            //relations don't have their own geometry, so their bbox has to do
+            _ => overlaps(&Circle::new(*center, *radius_meters), &element.bbox),
+        },
+        Filter::Ids(ids) => ids.contains(&element.id.inner_id()),
+    }
+}
+
+fn passes_tag_test(element: &Element, test: &TagTest) -> bool {
+    match test {
+        TagTest::Exists(k) => element.tag(k).is_some(),
+        TagTest::NotExists(k) => element.tag(k).is_none(),
+        TagTest::Equals(k, v) => element.tag(k) == Some(v.as_str()),
+        //like Overpass, an element without the key passes
+        TagTest::NotEquals(k, v) => element.tag(k) != Some(v.as_str()),
+        TagTest::Matches(k, re) => element.tag(k).is_some_and(|v| re.is_match(v)),
+        TagTest::NotMatches(k, re) => !element.tag(k).is_some_and(|v| re.is_match(v)),
+    }
+}
+
+//the blanket implementation for boxes makes the key ambiguous without these
+fn overlaps(q: &impl MultidimensionalQuery<2, BoundingBox<i32>>, bbox: &BoundingBox<i32>) -> bool {
+    q.overlaps_box(bbox)
+}
+fn bounds(q: &impl MultidimensionalQuery<2, BoundingBox<i32>>) -> BoundingBox<i32> {
+    q.bounding_box()
+}
+
+fn intersection(a: BoundingBox<i32>, b: BoundingBox<i32>) -> BoundingBox<i32> {
+    let x = *a.x().max(b.x());
+    let y = *a.y().max(b.y());
+    let x_end = *a.x_end().min(b.x_end());
+    let y_end = *a.y_end().min(b.y_end());
+
+    if x > x_end || y > y_end {
+        return BoundingBox::empty();
+    }
+
+    BoundingBox::new(x, y, x_end, y_end)
+}
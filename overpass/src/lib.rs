@@ -0,0 +1,22 @@
+//! An interpreter for a practical subset of Overpass QL, so that queries written for the
+//! public Overpass API can be run against a local map instead.
+//!
+//! Supported: `node`, `way`, `rel` and `nwr` queries with tag filters (`[k]`, `[!k]`,
+//! `[k=v]`, `[k!=v]`, `[k~re]`, `[k!~re]`), `(s,w,n,e)`, `(around:r,lat,lon)` and id
+//! filters; named sets; recursion with `>`, `>>`, `<` and `<<`; unions and differences;
+//! and `out` with `ids`, `skel`, `body`, `tags`, `meta`, `geom` or `count`. Results are
+//! written as Overpass JSON or OSM XML, chosen with `[out:json]` or `[out:xml]`.
+
+pub mod element;
+pub mod eval;
+pub mod map;
+pub mod output;
+pub mod parse;
+
+pub use element::{Element, Member, Source};
+pub use eval::{run, Output};
+pub use map::MapSource;
+pub use parse::{parse, ParseError, Query};
+
+#[cfg(test)]
+mod test;
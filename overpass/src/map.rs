@@ -0,0 +1,221 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use minimal_storage::{
+    packed_map::PackedMap,
+    pooled_storage::Pool,
+    provider::{Directory, StorageProvider},
+};
+use osm_tag_compression::{
+    compressed_data::{flattened_id, CompressedOsmData, OsmObjectType, UncompressedOsmData},
+    field::Field,
+    manifest::Manifest,
+};
+use osm_value_atom::LiteralValue;
+use osmpbfreader::OsmId;
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    dense::structure::StoredTree,
+    point_range::StoredBinaryTree,
+    sparse,
+};
+
+use crate::element::{Element, Member, Source};
+
+const CACHE_SATURATION: usize = 4_000;
+const DATA_SATURATION: usize = 8_000;
+
+/// The objects in a map directory, as written by the compressor, or in a `.tmap` it was
+/// packed into.
+///
+/// `&MapSource` is a `Source` too, so that threads can share one opened map, and with it
+/// the trees' page caches and the pools' read caches. Threads decode tags at the same
+/// time.
+pub struct MapSource {
+    geography: StoredTree<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>,
+    bboxes: StoredBinaryTree<CACHE_SATURATION, u64, BoundingBox<i32>>,
+    fields: Pool<Field>,
+    values: Pool<LiteralValue>,
+    undecodable: AtomicUsize,
+}
+
+impl MapSource {
+    /// Open the map directory, or the packed map, at `state_path`
+    pub fn open(state_path: &Path) -> std::io::Result<Self> {
+        if state_path.is_file() {
+            return Self::open_in(&PackedMap::open(state_path)?);
+        }
+
+        Self::open_in(&Directory::new(state_path))
+    }
+
+    /// Open the map whose files are in `files`. It's only read: files are mapped if
+    /// `files` can map them, and nothing is made or written.
+    pub fn open_in(files: &dyn StorageProvider) -> std::io::Result<Self> {
+        Manifest::check(files)?;
+
+        //mapped, so that threads serving the map read its pages without a lock
+        let geography = StoredTree::open_mapped_in(EARTH_BBOX, &*files.subdirectory("geography"))?;
+        let bboxes = sparse::open_mapped_in::<1, CACHE_SATURATION, u64, BoundingBox<i32>>(
+            0..=u64::MAX,
+            files,
+            "tmp.bboxes",
+        )?;
+        //fields can't be read past without being decoded, so the pool is only opened from
+        //its trailer, which the compressor always writes
+        let fields = Pool::open_flushed(files.open_for_reading("literals")?)?;
+        let values = Pool::open(files.open_for_reading("values")?)?;
+
+        Ok(MapSource {
+            geography,
+            bboxes,
+            fields,
+            values,
+            undecodable: AtomicUsize::new(0),
+        })
+    }
+
+    /// How many objects were found but couldn't be decoded, and so were left out
+    pub fn undecodable(&self) -> usize {
+        self.undecodable.load(Ordering::Relaxed)
+    }
+
+    fn decode(&self, bbox: BoundingBox<i32>, data: UncompressedOsmData) -> Option<Element> {
+        match decode(bbox, data, &self.fields, &self.values) {
+            Ok(element) => Some(element),
+            Err(_) => {
+                self.undecodable.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+impl Source for MapSource {
+    fn elements_in(&mut self, bbox: &BoundingBox<i32>) -> Vec<Element> {
+        (&*self).elements_in(bbox)
+    }
+
+    fn element(&mut self, id: OsmId) -> Option<Element> {
+        (&*self).element(id)
+    }
+}
+
+impl Source for &MapSource {
+    fn elements_in(&mut self, bbox: &BoundingBox<i32>) -> Vec<Element> {
+        let found: Vec<_> = self.geography.find_entries_in_box(bbox).collect();
+
+        found
+            .into_iter()
+            .filter_map(|(bbox, data)| self.decode(bbox, data))
+            .collect()
+    }
+
+    fn element(&mut self, id: OsmId) -> Option<Element> {
+        let bbox = self.bboxes.get_owned(&flattened_id(&id))?;
+
+        let found = self
+            .geography
+            .find_entries_in_box(&bbox)
+            .find(|(_, data)| stored_id(data).is_some_and(|i| i == id));
+
+        found.and_then(|(bbox, data)| self.decode(bbox, data))
+    }
+}
+
+/// The OSM id of a stored object, if it can be read
+pub fn stored_id(data: &UncompressedOsmData) -> Option<OsmId> {
+    match data.determine_type()? {
+        OsmObjectType::Node => data.decompress_node_id()?.ok().map(OsmId::Node),
+        OsmObjectType::Way => data.decompress_way_id()?.ok().map(OsmId::Way),
+        OsmObjectType::Relation => data.decompress_relation_id()?.ok().map(OsmId::Relation),
+    }
+}
+
+fn decode(
+    bbox: BoundingBox<i32>,
+    data: UncompressedOsmData,
+    fields: &Pool<Field>,
+    values: &Pool<LiteralValue>,
+) -> std::io::Result<Element> {
+    let invalid = || std::io::Error::from(std::io::ErrorKind::InvalidData);
+
+    match data.determine_type().ok_or_else(invalid)? {
+        OsmObjectType::Node => {
+            let id = data.decompress_node_id().ok_or_else(invalid)??;
+            let tags = match data.decompress_node_inlined_tag().flatten() {
+                Some(inlined) => inlined
+                    .tags()
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                None => pooled_tags(&data, fields, values)?,
+            };
+
+            Ok(Element {
+                id: OsmId::Node(id),
+                tags,
+                geometry: vec![(*bbox.x(), *bbox.y())],
+                bbox,
+                members: Vec::new(),
+            })
+        }
+        OsmObjectType::Way => {
+            let CompressedOsmData::Way {
+                id,
+                tags,
+                children,
+                ..
+            } = data.compress(&bbox, values)?
+            else {
+                return Err(invalid());
+            };
+
+            Ok(Element {
+                id: OsmId::Way(id),
+                tags: tags.iter().filter_map(tag).collect(),
+                geometry: children,
+                bbox,
+                members: Vec::new(),
+            })
+        }
+        OsmObjectType::Relation => {
+            let id = data.decompress_relation_id().ok_or_else(invalid)??;
+            let members = data.decompress_relation_members().ok_or_else(invalid)??;
+            let tags = pooled_tags(&data, fields, values)?;
+
+            Ok(Element {
+                id: OsmId::Relation(id),
+                tags,
+                geometry: Vec::new(),
+                bbox,
+                members: members
+                    .into_iter()
+                    .map(|r| Member {
+                        id: r.member,
+                        role: r.role.to_string(),
+                    })
+                    .collect(),
+            })
+        }
+    }
+}
+
+/// The OSM tag a field was made from. Fields without a value are left out, like the
+/// ones for a tag the object doesn't have.
+fn tag(field: &Field) -> Option<(String, String)> {
+    let (k, v) = field.as_tag().filter(|(_, v)| !v.is_empty())?;
+    Some((k.into_owned(), v.into_owned()))
+}
+
+fn pooled_tags(
+    data: &UncompressedOsmData,
+    fields: &Pool<Field>,
+    values: &Pool<LiteralValue>,
+) -> std::io::Result<Vec<(String, String)>> {
+    data.decompress_pooled_fields(fields, values)?
+        .filter_map(|field| field.map(|f| tag(&f)).transpose())
+        .collect()
+}
@@ -0,0 +1,213 @@
+use std::io::Write;
+
+use osmpbfreader::OsmId;
+use serde_json::{json, Map, Value};
+use tree::geo::decimicro_to_degrees;
+
+use crate::{
+    element::Element,
+    eval::{OutItem, Output},
+    parse::{Format, Verbosity},
+};
+
+const GENERATOR: &str = "offline-tiny-maps";
+
+pub fn write_output(output: &Output, to: &mut impl Write) -> std::io::Result<()> {
+    match output.format {
+        Format::Json => write_json(output, to),
+        Format::Xml => write_xml(output, to),
+    }
+}
+
+/// The same layout as the Overpass API's JSON.
+pub fn write_json(output: &Output, to: &mut impl Write) -> std::io::Result<()> {
+    let elements: Vec<Value> = output.items.iter().map(item_to_json).collect();
+
+    let document = json!({
+        "version": 0.6,
+        "generator": GENERATOR,
+        "elements": elements,
+    });
+
+    serde_json::to_writer_pretty(&mut *to, &document)?;
+    writeln!(to)
+}
+
+fn item_to_json(item: &OutItem) -> Value {
+    let (element, verbosity) = match item {
+        OutItem::Element(element, verbosity) => (element, *verbosity),
+        OutItem::Count {
+            nodes,
+            ways,
+            relations,
+        } => {
+            return json!({
+                "type": "count",
+                "id": 0,
+                "tags": {
+                    "nodes": nodes.to_string(),
+                    "ways": ways.to_string(),
+                    "relations": relations.to_string(),
+                    "total": (nodes + ways + relations).to_string(),
+                },
+            })
+        }
+    };
+
+    let mut object = Map::new();
+    object.insert("type".into(), type_name(&element.id).into());
+    object.insert("id".into(), element.id.inner_id().into());
+
+    if let (Some(point), true) = (element.point(), has_positions(verbosity)) {
+        let (lon, lat) = decimicro_to_degrees(point);
+        object.insert("lat".into(), lat.into());
+        object.insert("lon".into(), lon.into());
+    }
+
+    if verbosity == Verbosity::Geom && matches!(element.id, OsmId::Way(_)) {
+        let bounds = bounds(element);
+        object.insert(
+            "bounds".into(),
+            json!({
+                "minlat": bounds.0, "minlon": bounds.1, "maxlat": bounds.2, "maxlon": bounds.3,
+            }),
+        );
+
+        let geometry = element.geometry.iter().map(|p| {
+            let (lon, lat) = decimicro_to_degrees(*p);
+            json!({ "lat": lat, "lon": lon })
+        });
+        object.insert("geometry".into(), geometry.collect());
+    }
+
+    if has_positions(verbosity) && !element.members.is_empty() {
+        let members = element.members.iter().map(|m| {
+            json!({ "type": type_name(&m.id), "ref": m.id.inner_id(), "role": m.role })
+        });
+        object.insert("members".into(), members.collect());
+    }
+
+    if has_tags(verbosity) && !element.tags.is_empty() {
+        let tags = element
+            .tags
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::from(v.as_str())));
+        object.insert("tags".into(), tags.collect::<Map<_, _>>().into());
+    }
+
+    object.into()
+}
+
+/// The same layout as the Overpass API's XML, which is OSM XML.
+pub fn write_xml(output: &Output, to: &mut impl Write) -> std::io::Result<()> {
+    writeln!(to, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(to, r#"<osm version="0.6" generator="{GENERATOR}">"#)?;
+
+    for item in output.items.iter() {
+        let (element, verbosity) = match item {
+            OutItem::Element(element, verbosity) => (element, *verbosity),
+            OutItem::Count {
+                nodes,
+                ways,
+                relations,
+            } => {
+                writeln!(to, r#"  <count id="0">"#)?;
+                for (k, v) in [
+                    ("nodes", nodes),
+                    ("ways", ways),
+                    ("relations", relations),
+                    ("total", &(nodes + ways + relations)),
+                ] {
+                    writeln!(to, r#"    <tag k="{k}" v="{v}"/>"#)?;
+                }
+                writeln!(to, "  </count>")?;
+                continue;
+            }
+        };
+
+        write!(to, r#"  <{} id="{}""#, type_name(&element.id), element.id.inner_id())?;
+        if let (Some(point), true) = (element.point(), has_positions(verbosity)) {
+            let (lon, lat) = decimicro_to_degrees(point);
+            write!(to, r#" lat="{lat}" lon="{lon}""#)?;
+        }
+
+        let mut children = Vec::new();
+
+        if verbosity == Verbosity::Geom && matches!(element.id, OsmId::Way(_)) {
+            let (minlat, minlon, maxlat, maxlon) = bounds(element);
+            children.push(format!(
+                r#"<bounds minlat="{minlat}" minlon="{minlon}" maxlat="{maxlat}" maxlon="{maxlon}"/>"#
+            ));
+            for p in element.geometry.iter() {
+                let (lon, lat) = decimicro_to_degrees(*p);
+                children.push(format!(r#"<nd lat="{lat}" lon="{lon}"/>"#));
+            }
+        }
+        if has_positions(verbosity) {
+            for m in element.members.iter() {
+                children.push(format!(
+                    r#"<member type="{}" ref="{}" role="{}"/>"#,
+                    type_name(&m.id),
+                    m.id.inner_id(),
+                    escape_xml(&m.role)
+                ));
+            }
+        }
+        if has_tags(verbosity) {
+            for (k, v) in element.tags.iter() {
+                children.push(format!(r#"<tag k="{}" v="{}"/>"#, escape_xml(k), escape_xml(v)));
+            }
+        }
+
+        if children.is_empty() {
+            writeln!(to, "/>")?;
+        } else {
+            writeln!(to, ">")?;
+            for child in children {
+                writeln!(to, "    {child}")?;
+            }
+            writeln!(to, "  </{}>", type_name(&element.id))?;
+        }
+    }
+
+    writeln!(to, "</osm>")
+}
+
+fn type_name(id: &OsmId) -> &'static str {
+    match id {
+        OsmId::Node(_) => "node",
+        OsmId::Way(_) => "way",
+        OsmId::Relation(_) => "relation",
+    }
+}
+
+fn has_positions(verbosity: Verbosity) -> bool {
+    matches!(verbosity, Verbosity::Skel | Verbosity::Body | Verbosity::Geom)
+}
+
+fn has_tags(verbosity: Verbosity) -> bool {
+    matches!(verbosity, Verbosity::Body | Verbosity::Tags | Verbosity::Geom)
+}
+
+/// `(minlat, minlon, maxlat, maxlon)`
+fn bounds(element: &Element) -> (f64, f64, f64, f64) {
+    let (minlon, minlat) = decimicro_to_degrees((*element.bbox.x(), *element.bbox.y()));
+    let (maxlon, maxlat) = decimicro_to_degrees((*element.bbox.x_end(), *element.bbox.y_end()));
+
+    (minlat, minlon, maxlat, maxlon)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
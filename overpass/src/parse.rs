@@ -0,0 +1,568 @@
+use regex::{Regex, RegexBuilder};
+use tree::{bbox::BoundingBox, geo::degrees_to_decimicro};
+
+/// The set which statements read from and write to when no other is named.
+pub const DEFAULT_SET: &str = "_";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    Xml,
+}
+
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub format: Format,
+    /// The global bbox from `[bbox:s,w,n,e]`, for queries without a spatial filter.
+    pub bbox: Option<BoundingBox<i32>>,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Statement {
+    /// `node`, `way`, `rel` or `nwr`, from `input` if given, or else from the map.
+    Select {
+        types: Types,
+        input: Option<String>,
+        filters: Vec<Filter>,
+        into: String,
+    },
+    Union {
+        statements: Vec<Statement>,
+        into: String,
+    },
+    Difference {
+        from: Box<Statement>,
+        minus: Box<Statement>,
+        into: String,
+    },
+    Recurse {
+        input: String,
+        recurse: Recurse,
+        into: String,
+    },
+    /// A set on its own, as in `.a;`, which copies it.
+    Item {
+        input: String,
+        into: String,
+    },
+    Out {
+        input: String,
+        verbosity: Verbosity,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Types {
+    pub nodes: bool,
+    pub ways: bool,
+    pub relations: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Tag(TagTest),
+    Bbox(BoundingBox<i32>),
+    Around { center: (i32, i32), radius_meters: f64 },
+    Ids(Vec<i64>),
+}
+
+#[derive(Clone, Debug)]
+pub enum TagTest {
+    Exists(String),
+    NotExists(String),
+    Equals(String, String),
+    NotEquals(String, String),
+    Matches(String, Regex),
+    NotMatches(String, Regex),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Recurse {
+    /// `>`: ways' nodes and relations' members, and the nodes of member ways
+    Down,
+    /// `>>`: `>`, repeated until nothing new is found
+    DownAll,
+    /// `<`: the ways and relations which use the input, and the relations of those ways
+    Up,
+    /// `<<`: `<`, repeated until nothing new is found
+    UpAll,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verbosity {
+    Ids,
+    /// ids and positions
+    Skel,
+    /// ids, positions and tags. `meta` is the same, since no metadata is stored.
+    Body,
+    /// ids and tags
+    Tags,
+    /// `body`, with the points of ways
+    Geom,
+    Count,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    /// Byte offset into the query
+    pub offset: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(text: &str) -> Result<Query, ParseError> {
+    let mut parser = Parser { text, at: 0 };
+
+    let mut query = Query {
+        format: Format::Json,
+        bbox: None,
+        statements: Vec::new(),
+    };
+
+    if parser.peek() == Some('[') {
+        while parser.eat("[") {
+            parser.setting(&mut query)?;
+        }
+        parser.expect(";")?;
+    }
+
+    while parser.peek().is_some() {
+        let statement = parser.statement()?;
+        query.statements.push(statement);
+    }
+
+    Ok(query)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.at..]
+    }
+
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.at += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.at += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if let Some(comment) = trimmed.strip_prefix("/*") {
+                self.at += comment.find("*/").map(|e| e + 4).unwrap_or(trimmed.len());
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(token) {
+            self.at += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the keyword is next, and isn't just the start of a longer word.
+    fn at_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_space();
+        let rest = self.rest();
+        let follows = rest.get(keyword.len()..).and_then(|r| r.chars().next());
+
+        rest.starts_with(keyword) && !follows.is_some_and(is_word_char)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let at = self.at_keyword(keyword);
+        if at {
+            self.at += keyword.len();
+        }
+        at
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            offset: self.at,
+            message,
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        self.skip_space();
+        let start = self.at;
+        let len = self.rest().find(|c| !f(c)).unwrap_or(self.rest().len());
+        self.at += len;
+
+        &self.text[start..self.at]
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        match self.take_while(is_word_char) {
+            "" => Err(self.error("expected a name".into())),
+            word => Ok(word.to_string()),
+        }
+    }
+
+    /// A key or value: quoted, or a run of characters which can't be confused with the
+    /// surrounding syntax.
+    fn value(&mut self) -> Result<String, ParseError> {
+        let Some(quote) = self.peek().filter(|c| *c == '"' || *c == '\'') else {
+            return match self.take_while(|c| is_word_char(c) || ":.-".contains(c)) {
+                "" => Err(self.error("expected a key or value".into())),
+                value => Ok(value.to_string()),
+            };
+        };
+        self.at += 1;
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        value.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            c => c,
+                        });
+                    }
+                }
+                c if c == quote => {
+                    self.at += i + 1;
+                    return Ok(value);
+                }
+                c => value.push(c),
+            }
+        }
+
+        Err(self.error("unterminated string".into()))
+    }
+
+    fn number(&mut self) -> Result<f64, ParseError> {
+        let text = self.take_while(|c| c.is_ascii_digit() || c == '-' || c == '.');
+        text.parse()
+            .map_err(|_| self.error(format!("expected a number, not `{text}`")))
+    }
+
+    fn setting(&mut self, query: &mut Query) -> Result<(), ParseError> {
+        let name = self.word()?;
+        self.expect(":")?;
+
+        match name.as_str() {
+            "out" => {
+                query.format = match self.word()?.as_str() {
+                    "json" => Format::Json,
+                    "xml" => Format::Xml,
+                    other => return Err(self.error(format!("unsupported output format `{other}`"))),
+                }
+            }
+            "bbox" => query.bbox = Some(self.bbox()?),
+            //limits like `timeout` and `maxsize` don't apply to a local map
+            _ => {
+                self.value()?;
+            }
+        }
+
+        self.expect("]")
+    }
+
+    /// A bbox, in Overpass' `s,w,n,e` order.
+    fn bbox(&mut self) -> Result<BoundingBox<i32>, ParseError> {
+        let south = self.number()?;
+        self.expect(",")?;
+        let west = self.number()?;
+        self.expect(",")?;
+        let north = self.number()?;
+        self.expect(",")?;
+        let east = self.number()?;
+
+        let (x, y) = degrees_to_decimicro(west, south);
+        let (x_end, y_end) = degrees_to_decimicro(east, north);
+
+        Ok(BoundingBox::new(x, y, x_end, y_end))
+    }
+
+    fn assignment(&mut self) -> Result<String, ParseError> {
+        if self.eat("->") {
+            self.expect(".")?;
+            self.word()
+        } else {
+            Ok(DEFAULT_SET.to_string())
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        let statement = if self.eat("(") {
+            self.block()?
+        } else if self.eat(".") {
+            let input = self.word()?;
+            self.after_input(input)?
+        } else if matches!(self.peek(), Some('<' | '>')) || self.at_keyword("out") {
+            self.after_input(DEFAULT_SET.to_string())?
+        } else {
+            self.select()?
+        };
+
+        self.expect(";")?;
+
+        Ok(statement)
+    }
+
+    fn block(&mut self) -> Result<Statement, ParseError> {
+        let mut statements = Vec::new();
+        let mut minus = None;
+
+        while !self.eat(")") {
+            if self.peek().is_none() {
+                return Err(self.error("expected `)`".into()));
+            }
+
+            if self.eat("-") {
+                minus = Some(Box::new(self.statement()?));
+                self.expect(")")?;
+                break;
+            }
+
+            statements.push(self.statement()?);
+        }
+
+        let into = self.assignment()?;
+
+        let Some(minus) = minus else {
+            return Ok(Statement::Union { statements, into });
+        };
+
+        match <[Statement; 1]>::try_from(statements) {
+            Ok([from]) => Ok(Statement::Difference {
+                from: Box::new(from),
+                minus,
+                into,
+            }),
+            Err(_) => Err(self.error("a difference needs exactly one statement before `-`".into())),
+        }
+    }
+
+    /// Whatever can follow a set: recursion, `out`, or nothing.
+    fn after_input(&mut self, input: String) -> Result<Statement, ParseError> {
+        let recurse = if self.eat(">>") {
+            Some(Recurse::DownAll)
+        } else if self.eat(">") {
+            Some(Recurse::Down)
+        } else if self.eat("<<") {
+            Some(Recurse::UpAll)
+        } else if self.eat("<") {
+            Some(Recurse::Up)
+        } else {
+            None
+        };
+
+        if let Some(recurse) = recurse {
+            let into = self.assignment()?;
+            return Ok(Statement::Recurse {
+                input,
+                recurse,
+                into,
+            });
+        }
+
+        if self.eat_keyword("out") {
+            let verbosity = self.out_options()?;
+            return Ok(Statement::Out { input, verbosity });
+        }
+
+        let into = self.assignment()?;
+        Ok(Statement::Item { input, into })
+    }
+
+    fn out_options(&mut self) -> Result<Verbosity, ParseError> {
+        let mut verbosity = Verbosity::Body;
+
+        while self.peek().is_some_and(is_word_char) {
+            verbosity = match self.word()?.as_str() {
+                "ids" => Verbosity::Ids,
+                "skel" => Verbosity::Skel,
+                "body" | "meta" => Verbosity::Body,
+                "tags" => Verbosity::Tags,
+                "geom" => Verbosity::Geom,
+                "count" => Verbosity::Count,
+                //sorting is always by id
+                "asc" | "qt" => verbosity,
+                other => return Err(self.error(format!("unsupported `out` option `{other}`"))),
+            };
+        }
+
+        Ok(verbosity)
+    }
+
+    fn select(&mut self) -> Result<Statement, ParseError> {
+        let types = match self.word()?.as_str() {
+            "node" => Types::new(true, false, false),
+            "way" => Types::new(false, true, false),
+            "rel" | "relation" => Types::new(false, false, true),
+            "nwr" => Types::new(true, true, true),
+            "nw" => Types::new(true, true, false),
+            "wr" => Types::new(false, true, true),
+            "nr" => Types::new(true, false, true),
+            other => return Err(self.error(format!("unsupported statement `{other}`"))),
+        };
+
+        let input = if self.eat(".") { Some(self.word()?) } else { None };
+
+        let mut filters = Vec::new();
+        loop {
+            if self.eat("[") {
+                filters.push(Filter::Tag(self.tag_test()?));
+                self.expect("]")?;
+            } else if self.eat("(") {
+                filters.push(self.spatial_or_id()?);
+                self.expect(")")?;
+            } else {
+                break;
+            }
+        }
+
+        let into = self.assignment()?;
+
+        Ok(Statement::Select {
+            types,
+            input,
+            filters,
+            into,
+        })
+    }
+
+    fn tag_test(&mut self) -> Result<TagTest, ParseError> {
+        if self.eat("!") {
+            return Ok(TagTest::NotExists(self.value()?));
+        }
+
+        let key = self.value()?;
+
+        if self.peek() == Some(']') {
+            return Ok(TagTest::Exists(key));
+        }
+
+        if self.eat("!=") {
+            return Ok(TagTest::NotEquals(key, self.value()?));
+        }
+        if self.eat("=") {
+            return Ok(TagTest::Equals(key, self.value()?));
+        }
+
+        let negated = if self.eat("!~") {
+            true
+        } else if self.eat("~") {
+            false
+        } else {
+            return Err(self.error("expected `]`, `=`, `!=`, `~` or `!~`".into()));
+        };
+
+        let pattern = self.value()?;
+        let case_insensitive = self.eat(",") && {
+            self.expect("i")?;
+            true
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| self.error(format!("bad regex: {e}")))?;
+
+        Ok(match negated {
+            true => TagTest::NotMatches(key, regex),
+            false => TagTest::Matches(key, regex),
+        })
+    }
+
+    fn spatial_or_id(&mut self) -> Result<Filter, ParseError> {
+        if self.eat_keyword("around") {
+            self.expect(":")?;
+            let radius_meters = self.number()?;
+            self.expect(",")?;
+            let lat = self.number()?;
+            self.expect(",")?;
+            let lon = self.number()?;
+
+            return Ok(Filter::Around {
+                center: degrees_to_decimicro(lon, lat),
+                radius_meters,
+            });
+        }
+
+        if self.eat_keyword("id") {
+            self.expect(":")?;
+            let mut ids = vec![self.id()?];
+            while self.eat(",") {
+                ids.push(self.id()?);
+            }
+            return Ok(Filter::Ids(ids));
+        }
+
+        //either a single id, or the start of a bbox
+        let start = self.at;
+        self.number()?;
+        if self.peek() == Some(',') {
+            self.at = start;
+            return Ok(Filter::Bbox(self.bbox()?));
+        }
+
+        self.at = start;
+        Ok(Filter::Ids(vec![self.id()?]))
+    }
+
+    fn id(&mut self) -> Result<i64, ParseError> {
+        let text = self.take_while(|c| c.is_ascii_digit());
+        text.parse()
+            .map_err(|_| self.error(format!("expected an id, not `{text}`")))
+    }
+}
+
+impl Types {
+    fn new(nodes: bool, ways: bool, relations: bool) -> Self {
+        Types {
+            nodes,
+            ways,
+            relations,
+        }
+    }
+
+    pub fn allows(&self, id: &osmpbfreader::OsmId) -> bool {
+        match id {
+            osmpbfreader::OsmId::Node(_) => self.nodes,
+            osmpbfreader::OsmId::Way(_) => self.ways,
+            osmpbfreader::OsmId::Relation(_) => self.relations,
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
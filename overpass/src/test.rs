@@ -0,0 +1,386 @@
+use minimal_storage::{
+    pooled_storage::Pool,
+    provider::{MemoryDirectory, StorageProvider},
+};
+use osm_tag_compression::{
+    compressed_data::{flattened_id, CompressedOsmData, Fields, NodeFields, UncompressedOsmData},
+    manifest::Manifest,
+};
+use osmpbfreader::{NodeId, OsmId, Ref, RelationId, WayId};
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    geo::degrees_to_decimicro,
+    open_tree_dense_in, open_tree_sparse_in,
+};
+
+use crate::{
+    element::{Element, Member, Source},
+    eval::OutItem,
+    output::{write_json, write_xml},
+    parse, run, MapSource, Output,
+};
+
+/// Every element kept in a list, for queries to run against without a map.
+struct Elements(Vec<Element>);
+
+impl Source for Elements {
+    fn elements_in(&mut self, bbox: &BoundingBox<i32>) -> Vec<Element> {
+        self.0.iter().filter(|e| e.bbox.overlaps(bbox)).cloned().collect()
+    }
+
+    fn element(&mut self, id: OsmId) -> Option<Element> {
+        self.0.iter().find(|e| e.id == id).cloned()
+    }
+}
+
+fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+    tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+fn node(id: i64, lon: f64, lat: f64, t: &[(&str, &str)]) -> Element {
+    let (x, y) = degrees_to_decimicro(lon, lat);
+    Element {
+        id: OsmId::Node(NodeId(id)),
+        tags: tags(t),
+        geometry: vec![(x, y)],
+        bbox: BoundingBox::from_point(x, y),
+        members: Vec::new(),
+    }
+}
+
+fn way(id: i64, points: &[(f64, f64)], t: &[(&str, &str)]) -> Element {
+    let geometry: Vec<_> = points
+        .iter()
+        .map(|(lon, lat)| degrees_to_decimicro(*lon, *lat))
+        .collect();
+
+    let mut bbox = BoundingBox::from_point(geometry[0].0, geometry[0].1);
+    for (x, y) in geometry.iter() {
+        bbox.extend_with_point(*x, *y);
+    }
+
+    Element {
+        id: OsmId::Way(WayId(id)),
+        tags: tags(t),
+        geometry,
+        bbox,
+        members: Vec::new(),
+    }
+}
+
+fn relation(id: i64, members: &[(OsmId, &str)], of: &[&Element], t: &[(&str, &str)]) -> Element {
+    let mut bbox = of[0].bbox;
+    for e in of {
+        bbox.extend_with_point(*e.bbox.x(), *e.bbox.y());
+        bbox.extend_with_point(*e.bbox.x_end(), *e.bbox.y_end());
+    }
+
+    Element {
+        id: OsmId::Relation(RelationId(id)),
+        tags: tags(t),
+        geometry: Vec::new(),
+        bbox,
+        members: members
+            .iter()
+            .map(|(id, role)| Member {
+                id: *id,
+                role: role.to_string(),
+            })
+            .collect(),
+    }
+}
+
+fn town() -> Elements {
+    let cafe = node(1, 10.0, 50.0, &[("amenity", "cafe"), ("name", "Corner Cafe")]);
+    let pub_ = node(2, 10.001, 50.0, &[("amenity", "pub"), ("name", "The Anchor")]);
+    let far_cafe = node(3, 10.1, 50.1, &[("amenity", "cafe")]);
+    let crossing = node(4, 10.0005, 50.0005, &[("highway", "crossing")]);
+
+    let main_street = way(
+        10,
+        &[(10.0, 50.0005), (10.0005, 50.0005), (10.001, 50.0005)],
+        &[("highway", "residential"), ("name", "Main Street")],
+    );
+    let path = way(11, &[(10.1, 50.1), (10.1005, 50.1005)], &[("highway", "footway")]);
+
+    let route = relation(
+        20,
+        &[(main_street.id, ""), (cafe.id, "stop")],
+        &[&main_street, &cafe],
+        &[("type", "route"), ("route", "bus")],
+    );
+
+    Elements(vec![cafe, pub_, far_cafe, crossing, main_street, path, route])
+}
+
+fn ids(output: &Output) -> Vec<OsmId> {
+    output
+        .items
+        .iter()
+        .filter_map(|i| match i {
+            OutItem::Element(e, _) => Some(e.id),
+            OutItem::Count { .. } => None,
+        })
+        .collect()
+}
+
+fn query(text: &str) -> Output {
+    run(&parse(text).unwrap(), &mut town())
+}
+
+const NEAR: &str = "(49.99,9.99,50.01,10.01)";
+
+#[test]
+fn tag_filters() {
+    assert_eq!(
+        ids(&query(r#"node["amenity"="cafe"]; out;"#)),
+        [OsmId::Node(NodeId(1)), OsmId::Node(NodeId(3))]
+    );
+
+    assert_eq!(
+        ids(&query(&format!("node[amenity][amenity!=cafe]{NEAR}; out;"))),
+        [OsmId::Node(NodeId(2))]
+    );
+
+    assert_eq!(
+        ids(&query(r#"nwr[name~"^(the|corner) ",i]; out;"#)),
+        [OsmId::Node(NodeId(1)), OsmId::Node(NodeId(2))]
+    );
+
+    assert_eq!(
+        ids(&query("node[!amenity]; way[highway!~\"foot\"]; out;")),
+        [OsmId::Way(WayId(10))]
+    );
+}
+
+#[test]
+fn spatial_filters() {
+    assert_eq!(
+        ids(&query(&format!("nwr{NEAR}; out;"))),
+        [
+            OsmId::Node(NodeId(1)),
+            OsmId::Node(NodeId(2)),
+            OsmId::Node(NodeId(4)),
+            OsmId::Way(WayId(10)),
+            OsmId::Relation(RelationId(20)),
+        ]
+    );
+
+    //the global bbox limits queries without their own
+    assert_eq!(
+        ids(&query("[out:json][bbox:50.05,10.05,50.2,10.2]; node[amenity]; out;")),
+        [OsmId::Node(NodeId(3))]
+    );
+
+    //the crossing is 66m from the cafe and the pub 71m, but the street passes 55m from it
+    assert_eq!(
+        ids(&query("nwr(around:60,50.0,10.0); out ids;")),
+        [
+            OsmId::Node(NodeId(1)),
+            OsmId::Way(WayId(10)),
+            OsmId::Relation(RelationId(20)),
+        ]
+    );
+
+    assert_eq!(
+        ids(&query("node(id:3,2,99); way(11); out;")),
+        [OsmId::Way(WayId(11))]
+    );
+    assert_eq!(
+        ids(&query("node(id:3,2,99); out;")),
+        [OsmId::Node(NodeId(2)), OsmId::Node(NodeId(3))]
+    );
+}
+
+#[test]
+fn recursion_and_sets() {
+    //the crossing is the only node on the street's points
+    assert_eq!(
+        ids(&query("way(10); >; out;")),
+        [OsmId::Node(NodeId(4))]
+    );
+
+    assert_eq!(
+        ids(&query("rel(20); >; out;")),
+        [OsmId::Node(NodeId(1)), OsmId::Node(NodeId(4)), OsmId::Way(WayId(10))]
+    );
+
+    assert_eq!(
+        ids(&query("node(4); <; out;")),
+        [OsmId::Way(WayId(10)), OsmId::Relation(RelationId(20))]
+    );
+    assert_eq!(
+        ids(&query("node(4); <<; out;")),
+        [OsmId::Way(WayId(10)), OsmId::Relation(RelationId(20))]
+    );
+
+    assert_eq!(
+        ids(&query(
+            r#"
+            node[amenity=cafe]->.cafes;
+            node[amenity=pub]->.pubs;
+            (.cafes; .pubs;)->.all;
+            (.all; - node(3);)->.near;
+            .near out ids;
+            .cafes out count;
+            "#
+        )),
+        [OsmId::Node(NodeId(1)), OsmId::Node(NodeId(2))]
+    );
+
+    let output = query("node[amenity=cafe]; out count;");
+    assert_eq!(
+        output.items,
+        [OutItem::Count {
+            nodes: 2,
+            ways: 0,
+            relations: 0
+        }]
+    );
+
+    //sets which were never assigned are empty
+    assert_eq!(ids(&query(".missing out;")), []);
+}
+
+#[test]
+fn parse_errors() {
+    let e = parse("node[amenity=cafe] out;").unwrap_err();
+    assert_eq!(e.offset, 19);
+
+    assert!(parse("node[amenity=\"cafe]; out;").is_err());
+    assert!(parse("node(50,10,51); out;").is_err());
+    assert!(parse("foo; out;").is_err());
+
+    //comments are skipped
+    assert!(parse("/* cafes */ node[amenity=cafe]; // all of them\nout;").is_ok());
+}
+
+#[test]
+fn json_and_xml() {
+    let output = query(&format!("node[amenity=cafe]{NEAR}; out body; way(10); out geom;"));
+
+    let mut json = Vec::new();
+    write_json(&output, &mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+    let elements = json["elements"].as_array().unwrap();
+    assert_eq!(elements.len(), 2);
+    assert_eq!(elements[0]["type"], "node");
+    assert_eq!(elements[0]["id"], 1);
+    assert_eq!(elements[0]["tags"]["name"], "Corner Cafe");
+    assert!((elements[0]["lat"].as_f64().unwrap() - 50.0).abs() < 1e-6);
+    assert_eq!(elements[1]["type"], "way");
+    assert_eq!(elements[1]["geometry"].as_array().unwrap().len(), 3);
+
+    let output = query("[out:xml]; node(2); out;");
+    let mut xml = Vec::new();
+    write_xml(&output, &mut xml).unwrap();
+    let xml = String::from_utf8(xml).unwrap();
+
+    assert!(xml.contains(r#"<node id="2" lat="50" lon="10.001">"#));
+    assert!(xml.contains(r#"<tag k="name" v="The Anchor"/>"#));
+}
+
+#[test]
+fn map_source() {
+    let files = MemoryDirectory::new();
+    Manifest::current().write(&files).unwrap();
+
+    let pools = (
+        Pool::new(files.open("literals").unwrap()).unwrap(),
+        Pool::new(files.open("values").unwrap()).unwrap(),
+    );
+    let mut geography = open_tree_dense_in::<2, 8_000, BoundingBox<i32>, UncompressedOsmData>(
+        &*files.subdirectory("geography"),
+        EARTH_BBOX,
+    );
+    let mut bboxes = open_tree_sparse_in::<1, 4_000, u64, BoundingBox<i32>>(&files, "tmp.bboxes", 0..=u64::MAX);
+
+    let osm_tags = |t: &[(&str, &str)]| t.iter().map(|(k, v)| ((*k).into(), (*v).into())).collect();
+    let cafe = CompressedOsmData::Node {
+        id: NodeId(3),
+        tags: NodeFields::Multiple(Fields::from_tags(osm_tags(&[
+            ("amenity", "cafe"),
+            ("wheelchair", "yes"),
+            ("description", "Coffee, cake and a garden"),
+            ("opening_hours", "Mo-Fr 08:00-18:00"),
+        ]))),
+        point: BoundingBox::from_point(5, 5),
+    };
+    let route = CompressedOsmData::Relation {
+        bbox: BoundingBox::new(0, 0, 10, 10),
+        id: RelationId(4),
+        refs: vec![Ref {
+            member: NodeId(3).into(),
+            role: "stop".into(),
+        }],
+        tags: Fields::from_tags(osm_tags(&[("type", "route"), ("route", "bus"), ("ref", "42")])),
+    };
+    for (object, id) in [(&cafe, OsmId::Node(NodeId(3))), (&route, OsmId::Relation(RelationId(4)))] {
+        geography.insert(object.bbox(), UncompressedOsmData::new(object, &pools));
+        bboxes.insert(flattened_id(&id), object.bbox().clone());
+    }
+    pools.0.flush().unwrap();
+    pools.1.flush().unwrap();
+    geography.flush().unwrap();
+    bboxes.flush().unwrap();
+    drop((pools, geography, bboxes));
+
+    let check = |mut source: MapSource| {
+        let sorted = |mut tags: Vec<(String, String)>| {
+            tags.sort();
+            tags
+        };
+
+        let cafe = source.element(OsmId::Node(NodeId(3))).unwrap();
+        assert_eq!(
+            sorted(cafe.tags),
+            tags(&[
+                ("amenity", "cafe"),
+                ("description", "Coffee, cake and a garden"),
+                ("opening_hours", "Mo-Fr 08:00-18:00"),
+                ("wheelchair", "yes")
+            ])
+        );
+
+        let route = source.element(OsmId::Relation(RelationId(4))).unwrap();
+        assert_eq!(sorted(route.tags), tags(&[("ref", "42"), ("route", "bus"), ("type", "route")]));
+        assert_eq!(source.undecodable(), 0);
+    };
+    check(MapSource::open_in(&files).unwrap());
+
+    //on disk, the map is only read, and serving it leaves every file as it was
+    let folder = std::env::temp_dir().join("overpass_map_source");
+    let _ = std::fs::remove_dir_all(&folder);
+    for (name, contents) in files.files() {
+        let path = folder.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    let on_disk = || folder_contents(&folder);
+    let written = on_disk();
+
+    check(MapSource::open(&folder).unwrap());
+    assert_eq!(on_disk(), written);
+
+    //a missing file is an error, and isn't made
+    std::fs::remove_file(folder.join("tmp.bboxes")).unwrap();
+    assert!(MapSource::open(&folder).is_err());
+    assert!(!folder.join("tmp.bboxes").exists());
+}
+
+/// Every file under `folder`, with its contents, in order
+fn folder_contents(folder: &std::path::Path) -> Vec<(std::path::PathBuf, Vec<u8>)> {
+    let mut contents = Vec::new();
+    for entry in std::fs::read_dir(folder).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            contents.extend(folder_contents(&path));
+        } else {
+            contents.push((path.clone(), std::fs::read(path).unwrap()));
+        }
+    }
+    contents.sort();
+
+    contents
+}
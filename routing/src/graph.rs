@@ -0,0 +1,327 @@
+use std::path::PathBuf;
+
+use minimal_storage::{
+    provider::{Directory, StorageProvider},
+    serialize_fast::MinimalSerdeFast,
+    serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal},
+};
+use osm_tag_compression::field::Field;
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    dense::structure::StoredTree,
+    geo::distance_meters,
+    open_tree_dense_in, open_tree_sparse_in,
+    point_range::StoredBinaryTree,
+};
+
+use crate::{
+    profile::{HighwayClass, Profile, RoadAttributes},
+    restriction::{MalformedRestriction, TurnRestriction, Via},
+};
+
+const EDGE_SATURATION: usize = 4_000;
+const RESTRICTION_SATURATION: usize = 4_000;
+const VERTEX_SATURATION: usize = 8_000;
+
+/// How far away from a road a route may start or end.
+const MAX_SNAP_DISTANCE_METERS: f64 = 5_000.0;
+
+/// One direction of travel between two consecutive nodes of a road. Edges are stored
+/// under the OSM id of the node they start from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Edge {
+    pub to: u64,
+    pub to_point: (i32, i32),
+    pub way: u64,
+    pub length_dm: u32,
+    /// Low 4 bits: which profiles may travel from the start node to `to`.
+    /// High 4 bits: which may travel the other way. See `Profile::access_bit`
+    pub access: u8,
+    pub class: HighwayClass,
+    pub car_speed_kmh: u8,
+}
+
+impl Edge {
+    pub fn allows(&self, profile: Profile) -> bool {
+        self.access & profile.access_bit() != 0
+    }
+
+    pub fn allows_reverse(&self, profile: Profile) -> bool {
+        (self.access >> 4) & profile.access_bit() != 0
+    }
+
+    pub fn class(&self) -> HighwayClass {
+        self.class
+    }
+
+    pub fn length_meters(&self) -> f64 {
+        self.length_dm as f64 / 10.0
+    }
+
+    pub fn duration_seconds(&self, profile: Profile) -> f64 {
+        let speed_ms = profile.speed_kmh(self.class(), self.car_speed_kmh) / 3.6;
+        self.length_meters() / speed_ms
+    }
+}
+
+impl SerializeMinimal for Edge {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        self.to.minimally_serialize(write_to, ())?;
+        self.to_point.0.minimally_serialize(write_to, ())?;
+        self.to_point.1.minimally_serialize(write_to, ())?;
+        self.way.minimally_serialize(write_to, ())?;
+        self.length_dm.minimally_serialize(write_to, ())?;
+        write_to.write_all(&[self.access, self.class as u8, self.car_speed_kmh])
+    }
+}
+
+impl DeserializeFromMinimal for Edge {
+    type ExternalData<'d> = ();
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        Ok(Edge {
+            to: u64::deserialize_minimal(from, ())?,
+            to_point: (
+                i32::deserialize_minimal(from, ())?,
+                i32::deserialize_minimal(from, ())?,
+            ),
+            way: u64::deserialize_minimal(from, ())?,
+            length_dm: u32::deserialize_minimal(from, ())?,
+            access: from.read_one()?,
+            class: read_class(from)?,
+            car_speed_kmh: from.read_one()?,
+        })
+    }
+}
+
+impl MinimalSerdeFast for Edge {
+    fn fast_minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        self.to.fast_minimally_serialize(write_to, ())?;
+        self.to_point.0.fast_minimally_serialize(write_to, ())?;
+        self.to_point.1.fast_minimally_serialize(write_to, ())?;
+        self.way.fast_minimally_serialize(write_to, ())?;
+        self.length_dm.fast_minimally_serialize(write_to, ())?;
+        write_to.write_all(&[self.access, self.class as u8, self.car_speed_kmh])
+    }
+
+    fn fast_deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        Ok(Edge {
+            to: u64::fast_deserialize_minimal(from, ())?,
+            to_point: (
+                i32::fast_deserialize_minimal(from, ())?,
+                i32::fast_deserialize_minimal(from, ())?,
+            ),
+            way: u64::fast_deserialize_minimal(from, ())?,
+            length_dm: u32::fast_deserialize_minimal(from, ())?,
+            access: from.read_one()?,
+            class: read_class(from)?,
+            car_speed_kmh: from.read_one()?,
+        })
+    }
+
+    fn fast_seek_after<R: std::io::Read>(from: &mut R) -> std::io::Result<()> {
+        from.read_exact(&mut [0; 8 + 4 + 4 + 8 + 4 + 3])
+    }
+}
+
+/// An edge's highway class, which is an error if it isn't one this build knows, such as
+/// in a corrupt graph
+fn read_class(from: &mut impl std::io::Read) -> std::io::Result<HighwayClass> {
+    let class = from.read_one()?;
+
+    HighwayClass::from_u8(class).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("an edge has highway class {class}, which isn't one this build knows"),
+        )
+    })
+}
+
+/// A routable node, stored in the spatial index so that routes can start and end
+/// at arbitrary coordinates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Vertex(pub u64);
+
+impl SerializeMinimal for Vertex {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        self.0.minimally_serialize(write_to, ())
+    }
+}
+
+impl DeserializeFromMinimal for Vertex {
+    type ExternalData<'d> = &'d BoundingBox<i32>;
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: &'d BoundingBox<i32>,
+    ) -> Result<Self, std::io::Error> {
+        u64::deserialize_minimal(from, ()).map(Vertex)
+    }
+}
+
+/// The road network, as an adjacency list keyed by OSM node id and a spatial index
+/// of the nodes which appear in it. Turn restrictions are keyed by the way a route
+/// approaches the turn on, see `TurnRestriction::approach_way`.
+pub struct RoadGraph {
+    edges: StoredBinaryTree<EDGE_SATURATION, u64, Edge>,
+    vertices: StoredTree<2, VERTEX_SATURATION, BoundingBox<i32>, Vertex>,
+    restrictions: StoredBinaryTree<RESTRICTION_SATURATION, u64, TurnRestriction>,
+}
+
+impl RoadGraph {
+    pub fn open(folder: PathBuf) -> Self {
+        Self::open_in(&Directory::new(folder))
+    }
+
+    /// Like `open`, but with the graph's trees in `files`, which could be in memory
+    pub fn open_in(files: &dyn StorageProvider) -> Self {
+        let mut edges = open_tree_sparse_in::<1, EDGE_SATURATION, u64, Edge>(
+            files,
+            "edges",
+            0..=u64::MAX,
+        );
+        let mut vertices = open_tree_dense_in::<2, VERTEX_SATURATION, BoundingBox<i32>, Vertex>(
+            &*files.subdirectory("vertices"),
+            EARTH_BBOX,
+        );
+
+        let mut restrictions = open_tree_sparse_in::<1, RESTRICTION_SATURATION, u64, TurnRestriction>(
+            files,
+            "restrictions",
+            0..=u64::MAX,
+        );
+
+        edges.expand_to_depth(5);
+        vertices.expand_to_depth(5);
+        restrictions.expand_to_depth(5);
+
+        RoadGraph {
+            edges,
+            vertices,
+            restrictions,
+        }
+    }
+
+    /// Add a way to the graph, if its tags make it a road. `nodes` and `points` are the
+    /// way's node ids and their positions, in order.
+    pub fn add_way<'a>(
+        &self,
+        way: u64,
+        nodes: &[u64],
+        points: &[(i32, i32)],
+        fields: impl Iterator<Item = &'a Field>,
+    ) {
+        debug_assert_eq!(nodes.len(), points.len());
+
+        let Some(attributes) = RoadAttributes::from_fields(fields) else {
+            return;
+        };
+
+        for (node, point) in nodes.iter().zip(points.iter()) {
+            if self.edges.get_owned(node).is_none() {
+                self.vertices
+                    .insert(&BoundingBox::from_point(point.0, point.1), Vertex(*node));
+            }
+        }
+
+        for i in 1..nodes.len() {
+            let (a, b) = (nodes[i - 1], nodes[i]);
+            let (a_point, b_point) = (points[i - 1], points[i]);
+
+            if a == b {
+                continue;
+            }
+
+            let length_dm = (distance_meters(a_point, b_point) * 10.0).round() as u32;
+
+            let edge = |to, to_point, along: u8, against: u8| Edge {
+                to,
+                to_point,
+                way,
+                length_dm,
+                access: along | (against << 4),
+                class: attributes.class,
+                car_speed_kmh: attributes.car_speed_kmh,
+            };
+
+            self.edges.insert(
+                a,
+                edge(b, b_point, attributes.forward, attributes.backward),
+            );
+            self.edges.insert(
+                b,
+                edge(a, a_point, attributes.backward, attributes.forward),
+            );
+        }
+    }
+
+    /// Every edge leaving `node`, even if there are too many for one of the tree's nodes
+    pub fn edges_from(&self, node: u64) -> Vec<Edge> {
+        self.edges.find_items_in_box(&(node..=node)).collect()
+    }
+
+    /// Add a turn restriction. This should happen after all the ways are added, so that
+    /// via-node restrictions can be checked against the roads they join.
+    pub fn add_restriction(&self, restriction: TurnRestriction) -> Result<(), MalformedRestriction> {
+        if let Via::Node(node) = restriction.via {
+            let edges = self.edges_from(node);
+
+            let meets = |way| edges.iter().any(|e| e.way == way);
+            if !meets(restriction.from) || !meets(restriction.to) {
+                return Err(MalformedRestriction::NotConnected);
+            }
+        }
+
+        self.restrictions
+            .insert(restriction.approach_way(), restriction);
+
+        Ok(())
+    }
+
+    /// Every turn restriction which applies when travelling along `way`.
+    pub fn restrictions_from(&self, way: u64) -> Vec<TurnRestriction> {
+        self.restrictions.find_items_in_box(&(way..=way)).collect()
+    }
+
+    /// Find the closest node to `point` which can be used by `profile`, within
+    /// `MAX_SNAP_DISTANCE_METERS`.
+    pub fn nearest_vertex(&self, point: (i32, i32), profile: Profile) -> Option<(u64, (i32, i32))> {
+        self.vertices
+            .nearest(point, usize::MAX, |_, v| {
+                self.edges_from(v.0)
+                    .iter()
+                    .any(|e| e.allows(profile) || e.allows_reverse(profile))
+            })
+            .take_while(|(.., d)| *d <= MAX_SNAP_DISTANCE_METERS)
+            .map(|(bbox, v, _)| (v.0, (*bbox.x(), *bbox.y())))
+            .next()
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.edges.flush()?;
+        self.vertices.flush()?;
+        self.restrictions.flush()
+    }
+}
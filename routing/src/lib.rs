@@ -0,0 +1,16 @@
+//! Routing over the road network. The graph is built from highway ways while a map
+//! is compressed, and is searched with A*. Turn restrictions are decoded from
+//! `type=restriction` relations and obeyed by the search.
+
+pub mod graph;
+pub mod profile;
+pub mod restriction;
+pub mod search;
+
+pub use graph::RoadGraph;
+pub use profile::Profile;
+pub use restriction::{MalformedRestriction, TurnRestriction};
+pub use search::Route;
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,389 @@
+use osm_tag_compression::field::Field;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Profile {
+    Car,
+    Bike,
+    Foot,
+    Hgv,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [Profile::Car, Profile::Bike, Profile::Foot, Profile::Hgv];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "car" | "driving" => Some(Profile::Car),
+            "bike" | "bicycle" | "cycling" => Some(Profile::Bike),
+            "foot" | "walking" => Some(Profile::Foot),
+            "hgv" | "truck" => Some(Profile::Hgv),
+            _ => None,
+        }
+    }
+
+    /// Bit in `Edge::access` which is set if this profile may travel along the edge
+    /// in its stored direction. The bit shifted left by 4 is the same, for the
+    /// opposite direction.
+    pub(crate) fn access_bit(&self) -> u8 {
+        match self {
+            Profile::Car => 0b0001,
+            Profile::Bike => 0b0010,
+            Profile::Foot => 0b0100,
+            Profile::Hgv => 0b1000,
+        }
+    }
+
+    /// Speed this profile travels at along a road of the given class and posted speed.
+    pub fn speed_kmh(&self, class: HighwayClass, car_speed_kmh: u8) -> f64 {
+        match self {
+            Profile::Car => car_speed_kmh as f64,
+            Profile::Hgv => car_speed_kmh.min(MAX_HGV_SPEED_KMH) as f64,
+            Profile::Bike => match class {
+                HighwayClass::Steps => 2.0,
+                HighwayClass::Track | HighwayClass::Path | HighwayClass::Bridleway => 12.0,
+                HighwayClass::Footway | HighwayClass::Pedestrian => 8.0,
+                _ => 16.0,
+            },
+            Profile::Foot => match class {
+                HighwayClass::Steps => 2.5,
+                _ => 5.0,
+            },
+        }
+    }
+
+    /// An upper bound on `speed_kmh`, which keeps the A* heuristic admissible.
+    pub fn max_speed_kmh(&self) -> f64 {
+        match self {
+            Profile::Car => MAX_CAR_SPEED_KMH as f64,
+            Profile::Hgv => MAX_HGV_SPEED_KMH as f64,
+            Profile::Bike => 16.0,
+            Profile::Foot => 5.0,
+        }
+    }
+}
+
+const MAX_CAR_SPEED_KMH: u8 = 140;
+const MAX_HGV_SPEED_KMH: u8 = 80;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum HighwayClass {
+    Motorway = 0,
+    MotorwayLink = 1,
+    Trunk = 2,
+    TrunkLink = 3,
+    Primary = 4,
+    PrimaryLink = 5,
+    Secondary = 6,
+    SecondaryLink = 7,
+    Tertiary = 8,
+    TertiaryLink = 9,
+    Unclassified = 10,
+    Residential = 11,
+    LivingStreet = 12,
+    Service = 13,
+    Road = 14,
+    Track = 15,
+    Pedestrian = 16,
+    Footway = 17,
+    Path = 18,
+    Cycleway = 19,
+    Bridleway = 20,
+    Steps = 21,
+}
+
+impl HighwayClass {
+    pub fn from_tag_value(value: &str) -> Option<Self> {
+        Some(match value {
+            "motorway" => Self::Motorway,
+            "motorway_link" => Self::MotorwayLink,
+            "trunk" => Self::Trunk,
+            "trunk_link" => Self::TrunkLink,
+            "primary" => Self::Primary,
+            "primary_link" => Self::PrimaryLink,
+            "secondary" => Self::Secondary,
+            "secondary_link" => Self::SecondaryLink,
+            "tertiary" => Self::Tertiary,
+            "tertiary_link" => Self::TertiaryLink,
+            "unclassified" => Self::Unclassified,
+            "residential" => Self::Residential,
+            "living_street" => Self::LivingStreet,
+            "service" => Self::Service,
+            "road" => Self::Road,
+            "track" => Self::Track,
+            "pedestrian" => Self::Pedestrian,
+            "footway" | "corridor" => Self::Footway,
+            "path" => Self::Path,
+            "cycleway" => Self::Cycleway,
+            "bridleway" => Self::Bridleway,
+            "steps" => Self::Steps,
+            _ => return None,
+        })
+    }
+
+    /// The class whose `repr(u8)` is `value`, or `None` if there isn't one
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Motorway,
+            1 => Self::MotorwayLink,
+            2 => Self::Trunk,
+            3 => Self::TrunkLink,
+            4 => Self::Primary,
+            5 => Self::PrimaryLink,
+            6 => Self::Secondary,
+            7 => Self::SecondaryLink,
+            8 => Self::Tertiary,
+            9 => Self::TertiaryLink,
+            10 => Self::Unclassified,
+            11 => Self::Residential,
+            12 => Self::LivingStreet,
+            13 => Self::Service,
+            14 => Self::Road,
+            15 => Self::Track,
+            16 => Self::Pedestrian,
+            17 => Self::Footway,
+            18 => Self::Path,
+            19 => Self::Cycleway,
+            20 => Self::Bridleway,
+            21 => Self::Steps,
+            _ => return None,
+        })
+    }
+
+    /// 0 for classes which cars can't use unless their tags say so
+    fn default_car_speed_kmh(&self) -> u8 {
+        match self {
+            Self::Motorway => 110,
+            Self::Trunk => 90,
+            Self::Primary => 70,
+            Self::MotorwayLink | Self::Secondary => 60,
+            Self::TrunkLink | Self::PrimaryLink | Self::Tertiary => 50,
+            Self::SecondaryLink | Self::TertiaryLink | Self::Unclassified => 40,
+            Self::Residential | Self::Road => 30,
+            Self::Service => 20,
+            Self::Track => 15,
+            Self::LivingStreet => 10,
+            _ => 0,
+        }
+    }
+
+    /// Which profiles may use this road when no access tags say otherwise.
+    fn default_access(&self) -> u8 {
+        let car = Profile::Car.access_bit();
+        let bike = Profile::Bike.access_bit();
+        let foot = Profile::Foot.access_bit();
+        let hgv = Profile::Hgv.access_bit();
+
+        match self {
+            Self::Motorway | Self::MotorwayLink => car | hgv,
+            Self::Pedestrian | Self::Footway | Self::Steps | Self::Bridleway => foot,
+            Self::Path | Self::Cycleway => bike | foot,
+            _ => car | bike | foot | hgv,
+        }
+    }
+
+    fn implies_oneway(&self) -> bool {
+        matches!(self, Self::Motorway)
+    }
+}
+
+/// Everything the router needs to know about a way, taken from its tags.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RoadAttributes {
+    pub class: HighwayClass,
+    pub car_speed_kmh: u8,
+    /// Access bits for travelling along the way's node order, see `Profile::access_bit`
+    pub forward: u8,
+    /// Access bits for travelling against the way's node order
+    pub backward: u8,
+}
+
+/// Keys which restrict access, from least to most specific, for each profile.
+/// These come from `notes/restriction_tags`.
+const CAR_ACCESS_KEYS: [&str; 4] = ["access", "vehicle", "motor_vehicle", "motorcar"];
+const BIKE_ACCESS_KEYS: [&str; 3] = ["access", "vehicle", "bicycle"];
+const FOOT_ACCESS_KEYS: [&str; 2] = ["access", "foot"];
+const HGV_ACCESS_KEYS: [&str; 4] = ["access", "vehicle", "motor_vehicle", "hgv"];
+
+impl RoadAttributes {
+    /// Read the routing-relevant tags of a way. Returns `None` if the way isn't a road
+    /// that any profile can use.
+    pub fn from_fields<'a>(fields: impl Iterator<Item = &'a Field>) -> Option<Self> {
+        let tags: Vec<_> = fields.filter_map(|f| f.as_tag()).collect();
+        let tag = |key: &str| -> Option<&str> {
+            tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_ref())
+        };
+
+        let class = HighwayClass::from_tag_value(tag("highway")?)?;
+
+        if tag("area") == Some("yes") {
+            return None;
+        }
+
+        let mut access = class.default_access();
+        for (profile, keys) in [
+            (Profile::Car, &CAR_ACCESS_KEYS[..]),
+            (Profile::Bike, &BIKE_ACCESS_KEYS[..]),
+            (Profile::Foot, &FOOT_ACCESS_KEYS[..]),
+            (Profile::Hgv, &HGV_ACCESS_KEYS[..]),
+        ] {
+            //the most specific key that's present wins
+            let allowed = keys
+                .iter()
+                .rev()
+                .find_map(|k| tag(k).and_then(access_value_allows));
+
+            match allowed {
+                Some(true) => access |= profile.access_bit(),
+                Some(false) => access &= !profile.access_bit(),
+                None => {}
+            }
+        }
+
+        let car_speed_kmh = tag("maxspeed")
+            .and_then(parse_maxspeed_kmh)
+            .unwrap_or(class.default_car_speed_kmh() as f64);
+
+        //a way which cars can only use because its tags say so, with no speed to go at,
+        //such as a footway with `motor_vehicle=destination`, isn't routed along by car
+        if car_speed_kmh < 1.0 {
+            access &= !(Profile::Car.access_bit() | Profile::Hgv.access_bit());
+        }
+
+        if access == 0 {
+            return None;
+        }
+
+        let car_speed_kmh = car_speed_kmh.clamp(1.0, MAX_CAR_SPEED_KMH as f64) as u8;
+
+        let oneway = match tag("oneway") {
+            Some("yes" | "true" | "1") => Oneway::Forward,
+            Some("-1" | "reverse") => Oneway::Backward,
+            Some("no" | "false" | "0") => Oneway::No,
+            _ if tag("junction") == Some("roundabout") || class.implies_oneway() => {
+                Oneway::Forward
+            }
+            _ => Oneway::No,
+        };
+
+        //pedestrians can always walk both ways; cyclists only if told they can
+        let oneway_exempt = match tag("oneway:bicycle") {
+            Some("no") => Profile::Foot.access_bit() | Profile::Bike.access_bit(),
+            _ => Profile::Foot.access_bit(),
+        };
+
+        let (forward, backward) = match oneway {
+            Oneway::No => (access, access),
+            Oneway::Forward => (access, access & oneway_exempt),
+            Oneway::Backward => (access & oneway_exempt, access),
+        };
+
+        Some(RoadAttributes {
+            class,
+            car_speed_kmh,
+            forward,
+            backward,
+        })
+    }
+}
+
+enum Oneway {
+    No,
+    Forward,
+    Backward,
+}
+
+/// `Some(true)` if the value grants access, `Some(false)` if it denies it, and `None` if it
+/// doesn't say either way (in which case a less specific key is checked).
+fn access_value_allows(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "designated" | "permissive" | "destination" | "customers" | "delivery"
+        | "official" | "discouraged" => Some(true),
+        "no" | "private" | "agricultural" | "forestry" | "use_sidepath" | "dismount" => {
+            Some(false)
+        }
+        _ => None,
+    }
+}
+
+fn parse_maxspeed_kmh(value: &str) -> Option<f64> {
+    if value == "none" || value == "signals" {
+        return Some(MAX_CAR_SPEED_KMH as f64);
+    }
+
+    let number_len = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+
+    let number: f64 = value[..number_len].parse().ok()?;
+
+    if value[number_len..].trim() == "mph" {
+        Some(number * 1.609344)
+    } else {
+        Some(number)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn attributes(tags: &[(&str, &str)]) -> Option<RoadAttributes> {
+        let fields: Vec<Field> = tags.iter().map(|x| Field::from(*x)).collect();
+        RoadAttributes::from_fields(fields.iter())
+    }
+
+    #[test]
+    pub fn oneway_residential() {
+        let a = attributes(&[("highway", "residential"), ("oneway", "yes")]).unwrap();
+
+        assert_eq!(a.class, HighwayClass::Residential);
+        assert_eq!(a.car_speed_kmh, 30);
+        assert_eq!(a.forward, 0b1111);
+        assert_eq!(a.backward, Profile::Foot.access_bit());
+    }
+
+    #[test]
+    pub fn access_overrides() {
+        let a = attributes(&[
+            ("highway", "service"),
+            ("access", "private"),
+            ("foot", "yes"),
+        ])
+        .unwrap();
+
+        assert_eq!(a.forward, Profile::Foot.access_bit());
+
+        assert!(attributes(&[("highway", "footway"), ("foot", "no")]).is_none());
+        assert!(attributes(&[("building", "yes")]).is_none());
+    }
+
+    #[test]
+    pub fn tagged_car_access_without_a_speed() {
+        let a = attributes(&[("highway", "pedestrian"), ("motor_vehicle", "destination")]).unwrap();
+        assert_eq!(a.forward, Profile::Foot.access_bit());
+
+        assert!(attributes(&[("highway", "footway"), ("foot", "no"), ("motorcar", "yes")]).is_none());
+
+        //unless it's given one
+        let a = attributes(&[("highway", "pedestrian"), ("motorcar", "yes"), ("maxspeed", "10")]).unwrap();
+        assert_eq!(a.car_speed_kmh, 10);
+        assert!(a.forward & Profile::Car.access_bit() != 0);
+    }
+
+    #[test]
+    pub fn class_bytes() {
+        for value in 0..=HighwayClass::Steps as u8 {
+            assert_eq!(HighwayClass::from_u8(value).map(|c| c as u8), Some(value));
+        }
+        assert_eq!(HighwayClass::from_u8(HighwayClass::Steps as u8 + 1), None);
+        assert_eq!(HighwayClass::from_u8(u8::MAX), None);
+    }
+
+    #[test]
+    pub fn maxspeed() {
+        assert_eq!(parse_maxspeed_kmh("50"), Some(50.0));
+        assert_eq!(parse_maxspeed_kmh("30 mph").map(|x| x.round()), Some(48.0));
+        assert_eq!(parse_maxspeed_kmh("walk"), None);
+    }
+}
@@ -0,0 +1,466 @@
+use std::fmt::Display;
+
+use minimal_storage::{
+    serialize_fast::MinimalSerdeFast,
+    serialize_min::{DeserializeFromMinimal, ReadExtReadOne, SerializeMinimal},
+};
+use osm_tag_compression::field::Field;
+use osmpbfreader::{OsmId, Ref};
+
+use crate::profile::Profile;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RestrictionKind {
+    /// The turn from `from` onto `to` may not be made
+    No,
+    /// Coming from `from`, the only turn which may be made is onto `to`
+    Only,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Via {
+    Node(u64),
+    /// The ways travelled between `from` and `to`, in order
+    Ways(Vec<u64>),
+}
+
+/// One decoded turn restriction. Relations with several `from` ways (`no_entry`) or
+/// several `to` ways (`no_exit`) are split into one restriction per way.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    pub from: u64,
+    pub via: Via,
+    pub to: u64,
+    /// Which profiles must obey the restriction, see `Profile::access_bit`
+    pub profiles: u8,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MalformedRestriction {
+    NoRestrictionTag,
+    UnknownRestriction(String),
+    MissingMember(&'static str),
+    TooManyMembers(&'static str),
+    WrongMemberType(&'static str),
+    /// The via node isn't a point where both the `from` and `to` ways are routable
+    NotConnected,
+}
+
+impl Display for MalformedRestriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoRestrictionTag => write!(f, "no `restriction` tag"),
+            Self::UnknownRestriction(v) => write!(f, "unknown restriction `{v}`"),
+            Self::MissingMember(role) => write!(f, "no `{role}` member"),
+            Self::TooManyMembers(role) => write!(f, "too many `{role}` members"),
+            Self::WrongMemberType(role) => write!(f, "`{role}` member has the wrong type"),
+            Self::NotConnected => write!(f, "`from` and `to` don't meet at the `via` node"),
+        }
+    }
+}
+
+/// Turn restrictions are vehicle rules; pedestrians ignore them.
+const VEHICLE_PROFILES: [(&str, &[Profile]); 6] = [
+    ("vehicle", &[Profile::Car, Profile::Bike, Profile::Hgv]),
+    ("motor_vehicle", &[Profile::Car, Profile::Hgv]),
+    ("motorcar", &[Profile::Car]),
+    ("bicycle", &[Profile::Bike]),
+    ("hgv", &[Profile::Hgv]),
+    ("goods", &[Profile::Hgv]),
+];
+
+fn vehicle_profiles(vehicle: &str) -> u8 {
+    VEHICLE_PROFILES
+        .iter()
+        .find(|(v, _)| *v == vehicle)
+        .map(|(_, profiles)| profiles.iter().fold(0, |acc, p| acc | p.access_bit()))
+        .unwrap_or(0)
+}
+
+impl TurnRestriction {
+    /// Decode a relation's restrictions. Relations which aren't `type=restriction`, or
+    /// which only restrict vehicles that aren't routed (e.g. `restriction:psv`), decode
+    /// to nothing.
+    pub fn from_relation<'a>(
+        fields: impl Iterator<Item = &'a Field>,
+        refs: &[Ref],
+    ) -> Result<Vec<Self>, MalformedRestriction> {
+        let tags: Vec<_> = fields.filter_map(|f| f.as_tag()).collect();
+        let tag = |key: &str| -> Option<&str> {
+            tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_ref())
+        };
+
+        if tag("type") != Some("restriction") {
+            return Ok(Vec::new());
+        }
+
+        let exempt = tag("except")
+            .map(|e| e.split(';').fold(0, |acc, v| acc | vehicle_profiles(v.trim())))
+            .unwrap_or(0);
+
+        //`restriction` applies to every vehicle, `restriction:<vehicle>` to just one type
+        let rules: Vec<(&str, u8)> = tags
+            .iter()
+            .filter_map(|(k, v)| {
+                let profiles = match k.strip_prefix("restriction") {
+                    Some("") => vehicle_profiles("vehicle"),
+                    Some(vehicle) => vehicle_profiles(vehicle.strip_prefix(':')?),
+                    None => return None,
+                };
+                Some((v.as_ref(), profiles & !exempt))
+            })
+            .collect();
+
+        if rules.is_empty() {
+            return Err(MalformedRestriction::NoRestrictionTag);
+        }
+
+        let mut restrictions = Vec::new();
+
+        for (value, profiles) in rules {
+            if profiles == 0 {
+                continue;
+            }
+
+            let kind = restriction_kind(value)?;
+
+            let from = member_ways(refs, "from")?;
+            let to = member_ways(refs, "to")?;
+
+            if from.len() > 1 && value != "no_entry" {
+                return Err(MalformedRestriction::TooManyMembers("from"));
+            }
+            if to.len() > 1 && value != "no_exit" {
+                return Err(MalformedRestriction::TooManyMembers("to"));
+            }
+
+            let via = member_via(refs)?;
+
+            for from in from.iter() {
+                for to in to.iter() {
+                    restrictions.push(TurnRestriction {
+                        kind,
+                        from: *from,
+                        via: via.clone(),
+                        to: *to,
+                        profiles,
+                    });
+                }
+            }
+        }
+
+        Ok(restrictions)
+    }
+
+    /// The way a route is on when it reaches the turn. Restrictions are stored under
+    /// this way's id.
+    pub fn approach_way(&self) -> u64 {
+        match &self.via {
+            Via::Node(_) => self.from,
+            Via::Ways(ways) => *ways.last().unwrap(),
+        }
+    }
+
+    pub fn applies_to(&self, profile: Profile) -> bool {
+        self.profiles & profile.access_bit() != 0
+    }
+}
+
+fn restriction_kind(value: &str) -> Result<RestrictionKind, MalformedRestriction> {
+    match value {
+        "no_left_turn" | "no_right_turn" | "no_straight_on" | "no_u_turn" | "no_entry"
+        | "no_exit" => Ok(RestrictionKind::No),
+        "only_left_turn" | "only_right_turn" | "only_straight_on" | "only_u_turn" => {
+            Ok(RestrictionKind::Only)
+        }
+        _ => Err(MalformedRestriction::UnknownRestriction(value.to_string())),
+    }
+}
+
+fn member_ways(refs: &[Ref], role: &'static str) -> Result<Vec<u64>, MalformedRestriction> {
+    let ways = refs
+        .iter()
+        .filter(|r| r.role.as_str() == role)
+        .map(|r| match r.member {
+            OsmId::Way(w) => Ok(w.0 as u64),
+            _ => Err(MalformedRestriction::WrongMemberType(role)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if ways.is_empty() {
+        return Err(MalformedRestriction::MissingMember(role));
+    }
+
+    Ok(ways)
+}
+
+fn member_via(refs: &[Ref]) -> Result<Via, MalformedRestriction> {
+    let vias: Vec<_> = refs.iter().filter(|r| r.role.as_str() == "via").collect();
+
+    match vias.as_slice() {
+        [] => Err(MalformedRestriction::MissingMember("via")),
+        [single] => match single.member {
+            OsmId::Node(n) => Ok(Via::Node(n.0 as u64)),
+            OsmId::Way(w) => Ok(Via::Ways(vec![w.0 as u64])),
+            OsmId::Relation(_) => Err(MalformedRestriction::WrongMemberType("via")),
+        },
+        multiple => multiple
+            .iter()
+            .map(|r| match r.member {
+                OsmId::Way(w) => Ok(w.0 as u64),
+                OsmId::Node(_) => Err(MalformedRestriction::TooManyMembers("via")),
+                OsmId::Relation(_) => Err(MalformedRestriction::WrongMemberType("via")),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Via::Ways),
+    }
+}
+
+//header byte layout:
+//bit 0: 1 if `Only`, 0 if `No`
+//bit 1: 1 if the via is a node, 0 if it's a list of ways
+impl SerializeMinimal for TurnRestriction {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        _external_data: (),
+    ) -> std::io::Result<()> {
+        let header = match self.kind {
+            RestrictionKind::No => 0,
+            RestrictionKind::Only => 0b01,
+        } | match self.via {
+            Via::Node(_) => 0b10,
+            Via::Ways(_) => 0,
+        };
+
+        write_to.write_all(&[header, self.profiles])?;
+        self.from.minimally_serialize(write_to, ())?;
+        self.to.minimally_serialize(write_to, ())?;
+
+        match &self.via {
+            Via::Node(node) => node.minimally_serialize(write_to, ()),
+            Via::Ways(ways) => {
+                ways.len().minimally_serialize(write_to, ())?;
+                for way in ways.iter() {
+                    way.minimally_serialize(write_to, ())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl DeserializeFromMinimal for TurnRestriction {
+    type ExternalData<'d> = ();
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        _external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        let header = from.read_one()?;
+        let profiles = from.read_one()?;
+        let from_way = u64::deserialize_minimal(from, ())?;
+        let to = u64::deserialize_minimal(from, ())?;
+
+        let via = if header & 0b10 != 0 {
+            Via::Node(u64::deserialize_minimal(from, ())?)
+        } else {
+            let len = usize::deserialize_minimal(from, ())?;
+            Via::Ways(
+                (0..len)
+                    .map(|_| u64::deserialize_minimal(from, ()))
+                    .collect::<Result<_, _>>()?,
+            )
+        };
+
+        Ok(TurnRestriction {
+            kind: if header & 0b01 != 0 {
+                RestrictionKind::Only
+            } else {
+                RestrictionKind::No
+            },
+            from: from_way,
+            via,
+            to,
+            profiles,
+        })
+    }
+}
+
+impl MinimalSerdeFast for TurnRestriction {
+    fn fast_minimally_serialize<'a, 's: 'a, W: std::io::Write>(
+        &'a self,
+        write_to: &mut W,
+        external_data: (),
+    ) -> std::io::Result<()> {
+        //restrictions are rare enough that the varint form is fast enough
+        self.minimally_serialize(write_to, external_data)
+    }
+
+    fn fast_deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
+        from: &'a mut R,
+        external_data: (),
+    ) -> Result<Self, std::io::Error> {
+        Self::deserialize_minimal(from, external_data)
+    }
+
+    fn fast_seek_after<R: std::io::Read>(from: &mut R) -> std::io::Result<()> {
+        Self::deserialize_minimal(from, ()).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use osmpbfreader::{NodeId, RelationId, WayId};
+
+    use super::*;
+
+    fn decode(
+        tags: &[(&str, &str)],
+        members: &[(&str, OsmId)],
+    ) -> Result<Vec<TurnRestriction>, MalformedRestriction> {
+        let fields: Vec<Field> = tags.iter().map(|x| Field::from(*x)).collect();
+        let refs: Vec<Ref> = members
+            .iter()
+            .map(|(role, member)| Ref {
+                member: *member,
+                role: (*role).into(),
+            })
+            .collect();
+
+        TurnRestriction::from_relation(fields.iter(), &refs)
+    }
+
+    #[test]
+    pub fn via_node_and_via_way() {
+        let r = decode(
+            &[("type", "restriction"), ("restriction", "no_left_turn")],
+            &[
+                ("from", OsmId::Way(WayId(1))),
+                ("via", OsmId::Node(NodeId(2))),
+                ("to", OsmId::Way(WayId(3))),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            r,
+            vec![TurnRestriction {
+                kind: RestrictionKind::No,
+                from: 1,
+                via: Via::Node(2),
+                to: 3,
+                profiles: 0b1011,
+            }]
+        );
+        assert_eq!(r[0].approach_way(), 1);
+
+        let r = decode(
+            &[("type", "restriction"), ("restriction", "only_straight_on")],
+            &[
+                ("from", OsmId::Way(WayId(1))),
+                ("via", OsmId::Way(WayId(4))),
+                ("via", OsmId::Way(WayId(5))),
+                ("to", OsmId::Way(WayId(3))),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(r[0].kind, RestrictionKind::Only);
+        assert_eq!(r[0].via, Via::Ways(vec![4, 5]));
+        assert_eq!(r[0].approach_way(), 5);
+
+        let mut bytes = Vec::new();
+        r[0].minimally_serialize(&mut bytes, ()).unwrap();
+        assert_eq!(
+            TurnRestriction::deserialize_minimal(&mut &bytes[..], ()).unwrap(),
+            r[0]
+        );
+    }
+
+    #[test]
+    pub fn vehicle_types_and_exceptions() {
+        let members = [
+            ("from", OsmId::Way(WayId(1))),
+            ("via", OsmId::Node(NodeId(2))),
+            ("to", OsmId::Way(WayId(3))),
+        ];
+
+        let hgv = decode(
+            &[("type", "restriction"), ("restriction:hgv", "no_right_turn")],
+            &members,
+        )
+        .unwrap();
+        assert!(hgv[0].applies_to(Profile::Hgv));
+        assert!(!hgv[0].applies_to(Profile::Car));
+
+        let except = decode(
+            &[
+                ("type", "restriction"),
+                ("restriction", "no_right_turn"),
+                ("except", "bicycle;psv"),
+            ],
+            &members,
+        )
+        .unwrap();
+        assert!(except[0].applies_to(Profile::Car));
+        assert!(!except[0].applies_to(Profile::Bike));
+        assert!(!except[0].applies_to(Profile::Foot));
+
+        let psv = decode(
+            &[("type", "restriction"), ("restriction:psv", "no_right_turn")],
+            &members,
+        );
+        assert_eq!(psv, Ok(Vec::new()));
+    }
+
+    #[test]
+    pub fn malformed() {
+        let restriction = [("type", "restriction"), ("restriction", "no_u_turn")];
+
+        assert_eq!(
+            decode(
+                &restriction,
+                &[("from", OsmId::Way(WayId(1))), ("to", OsmId::Way(WayId(1)))]
+            ),
+            Err(MalformedRestriction::MissingMember("via"))
+        );
+        assert_eq!(
+            decode(
+                &restriction,
+                &[
+                    ("from", OsmId::Node(NodeId(1))),
+                    ("via", OsmId::Node(NodeId(2))),
+                    ("to", OsmId::Way(WayId(1)))
+                ]
+            ),
+            Err(MalformedRestriction::WrongMemberType("from"))
+        );
+        assert_eq!(
+            decode(
+                &restriction,
+                &[
+                    ("from", OsmId::Way(WayId(1))),
+                    ("from", OsmId::Way(WayId(4))),
+                    ("via", OsmId::Relation(RelationId(2))),
+                    ("to", OsmId::Way(WayId(1)))
+                ]
+            ),
+            Err(MalformedRestriction::TooManyMembers("from"))
+        );
+        assert_eq!(
+            decode(
+                &[("type", "restriction"), ("restriction", "no_parking")],
+                &[]
+            ),
+            Err(MalformedRestriction::UnknownRestriction("no_parking".into()))
+        );
+        assert_eq!(
+            decode(&[("type", "restriction")], &[]),
+            Err(MalformedRestriction::NoRestrictionTag)
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, BinaryHeap, HashMap},
+};
+
+use tree::geo::distance_meters;
+
+use crate::{
+    graph::{Edge, RoadGraph},
+    profile::Profile,
+    restriction::{RestrictionKind, TurnRestriction, Via},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    /// Every point along the route, in decimicro degrees, from start to end
+    pub geometry: Vec<(i32, i32)>,
+    /// The ways travelled along, in order, with consecutive duplicates removed
+    pub ways: Vec<u64>,
+    pub length_meters: f64,
+    pub duration_seconds: f64,
+}
+
+/// A node, and the way it was reached along. Turn restrictions depend on which way a
+/// route arrives on, so the same node can be visited once per way.
+type State = (u64, u64);
+
+/// The way of the start state, which no restriction is stored under.
+const NO_WAY: u64 = u64::MAX;
+
+struct Candidate {
+    estimated_total: f64,
+    state: State,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        //reversed, so that the BinaryHeap pops the lowest estimate first
+        other.estimated_total.total_cmp(&self.estimated_total)
+    }
+}
+
+struct Visit {
+    seconds: f64,
+    point: (i32, i32),
+    reached_by: Option<(State, Edge)>,
+}
+
+impl RoadGraph {
+    /// Find the fastest route between two points (in decimicro degrees) for the given
+    /// profile, using A* with a straight-line heuristic. Both points are first moved to
+    /// the closest node on a road which the profile can use. Turn restrictions which
+    /// apply to the profile are obeyed.
+    pub fn route(&self, from: (i32, i32), to: (i32, i32), profile: Profile) -> Option<Route> {
+        let (start, start_point) = self.nearest_vertex(from, profile)?;
+        let (goal, goal_point) = self.nearest_vertex(to, profile)?;
+
+        self.route_between_nodes(start, start_point, goal, goal_point, profile)
+    }
+
+    pub fn route_between_nodes(
+        &self,
+        start: u64,
+        start_point: (i32, i32),
+        goal: u64,
+        goal_point: (i32, i32),
+        profile: Profile,
+    ) -> Option<Route> {
+        let max_speed_ms = profile.max_speed_kmh() / 3.6;
+        let heuristic = |point| distance_meters(point, goal_point) / max_speed_ms;
+
+        let mut visits = HashMap::<State, Visit>::new();
+        let mut open = BinaryHeap::new();
+        let mut restrictions = HashMap::<u64, Vec<TurnRestriction>>::new();
+
+        visits.insert(
+            (start, NO_WAY),
+            Visit {
+                seconds: 0.0,
+                point: start_point,
+                reached_by: None,
+            },
+        );
+        open.push(Candidate {
+            estimated_total: heuristic(start_point),
+            state: (start, NO_WAY),
+        });
+
+        while let Some(Candidate {
+            estimated_total,
+            state,
+        }) = open.pop()
+        {
+            let (node, way) = state;
+            let visit = &visits[&state];
+
+            //a shorter path to this node has already been expanded
+            if estimated_total > visit.seconds + heuristic(visit.point) {
+                continue;
+            }
+
+            if node == goal {
+                return Some(self.reconstruct(&visits, state));
+            }
+
+            let seconds_here = visit.seconds;
+
+            let restrictions_here = restrictions.entry(way).or_insert_with(|| {
+                if way == NO_WAY {
+                    return Vec::new();
+                }
+                let mut r = self.restrictions_from(way);
+                r.retain(|r| r.applies_to(profile));
+                r
+            });
+
+            for edge in self.edges_from(node) {
+                if !edge.allows(profile) {
+                    continue;
+                }
+
+                if restrictions_here
+                    .iter()
+                    .any(|r| forbids(r, &visits, state, &edge))
+                {
+                    continue;
+                }
+
+                let seconds = seconds_here + edge.duration_seconds(profile);
+                let next = (edge.to, edge.way);
+
+                let improved = match visits.entry(next) {
+                    Entry::Occupied(mut o) => {
+                        if o.get().seconds <= seconds {
+                            false
+                        } else {
+                            *o.get_mut() = Visit {
+                                seconds,
+                                point: edge.to_point,
+                                reached_by: Some((state, edge)),
+                            };
+                            true
+                        }
+                    }
+                    Entry::Vacant(v) => {
+                        v.insert(Visit {
+                            seconds,
+                            point: edge.to_point,
+                            reached_by: Some((state, edge)),
+                        });
+                        true
+                    }
+                };
+
+                if improved {
+                    open.push(Candidate {
+                        estimated_total: seconds + heuristic(edge.to_point),
+                        state: next,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    fn reconstruct(&self, visits: &HashMap<State, Visit>, goal: State) -> Route {
+        let mut geometry = vec![visits[&goal].point];
+        let mut edges = Vec::new();
+
+        let mut state = goal;
+        while let Some((previous, edge)) = visits[&state].reached_by {
+            edges.push(edge);
+            geometry.push(visits[&previous].point);
+            state = previous;
+        }
+
+        geometry.reverse();
+        edges.reverse();
+
+        let mut ways: Vec<u64> = edges.iter().map(|e| e.way).collect();
+        ways.dedup();
+
+        Route {
+            geometry,
+            ways,
+            length_meters: edges.iter().map(|e| e.length_meters()).sum(),
+            duration_seconds: visits[&goal].seconds,
+        }
+    }
+}
+
+/// Whether `restriction`, which is stored under the way `state` arrived on, forbids
+/// continuing from `state` along `edge`.
+fn forbids(
+    restriction: &TurnRestriction,
+    visits: &HashMap<State, Visit>,
+    state: State,
+    edge: &Edge,
+) -> bool {
+    let (node, way) = state;
+
+    let at_turn = match &restriction.via {
+        Via::Node(via) => *via == node,
+        //the turn is made where the route leaves the last via way, and only counts if
+        // the route came along the rest of the via ways and the from way before that
+        Via::Ways(ways) => {
+            edge.way != way
+                && came_along(
+                    visits,
+                    state,
+                    ways[..ways.len() - 1]
+                        .iter()
+                        .rev()
+                        .chain(std::iter::once(&restriction.from)),
+                )
+        }
+    };
+
+    if !at_turn {
+        return false;
+    }
+
+    let onto_to = if restriction.from == restriction.to {
+        //a u-turn: the only way to tell it apart from carrying on along the same way
+        // is that it goes back to the node the route just came from
+        visits[&state]
+            .reached_by
+            .is_some_and(|((previous, _), _)| previous == edge.to)
+    } else {
+        edge.way == restriction.to
+    };
+
+    match restriction.kind {
+        RestrictionKind::No => onto_to,
+        RestrictionKind::Only => !onto_to,
+    }
+}
+
+/// Whether, before the way it's currently on, the route to `state` travelled along
+/// `ways` (most recent first).
+fn came_along<'a>(
+    visits: &HashMap<State, Visit>,
+    state: State,
+    ways: impl Iterator<Item = &'a u64>,
+) -> bool {
+    let mut current = state;
+
+    for expected in ways {
+        //skip back past the rest of the current way
+        loop {
+            let Some((previous, _)) = visits[&current].reached_by else {
+                return false;
+            };
+            let changed_way = previous.1 != current.1;
+            current = previous;
+            if changed_way {
+                break;
+            }
+        }
+
+        if current.1 != *expected {
+            return false;
+        }
+    }
+
+    true
+}
@@ -0,0 +1,238 @@
+use minimal_storage::{
+    provider::MemoryDirectory,
+    serialize_min::{DeserializeFromMinimal, SerializeMinimal},
+};
+
+use osm_tag_compression::field::Field;
+
+use tree::geo::degrees_to_decimicro;
+
+use crate::{
+    graph::Edge,
+    profile::HighwayClass,
+    restriction::{RestrictionKind, Via},
+    MalformedRestriction, Profile, RoadGraph, TurnRestriction,
+};
+
+fn open_test_graph() -> RoadGraph {
+    //in memory, so that tests never share a folder
+    RoadGraph::open_in(&MemoryDirectory::new())
+}
+
+fn tags(tags: &[(&str, &str)]) -> Vec<Field> {
+    tags.iter().map(|x| Field::from(*x)).collect()
+}
+
+///   1 --- 2 --- 3
+///   |           |
+///   4 --------- 5
+///
+/// 1-2-3 is a fast oneway primary road (eastbound), 1-4-5-3 is a slow residential street.
+fn grid(graph: &RoadGraph) -> Vec<(i32, i32)> {
+    let points = vec![
+        degrees_to_decimicro(0.0, 0.01),
+        degrees_to_decimicro(0.01, 0.01),
+        degrees_to_decimicro(0.02, 0.01),
+        degrees_to_decimicro(0.0, 0.0),
+        degrees_to_decimicro(0.02, 0.0),
+    ];
+
+    graph.add_way(
+        10,
+        &[1, 2, 3],
+        &[points[0], points[1], points[2]],
+        tags(&[("highway", "primary"), ("oneway", "yes")]).iter(),
+    );
+    graph.add_way(
+        11,
+        &[1, 4, 5, 3],
+        &[points[0], points[3], points[4], points[2]],
+        tags(&[("highway", "residential")]).iter(),
+    );
+    graph.add_way(
+        12,
+        &[4, 5],
+        &[points[3], points[4]],
+        tags(&[("building", "yes")]).iter(),
+    );
+
+    points
+}
+
+#[test]
+pub fn routes_prefer_faster_roads() {
+    let graph = open_test_graph();
+    let points = grid(&graph);
+
+    let route = graph.route(points[0], points[2], Profile::Car).unwrap();
+
+    assert_eq!(route.ways, vec![10]);
+    assert_eq!(route.geometry, vec![points[0], points[1], points[2]]);
+    assert!((route.length_meters - 2226.0).abs() < 5.0, "{}", route.length_meters);
+    assert!((route.duration_seconds - route.length_meters / (70.0 / 3.6)).abs() < 1.0);
+}
+
+#[test]
+pub fn oneway_is_respected() {
+    let graph = open_test_graph();
+    let points = grid(&graph);
+
+    let car = graph.route(points[2], points[0], Profile::Car).unwrap();
+    assert_eq!(car.ways, vec![11]);
+    assert_eq!(car.geometry.len(), 4);
+
+    //pedestrians may walk against the oneway
+    let foot = graph.route(points[2], points[0], Profile::Foot).unwrap();
+    assert_eq!(foot.ways, vec![10]);
+}
+
+#[test]
+pub fn snaps_to_nearby_roads() {
+    let graph = open_test_graph();
+    let points = grid(&graph);
+
+    let near_start = (points[0].0 + 50, points[0].1 - 50);
+    let route = graph.route(near_start, points[1], Profile::Bike).unwrap();
+
+    assert_eq!(route.geometry.first(), Some(&points[0]));
+
+    let nowhere = degrees_to_decimicro(10.0, 10.0);
+    assert!(graph.route(nowhere, points[1], Profile::Bike).is_none());
+}
+
+#[test]
+pub fn busy_junction() {
+    let graph = open_test_graph();
+    let hub = degrees_to_decimicro(0.0, 0.0);
+    let residential = tags(&[("highway", "residential")]);
+
+    //more edges from the hub than fit in one of the tree's nodes
+    let spokes = 4_100u64;
+    for i in 1..=spokes {
+        let point = degrees_to_decimicro(0.001 * (i % 100) as f64, 0.001 * (i / 100) as f64 + 0.001);
+        graph.add_way(i, &[0, i], &[hub, point], residential.iter());
+    }
+
+    let mut ways: Vec<_> = graph.edges_from(0).iter().map(|e| e.way).collect();
+    ways.sort();
+    assert_eq!(ways, (1..=spokes).collect::<Vec<_>>());
+}
+
+///         3
+///       / |
+///   1 - 2 - 4  (4 is joined to 3 by way 22)
+///       |
+///       5
+///
+/// Way 20 is 1-2-4, way 21 is 5-2-3, and way 22 is 4-3. All are residential.
+fn junction(graph: &RoadGraph) -> Vec<(i32, i32)> {
+    let points = vec![
+        degrees_to_decimicro(-0.01, 0.0),
+        degrees_to_decimicro(0.0, 0.0),
+        degrees_to_decimicro(0.0, 0.01),
+        degrees_to_decimicro(0.01, 0.0),
+        degrees_to_decimicro(0.0, -0.01),
+    ];
+    let road = || tags(&[("highway", "residential")]);
+
+    graph.add_way(20, &[1, 2, 4], &[points[0], points[1], points[3]], road().iter());
+    graph.add_way(21, &[5, 2, 3], &[points[4], points[1], points[2]], road().iter());
+    graph.add_way(22, &[4, 3], &[points[3], points[2]], road().iter());
+
+    points
+}
+
+fn restriction(kind: RestrictionKind, from: u64, via: Via, to: u64) -> TurnRestriction {
+    TurnRestriction {
+        kind,
+        from,
+        via,
+        to,
+        profiles: Profile::Car.access_bit(),
+    }
+}
+
+#[test]
+pub fn no_turn_restriction() {
+    let graph = open_test_graph();
+    let points = junction(&graph);
+
+    assert_eq!(graph.route(points[0], points[2], Profile::Car).unwrap().ways, vec![20, 21]);
+
+    graph
+        .add_restriction(restriction(RestrictionKind::No, 20, Via::Node(2), 21))
+        .unwrap();
+
+    assert_eq!(graph.route(points[0], points[2], Profile::Car).unwrap().ways, vec![20, 22]);
+    //only cars were restricted
+    assert_eq!(graph.route(points[0], points[2], Profile::Bike).unwrap().ways, vec![20, 21]);
+
+    assert_eq!(
+        graph.add_restriction(restriction(RestrictionKind::No, 20, Via::Node(3), 21)),
+        Err(MalformedRestriction::NotConnected)
+    );
+}
+
+#[test]
+pub fn only_turn_restriction() {
+    let graph = open_test_graph();
+    let points = junction(&graph);
+
+    graph
+        .add_restriction(restriction(RestrictionKind::Only, 20, Via::Node(2), 21))
+        .unwrap();
+
+    let route = graph.route(points[0], points[3], Profile::Car).unwrap();
+    assert_eq!(route.ways, vec![20, 21, 22]);
+}
+
+#[test]
+pub fn via_way_restriction() {
+    let graph = open_test_graph();
+    let points = junction(&graph);
+
+    graph
+        .add_restriction(restriction(RestrictionKind::No, 21, Via::Node(2), 20))
+        .unwrap();
+
+    let route = graph.route(points[4], points[0], Profile::Car).unwrap();
+    assert_eq!(route.ways, vec![21, 22, 20]);
+
+    graph
+        .add_restriction(restriction(
+            RestrictionKind::No,
+            21,
+            Via::Ways(vec![22]),
+            20,
+        ))
+        .unwrap();
+
+    assert!(graph.route(points[4], points[0], Profile::Car).is_none());
+    //starting part of the way along the restriction doesn't trigger it
+    let route = graph.route(points[3], points[0], Profile::Car).unwrap();
+    assert_eq!(route.ways, vec![20]);
+}
+
+#[test]
+fn unknown_highway_class() {
+    let edge = Edge {
+        to: 2,
+        to_point: (10, 20),
+        way: 3,
+        length_dm: 40,
+        access: 0b1111,
+        class: HighwayClass::Steps,
+        car_speed_kmh: 30,
+    };
+
+    let mut bytes = Vec::new();
+    edge.minimally_serialize(&mut bytes, ()).unwrap();
+    assert_eq!(Edge::deserialize_minimal(&mut &bytes[..], ()).unwrap(), edge);
+
+    //the class is the second to last byte
+    let class = bytes.len() - 2;
+    bytes[class] = HighwayClass::Steps as u8 + 1;
+    let error = Edge::deserialize_minimal(&mut &bytes[..], ()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("class 22"), "{error}");
+}
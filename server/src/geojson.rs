@@ -0,0 +1,89 @@
+use osmpbfreader::OsmId;
+use overpass::Element;
+use serde_json::{json, Map, Value};
+use tree::geo::decimicro_to_degrees;
+
+/// Keys which make a closed way an area rather than a loop, unless it's tagged `area=no`.
+const AREA_KEYS: [&str; 8] = [
+    "building", "landuse", "natural", "leisure", "amenity", "water", "place", "aeroway",
+];
+
+pub fn feature_collection<'a>(elements: impl IntoIterator<Item = &'a Element>) -> Value {
+    let features: Vec<Value> = elements.into_iter().map(feature).collect();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// Relations don't have their own geometry, so theirs is `null`, and their members are
+/// listed in the properties instead.
+pub fn feature(element: &Element) -> Value {
+    let mut properties = Map::new();
+    properties.insert("type".into(), type_name(&element.id).into());
+    properties.insert("id".into(), element.id.inner_id().into());
+
+    let tags = element
+        .tags
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::from(v.as_str())));
+    properties.insert("tags".into(), tags.collect::<Map<_, _>>().into());
+
+    if let OsmId::Relation(_) = element.id {
+        let members = element.members.iter().map(|m| {
+            json!({ "type": type_name(&m.id), "ref": m.id.inner_id(), "role": m.role })
+        });
+        properties.insert("members".into(), members.collect());
+    }
+
+    json!({
+        "type": "Feature",
+        "id": format!("{}/{}", type_name(&element.id), element.id.inner_id()),
+        "geometry": geometry(element),
+        "properties": properties,
+    })
+}
+
+fn geometry(element: &Element) -> Value {
+    let position = |p: &(i32, i32)| {
+        let (lon, lat) = decimicro_to_degrees(*p);
+        json!([lon, lat])
+    };
+
+    match element.id {
+        OsmId::Node(_) => match element.geometry.first() {
+            Some(p) => json!({ "type": "Point", "coordinates": position(p) }),
+            None => Value::Null,
+        },
+        OsmId::Way(_) if is_area(element) => {
+            let ring: Vec<Value> = element.geometry.iter().map(position).collect();
+            json!({ "type": "Polygon", "coordinates": [ring] })
+        }
+        OsmId::Way(_) if element.geometry.len() >= 2 => {
+            let line: Vec<Value> = element.geometry.iter().map(position).collect();
+            json!({ "type": "LineString", "coordinates": line })
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Whether a way is a closed area, such as a building, rather than a line.
+pub fn is_area(element: &Element) -> bool {
+    let closed = element.geometry.len() >= 4 && element.geometry.first() == element.geometry.last();
+
+    closed
+        && match element.tag("area") {
+            Some("yes") => true,
+            Some("no") => false,
+            _ => AREA_KEYS.iter().any(|k| element.tag(k).is_some()),
+        }
+}
+
+pub fn type_name(id: &OsmId) -> &'static str {
+    match id {
+        OsmId::Node(_) => "node",
+        OsmId::Way(_) => "way",
+        OsmId::Relation(_) => "relation",
+    }
+}
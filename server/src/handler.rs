@@ -0,0 +1,266 @@
+use osmpbfreader::{NodeId, OsmId, RelationId, WayId};
+use overpass::{eval::OutItem, parse::Statement, Element, Source};
+use tree::{
+    bbox::BoundingBox,
+    geo::{decimicro_to_degrees, degrees_to_decimicro},
+};
+
+use crate::{
+    geojson::{feature, feature_collection},
+    mvt::encode_tile,
+    render::render_png,
+    tile::{Tile, Viewport},
+};
+
+/// Like the OSM API's limit, so that one request can't load a whole country.
+pub const MAX_BBOX_SQUARE_DEGREES: f64 = 0.25;
+
+/// Below this zoom, tiles would cover too much of the map to load, so they're empty.
+pub const MIN_TILE_ZOOM: u8 = 12;
+
+const TILE_PIXELS: u32 = 256;
+const MAX_RENDER_PIXELS: u32 = 4096;
+
+const GEOJSON: &str = "application/geo+json";
+const MVT: &str = "application/vnd.mapbox-vector-tile";
+const PNG: &str = "image/png";
+const TEXT: &str = "text/plain; charset=utf-8";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type: TEXT,
+            body: message.into().into_bytes(),
+        }
+    }
+}
+
+/// Answer a `GET` for `url` (the path and query string) from the source.
+pub fn handle(url: &str, source: &mut impl Source) -> Response {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (decode_component(k), decode_component(v))
+        })
+        .collect();
+    let param = |name: &str| params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let result = match segments.as_slice() {
+        ["bbox"] => bbox_query(param("bbox"), source),
+        ["object", kind, id] => object(kind, id, source),
+        ["query"] => filtered_query(param("bbox"), param("filter"), param("type"), source),
+        ["tiles", z, x, y] => tile(z, x, y, source),
+        ["render.png"] => render(param("bbox"), param("width"), param("height"), source),
+        _ => Err(Response::error(404, "no such endpoint")),
+    };
+
+    result.unwrap_or_else(|e| e)
+}
+
+fn bbox_query(bbox: Option<&str>, source: &mut impl Source) -> Result<Response, Response> {
+    let bbox = parse_bbox(bbox)?;
+    let elements = source.elements_in(&bbox);
+
+    Ok(geojson(&feature_collection(&elements)))
+}
+
+fn object(kind: &str, id: &str, source: &mut impl Source) -> Result<Response, Response> {
+    let id: i64 = id
+        .parse()
+        .map_err(|_| Response::error(400, format!("`{id}` isn't an id")))?;
+
+    let id = match kind {
+        "node" => OsmId::Node(NodeId(id)),
+        "way" => OsmId::Way(WayId(id)),
+        "relation" => OsmId::Relation(RelationId(id)),
+        _ => return Err(Response::error(404, "objects are `node`, `way` or `relation`")),
+    };
+
+    let element = source
+        .element(id)
+        .ok_or_else(|| Response::error(404, "no such object"))?;
+
+    Ok(geojson(&feature(&element)))
+}
+
+/// `filter` is Overpass QL tag filters, such as `[amenity=cafe][name]`.
+fn filtered_query(
+    bbox: Option<&str>,
+    filter: Option<&str>,
+    types: Option<&str>,
+    source: &mut impl Source,
+) -> Result<Response, Response> {
+    let bbox = parse_bbox(bbox)?;
+    let filter = filter.unwrap_or_default();
+    let types = types.unwrap_or("nwr");
+
+    if !["node", "way", "rel", "relation", "nwr"].contains(&types) {
+        return Err(Response::error(400, "`type` is `node`, `way`, `relation` or `nwr`"));
+    }
+    let types = if types == "relation" { "rel" } else { types };
+
+    let (west, south) = decimicro_to_degrees((*bbox.x(), *bbox.y()));
+    let (east, north) = decimicro_to_degrees((*bbox.x_end(), *bbox.y_end()));
+    let text = format!("{types}{filter}({south},{west},{north},{east}); out;");
+
+    let query = overpass::parse(&text).map_err(|e| Response::error(400, e.to_string()))?;
+
+    //anything but filters would make it more than one selection
+    if !matches!(query.statements.as_slice(), [Statement::Select { .. }, Statement::Out { .. }]) {
+        return Err(Response::error(400, "`filter` can only hold tag filters"));
+    }
+
+    let output = overpass::run(&query, source);
+    let elements = output.items.iter().filter_map(|i| match i {
+        OutItem::Element(e, _) => Some(e),
+        OutItem::Count { .. } => None,
+    });
+
+    Ok(geojson(&feature_collection(elements)))
+}
+
+fn tile(z: &str, x: &str, y: &str, source: &mut impl Source) -> Result<Response, Response> {
+    let (y, format) = y
+        .rsplit_once('.')
+        .ok_or_else(|| Response::error(404, "tiles end in `.mvt` or `.png`"))?;
+
+    let number = |n: &str| {
+        n.parse::<u32>()
+            .map_err(|_| Response::error(400, format!("`{n}` isn't a tile number")))
+    };
+    let z = u8::try_from(number(z)?).map_err(|_| Response::error(400, "zoom is too high"))?;
+    let tile = Tile::new(z, number(x)?, number(y)?)
+        .ok_or_else(|| Response::error(400, "tile is outside the grid"))?;
+
+    let elements = if tile.z >= MIN_TILE_ZOOM {
+        source.elements_in(&tile.bbox())
+    } else {
+        Vec::new()
+    };
+
+    match format {
+        "mvt" => Ok(Response::ok(MVT, encode_tile(tile, &elements))),
+        "png" => {
+            let viewport = Viewport::of_tile(tile, TILE_PIXELS as f64);
+            png(render_png(&viewport, TILE_PIXELS, TILE_PIXELS, &elements))
+        }
+        _ => Err(Response::error(404, "tiles end in `.mvt` or `.png`")),
+    }
+}
+
+fn render(
+    bbox: Option<&str>,
+    width: Option<&str>,
+    height: Option<&str>,
+    source: &mut impl Source,
+) -> Result<Response, Response> {
+    let bbox = parse_bbox(bbox)?;
+
+    let size = |s: Option<&str>| match s.map(str::parse::<u32>) {
+        None => Ok(TILE_PIXELS * 2),
+        Some(Ok(s)) if (1..=MAX_RENDER_PIXELS).contains(&s) => Ok(s),
+        _ => Err(Response::error(
+            400,
+            format!("`width` and `height` are from 1 to {MAX_RENDER_PIXELS}"),
+        )),
+    };
+    let (width, height) = (size(width)?, size(height)?);
+
+    let elements: Vec<Element> = source.elements_in(&bbox);
+    let viewport = Viewport::of_bbox(&bbox, width as f64, height as f64);
+
+    png(render_png(&viewport, width, height, &elements))
+}
+
+/// `west,south,east,north` in degrees, as in the OSM API.
+fn parse_bbox(bbox: Option<&str>) -> Result<BoundingBox<i32>, Response> {
+    let bbox = bbox.ok_or_else(|| Response::error(400, "`bbox` is required"))?;
+    let invalid = || Response::error(400, "`bbox` is `west,south,east,north` in degrees");
+
+    let numbers = bbox
+        .split(',')
+        .map(|n| n.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    let [west, south, east, north] = numbers[..] else {
+        return Err(invalid());
+    };
+
+    let in_range = (-180.0..=180.0).contains(&west)
+        && (-180.0..=180.0).contains(&east)
+        && (-90.0..=90.0).contains(&south)
+        && (-90.0..=90.0).contains(&north);
+    if !in_range || west > east || south > north {
+        return Err(invalid());
+    }
+
+    if (east - west) * (north - south) > MAX_BBOX_SQUARE_DEGREES {
+        return Err(Response::error(
+            400,
+            format!("`bbox` can cover at most {MAX_BBOX_SQUARE_DEGREES} square degrees"),
+        ));
+    }
+
+    let (x, y) = degrees_to_decimicro(west, south);
+    let (x_end, y_end) = degrees_to_decimicro(east, north);
+
+    Ok(BoundingBox::new(x, y, x_end, y_end))
+}
+
+fn geojson(value: &serde_json::Value) -> Response {
+    Response::ok(GEOJSON, value.to_string().into_bytes())
+}
+
+fn png(image: std::io::Result<Vec<u8>>) -> Result<Response, Response> {
+    image
+        .map(|image| Response::ok(PNG, image))
+        .map_err(|e| Response::error(500, e.to_string()))
+}
+
+/// Percent-decoding, with `+` as a space, as in form-encoded query strings.
+fn decode_component(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,67 @@
+//! A local HTTP server over a compressed map, so that web frontends and scripts can use
+//! it without linking Rust.
+//!
+//! Every endpoint answers `GET`:
+//! - `/bbox?bbox=w,s,e,n`: every object in the box, as GeoJSON
+//! - `/object/{node|way|relation}/{id}`: one object, as a GeoJSON feature
+//! - `/query?bbox=w,s,e,n&filter=[k=v][k2]&type=nwr`: the objects in the box matching
+//!   Overpass QL tag filters, as GeoJSON
+//! - `/tiles/{z}/{x}/{y}.mvt`: a Mapbox vector tile
+//! - `/tiles/{z}/{x}/{y}.png`: the same tile, rendered
+//! - `/render.png?bbox=w,s,e,n&width=512&height=512`: a box, rendered
+
+pub mod geojson;
+pub mod handler;
+pub mod mvt;
+pub mod render;
+pub mod tile;
+
+use std::io;
+
+use overpass::MapSource;
+use tiny_http::{Header, Method, Server};
+
+pub use handler::{handle, Response};
+
+/// Answer requests on `address` with `threads` workers until the process ends. The
+/// workers share the one opened map, and so its page caches.
+pub fn serve(map: &MapSource, address: &str, threads: usize) -> io::Result<()> {
+    let server = Server::http(address).map_err(io::Error::other)?;
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                let mut source = map;
+
+                for request in server.incoming_requests() {
+                    let response = match request.method() {
+                        Method::Get => handle(request.url(), &mut source),
+                        _ => Response {
+                            status: 405,
+                            content_type: "text/plain; charset=utf-8",
+                            body: b"only GET is supported".to_vec(),
+                        },
+                    };
+
+                    let headers = [
+                        ("Content-Type", response.content_type),
+                        ("Access-Control-Allow-Origin", "*"),
+                    ];
+                    let mut reply = tiny_http::Response::from_data(response.body)
+                        .with_status_code(response.status);
+                    for (name, value) in headers {
+                        reply.add_header(Header::from_bytes(name, value).unwrap());
+                    }
+
+                    //the client may have gone away, which only matters to it
+                    let _ = request.respond(reply);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,277 @@
+//! Mapbox Vector Tile (version 2) encoding.
+//!
+//! The protobuf messages are written by hand, since only a handful of fields are needed.
+
+use std::collections::HashMap;
+
+use osmpbfreader::OsmId;
+use overpass::Element;
+
+use crate::{
+    geojson::is_area,
+    tile::{Tile, Viewport},
+};
+
+pub const EXTENT: u32 = 4096;
+
+/// Points further outside the tile than this are clamped, so that they fit in an `i32`
+/// even at high zooms. Only lines spanning tens of thousands of tiles are bent by it.
+const MAX_OFFSET: i64 = 1 << 28;
+
+const GEOMETRY_POINT: u64 = 1;
+const GEOMETRY_LINESTRING: u64 = 2;
+const GEOMETRY_POLYGON: u64 = 3;
+
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+const COMMAND_CLOSE_PATH: u32 = 7;
+
+/// Encode the elements into a tile with a `nodes` and a `ways` layer. Relations have no
+/// geometry of their own, so they're left out.
+pub fn encode_tile(tile: Tile, elements: &[Element]) -> Vec<u8> {
+    let viewport = Viewport::of_tile(tile, EXTENT as f64);
+
+    let mut nodes = Layer::new("nodes");
+    let mut ways = Layer::new("ways");
+
+    for element in elements {
+        let points: Vec<(i32, i32)> = element
+            .geometry
+            .iter()
+            .map(|p| to_tile(viewport.project(*p)))
+            .collect();
+
+        match element.id {
+            OsmId::Node(_) => nodes.add(element, GEOMETRY_POINT, point_geometry(&points)),
+            OsmId::Way(_) if is_area(element) => {
+                ways.add(element, GEOMETRY_POLYGON, polygon_geometry(&points))
+            }
+            OsmId::Way(_) => ways.add(element, GEOMETRY_LINESTRING, line_geometry(&points)),
+            OsmId::Relation(_) => {}
+        }
+    }
+
+    let mut tile = Vec::new();
+    for layer in [nodes, ways] {
+        if !layer.features.is_empty() {
+            write_bytes(&mut tile, 3, &layer.encode());
+        }
+    }
+    tile
+}
+
+fn to_tile((x, y): (f64, f64)) -> (i32, i32) {
+    let clamp = |v: f64| (v.round() as i64).clamp(-MAX_OFFSET, MAX_OFFSET) as i32;
+    (clamp(x), clamp(y))
+}
+
+struct Layer {
+    name: &'static str,
+    features: Vec<Vec<u8>>,
+    keys: Vec<String>,
+    values: Vec<String>,
+    key_indices: HashMap<String, u32>,
+    value_indices: HashMap<String, u32>,
+}
+
+impl Layer {
+    fn new(name: &'static str) -> Self {
+        Layer {
+            name,
+            features: Vec::new(),
+            keys: Vec::new(),
+            values: Vec::new(),
+            key_indices: HashMap::new(),
+            value_indices: HashMap::new(),
+        }
+    }
+
+    /// Features whose geometry collapsed to nothing at this zoom are skipped.
+    fn add(&mut self, element: &Element, geometry_type: u64, geometry: Option<Vec<u32>>) {
+        let Some(geometry) = geometry else {
+            return;
+        };
+
+        let mut tags = Vec::with_capacity(element.tags.len() * 2);
+        for (k, v) in element.tags.iter() {
+            tags.push(index_of(k, &mut self.keys, &mut self.key_indices));
+            tags.push(index_of(v, &mut self.values, &mut self.value_indices));
+        }
+
+        let mut feature = Vec::new();
+        write_varint_field(&mut feature, 1, element.id.inner_id() as u64);
+        write_packed(&mut feature, 2, &tags);
+        write_varint_field(&mut feature, 3, geometry_type);
+        write_packed(&mut feature, 4, &geometry);
+
+        self.features.push(feature);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut layer = Vec::new();
+        write_varint_field(&mut layer, 15, 2);
+        write_bytes(&mut layer, 1, self.name.as_bytes());
+        for feature in self.features.iter() {
+            write_bytes(&mut layer, 2, feature);
+        }
+        for key in self.keys.iter() {
+            write_bytes(&mut layer, 3, key.as_bytes());
+        }
+        for value in self.values.iter() {
+            let mut v = Vec::new();
+            write_bytes(&mut v, 1, value.as_bytes());
+            write_bytes(&mut layer, 4, &v);
+        }
+        write_varint_field(&mut layer, 5, EXTENT as u64);
+        layer
+    }
+}
+
+fn index_of(text: &str, list: &mut Vec<String>, indices: &mut HashMap<String, u32>) -> u32 {
+    *indices.entry(text.to_string()).or_insert_with(|| {
+        list.push(text.to_string());
+        (list.len() - 1) as u32
+    })
+}
+
+fn point_geometry(points: &[(i32, i32)]) -> Option<Vec<u32>> {
+    let mut geometry = Geometry::default();
+    geometry.move_to(*points.first()?);
+    Some(geometry.commands)
+}
+
+fn line_geometry(points: &[(i32, i32)]) -> Option<Vec<u32>> {
+    let points = without_repeats(points);
+    if points.len() < 2 {
+        return None;
+    }
+
+    let mut geometry = Geometry::default();
+    geometry.move_to(points[0]);
+    geometry.line_to(&points[1..]);
+    Some(geometry.commands)
+}
+
+/// The exterior ring has to be clockwise (in tile coordinates, with `y` down), and isn't
+/// closed by repeating its first point.
+fn polygon_geometry(points: &[(i32, i32)]) -> Option<Vec<u32>> {
+    let mut ring = without_repeats(points);
+    if ring.first() == ring.last() {
+        ring.pop();
+    }
+    if ring.len() < 3 {
+        return None;
+    }
+
+    let area: i64 = ring
+        .iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 as i64 * b.1 as i64 - b.0 as i64 * a.1 as i64)
+        .sum();
+    match area {
+        0 => return None,
+        a if a < 0 => ring.reverse(),
+        _ => {}
+    }
+
+    let mut geometry = Geometry::default();
+    geometry.move_to(ring[0]);
+    geometry.line_to(&ring[1..]);
+    geometry.commands.push(command(COMMAND_CLOSE_PATH, 1));
+    Some(geometry.commands)
+}
+
+fn without_repeats(points: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let mut points = points.to_vec();
+    points.dedup();
+    points
+}
+
+/// Geometry commands, with every position relative to the one before.
+#[derive(Default)]
+struct Geometry {
+    commands: Vec<u32>,
+    cursor: (i32, i32),
+}
+
+impl Geometry {
+    fn move_to(&mut self, point: (i32, i32)) {
+        self.commands.push(command(COMMAND_MOVE_TO, 1));
+        self.step(point);
+    }
+
+    fn line_to(&mut self, points: &[(i32, i32)]) {
+        self.commands.push(command(COMMAND_LINE_TO, points.len() as u32));
+        for point in points {
+            self.step(*point);
+        }
+    }
+
+    fn step(&mut self, point: (i32, i32)) {
+        self.commands.push(zigzag(point.0 - self.cursor.0));
+        self.commands.push(zigzag(point.1 - self.cursor.1));
+        self.cursor = point;
+    }
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0b111) | (count << 3)
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+fn write_varint(to: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        to.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    to.push(value as u8);
+}
+
+fn write_varint_field(to: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(to, field << 3);
+    write_varint(to, value);
+}
+
+fn write_bytes(to: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(to, (field << 3) | 2);
+    write_varint(to, bytes.len() as u64);
+    to.extend_from_slice(bytes);
+}
+
+fn write_packed(to: &mut Vec<u8>, field: u64, values: &[u32]) {
+    let mut packed = Vec::new();
+    for v in values {
+        write_varint(&mut packed, *v as u64);
+    }
+    write_bytes(to, field, &packed);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn commands() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(zigzag(-2), 3);
+
+        //the example from the specification
+        assert_eq!(
+            line_geometry(&[(2, 2), (2, 10), (10, 10)]),
+            Some(vec![9, 4, 4, 18, 0, 16, 16, 0])
+        );
+
+        //a counter-clockwise square is reversed
+        assert_eq!(
+            polygon_geometry(&[(0, 0), (0, 10), (10, 10), (10, 0), (0, 0)]),
+            Some(vec![9, 20, 0, 26, 0, 20, 19, 0, 0, 19, 15])
+        );
+
+        assert_eq!(line_geometry(&[(5, 5), (5, 5)]), None);
+    }
+}
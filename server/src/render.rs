@@ -0,0 +1,237 @@
+//! A plain raster rendering, for previews and QA rather than as a basemap.
+
+use osmpbfreader::OsmId;
+use overpass::Element;
+
+use crate::{geojson::is_area, tile::Viewport};
+
+type Color = [u8; 4];
+
+const BACKGROUND: Color = [242, 239, 233, 255];
+const WATER: Color = [170, 211, 223, 255];
+const GREEN: Color = [200, 230, 180, 255];
+const BUILDING: Color = [217, 208, 201, 255];
+const AREA: Color = [224, 220, 212, 255];
+const MAJOR_ROAD: Color = [247, 184, 120, 255];
+const ROAD: Color = [255, 255, 255, 255];
+const PATH: Color = [250, 128, 114, 255];
+const RAILWAY: Color = [112, 112, 112, 255];
+const LINE: Color = [150, 150, 150, 255];
+const POINT: Color = [80, 80, 160, 255];
+
+/// Draw the elements onto a `width` by `height` image, areas first, then lines, then
+/// points, and encode it as a PNG.
+pub fn render_png(
+    viewport: &Viewport,
+    width: u32,
+    height: u32,
+    elements: &[Element],
+) -> std::io::Result<Vec<u8>> {
+    let mut canvas = Canvas::new(width, height);
+
+    let project =
+        |e: &Element| -> Vec<(f64, f64)> { e.geometry.iter().map(|p| viewport.project(*p)).collect() };
+
+    for element in elements.iter().filter(|e| is_area(e)) {
+        canvas.fill_polygon(&project(element), area_color(element));
+    }
+
+    let mut lines: Vec<_> = elements
+        .iter()
+        .filter(|e| matches!(e.id, OsmId::Way(_)) && !is_area(e))
+        .map(|e| (line_style(e), e))
+        .collect();
+    lines.sort_by_key(|((_, width), _)| *width);
+    for ((color, width), element) in lines {
+        canvas.stroke(&project(element), width, color);
+    }
+
+    for element in elements.iter().filter(|e| matches!(e.id, OsmId::Node(_))) {
+        if let Some(point) = element.point() {
+            let (x, y) = viewport.project(point);
+            canvas.dot(x, y, 2, POINT);
+        }
+    }
+
+    canvas.encode_png()
+}
+
+fn area_color(element: &Element) -> Color {
+    let is = |k: &str, values: &[&str]| element.tag(k).is_some_and(|v| values.contains(&v));
+
+    if element.tag("building").is_some() {
+        BUILDING
+    } else if is("natural", &["water"]) || element.tag("water").is_some() {
+        WATER
+    } else if is("landuse", &["grass", "forest", "meadow", "recreation_ground"])
+        || is("leisure", &["park", "garden", "pitch"])
+        || is("natural", &["wood", "scrub", "grassland"])
+    {
+        GREEN
+    } else {
+        AREA
+    }
+}
+
+/// The colour and width in pixels of a line.
+fn line_style(element: &Element) -> (Color, u32) {
+    match (element.tag("highway"), element.tag("railway"), element.tag("waterway")) {
+        (Some("motorway" | "trunk" | "primary" | "secondary"), _, _) => (MAJOR_ROAD, 4),
+        (Some("footway" | "path" | "cycleway" | "steps" | "track" | "bridleway"), _, _) => {
+            (PATH, 1)
+        }
+        (Some(_), _, _) => (ROAD, 3),
+        (_, Some(_), _) => (RAILWAY, 2),
+        (_, _, Some("river" | "canal")) => (WATER, 3),
+        (_, _, Some(_)) => (WATER, 1),
+        _ => (LINE, 1),
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: BACKGROUND.repeat((width * height) as usize),
+        }
+    }
+
+    fn set(&mut self, x: i64, y: i64, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&color);
+    }
+
+    fn dot(&mut self, x: f64, y: f64, radius: i64, color: Color) {
+        let (cx, cy) = (x.round() as i64, y.round() as i64);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if dx * dx + dy * dy <= radius * radius {
+                    self.set(cx + dx, cy + dy, color);
+                }
+            }
+        }
+    }
+
+    /// Segments are clipped to the canvas first, so that a long way passing by doesn't get
+    /// walked pixel by pixel.
+    fn stroke(&mut self, points: &[(f64, f64)], width: u32, color: Color) {
+        let radius = (width as i64 - 1) / 2;
+        let margin = radius as f64 + 1.0;
+        let bounds = (
+            (-margin, -margin),
+            (self.width as f64 + margin, self.height as f64 + margin),
+        );
+
+        for pair in points.windows(2) {
+            let Some(((x0, y0), (x1, y1))) = clip(pair[0], pair[1], bounds) else {
+                continue;
+            };
+
+            let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let t = step as f64 / steps as f64;
+                let (x, y) = (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+                if radius == 0 {
+                    self.set(x.round() as i64, y.round() as i64, color);
+                } else {
+                    self.dot(x, y, radius, color);
+                }
+            }
+        }
+    }
+
+    /// Even-odd scanline fill, sampling at the centre of each pixel.
+    fn fill_polygon(&mut self, ring: &[(f64, f64)], color: Color) {
+        if ring.len() < 3 {
+            return;
+        }
+
+        let min_y = ring.iter().map(|p| p.1).fold(f64::INFINITY, f64::min).max(0.0);
+        let max_y = ring
+            .iter()
+            .map(|p| p.1)
+            .fold(f64::NEG_INFINITY, f64::max)
+            .min(self.height as f64);
+
+        let mut crossings = Vec::new();
+        for y in (min_y.floor() as i64)..(max_y.ceil() as i64) {
+            let sample = y as f64 + 0.5;
+
+            crossings.clear();
+            for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+                if (a.1 <= sample) != (b.1 <= sample) {
+                    crossings.push(a.0 + (sample - a.1) / (b.1 - a.1) * (b.0 - a.0));
+                }
+            }
+            crossings.sort_by(f64::total_cmp);
+
+            for span in crossings.chunks_exact(2) {
+                let start = (span[0] - 0.5).ceil().max(0.0) as i64;
+                let end = (span[1] - 0.5).floor().min(self.width as f64 - 1.0) as i64;
+                for x in start..=end {
+                    self.set(x, y, color);
+                }
+            }
+        }
+    }
+
+    fn encode_png(&self) -> std::io::Result<Vec<u8>> {
+        let mut png = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(std::io::Error::other)?;
+        writer.finish().map_err(std::io::Error::other)?;
+
+        Ok(png)
+    }
+}
+
+type Point = (f64, f64);
+
+/// The part of the segment from `a` to `b` which is inside `bounds` (Liang-Barsky).
+fn clip(a: Point, b: Point, (min, max): (Point, Point)) -> Option<(Point, Point)> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut enter, mut exit) = (0.0f64, 1.0f64);
+
+    for (p, q) in [
+        (-dx, a.0 - min.0),
+        (dx, max.0 - a.0),
+        (-dy, a.1 - min.1),
+        (dy, max.1 - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+
+        let t = q / p;
+        if p < 0.0 {
+            enter = enter.max(t);
+        } else {
+            exit = exit.min(t);
+        }
+    }
+
+    (enter <= exit).then_some((
+        (a.0 + dx * enter, a.1 + dy * enter),
+        (a.0 + dx * exit, a.1 + dy * exit),
+    ))
+}
@@ -0,0 +1,215 @@
+use osmpbfreader::{NodeId, OsmId, RelationId, WayId};
+use overpass::{Element, Member, Source};
+use serde_json::{json, Value};
+use tree::{bbox::BoundingBox, geo::degrees_to_decimicro};
+
+use crate::{
+    handle,
+    tile::{Tile, Viewport},
+};
+
+struct Elements(Vec<Element>);
+
+impl Source for Elements {
+    fn elements_in(&mut self, bbox: &BoundingBox<i32>) -> Vec<Element> {
+        self.0.iter().filter(|e| e.bbox.overlaps(bbox)).cloned().collect()
+    }
+
+    fn element(&mut self, id: OsmId) -> Option<Element> {
+        self.0.iter().find(|e| e.id == id).cloned()
+    }
+}
+
+fn element(id: OsmId, points: &[(f64, f64)], tags: &[(&str, &str)]) -> Element {
+    let geometry: Vec<_> = points
+        .iter()
+        .map(|(lon, lat)| degrees_to_decimicro(*lon, *lat))
+        .collect();
+
+    let mut bbox = BoundingBox::from_point(geometry[0].0, geometry[0].1);
+    for (x, y) in geometry.iter() {
+        bbox.extend_with_point(*x, *y);
+    }
+
+    Element {
+        id,
+        tags: tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        geometry: if let OsmId::Relation(_) = id { Vec::new() } else { geometry },
+        bbox,
+        members: Vec::new(),
+    }
+}
+
+fn town() -> Elements {
+    let cafe = element(
+        OsmId::Node(NodeId(1)),
+        &[(10.0, 50.0)],
+        &[("amenity", "cafe"), ("name", "Corner Cafe")],
+    );
+    let street = element(
+        OsmId::Way(WayId(10)),
+        &[(9.999, 50.0005), (10.001, 50.0005)],
+        &[("highway", "primary")],
+    );
+    let building = element(
+        OsmId::Way(WayId(11)),
+        &[
+            (10.0002, 50.0002),
+            (10.0004, 50.0002),
+            (10.0004, 50.0004),
+            (10.0002, 50.0004),
+            (10.0002, 50.0002),
+        ],
+        &[("building", "yes")],
+    );
+    let mut route = element(
+        OsmId::Relation(RelationId(20)),
+        &[(9.999, 50.0), (10.001, 50.0005)],
+        &[("type", "route")],
+    );
+    route.members.push(Member {
+        id: street.id,
+        role: String::new(),
+    });
+
+    Elements(vec![cafe, street, building, route])
+}
+
+fn json(url: &str) -> Value {
+    let response = handle(url, &mut town());
+    assert_eq!(response.status, 200, "{}", String::from_utf8_lossy(&response.body));
+    assert_eq!(response.content_type, "application/geo+json");
+
+    serde_json::from_slice(&response.body).unwrap()
+}
+
+const BBOX: &str = "bbox=9.99,49.99,10.01,50.01";
+
+#[test]
+fn geojson() {
+    let collection = json(&format!("/bbox?{BBOX}"));
+    let features = collection["features"].as_array().unwrap();
+    assert_eq!(features.len(), 4);
+
+    let kinds: Vec<_> = features.iter().map(|f| f["geometry"]["type"].clone()).collect();
+    assert_eq!(kinds, [json!("Point"), json!("LineString"), json!("Polygon"), Value::Null]);
+    assert_eq!(features[3]["properties"]["members"][0]["ref"], 10);
+
+    let cafe = json("/object/node/1");
+    assert_eq!(cafe["id"], "node/1");
+    assert_eq!(cafe["properties"]["tags"]["name"], "Corner Cafe");
+    assert_eq!(cafe["geometry"]["coordinates"][0], 10.0);
+
+    assert_eq!(handle("/object/node/2", &mut town()).status, 404);
+    assert_eq!(handle("/object/area/1", &mut town()).status, 404);
+    assert_eq!(handle("/nothing", &mut town()).status, 404);
+}
+
+#[test]
+fn filtered_queries() {
+    let ids = |url: &str| -> Vec<Value> {
+        let collection = json(url);
+        collection["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["id"].clone())
+            .collect()
+    };
+
+    assert_eq!(
+        ids(&format!("/query?{BBOX}&filter=%5Bamenity%3Dcafe%5D")),
+        ["node/1"]
+    );
+    assert_eq!(ids(&format!("/query?{BBOX}&filter=[highway]&type=way")), ["way/10"]);
+    assert_eq!(ids(&format!("/query?{BBOX}&filter=[highway]&type=node")), Vec::<Value>::new());
+    assert_eq!(
+        ids(&format!("/query?{BBOX}&filter=[name~\"corner+cafe\",i]")),
+        ["node/1"]
+    );
+
+    //only filters, so another statement can't escape the bbox
+    let escape = format!("/query?{BBOX}&filter=[a];nwr;out;node[b]");
+    assert_eq!(handle(&escape, &mut town()).status, 400);
+    assert_eq!(handle(&format!("/query?{BBOX}&filter=[a"), &mut town()).status, 400);
+}
+
+#[test]
+fn bad_bboxes() {
+    for bbox in ["", "bbox=1,2,3", "bbox=a,b,c,d", "bbox=10,50,9,51", "bbox=0,0,1,1"] {
+        let response = handle(&format!("/bbox?{bbox}"), &mut town());
+        assert_eq!(response.status, 400, "{bbox}");
+        assert_eq!(response.content_type, "text/plain; charset=utf-8");
+    }
+}
+
+#[test]
+fn tiles() {
+    let world = Tile::new(0, 0, 0).unwrap().bbox();
+    assert_eq!(*world.x(), -1_800_000_000);
+    assert_eq!(*world.x_end(), 1_800_000_000);
+    assert!((*world.y_end() as f64 / 1e7 - 85.0511).abs() < 1e-3);
+
+    let north_east = Tile::new(1, 1, 0).unwrap().bbox();
+    assert_eq!((*north_east.x(), *north_east.y()), (0, 0));
+    assert!(Tile::new(1, 2, 0).is_none());
+
+    let viewport = Viewport::of_tile(Tile::new(1, 1, 0).unwrap(), 256.0);
+    let (x, y) = viewport.project((0, 0));
+    assert!(x.abs() < 1e-6 && (y - 256.0).abs() < 1e-6);
+
+    //the tile with the cafe in it
+    let tile = |format: &str| handle(&format!("/tiles/16/34588/22226.{format}"), &mut town());
+
+    let mvt = tile("mvt");
+    assert_eq!(mvt.status, 200);
+    assert_eq!(mvt.content_type, "application/vnd.mapbox-vector-tile");
+    //a layer, then its version
+    assert_eq!(mvt.body[0], 0x1a);
+    let layer_names = ["nodes", "ways"].map(|name| {
+        mvt.body
+            .windows(name.len())
+            .any(|w| w == name.as_bytes())
+    });
+    assert_eq!(layer_names, [true, true]);
+
+    let png = tile("png");
+    assert_eq!(png.status, 200);
+    let (width, height, pixels) = decode_png(&png.body);
+    assert_eq!((width, height), (256, 256));
+    assert!(pixels.chunks(4).any(|p| p != [242, 239, 233, 255]));
+
+    //too far out to load, so nothing is drawn
+    let empty = handle("/tiles/2/2/1.mvt", &mut town());
+    assert_eq!((empty.status, empty.body.len()), (200, 0));
+
+    assert_eq!(handle("/tiles/1/2/0.mvt", &mut town()).status, 400);
+    assert_eq!(handle("/tiles/16/34588/22226.jpg", &mut town()).status, 404);
+}
+
+#[test]
+fn render() {
+    let response = handle(&format!("/render.png?{BBOX}&width=300&height=200"), &mut town());
+    assert_eq!(response.status, 200);
+    assert_eq!(response.content_type, "image/png");
+
+    let (width, height, pixels) = decode_png(&response.body);
+    assert_eq!((width, height), (300, 200));
+
+    //the primary road crosses the middle
+    let column: Vec<_> = (0..height)
+        .map(|y| &pixels[((y * width + 150) * 4) as usize..][..4])
+        .collect();
+    assert!(column.contains(&&[247, 184, 120, 255][..]));
+
+    let too_big = handle(&format!("/render.png?{BBOX}&width=100000"), &mut town());
+    assert_eq!(too_big.status, 400);
+}
+
+fn decode_png(bytes: &[u8]) -> (u32, u32, Vec<u8>) {
+    let mut reader = png::Decoder::new(bytes).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+
+    (info.width, info.height, pixels)
+}
@@ -0,0 +1,99 @@
+use std::f64::consts::PI;
+
+use tree::{
+    bbox::BoundingBox,
+    geo::{decimicro_to_degrees, degrees_to_decimicro},
+};
+
+/// The furthest latitude which Web Mercator shows.
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+/// A Web Mercator (slippy map) tile.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tile {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Tile {
+    /// `None` if `x` or `y` is outside the grid at zoom `z`.
+    pub fn new(z: u8, x: u32, y: u32) -> Option<Self> {
+        let size = 1u64 << z.min(31);
+        (z <= 31 && (x as u64) < size && (y as u64) < size).then_some(Tile { z, x, y })
+    }
+
+    pub fn bbox(&self) -> BoundingBox<i32> {
+        let size = (1u64 << self.z) as f64;
+
+        let (west, north) = unproject(self.x as f64 / size, self.y as f64 / size);
+        let (east, south) = unproject((self.x + 1) as f64 / size, (self.y + 1) as f64 / size);
+
+        let (x, y) = degrees_to_decimicro(west, south);
+        let (x_end, y_end) = degrees_to_decimicro(east, north);
+
+        BoundingBox::new(x, y, x_end, y_end)
+    }
+}
+
+/// Where on a flat, `width` by `height` canvas a point in decimicro degrees goes, with
+/// `y` pointing down.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    /// Web Mercator position of the top left, with the whole world in `0..1`
+    origin: (f64, f64),
+    /// Canvas units per Web Mercator unit
+    scale: (f64, f64),
+}
+
+impl Viewport {
+    pub fn of_tile(tile: Tile, extent: f64) -> Self {
+        let size = (1u64 << tile.z) as f64;
+
+        Viewport {
+            origin: (tile.x as f64 / size, tile.y as f64 / size),
+            scale: (extent * size, extent * size),
+        }
+    }
+
+    pub fn of_bbox(bbox: &BoundingBox<i32>, width: f64, height: f64) -> Self {
+        let (west, north) = project((*bbox.x(), *bbox.y_end()));
+        let (east, south) = project((*bbox.x_end(), *bbox.y()));
+
+        Viewport {
+            origin: (west, north),
+            scale: (
+                width / (east - west).max(f64::EPSILON),
+                height / (south - north).max(f64::EPSILON),
+            ),
+        }
+    }
+
+    pub fn project(&self, point: (i32, i32)) -> (f64, f64) {
+        let (x, y) = project(point);
+
+        (
+            (x - self.origin.0) * self.scale.0,
+            (y - self.origin.1) * self.scale.1,
+        )
+    }
+}
+
+/// A point in decimicro degrees to Web Mercator, with the world in `0..1`.
+fn project(point: (i32, i32)) -> (f64, f64) {
+    let (lon, lat) = decimicro_to_degrees(point);
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+
+    let x = (lon + 180.0) / 360.0;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0;
+
+    (x, y)
+}
+
+/// Web Mercator, with the world in `0..1`, to `(lon, lat)` in degrees.
+fn unproject(x: f64, y: f64) -> (f64, f64) {
+    let lon = x * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees();
+
+    (lon, lat)
+}
@@ -0,0 +1,41 @@
+use std::env;
+
+use clap::Parser;
+use offline_tiny_maps::compact::compact;
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let stats = match compact(&state_dir) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("{} couldn't be compacted, and wasn't changed: {e}", state_dir.display());
+            std::process::exit(1);
+        }
+    };
+
+    println!("{} objects rewritten", stats.objects);
+    println!(
+        "fields: {} live, {} -> {} bytes",
+        stats.live_fields, stats.fields_bytes.0, stats.fields_bytes.1
+    );
+    match (stats.live_values, &stats.values_kept) {
+        (Some(live), _) => println!(
+            "values: {live} live, {} -> {} bytes",
+            stats.values_bytes.0, stats.values_bytes.1
+        ),
+        (None, Some(reason)) => println!("values: kept as they were, since {reason}"),
+        (None, None) => {}
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// directory the map was compressed to. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
@@ -0,0 +1,396 @@
+use std::{collections::BTreeMap, env, fs::File, path::Path};
+
+use clap::{Parser, Subcommand};
+use minimal_storage::pooled_storage::{Pool, ValueLocation};
+use osm_tag_compression::compressed_data::{flattened_id, OsmObjectType, UncompressedOsmData};
+use osm_value_atom::LiteralValue;
+use osmpbfreader::{NodeId, OsmId, RelationId, WayId};
+use overpass::map::stored_id;
+use serde_json::{json, Value};
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    dense::{inspect::NodeSummary, StoredTree},
+    geo::decimicro_to_degrees,
+    open_tree_dense, open_tree_sparse,
+};
+
+const CACHE_SATURATION: usize = 4_000;
+const DATA_SATURATION: usize = 8_000;
+
+type Geography = StoredTree<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>;
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let geography = open_tree_dense::<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>(
+        state_dir.join("geography"),
+        EARTH_BBOX,
+    );
+
+    let report = match args.command {
+        Command::Tree { top } => tree_report(&geography, top),
+        Command::Page { id, limit } => page_report(&geography, id, limit),
+        Command::Object { id } => object_report(&state_dir, &geography, &id),
+        Command::Pool { top } => pool_report(&state_dir, &geography, top),
+    };
+
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.json {
+        println!("{report}");
+    } else {
+        print_human(&report, 0);
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// print the report as JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// directory the map was compressed to. Default: `.map`
+    #[arg(short, long, global = true)]
+    map: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// the geography tree's shape: nodes per depth, how full leaves are, and the largest
+    Tree {
+        /// how many of the largest leaves to list
+        #[arg(long, default_value = "10")]
+        top: usize,
+    },
+    /// one node's page: the physical pages it's spread over and the objects in it
+    Page {
+        /// the page's position in the file, as `tree` and `object` report it
+        id: usize,
+
+        /// how many of the page's objects to list
+        #[arg(long, default_value = "50")]
+        limit: usize,
+    },
+    /// where an object is stored, as `node/1`, `way/2` or `relation/3`
+    Object { id: String },
+    /// the pooled values referenced most by ways, and how each is encoded
+    Pool {
+        /// how many values to list
+        #[arg(long, default_value = "20")]
+        top: usize,
+    },
+}
+
+fn tree_report(geography: &Geography, top: usize) -> Result<Value, String> {
+    let nodes = geography.nodes();
+
+    let mut depths = BTreeMap::<usize, (usize, usize, usize)>::new();
+    for node in nodes.iter() {
+        let (count, leaves, entries) = depths.entry(node.depth).or_default();
+        *count += 1;
+        *leaves += node.is_leaf as usize;
+        *entries += node.children;
+    }
+
+    //in tenths of the saturation point, which is when a leaf splits
+    let mut fill = [0usize; 11];
+    let leaves: Vec<_> = nodes.iter().filter(|n| n.is_leaf).collect();
+    for leaf in leaves.iter() {
+        fill[(leaf.children * 10 / DATA_SATURATION).min(10)] += 1;
+    }
+    let mean_fill = leaves.iter().map(|n| n.children).sum::<usize>() as f64
+        / (leaves.len().max(1) * DATA_SATURATION) as f64;
+
+    let mut largest = leaves.clone();
+    largest.sort_by_key(|n| std::cmp::Reverse(n.children));
+
+    Ok(json!({
+        "nodes": nodes.len(),
+        "leaves": leaves.len(),
+        "entries": nodes.iter().map(|n| n.children).sum::<usize>(),
+        "depths": depths.iter().map(|(depth, (count, leaves, entries))| json!({
+            "depth": depth,
+            "nodes": count,
+            "leaves": leaves,
+            "entries": entries,
+        })).collect::<Vec<_>>(),
+        "leaf_fill": {
+            "mean": mean_fill,
+            "histogram": fill.iter().enumerate().map(|(tenth, count)| json!({
+                "from_percent": tenth * 10,
+                "leaves": count,
+            })).collect::<Vec<_>>(),
+        },
+        "largest_leaves": largest.iter().take(top).map(|n| node_json(n)).collect::<Vec<_>>(),
+    }))
+}
+
+fn page_report(geography: &Geography, id: usize, limit: usize) -> Result<Value, String> {
+    let node = find_node_by_page(geography, id).ok_or_else(|| {
+        format!("page {id} isn't the first page of any node; `tree` lists nodes' pages")
+    })?;
+    let contents = geography
+        .node_contents(node.id)
+        .ok_or_else(|| format!("page {id} couldn't be read"))?;
+
+    let objects: Vec<_> = contents
+        .entries
+        .iter()
+        .take(limit)
+        .map(|(bbox, data)| {
+            json!({
+                "id": stored_id(data).map(osm_id_name),
+                "type": data.determine_type().map(type_name),
+                "bbox": bbox_json(bbox),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "node": node_json(&node),
+        "component_pages": contents.component_pages.iter().map(|p| p.index()).collect::<Vec<_>>(),
+        "object_count": contents.entries.len(),
+        "objects": objects,
+    }))
+}
+
+fn object_report(state_dir: &Path, geography: &Geography, id: &str) -> Result<Value, String> {
+    let osm_id = parse_osm_id(id)?;
+
+    let bboxes = open_tree_sparse::<1, CACHE_SATURATION, u64, BoundingBox<i32>>(
+        state_dir.join("tmp.bboxes"),
+        0..=u64::MAX,
+    );
+    let bbox = bboxes
+        .get_owned(&flattened_id(&osm_id))
+        .ok_or_else(|| format!("{id} isn't in the map"))?;
+
+    //objects are kept in the deepest node which contains them, so only those can have it
+    let candidates = geography
+        .nodes()
+        .into_iter()
+        .filter(|n| n.page_id.is_some() && n.bbox.contains(&bbox));
+
+    for node in candidates {
+        let Some(contents) = geography.node_contents(node.id) else {
+            continue;
+        };
+        let Some(position) = contents
+            .entries
+            .iter()
+            .position(|(_, data)| stored_id(data) == Some(osm_id))
+        else {
+            continue;
+        };
+
+        return Ok(json!({
+            "id": osm_id_name(osm_id),
+            "bbox": bbox_json(&bbox),
+            "node": node_json(&node),
+            "component_pages": contents.component_pages.iter().map(|p| p.index()).collect::<Vec<_>>(),
+            "position_in_page": position,
+            "objects_in_page": contents.entries.len(),
+        }));
+    }
+
+    Err(format!("{id} has a bbox, but isn't stored in any node containing it"))
+}
+
+fn pool_report(state_dir: &Path, geography: &Geography, top: usize) -> Result<Value, String> {
+    let file = File::open(state_dir.join("values")).map_err(|e| e.to_string())?;
+    let pool = Pool::<LiteralValue>::open(Box::new(file)).map_err(|e| e.to_string())?;
+
+    //only ways' tags are read through the pool, so decoding every way counts every reference
+    pool.count_references();
+    let mut ways = 0;
+    let mut undecodable = 0;
+    for node in geography.nodes().iter().filter(|n| n.page_id.is_some()) {
+        let Some(contents) = geography.node_contents(node.id) else {
+            continue;
+        };
+
+        for (bbox, data) in contents.entries {
+            if data.determine_type() != Some(OsmObjectType::Way) {
+                continue;
+            }
+            ways += 1;
+            if data.compress(&bbox, &pool).is_err() {
+                undecodable += 1;
+            }
+        }
+    }
+    let counts = pool.take_reference_counts();
+
+    let mut most_used: Vec<_> = counts.iter().map(|(id, count)| (*id, *count)).collect();
+    most_used.sort_by_key(|(id, count)| (std::cmp::Reverse(*count), *id));
+
+    let mut values = Vec::new();
+    for (id, references) in most_used.into_iter().take(top) {
+        let value = pool.get(id, ()).map_err(|e| e.to_string())?.map(|v| v.to_string());
+        let location = pool.value_location(id).map_err(|e| e.to_string())?;
+
+        let encoding = match location {
+            Some(ValueLocation::Inlined { bytes }) => json!({
+                "inlined": true,
+                "bytes": bytes,
+            }),
+            Some(ValueLocation::Pooled {
+                index,
+                block,
+                offset,
+                length,
+            }) => json!({
+                "inlined": false,
+                "index": index,
+                "block": block,
+                "offset": offset,
+                "length": length,
+            }),
+            Some(ValueLocation::Compressed {
+                index,
+                block,
+                offset,
+                length,
+            }) => json!({
+                "inlined": false,
+                "compressed": true,
+                "index": index,
+                "block": block,
+                "offset": offset,
+                "length": length,
+            }),
+            None => Value::Null,
+        };
+
+        values.push(json!({
+            "id": id,
+            "references": references,
+            "value": value,
+            "encoding": encoding,
+        }));
+    }
+
+    Ok(json!({
+        "ways": ways,
+        "undecodable_ways": undecodable,
+        "distinct_values": counts.len(),
+        "inlined_values": counts.keys().filter(|id| *id & 1 == 0).count(),
+        "references": counts.values().sum::<usize>(),
+        "most_referenced": values,
+    }))
+}
+
+fn find_node_by_page(geography: &Geography, page: usize) -> Option<NodeSummary<BoundingBox<i32>>> {
+    geography
+        .nodes()
+        .into_iter()
+        .find(|n| n.page_id.is_some_and(|p| p.index() == page))
+}
+
+fn node_json(node: &NodeSummary<BoundingBox<i32>>) -> Value {
+    json!({
+        "id": node.id,
+        "depth": node.depth,
+        "leaf": node.is_leaf,
+        "page": node.page_id.map(|p| p.index()),
+        "entries": node.children,
+        "bbox": bbox_json(&node.bbox),
+    })
+}
+
+/// `[west, south, east, north]` in degrees
+fn bbox_json(bbox: &BoundingBox<i32>) -> Value {
+    let (west, south) = decimicro_to_degrees((*bbox.x(), *bbox.y()));
+    let (east, north) = decimicro_to_degrees((*bbox.x_end(), *bbox.y_end()));
+
+    json!([west, south, east, north])
+}
+
+fn parse_osm_id(id: &str) -> Result<OsmId, String> {
+    let invalid = || format!("`{id}` isn't `node/<id>`, `way/<id>` or `relation/<id>`");
+
+    let (kind, number) = id.split_once('/').ok_or_else(invalid)?;
+    let number: i64 = number.parse().map_err(|_| invalid())?;
+
+    match kind {
+        "node" => Ok(OsmId::Node(NodeId(number))),
+        "way" => Ok(OsmId::Way(WayId(number))),
+        "relation" => Ok(OsmId::Relation(RelationId(number))),
+        _ => Err(invalid()),
+    }
+}
+
+fn osm_id_name(id: OsmId) -> String {
+    match id {
+        OsmId::Node(NodeId(n)) => format!("node/{n}"),
+        OsmId::Way(WayId(n)) => format!("way/{n}"),
+        OsmId::Relation(RelationId(n)) => format!("relation/{n}"),
+    }
+}
+
+fn type_name(t: OsmObjectType) -> &'static str {
+    match t {
+        OsmObjectType::Node => "node",
+        OsmObjectType::Way => "way",
+        OsmObjectType::Relation => "relation",
+    }
+}
+
+/// Indented `key: value` lines, with short arrays of plain values kept on one line.
+fn print_human(value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+
+    match value {
+        Value::Object(fields) => {
+            for (k, v) in fields {
+                if is_inline(v) {
+                    println!("{pad}{k}: {}", inline(v));
+                } else {
+                    println!("{pad}{k}:");
+                    print_human(v, indent + 1);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                if is_inline(item) {
+                    println!("{pad}- {}", inline(item));
+                } else {
+                    println!("{pad}-");
+                    print_human(item, indent + 1);
+                }
+            }
+        }
+        v => println!("{pad}{}", inline(v)),
+    }
+}
+
+fn is_inline(value: &Value) -> bool {
+    match value {
+        Value::Object(_) => false,
+        Value::Array(items) => items.iter().all(|i| !i.is_object() && !i.is_array()),
+        _ => true,
+    }
+}
+
+fn inline(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".into(),
+        Value::Array(items) => items.iter().map(inline).collect::<Vec<_>>().join(", "),
+        v => v.to_string(),
+    }
+}
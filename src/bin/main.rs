@@ -1,7 +1,7 @@
 use std::{env, fs::File, io::Write};
 
 use clap::Parser;
-use offline_tiny_maps::compressor::Compressor;
+use offline_tiny_maps::compressor::{Compressor, CompressorConfig};
 
 use osmpbfreader::blobs::result_blob_into_iter;
 
@@ -18,7 +18,12 @@ fn main() {
         .unwrap()
         .join(args.output.unwrap_or(".map".into()));
 
-    let mut compressor = Compressor::new(&state_dir);
+    let mut compressor = Compressor::with_config(
+        &state_dir,
+        CompressorConfig {
+            dictionary_size: args.dictionary_size,
+        },
+    );
 
     //we need to make a new reader in order to get the blob count, but this iterator is much faster than anything else b/c it doesn't need to
     //decompress or process
@@ -60,16 +65,92 @@ fn main() {
 
     println!("moving on to the incomplete relations");
 
-    let incompleted_relations = compressor.attempt_retry_queue();
+    let incompleted_relations: Vec<_> = compressor.attempt_retry_queue().collect();
+
+    println!("applying turn restrictions");
+
+    let malformed_restrictions = compressor.apply_turn_restrictions();
+
+    println!("building administrative boundaries");
+
+    let unclosed_boundaries = compressor.build_admin_areas();
+
+    println!("interpolating addresses");
+
+    let unnumbered_interpolations = compressor.build_address_index();
 
     let mut incomplete_file =
-        std::fs::File::create(&state_dir.join("incomplete_relations.note")).unwrap();
+        std::fs::File::create(state_dir.join("incomplete_relations.note")).unwrap();
 
     writeln!(&mut incomplete_file, "Incomplete relations:").unwrap();
     for item in incompleted_relations {
         writeln!(&mut incomplete_file, "{:?}", item.id()).unwrap();
     }
 
+    let mut report_file =
+        std::fs::File::create(state_dir.join("ingest_report.note")).unwrap();
+
+    writeln!(&mut report_file, "Malformed turn restrictions:").unwrap();
+    for (id, reason) in malformed_restrictions {
+        writeln!(&mut report_file, "{:?}: {reason}", id).unwrap();
+    }
+
+    writeln!(&mut report_file, "\nAdministrative boundaries which aren't closed:").unwrap();
+    for id in unclosed_boundaries {
+        writeln!(&mut report_file, "{:?}", id).unwrap();
+    }
+
+    writeln!(&mut report_file, "\nAddress interpolations without numbered ends:").unwrap();
+    for id in unnumbered_interpolations {
+        writeln!(&mut report_file, "{:?}", id).unwrap();
+    }
+
+    writeln!(&mut report_file, "\nDeduplicated pool inserts:").unwrap();
+    let (literals, values) = compressor.dedup_stats();
+    for (pool, stats) in [("literals", literals), ("values", values)] {
+        writeln!(
+            &mut report_file,
+            "{pool}: {} of {} ({:.1}%)",
+            stats.deduplicated,
+            stats.stored + stats.deduplicated,
+            stats.hit_rate() * 100.0
+        )
+        .unwrap();
+    }
+
+    if args.dictionary_size.is_some() {
+        println!("Compressing with a trained dictionary...");
+    }
+
+    match compressor.compress_with_dictionary() {
+        Ok(None) => {}
+        Ok(Some(compression)) => {
+            writeln!(
+                &mut report_file,
+                "\nDictionary compression ({} byte dictionary):",
+                compression.dictionary_size
+            )
+            .unwrap();
+            for (part, stats) in [
+                ("literals", compression.literals),
+                ("values", compression.values),
+                ("geography", compression.geography),
+            ] {
+                writeln!(
+                    &mut report_file,
+                    "{part}: {} -> {} bytes ({:.1}%)",
+                    stats.uncompressed,
+                    stats.compressed,
+                    stats.ratio() * 100.0
+                )
+                .unwrap();
+            }
+        }
+        Err(e) => {
+            writeln!(&mut report_file, "\nDictionary compression failed: {e}").unwrap();
+        }
+    }
+
     println!("Garbage collecting and compressing...");
     compressor.flush_to_storage().unwrap();
 }
@@ -81,4 +162,8 @@ struct Args {
 
     /// directory to output data to. Default: `.map`
     output: Option<String>,
+
+    /// train a zstd dictionary of at most this many bytes and compress the map with it
+    #[arg(long)]
+    dictionary_size: Option<usize>,
 }
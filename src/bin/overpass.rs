@@ -0,0 +1,53 @@
+use std::{
+    env, fs,
+    io::{self, Read},
+};
+
+use clap::Parser;
+use overpass::{output::write_output, parse, run, MapSource};
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let text = if args.query == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .expect("Unable to read the query from stdin");
+        text
+    } else {
+        fs::read_to_string(&args.query).expect("Unable to read the query file")
+    };
+
+    let query = match parse(&text) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let source = MapSource::open(&state_dir).expect("Unable to open the map");
+
+    let output = run(&query, &mut &source);
+
+    write_output(&output, &mut io::stdout().lock()).unwrap();
+
+    if source.undecodable() > 0 {
+        eprintln!("{} objects couldn't be decoded and were left out", source.undecodable());
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// file containing the Overpass QL query, or `-` to read it from stdin
+    query: String,
+
+    /// directory the map was compressed to, or a `.tmap` it was packed into. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
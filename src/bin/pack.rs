@@ -0,0 +1,35 @@
+use std::env;
+
+use clap::Parser;
+use offline_tiny_maps::pack::pack;
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let stats = match pack(&state_dir, args.output.as_ref()) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("{} couldn't be packed: {e}", state_dir.display());
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "{} files packed into {}: {} -> {} bytes",
+        stats.sections, args.output, stats.bytes.0, stats.bytes.1
+    );
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// `.tmap` file to write the map to
+    output: String,
+
+    /// directory the map was compressed to. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
@@ -0,0 +1,78 @@
+use std::env;
+
+use clap::Parser;
+use routing::{Profile, RoadGraph};
+use tree::geo::{decimicro_to_degrees, degrees_to_decimicro};
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let profile = Profile::from_name(&args.profile).expect("Profile must be car, bike, foot, or hgv");
+
+    let graph = RoadGraph::open(state_dir.join("routing"));
+
+    let Some(route) = graph.route(args.from.0, args.to.0, profile) else {
+        eprintln!("No route found");
+        std::process::exit(1);
+    };
+
+    let coordinates = route
+        .geometry
+        .iter()
+        .map(|p| {
+            let (lon, lat) = decimicro_to_degrees(*p);
+            format!("[{lon},{lat}]")
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let ways = route
+        .ways
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    println!(
+        r#"{{"type":"Feature","geometry":{{"type":"LineString","coordinates":[{coordinates}]}},"properties":{{"length_meters":{:.1},"duration_seconds":{:.1},"ways":[{ways}]}}}}"#,
+        route.length_meters, route.duration_seconds
+    );
+}
+
+#[derive(Clone, Debug)]
+struct Coordinate((i32, i32));
+
+impl std::str::FromStr for Coordinate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lon, lat) = s.split_once(',').ok_or("expected `lon,lat`")?;
+        let lon: f64 = lon.trim().parse().map_err(|e| format!("{e}"))?;
+        let lat: f64 = lat.trim().parse().map_err(|e| format!("{e}"))?;
+
+        Ok(Coordinate(degrees_to_decimicro(lon, lat)))
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// start of the route, as `lon,lat`
+    #[arg(allow_hyphen_values = true)]
+    from: Coordinate,
+
+    /// end of the route, as `lon,lat`
+    #[arg(allow_hyphen_values = true)]
+    to: Coordinate,
+
+    /// one of `car`, `bike`, `foot`, or `hgv`
+    #[arg(short, long, default_value = "car")]
+    profile: String,
+
+    /// directory the map was compressed to. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
@@ -0,0 +1,38 @@
+use std::{env, thread::available_parallelism};
+
+use clap::Parser;
+use overpass::MapSource;
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let map = MapSource::open(&state_dir).expect("Unable to open the map");
+
+    let threads = args
+        .threads
+        .unwrap_or_else(|| available_parallelism().map(|n| n.get()).unwrap_or(1));
+    let address = format!("127.0.0.1:{}", args.port);
+
+    eprintln!("Serving {} on http://{address}", state_dir.display());
+
+    server::serve(&map, &address, threads).expect("Unable to serve");
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// port to listen on, on localhost
+    #[arg(short, long, default_value = "8080")]
+    port: u16,
+
+    /// how many requests to answer at once. Default: the number of CPUs
+    #[arg(short, long)]
+    threads: Option<usize>,
+
+    /// directory the map was compressed to, or a `.tmap` it was packed into. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
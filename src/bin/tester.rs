@@ -20,7 +20,7 @@ fn bingbong() {
     let paged: PagedStorage<
         8,
         tree::dense::structure::Inner<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>,
-    > = PagedStorage::open(File::open(".map/geography/data").unwrap());
+    > = PagedStorage::open(File::open(".map/geography/data").unwrap()).unwrap();
 
     //given is 2767
 
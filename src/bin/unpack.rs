@@ -0,0 +1,30 @@
+use std::env;
+
+use clap::Parser;
+use offline_tiny_maps::pack::unpack;
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    match unpack(args.tmap.as_ref(), &state_dir) {
+        Ok(files) => println!("{files} files unpacked into {}", state_dir.display()),
+        Err(e) => {
+            eprintln!("{} couldn't be unpacked: {e}", args.tmap);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// `.tmap` file to unpack
+    tmap: String,
+
+    /// directory to unpack the map to, which mustn't have anything in it. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
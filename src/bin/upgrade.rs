@@ -0,0 +1,35 @@
+use std::env;
+
+use clap::Parser;
+use offline_tiny_maps::upgrade::upgrade;
+
+fn main() {
+    let args = Args::parse();
+
+    let state_dir = env::current_dir()
+        .unwrap()
+        .join(args.map.unwrap_or(".map".into()));
+
+    let report = match upgrade(&state_dir) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{} couldn't be upgraded: {e}", state_dir.display());
+            std::process::exit(1);
+        }
+    };
+
+    if report.steps.is_empty() {
+        println!("{} is already in format version {}", state_dir.display(), report.from);
+    }
+    for (version, step) in (report.from..).zip(report.steps) {
+        println!("format version {version} -> {}: {step}", version + 1);
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// directory the map was compressed to, which has to be unpacked first if it's a
+    /// `.tmap`. Default: `.map`
+    #[arg(short, long)]
+    map: Option<String>,
+}
@@ -0,0 +1,394 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs::File,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use minimal_storage::{
+    dedup_index::DedupIndex,
+    pooled_storage::{Pool, PooledId},
+    provider::Directory,
+    serialize_min::{DeserializeFromMinimal, SerializeMinimal},
+};
+use osm_tag_compression::{
+    compressed_data::{CompressedOsmData, OsmObjectType, UncompressedOsmData},
+    field::Field,
+    manifest::Manifest,
+};
+use osm_value_atom::LiteralValue;
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    dense::StoredTree,
+};
+
+const DATA_SATURATION: usize = 8_000;
+
+type Geography = StoredTree<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>;
+
+/// The map's parts which `compact` can rewrite. Each is built next to the old one,
+/// under `NEW_SUFFIX`, and only swapped in once they've all been built.
+const REWRITTEN: [&str; 5] = ["geography", "literals", "values", "literals.index", "values.index"];
+const NEW_SUFFIX: &str = "compact";
+const OLD_SUFFIX: &str = "old";
+
+/// What `compact` did, for its report
+#[derive(Clone, Debug, Default)]
+pub struct CompactStats {
+    pub objects: usize,
+    /// How many fields and values are written to the pools after compacting. Values
+    /// small enough to be inlined into their ids aren't counted.
+    pub live_fields: usize,
+    pub live_values: Option<usize>,
+    /// Why the value pool was kept as it was, if it was
+    pub values_kept: Option<String>,
+    /// The size in bytes of the field pool and of the value pool, before and after
+    pub fields_bytes: (u64, u64),
+    pub values_bytes: (u64, u64),
+}
+
+/// The map's objects and value pool as they are before compacting, and its live fields
+struct Map {
+    dir: PathBuf,
+    geography: Geography,
+    values: Pool<LiteralValue>,
+    /// How many objects refer to each field, by id
+    field_references: HashMap<PooledId, usize>,
+    /// The bytes of each field which is referred to, unless it's inlined into its id
+    live_fields: HashMap<PooledId, Vec<u8>>,
+}
+
+/// Rewrite the map's pools with only the values which its objects still refer to, most
+/// referred to first, and rewrite its objects to refer to them. Values which were
+/// stored twice end up stored once.
+///
+/// The fields of nodes and relations are copied as they were stored. The value pool
+/// can only be rewritten if every way and every field which refers to it decodes, and
+/// reads back the same once it's written again; otherwise it's kept as it is, and so
+/// are the ways, and `CompactStats::values_kept` says why.
+pub fn compact(state_dir: &Path) -> io::Result<CompactStats> {
+    //the values are decoded and written again, which only works in this build's encoding
+    Manifest::check(&Directory::new(state_dir))?;
+    let map = Map::open(state_dir)?;
+
+    let mut stats = CompactStats {
+        fields_bytes: (std::fs::metadata(map.path("literals", None))?.len(), 0),
+        values_bytes: (std::fs::metadata(map.path("values", None))?.len(), 0),
+        ..Default::default()
+    };
+
+    map.remove_new()?;
+    match map.rewrite_with_values()? {
+        Ok((objects, live_fields, live_values)) => {
+            stats.objects = objects;
+            stats.live_fields = live_fields;
+            stats.live_values = Some(live_values);
+        }
+        Err(reason) => {
+            map.remove_new()?;
+            (stats.objects, stats.live_fields) = map.rewrite_fields_only()?;
+            stats.values_kept = Some(reason);
+        }
+    }
+    drop(map);
+
+    //the old parts are only removed once every new one is in place
+    let path = |name: &str, suffix: &str| state_dir.join(format!("{name}.{suffix}"));
+    for name in REWRITTEN {
+        if !path(name, NEW_SUFFIX).exists() {
+            continue;
+        }
+        remove(&path(name, OLD_SUFFIX))?;
+        rename_if_exists(&state_dir.join(name), &path(name, OLD_SUFFIX))?;
+        std::fs::rename(path(name, NEW_SUFFIX), state_dir.join(name))?;
+    }
+    for name in REWRITTEN {
+        remove(&path(name, OLD_SUFFIX))?;
+    }
+
+    stats.fields_bytes.1 = std::fs::metadata(state_dir.join("literals"))?.len();
+    stats.values_bytes.1 = std::fs::metadata(state_dir.join("values"))?.len();
+
+    Ok(stats)
+}
+
+impl Map {
+    /// Open the map, and find which fields its nodes and relations refer to
+    fn open(dir: &Path) -> io::Result<Self> {
+        let geography = Geography::open(EARTH_BBOX, dir.join("geography"))?;
+        let fields = Pool::<Field>::open_flushed(Box::new(File::open(dir.join("literals"))?))?;
+        let values = Pool::<LiteralValue>::open(Box::new(File::open(dir.join("values"))?))?;
+
+        let mut field_references = HashMap::<PooledId, usize>::new();
+        for (_, data) in geography.find_entries_in_box(geography.root_bbox()) {
+            for id in data.decompress_pooled_field_ids()? {
+                *field_references.entry(id).or_default() += 1;
+            }
+        }
+
+        let mut live_fields = HashMap::new();
+        for field in fields.serialized_values() {
+            let (id, blob) = field?;
+            if field_references.contains_key(&id) {
+                live_fields.insert(id, blob);
+            }
+        }
+
+        let missing = field_references
+            .keys()
+            .find(|id| **id & 1 == 1 && !live_fields.contains_key(id));
+        if let Some(id) = missing {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("pooled field {id} doesn't exist"),
+            ));
+        }
+
+        Ok(Map {
+            dir: dir.to_path_buf(),
+            geography,
+            values,
+            field_references,
+            live_fields,
+        })
+    }
+
+    fn path(&self, name: &str, suffix: Option<&str>) -> PathBuf {
+        match suffix {
+            Some(suffix) => self.dir.join(format!("{name}.{suffix}")),
+            None => self.dir.join(name),
+        }
+    }
+
+    /// Remove whatever an earlier attempt left half built
+    fn remove_new(&self) -> io::Result<()> {
+        for name in REWRITTEN {
+            remove(&self.path(name, Some(NEW_SUFFIX)))?;
+        }
+        Ok(())
+    }
+
+    /// A new, empty pool to replace the one called `name`. It has its own dedup index,
+    /// since values are inserted again long after they've left its recently written
+    /// values.
+    fn new_pool<T: SerializeMinimal>(&self, name: &str) -> io::Result<Pool<T>> {
+        let file = File::options()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(self.path(name, Some(NEW_SUFFIX)))?;
+        let mut pool = Pool::new(Box::new(file))?;
+
+        let index = DedupIndex::open(self.path(&format!("{name}.index"), Some(NEW_SUFFIX)))?;
+        pool.use_dedup_index(index)?;
+
+        Ok(pool)
+    }
+
+    /// The fields' ids, most referred to first
+    fn field_order(&self) -> Vec<PooledId> {
+        let mut order: Vec<_> = self.field_references.iter().map(|(id, count)| (*id, *count)).collect();
+        order.sort_by_key(|(id, count)| (Reverse(*count), *id));
+        order.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Write a new geography tree, with each object as `rewrite` makes it. Returns how
+    /// many objects there are, or why one of them couldn't be rewritten.
+    fn rewrite_geography(
+        &self,
+        mut rewrite: impl FnMut(&BoundingBox<i32>, UncompressedOsmData) -> io::Result<Result<UncompressedOsmData, String>>,
+    ) -> io::Result<Result<usize, String>> {
+        let dir = self.path("geography", Some(NEW_SUFFIX));
+        std::fs::create_dir_all(&dir)?;
+        let mut geography = Geography::open(EARTH_BBOX, dir)?;
+        geography.expand_to_depth(5);
+
+        let mut objects = 0;
+        for (bbox, data) in self.geography.find_entries_in_box(self.geography.root_bbox()) {
+            let data = match rewrite(&bbox, data)? {
+                Ok(data) => data,
+                Err(reason) => return Ok(Err(reason)),
+            };
+            geography.insert(&bbox, data);
+            objects += 1;
+        }
+
+        geography.flush()?;
+        Ok(Ok(objects))
+    }
+
+    /// Copy the live fields to a new field pool as they were stored, and leave the value
+    /// pool and the ways as they are. Returns how many objects and fields there are.
+    fn rewrite_fields_only(&self) -> io::Result<(usize, usize)> {
+        let fields = self.new_pool::<Field>("literals")?;
+
+        //fields small enough to be inlined into their ids aren't in the pool
+        let mut new_ids = HashMap::with_capacity(self.field_references.len());
+        for id in self.field_order() {
+            let new_id = match self.live_fields.get(&id) {
+                Some(blob) => fields.insert_serialized(blob)?,
+                None => id,
+            };
+            new_ids.insert(id, new_id);
+        }
+
+        let objects = self.rewrite_geography(|_, data| {
+            let ids: Vec<_> = data.decompress_pooled_field_ids()?.iter().map(|id| new_ids[id]).collect();
+            data.with_pooled_field_ids(&ids).map(Ok)
+        })?;
+
+        fields.flush()?;
+
+        //nothing's decoded, so every object can be rewritten
+        Ok((objects.unwrap(), fields.dedup_stats().stored))
+    }
+
+    /// Rewrite both pools, and every object. Returns how many objects, fields and values
+    /// there are, or why the value pool can't be rewritten.
+    fn rewrite_with_values(&self) -> io::Result<Result<(usize, usize, usize), String>> {
+        //ways refer to values directly, and nodes and relations refer to fields, which
+        //refer to values in turn
+        self.values.count_references();
+        for (bbox, data) in self.geography.find_entries_in_box(self.geography.root_bbox()) {
+            if data.determine_type() == Some(OsmObjectType::Way) {
+                if let Err(reason) = decode_way(data, &bbox, &self.values)? {
+                    return Ok(Err(reason));
+                }
+            }
+        }
+        let mut value_references = self.values.take_reference_counts();
+
+        //each field's values are as live as the field itself
+        let mut fields = HashMap::with_capacity(self.field_references.len());
+        for (id, references) in self.field_references.iter() {
+            self.values.count_references();
+            let field = match decode_field(*id, self.live_fields.get(id), &self.values) {
+                Ok(field) => field,
+                Err(reason) => return Ok(Err(reason)),
+            };
+            for (value, count) in self.values.take_reference_counts() {
+                *value_references.entry(value).or_default() += count * references;
+            }
+            fields.insert(*id, field);
+        }
+
+        let new_pools = (
+            self.new_pool::<Field>("literals")?,
+            self.new_pool::<LiteralValue>("values")?,
+        );
+
+        //the most used values go first, so they're in the same few blocks
+        let mut value_order: Vec<_> = value_references.into_iter().filter(|(id, _)| *id & 1 == 1).collect();
+        value_order.sort_by_key(|(id, count)| (Reverse(*count), *id));
+
+        for (id, _) in value_order {
+            let Some(value) = self.values.get(id, ())? else {
+                return Ok(Err(format!("pooled value {id} doesn't exist")));
+            };
+            new_pools.1.insert(&value, ())?;
+        }
+
+        let mut new_ids = HashMap::with_capacity(fields.len());
+        for id in self.field_order() {
+            let mut blob = Vec::new();
+            fields[&id].minimally_serialize(&mut blob, &new_pools.1)?;
+
+            match decode_field(id, Some(&blob), &new_pools.1) {
+                Ok(field) if format!("{field:?}") == format!("{:?}", fields[&id]) => {}
+                _ => return Ok(Err(format!("field {id} doesn't read back the same once it's written again"))),
+            }
+            new_ids.insert(id, new_pools.0.insert_serialized(&blob)?);
+        }
+        drop(fields);
+
+        let objects = self.rewrite_geography(|bbox, data| {
+            if data.determine_type() != Some(OsmObjectType::Way) {
+                let ids: Vec<_> = data.decompress_pooled_field_ids()?.iter().map(|id| new_ids[id]).collect();
+                return data.with_pooled_field_ids(&ids).map(Ok);
+            }
+
+            let way = match decode_way(data, bbox, &self.values)? {
+                Ok(way) => way,
+                Err(reason) => return Ok(Err(reason)),
+            };
+            let data = UncompressedOsmData::new(&way, &new_pools);
+
+            match decode_way(data.clone(), bbox, &new_pools.1)? {
+                Ok(new) if format!("{new:?}") == format!("{way:?}") => Ok(Ok(data)),
+                _ => Ok(Err(format!(
+                    "way {} doesn't read back the same once it's written again",
+                    way.osm_id().inner_id()
+                ))),
+            }
+        })?;
+        let objects = match objects {
+            Ok(objects) => objects,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        new_pools.0.flush()?;
+        new_pools.1.flush()?;
+
+        Ok(Ok((objects, new_pools.0.dedup_stats().stored, new_pools.1.dedup_stats().stored)))
+    }
+}
+
+/// Decode a way's fields from the value pool, or say why they can't be
+fn decode_way(
+    data: UncompressedOsmData,
+    bbox: &BoundingBox<i32>,
+    values: &Pool<LiteralValue>,
+) -> io::Result<Result<CompressedOsmData, String>> {
+    let id = data.decompress_way_id().unwrap()?;
+
+    Ok(without_panicking(|| data.compress(bbox, values)).map_err(|e| format!("way {} can't be decoded: {e}", id.0)))
+}
+
+/// Decode a field from its bytes, or from its id if it's inlined, or say why it can't
+/// be. It has to use exactly the bytes it was stored as.
+fn decode_field(id: PooledId, blob: Option<&Vec<u8>>, values: &Pool<LiteralValue>) -> Result<Field, String> {
+    let Some(blob) = blob else {
+        let field = without_panicking(|| Pool::<Field>::inlined_value(id, values).unwrap());
+        return field.map_err(|e| format!("field {id} can't be decoded: {e}"));
+    };
+
+    let mut from = &blob[..];
+    let field = without_panicking(|| Field::deserialize_minimal(&mut from, values))
+        .map_err(|e| format!("field {id} can't be decoded: {e}"))?;
+
+    if !from.is_empty() {
+        return Err(format!("field {id} has bytes left over once it's decoded"));
+    }
+    Ok(field)
+}
+
+/// Some values' decoders aren't written yet, and panic instead of failing, which
+/// mustn't stop the rest of the map being compacted
+fn without_panicking<T>(decode: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(decode)) {
+        Ok(decoded) => decoded,
+        Err(_) => Err(io::Error::new(ErrorKind::Unsupported, "its decoder panicked")),
+    }
+}
+
+/// Remove a file or a directory, if there's one at `path`
+fn remove(path: &Path) -> io::Result<()> {
+    let removed = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+
+    match removed {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
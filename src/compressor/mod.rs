@@ -1,65 +1,141 @@
 use std::{
-    collections::VecDeque, fs::{create_dir_all, File}, io::{self}, path::PathBuf, usize
+    collections::VecDeque, io::{self}, path::PathBuf, sync::Arc, usize
 };
 
 use debug_logs::debug_print;
 use parking_lot::Mutex;
 
-use minimal_storage::{pooled_storage::Pool};
-use osm_tag_compression::{compressed_data::{CompressedOsmData, UncompressedOsmData}, field::Field};
+use minimal_storage::{compression::{CompressionStats, Dictionary}, dedup_index::DedupIndex, pooled_storage::{DedupStats, Pool}, provider::{Directory, StorageProvider}};
+use geocoding::{address::{AssociatedStreet, PendingInterpolation}, admin::{AdminArea, AdminBoundary}, polygon::polygon_from_ways, Geocoder};
+use osm_tag_compression::{compressed_data::{flattened_id, CompressedOsmData, UncompressedOsmData}, field::Field, manifest::Manifest};
 use osm_value_atom::LiteralValue;
-use osmpbfreader::{OsmObj};
+use osmpbfreader::{OsmId, OsmObj, Ref, RelationId, WayId};
+use routing::{MalformedRestriction, RoadGraph, TurnRestriction};
 
 use tree::{
-    bbox::{BoundingBox, EARTH_BBOX}, open_tree_dense, open_tree_sparse, point_range::StoredBinaryTree, dense::structure::StoredTree
+    bbox::{BoundingBox, EARTH_BBOX}, open_tree_dense_in, open_tree_sparse_in, point_range::StoredBinaryTree, dense::structure::StoredTree
 };
 
 
 const CACHE_SATURATION: usize = 4_000;
 const DATA_SATURATION: usize = 8_000;
 
+/// How many of each pool's values, and of the geography's pages, a dictionary is
+/// trained on
+const DICTIONARY_VALUE_SAMPLES: usize = 50_000;
+const DICTIONARY_PAGE_SAMPLES: usize = 2_000;
+
+/// How a map is written
+#[derive(Clone, Debug, Default)]
+pub struct CompressorConfig {
+    /// Once everything's been ingested, train a zstd dictionary of at most this many
+    /// bytes on the pools' values and the geography's pages, then compress the pools'
+    /// blocks and the pages with it. `None` leaves them uncompressed.
+    pub dictionary_size: Option<usize>,
+}
+
+/// The size of each part of a map, before and after it was compressed with a dictionary
+#[derive(Clone, Copy, Debug)]
+pub struct DictionaryCompression {
+    pub dictionary_size: usize,
+    pub literals: CompressionStats,
+    pub values: CompressionStats,
+    pub geography: CompressionStats,
+}
+
 pub struct Compressor {
+    files: Arc<dyn StorageProvider>,
+    config: CompressorConfig,
     values: (Pool<Field>, Pool<LiteralValue>),
     pub cache_bboxes: StoredBinaryTree<CACHE_SATURATION, u64, BoundingBox<i32>>,
     pub geography: StoredTree<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>,
+    pub routing: RoadGraph,
+    pub geocoder: Geocoder,
     queue_to_handle_at_end: Mutex<VecDeque<OsmObj>>,
+    //restrictions can only be checked against the road graph once all the ways are in it
+    pending_restrictions: Mutex<Vec<(RelationId, TurnRestriction)>>,
+    malformed_restrictions: Mutex<Vec<(RelationId, MalformedRestriction)>>,
+    //boundaries are assembled from the geometry of their ways, which is only all stored
+    //once the ways have been written
+    pending_boundaries: Mutex<Vec<(AdminBoundary, Vec<Ref>)>>,
+    //interpolations are numbered from the addresses of their end nodes, and associated
+    //streets name houses which may be written after the relation is read
+    pending_interpolations: Mutex<Vec<PendingInterpolation>>,
+    pending_streets: Mutex<Vec<AssociatedStreet>>,
 }
 
 impl Compressor {
     pub fn new(state_path: &PathBuf) -> Self {
-        create_dir_all(state_path).unwrap();
+        Self::with_config(state_path, CompressorConfig::default())
+    }
+
+    pub fn with_config(state_path: &PathBuf, config: CompressorConfig) -> Self {
+        Self::in_storage(Arc::new(Directory::new(state_path)), config)
+    }
 
-        let lit_file = open_file_with_write(&state_path.join("literals"));
-        let val_file = open_file_with_write(&state_path.join("values"));
+    /// Write the map's files to `files`, which could be in memory instead of on disk
+    pub fn in_storage(files: Arc<dyn StorageProvider>, config: CompressorConfig) -> Self {
+        Manifest::check_or_create(&*files).unwrap_or_else(|e| panic!("{e}"));
 
-        let mut geography = open_tree_dense::<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>(
-            state_path.join("geography"),
+        let lit_file = files.open("literals").unwrap();
+        let val_file = files.open("values").unwrap();
+
+        let mut geography = open_tree_dense_in::<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>(
+            &*files.subdirectory("geography"),
             EARTH_BBOX,
         );
 
-        let mut cache_bboxes = open_tree_sparse::<
+        let mut cache_bboxes = open_tree_sparse_in::<
             1,
             CACHE_SATURATION,
             u64,
             BoundingBox<i32>,
-        >(state_path.join("tmp.bboxes"), 0..=u64::MAX);
+        >(&*files, "tmp.bboxes", 0..=u64::MAX);
 
         geography.expand_to_depth(5);
         cache_bboxes.expand_to_depth(5);
 
+        let routing = RoadGraph::open_in(&*files.subdirectory("routing"));
+        let geocoder = Geocoder::open_in(&*files.subdirectory("geocoding"));
+
+        //reopened, so that compressing into an existing map adds to its pools
+        let mut values = (
+            Pool::open_flushed(lit_file).unwrap(),
+            Pool::open(val_file).unwrap(),
+        );
+        //common values recur far apart in the input, long after they've left the pools'
+        //recently written values
+        values.0.use_dedup_index(DedupIndex::open_in(Arc::clone(&files), "literals.index").unwrap()).unwrap();
+        values.1.use_dedup_index(DedupIndex::open_in(Arc::clone(&files), "values.index").unwrap()).unwrap();
+
         Compressor {
-            values: (
-                Pool::new(Box::new(lit_file)).unwrap(),
-                Pool::new(Box::new(val_file)).unwrap(),
-            ),
+            files,
+            config,
+            values,
             cache_bboxes,
             geography,
+            routing,
+            geocoder,
             queue_to_handle_at_end: Mutex::new(VecDeque::new()),
+            pending_restrictions: Mutex::new(Vec::new()),
+            malformed_restrictions: Mutex::new(Vec::new()),
+            pending_boundaries: Mutex::new(Vec::new()),
+            pending_interpolations: Mutex::new(Vec::new()),
+            pending_streets: Mutex::new(Vec::new()),
         }
     }
     pub fn write_element(&self, element: OsmObj) {
         debug_print!("begin");
 
+        //the compressed way only keeps its nodes' positions, but the road graph needs to
+        //know which ways share a node, and interpolations need their end nodes' addresses
+        let way_nodes = match &element {
+            OsmObj::Way(w) if w.tags.contains_key("highway") || w.tags.contains_key("addr:interpolation") => {
+                Some(w.nodes.iter().map(|n| n.0 as u64).collect::<Vec<_>>())
+            }
+            _ => None,
+        };
+
         let data = CompressedOsmData::make_from_obj(element, &self.cache_bboxes);
 
         debug_print!("after make_from_obj");
@@ -79,6 +155,34 @@ impl Compressor {
 
         debug_assert!(self.geography.root_bbox().contains(&bbox));
 
+        if let (Some(nodes), CompressedOsmData::Way { id, tags, children, .. }) = (way_nodes, &data) {
+            self.routing.add_way(id.0 as u64, &nodes, children, tags.iter());
+
+            if let Some(interpolation) = PendingInterpolation::from_way(&nodes, &data) {
+                self.pending_interpolations.lock().push(interpolation);
+            }
+        }
+
+        if let CompressedOsmData::Relation { id, refs, tags, .. } = &data {
+            match TurnRestriction::from_relation(tags.iter(), refs) {
+                Ok(restrictions) => self
+                    .pending_restrictions
+                    .lock()
+                    .extend(restrictions.into_iter().map(|r| (*id, r))),
+                Err(e) => self.malformed_restrictions.lock().push((*id, e)),
+            }
+
+            if let Some(boundary) = AdminBoundary::from_fields(id.0 as u64, tags.iter()) {
+                self.pending_boundaries.lock().push((boundary, refs.clone()));
+            }
+
+            if let Some(street) = AssociatedStreet::from_relation(tags.iter(), refs) {
+                self.pending_streets.lock().push(street);
+            }
+        }
+
+        self.geocoder.add_place(&data);
+
         let data = UncompressedOsmData::new(&data, &self.values);
 
         self.geography.insert(bbox, data)
@@ -87,6 +191,8 @@ impl Compressor {
     pub fn flush_to_storage(&mut self) -> Result<(), io::Error> {
         self.geography.flush()?;
         self.cache_bboxes.flush()?;
+        self.routing.flush()?;
+        self.geocoder.flush()?;
 
         let values = &self.values;
         values.0.flush()?;
@@ -95,6 +201,141 @@ impl Compressor {
         Ok(())
     }
 
+    /// Compress the pools and the geography with a dictionary trained on them, if the
+    /// config asks for one. Each is swapped for its compressed version as a whole, so a
+    /// failure part of the way through leaves every part readable.
+    pub fn compress_with_dictionary(&mut self) -> Result<Option<DictionaryCompression>, io::Error> {
+        let Some(max_size) = self.config.dictionary_size else {
+            return Ok(None);
+        };
+
+        self.flush_to_storage()?;
+
+        let mut samples = value_samples(&self.values.0)?;
+        samples.extend(value_samples(&self.values.1)?);
+        samples.extend(self.geography.page_samples(DICTIONARY_PAGE_SAMPLES)?);
+
+        let dictionary = Dictionary::train(&samples, max_size)?;
+        let bytes = dictionary.as_bytes().to_vec();
+
+        let files = &*self.files;
+        let literals = compress_pool(&self.values.0, files, "literals", Dictionary::from_bytes(bytes.clone()))?;
+        let values = compress_pool(&self.values.1, files, "values", Dictionary::from_bytes(bytes.clone()))?;
+
+        //the old pools have to let go of the dedup indexes before they're reopened
+        drop(std::mem::replace(
+            &mut self.values,
+            (
+                Pool::open_flushed(files.open("literals")?)?,
+                Pool::open_flushed(files.open("values")?)?,
+            ),
+        ));
+        self.values.0.use_dedup_index(DedupIndex::open_in(Arc::clone(&self.files), "literals.index")?)?;
+        self.values.1.use_dedup_index(DedupIndex::open_in(Arc::clone(&self.files), "values.index")?)?;
+
+        let geography = self.geography.compress_pages(dictionary)?;
+
+        Ok(Some(DictionaryCompression {
+            dictionary_size: bytes.len(),
+            literals,
+            values,
+            geography,
+        }))
+    }
+
+    /// How often the literal and value pools found an inserted value already stored,
+    /// for the ingest report.
+    pub fn dedup_stats(&self) -> (DedupStats, DedupStats) {
+        (self.values.0.dedup_stats(), self.values.1.dedup_stats())
+    }
+
+    /// Add the turn restrictions found so far to the road graph. Returns every restriction
+    /// relation which couldn't be used, for the ingest report.
+    pub fn apply_turn_restrictions(&mut self) -> Vec<(RelationId, MalformedRestriction)> {
+        let mut malformed = std::mem::take(self.malformed_restrictions.get_mut());
+
+        for (id, restriction) in self.pending_restrictions.get_mut().drain(..) {
+            if let Err(e) = self.routing.add_restriction(restriction) {
+                malformed.push((id, e));
+            }
+        }
+
+        malformed
+    }
+
+    /// Assemble the administrative boundaries found so far into polygons, and add them
+    /// to the geocoder. Returns the boundaries whose ways don't form closed rings, for the
+    /// ingest report.
+    pub fn build_admin_areas(&mut self) -> Vec<RelationId> {
+        let mut unclosed = Vec::new();
+
+        for (boundary, refs) in std::mem::take(self.pending_boundaries.get_mut()) {
+            let mut outer = Vec::new();
+            let mut inner = Vec::new();
+
+            for r in refs.iter() {
+                let OsmId::Way(way) = r.member else {
+                    continue;
+                };
+                let Some(points) = self.way_points(way) else {
+                    continue;
+                };
+
+                match r.role.as_str() {
+                    "inner" => inner.push(points),
+                    "outer" | "" => outer.push(points),
+                    _ => {}
+                }
+            }
+
+            match polygon_from_ways(outer, inner) {
+                Some(polygon) => self.geocoder.add_admin_area(AdminArea { boundary, polygon }),
+                None => unclosed.push(RelationId(boundary.relation as i64)),
+            }
+        }
+
+        unclosed
+    }
+
+    /// Add the house numbers which can only be worked out once every object has been
+    /// written: those along interpolation ways, and those of houses in associated street
+    /// relations. Returns the interpolation ways whose ends don't have usable numbers,
+    /// for the ingest report.
+    pub fn build_address_index(&mut self) -> Vec<WayId> {
+        let mut unnumbered = Vec::new();
+
+        for interpolation in std::mem::take(self.pending_interpolations.get_mut()) {
+            let way = WayId(interpolation.way as i64);
+            if !self.geocoder.add_interpolation(interpolation) {
+                unnumbered.push(way);
+            }
+        }
+
+        for street in std::mem::take(self.pending_streets.get_mut()) {
+            let houses = street.houses.iter().filter_map(|house| {
+                let id = flattened_id(house);
+                self.cache_bboxes.get_owned(&id).map(|bbox| (bbox, id))
+            });
+
+            self.geocoder.add_associated_street(&street, houses);
+        }
+
+        unnumbered
+    }
+
+    /// Read a way's points back out of the geography tree.
+    fn way_points(&self, way: WayId) -> Option<Vec<(i32, i32)>> {
+        let bbox = self.cache_bboxes.get_owned(&flattened_id(&OsmId::Way(way)))?;
+
+        let points = self
+            .geography
+            .find_entries_in_box(&bbox)
+            .filter(|(_, data)| matches!(data.decompress_way_id(), Some(Ok(id)) if id == way))
+            .find_map(|(bbox, data)| data.decompress_way_points(&bbox)?.ok());
+
+        points
+    }
+
     pub fn attempt_retry_queue<'a>(&'a mut self) -> impl Iterator<Item = OsmObj> + 'a {
         //try 5 times to reduce the size
         for attempt in 0..5 {
@@ -121,11 +362,27 @@ impl Compressor {
     }
 }
 
-fn open_file_with_write(path: &PathBuf) -> File {
-    File::options()
-        .create(true)
-        .read(true)
-        .write(true)
-        .open(&path)
-        .unwrap()
+/// Up to `DICTIONARY_VALUE_SAMPLES` of the pool's values, spread across it
+fn value_samples<T>(pool: &Pool<T>) -> io::Result<Vec<Vec<u8>>> {
+    let values: Vec<_> = pool.serialized_values().collect::<io::Result<_>>()?;
+    let step = values.len().div_ceil(DICTIONARY_VALUE_SAMPLES).max(1);
+
+    Ok(values.into_iter().step_by(step).map(|(_, value)| value).collect())
+}
+
+/// Write the pool compressed next to its file, which is named `name`, then put it in
+/// the file's place. The pool has to be reopened afterwards.
+fn compress_pool<T>(
+    pool: &Pool<T>,
+    files: &dyn StorageProvider,
+    name: &str,
+    dictionary: Dictionary,
+) -> io::Result<CompressionStats> {
+    let compressed_name = format!("{name}.compressed");
+    let compressed = files.create(&compressed_name)?;
+
+    let stats = pool.write_compressed(compressed, dictionary)?;
+    files.rename(&compressed_name, name)?;
+
+    Ok(stats)
 }
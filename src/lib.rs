@@ -1 +1,4 @@
-pub mod compressor;
\ No newline at end of file
+pub mod compact;
+pub mod compressor;
+pub mod pack;
+pub mod upgrade;
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use minimal_storage::{
+    packed_map::{self, PackedMap, SectionKind},
+    pooled_storage::Filelike,
+    provider::Directory,
+};
+
+/// What `pack` wrote, for its report
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PackStats {
+    pub sections: usize,
+    /// The size of the map's files, and of the packed map
+    pub bytes: (u64, u64),
+}
+
+/// Pack every file in the map's directory into one `.tmap` at `to`, which readers can
+/// open without unpacking it. It's written next to `to` first, so a failure leaves
+/// anything already at `to` as it was.
+pub fn pack(state_dir: &Path, to: &Path) -> io::Result<PackStats> {
+    let inside_map = to
+        .parent()
+        .map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent })
+        .and_then(|parent| parent.canonicalize().ok())
+        .is_some_and(|parent| state_dir.canonicalize().is_ok_and(|dir| parent.starts_with(dir)));
+    if inside_map {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the packed map can't be written inside the map it's packed from",
+        ));
+    }
+
+    let mut names = Vec::new();
+    file_names(state_dir, "", &mut names)?;
+    names.sort();
+
+    let files = names
+        .iter()
+        .map(|name| File::open(state_dir.join(name)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let sections: Vec<_> = names
+        .iter()
+        .zip(&files)
+        .map(|(name, file)| (section_kind(name), name.as_str(), file as &dyn Filelike))
+        .collect();
+
+    let partial = PathBuf::from(format!("{}.partial", to.display()));
+    let packed = File::create(&partial)
+        .and_then(|file| {
+            let len = packed_map::pack(&sections, &file)?;
+            //on disk before it's renamed, so that a crash never leaves a truncated map at `to`
+            file.sync_all()?;
+            Ok(len)
+        })
+        .and_then(|len| std::fs::rename(&partial, to).map(|_| len));
+    let packed = match packed {
+        Ok(len) => len,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    //and so is the rename
+    sync_parent_dir(to)?;
+
+    Ok(PackStats {
+        sections: sections.len(),
+        bytes: (files.iter().map(|f| f.len()).sum::<io::Result<u64>>()?, packed),
+    })
+}
+
+/// Make the entries of the directory which `path` is in durable, such as a file renamed
+/// into it. Only Unix can open a directory to sync it.
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(parent)?.sync_all()?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+/// Check every section of the `.tmap` at `from`, and write them as files in `state_dir`,
+/// which mustn't have anything in it yet. Returns how many files were written.
+pub fn unpack(from: &Path, state_dir: &Path) -> io::Result<usize> {
+    let packed = PackedMap::open(from)?;
+
+    if state_dir.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already has files in it", state_dir.display()),
+        ));
+    }
+
+    packed.verify()?;
+    packed.unpack(&Directory::new(state_dir))?;
+
+    Ok(packed.sections().count())
+}
+
+/// The name, relative to the map's directory, of every file in `dir`, which is `prefix`
+/// in the map's directory
+fn file_names(dir: &Path, prefix: &str, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(ErrorKind::InvalidData, format!("{name:?} isn't a UTF-8 file name"))
+        })?;
+
+        if entry.file_type()?.is_dir() {
+            file_names(&entry.path(), &format!("{prefix}{name}/"), names)?;
+        } else {
+            names.push(format!("{prefix}{name}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// What the file with the given name in the map's directory holds
+pub fn section_kind(name: &str) -> SectionKind {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+
+    match file_name {
+        "structure" => SectionKind::TreeStructure,
+        "data" => SectionKind::TreePages,
+        "journal" => SectionKind::Journal,
+        "literals" | "values" => SectionKind::Pool,
+        "manifest" => SectionKind::Manifest,
+        "config" => SectionKind::Config,
+        _ if file_name.ends_with(".index") => SectionKind::Index,
+        _ if file_name.ends_with(".note") => SectionKind::Other,
+        //everything else the compressor writes is a sparse tree, such as `tmp.bboxes`
+        //and the routing graph's edges
+        _ => SectionKind::Tree,
+    }
+}
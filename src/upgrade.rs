@@ -0,0 +1,253 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use geocoding::{admin::AdminArea, geocoder::AREA_SATURATION};
+use minimal_storage::{
+    pooled_storage::Pool,
+    provider::Directory,
+    serialize_min::{DeserializeFromMinimal, SerializeMinimal},
+    varint::ToVarint,
+};
+use osm_tag_compression::{
+    compressed_data::UncompressedOsmData,
+    field::Field,
+    manifest::{FormatMismatch, Manifest, FORMAT_VERSION},
+};
+use osm_value_atom::LiteralValue;
+use overpass::map::stored_id;
+use tree::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    dense::StoredTree,
+    tree_traits::MultidimensionalValue,
+};
+
+const DATA_SATURATION: usize = 8_000;
+
+type Geography = StoredTree<2, DATA_SATURATION, BoundingBox<i32>, UncompressedOsmData>;
+
+/// The format version of maps written before they had manifests
+const UNVERSIONED: u32 = 0;
+
+/// Rewrites a map in format version `from` as the version after it would've written it,
+/// by decoding it with the old version's codec and encoding it with the new one's. The
+/// old codec is kept with its step, since nothing else needs it any more.
+struct Migration {
+    from: u32,
+    describe: &'static str,
+    migrate: fn(&Path) -> io::Result<()>,
+}
+
+/// One step from each version to the next, up to `FORMAT_VERSION`
+const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        from: UNVERSIONED,
+        describe: "checked that every object and pool is in version 1's encoding, which later unversioned maps were written in",
+        migrate: check_unversioned,
+    },
+    Migration {
+        from: 1,
+        describe: "rewrote the headers of nodes with uninlined tags, which version 1 wrote as varints",
+        migrate: rewrite_node_headers,
+    },
+    Migration {
+        from: 2,
+        describe: "re-sorted the keys of the trees keyed by areas, which version 2 ordered by their corners alone",
+        migrate: resort_area_keys,
+    },
+];
+
+/// What `upgrade` did, for its report
+#[derive(Clone, Debug, Default)]
+pub struct UpgradeReport {
+    /// The map's format version before it was upgraded
+    pub from: u32,
+    /// What each step did, oldest first
+    pub steps: Vec<&'static str>,
+}
+
+/// Migrate the map forward, one format version at a time, to the version this build
+/// reads. The manifest is written after each step, so a failure leaves it saying which
+/// version the map is in.
+///
+/// A map whose fields were generated from a different tagging schema can't be
+/// upgraded, since the fields' old encodings aren't part of this build.
+pub fn upgrade(state_dir: &Path) -> io::Result<UpgradeReport> {
+    let files = Directory::new(state_dir);
+    let found = Manifest::read(&files)?;
+    let current = Manifest::current();
+
+    let from = match found {
+        None => UNVERSIONED,
+        Some(manifest) if manifest == current => {
+            return Ok(UpgradeReport { from: FORMAT_VERSION, steps: Vec::new() });
+        }
+        Some(manifest) if manifest.format_version > FORMAT_VERSION => {
+            return Err(io::Error::new(ErrorKind::InvalidData, FormatMismatch { found }));
+        }
+        Some(manifest) if manifest.schema_hash != current.schema_hash => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the map's fields were generated from a different tagging schema ({:#018x}, where this build's is {:#018x}), so it can't be upgraded; it has to be compressed again",
+                    manifest.schema_hash, current.schema_hash
+                ),
+            ));
+        }
+        Some(manifest) => manifest.format_version,
+    };
+
+    let mut report = UpgradeReport { from, steps: Vec::new() };
+    for version in from..FORMAT_VERSION {
+        let migration = MIGRATIONS.iter().find(|m| m.from == version).ok_or_else(|| {
+            io::Error::new(ErrorKind::Unsupported, format!("there's no upgrade from format version {version}"))
+        })?;
+
+        (migration.migrate)(state_dir)?;
+        Manifest {
+            format_version: version + 1,
+            ..current
+        }
+        .write(&files)?;
+
+        report.steps.push(migration.describe);
+    }
+
+    Ok(report)
+}
+
+/// Version 1 encodes everything as later unversioned maps did, so nothing's rewritten;
+/// every object's header and id are decoded, and every pool's blocks are read, to check
+/// that the map really was written that way. Unversioned maps don't say which tagging
+/// schema their fields came from, so they're taken to be this build's.
+///
+/// The first unversioned maps wrote nodes with uninlined tags without their ids, which
+/// can't be made up, so a map with one of those has to be compressed again.
+fn check_unversioned(state_dir: &Path) -> io::Result<()> {
+    let not_version_1 = |what: String| {
+        io::Error::new(ErrorKind::InvalidData, format!("{what}, so the map isn't in version 1's encoding"))
+    };
+
+    let geography = Geography::open(EARTH_BBOX, state_dir.join("geography"))?;
+    for (_, data) in geography.find_entries_in_box(geography.root_bbox()) {
+        if is_idless_node(&data)? {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "a node with uninlined tags was written without its id, as the first unversioned maps were, so the map can't be upgraded; it has to be compressed again",
+            ));
+        }
+        if stored_id(&data).is_none() {
+            return Err(not_version_1("an object's header or id can't be decoded".into()));
+        }
+    }
+
+    let fields = Pool::<Field>::open_flushed(Box::new(File::open(state_dir.join("literals"))?))?;
+    let values = Pool::<LiteralValue>::open(Box::new(File::open(state_dir.join("values"))?))?;
+    for field in fields.serialized_values() {
+        field.map_err(|e| not_version_1(format!("the literals pool can't be read: {e}")))?;
+    }
+    for value in values.serialized_values() {
+        value.map_err(|e| not_version_1(format!("the values pool can't be read: {e}")))?;
+    }
+
+    Ok(())
+}
+
+/// Whether the object is a node with uninlined tags as the first unversioned maps wrote
+/// it: a varint header, `0b1000_cccc`, so `[0b1000_0001, 0b0000_cccc]`, then its tags
+/// with no id. An inlined tree's id only starts with a byte below `0b1000_0000` if it's
+/// its only byte, and version 1's header has `0b0100_0000` set in its second byte.
+fn is_idless_node(data: &UncompressedOsmData) -> io::Result<bool> {
+    let mut stored = Vec::new();
+    data.minimally_serialize(&mut stored, ())?;
+
+    let mut blob = &stored[..];
+    usize::deserialize_minimal(&mut blob, ())?;
+
+    Ok(matches!(blob, [0b1000_0001, second, rest @ ..] if second >> 4 == 0 && !rest.is_empty()))
+}
+
+/// Version 1 wrote the header of a node with uninlined tags, `0b1100_cccc`, as a varint,
+/// so its first byte was `0b1000_0001`, the same as a node with an inlined tree. Version
+/// 2 writes it as it is. The geography is rewritten next to the old one and swapped in
+/// once it's been flushed.
+fn rewrite_node_headers(state_dir: &Path) -> io::Result<()> {
+    rewrite_tree::<DATA_SATURATION, _>(&state_dir.join("geography"), version_2_node_header)
+}
+
+/// Version 2 ordered `DeltaBoundingBox32` keys by their corners alone, so a tree's keys
+/// which share a corner are in whichever order they were inserted; version 3 orders them
+/// by width and height after that. Points all have the same width and height, so only
+/// the trees keyed by areas, the geography and the geocoder's areas, are rewritten.
+fn resort_area_keys(state_dir: &Path) -> io::Result<()> {
+    rewrite_tree::<DATA_SATURATION, UncompressedOsmData>(&state_dir.join("geography"), |_, data| Ok(data))?;
+
+    let areas = state_dir.join("geocoding").join("areas");
+    if areas.exists() {
+        rewrite_tree::<AREA_SATURATION, AdminArea>(&areas, |_, area| Ok(area))?;
+    }
+
+    Ok(())
+}
+
+/// Insert every entry of the dense tree in `old_dir`, passed through `rewrite`, into a new
+/// tree next to it, which is swapped in once it's been flushed.
+fn rewrite_tree<const SATURATION: usize, Value: MultidimensionalValue<BoundingBox<i32>>>(
+    old_dir: &Path,
+    rewrite: impl Fn(&BoundingBox<i32>, Value) -> io::Result<Value>,
+) -> io::Result<()> {
+    let sibling = |suffix: &str| {
+        let mut name = old_dir.file_name().unwrap_or_default().to_owned();
+        name.push(suffix);
+        old_dir.with_file_name(name)
+    };
+
+    let new_dir = sibling(".upgrade");
+    if new_dir.exists() {
+        std::fs::remove_dir_all(&new_dir)?;
+    }
+    std::fs::create_dir_all(&new_dir)?;
+
+    {
+        let tree = StoredTree::<2, SATURATION, BoundingBox<i32>, Value>::open(EARTH_BBOX, old_dir.to_owned())?;
+        let mut upgraded = StoredTree::<2, SATURATION, BoundingBox<i32>, Value>::open(EARTH_BBOX, new_dir.clone())?;
+        upgraded.expand_to_depth(5);
+
+        for (bbox, value) in tree.find_entries_in_box(tree.root_bbox()) {
+            upgraded.insert(&bbox, rewrite(&bbox, value)?);
+        }
+        upgraded.flush()?;
+    }
+
+    let replaced_dir = sibling(".old");
+    std::fs::rename(old_dir, &replaced_dir)?;
+    std::fs::rename(&new_dir, old_dir)?;
+    std::fs::remove_dir_all(replaced_dir)
+}
+
+/// The object, with its header written as it is if it's a node with uninlined tags.
+/// Their varint headers are told apart from inlined trees by the byte after: a tree's id
+/// only starts with `0b0100_xxxx` if it's its only byte, and a node with uninlined tags
+/// has at least an id after its header.
+fn version_2_node_header(bbox: &BoundingBox<i32>, data: UncompressedOsmData) -> io::Result<UncompressedOsmData> {
+    let mut stored = Vec::new();
+    data.minimally_serialize(&mut stored, ())?;
+
+    let mut blob = &stored[..];
+    let len = usize::deserialize_minimal(&mut blob, ())?;
+    let blob = match blob {
+        [0b1000_0001, second, rest @ ..] if second >> 4 == 0b0100 && !rest.is_empty() => {
+            [&[0b1000_0000 | second][..], rest].concat()
+        }
+        _ => return Ok(data),
+    };
+
+    let mut upgraded = Vec::with_capacity(blob.len() + 1);
+    (len - 1).write_varint(&mut upgraded)?;
+    upgraded.extend_from_slice(&blob);
+
+    UncompressedOsmData::deserialize_minimal(&mut &upgraded[..], bbox)
+}
+
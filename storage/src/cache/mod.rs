@@ -43,6 +43,11 @@ where
         self.cache.get_mut().contains_key(&id)
     }
 
+    /// Whether nothing is cached, with the same caveat as `exists`
+    pub fn is_empty(&mut self) -> bool {
+        self.cache.get_mut().is_empty()
+    }
+
     pub fn get_or_insert(&self, id: K, f: impl FnOnce() -> (usize, Arc<V>)) -> Arc<V> {
 
         debug_print!("Cache::get_or_insert started");
@@ -97,6 +102,29 @@ where
         value
     }
 
+    /// Like `get_or_insert`, but if `f` fails, nothing is cached and its error is returned.
+    /// `f` is called outside of the cache's locks, so two threads may both call it for the
+    /// same id; then only the first value is kept.
+    pub fn try_get_or_insert<E>(
+        &self,
+        id: K,
+        f: impl FnOnce() -> Result<(usize, Arc<V>), E>,
+    ) -> Result<Arc<V>, E> {
+        if let Some(prev) = self
+            .cache
+            .read()
+            .get(&id)
+            .and_then(OnceLock::get)
+            .map(|x| Arc::clone(&x.1))
+        {
+            return Ok(prev);
+        }
+
+        let value = f()?;
+
+        Ok(self.get_or_insert(id, || value))
+    }
+
     fn evict(&self) {
         debug_print!("Cache::evict started");
 
@@ -1,7 +1,14 @@
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::Read;
+
+#[cfg(feature = "compression")]
 use crate::serialize_min::{DeserializeFromMinimal, MinimalSerializedSeek, SerializeMinimal};
 
 pub struct Compressed<T: ?Sized>(T);
 
+
+#[cfg(feature = "compression")]
 impl<T: DeserializeFromMinimal> DeserializeFromMinimal for Compressed<T> {
     type ExternalData<'d> = T::ExternalData<'d>;
 
@@ -20,6 +27,7 @@ impl<T: DeserializeFromMinimal> DeserializeFromMinimal for Compressed<T> {
     }
 }
 
+#[cfg(feature = "compression")]
 impl<T: MinimalSerializedSeek> MinimalSerializedSeek for Compressed<T> {
     fn seek_past<R: std::io::Read>(from: &mut R) -> std::io::Result<()> {
         T::seek_past(&mut zstd::Decoder::new(from)?)
@@ -27,6 +35,7 @@ impl<T: MinimalSerializedSeek> MinimalSerializedSeek for Compressed<T> {
 }
 
 
+#[cfg(feature = "compression")]
 impl<T: SerializeMinimal> SerializeMinimal for Compressed<T> {
     type ExternalData<'d> = T::ExternalData<'d>;
     
@@ -43,3 +52,123 @@ impl<T: SerializeMinimal> SerializeMinimal for Compressed<T> {
 
     
 }
+
+/// The zstd level which dictionaries compress with: zstd's default
+#[cfg(feature = "compression")]
+const DICTIONARY_LEVEL: i32 = 0;
+
+/// A zstd dictionary, trained on samples of the data it's going to compress. Pool values
+/// and tree pages are small and alike, so they compress far better with one than on
+/// their own. Whatever compressed data with a dictionary has to keep it to decompress.
+pub struct Dictionary {
+    bytes: Vec<u8>,
+    #[cfg(feature = "compression")]
+    encoder: zstd::dict::EncoderDictionary<'static>,
+    #[cfg(feature = "compression")]
+    decoder: zstd::dict::DecoderDictionary<'static>,
+}
+
+impl std::fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Dictionary {
+    /// Train a dictionary of at most `max_size` bytes. zstd needs a good number of
+    /// samples, and fails if there are too few.
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> io::Result<Self> {
+        Ok(Self::from_bytes(zstd::dict::from_samples(samples, max_size)?))
+    }
+
+    /// A dictionary which was trained earlier, as `as_bytes` gave it
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            encoder: zstd::dict::EncoderDictionary::copy(&bytes, DICTIONARY_LEVEL),
+            decoder: zstd::dict::DecoderDictionary::copy(&bytes),
+            bytes,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)?.compress(data)
+    }
+
+    pub fn decompress(&self, compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        zstd::stream::read::Decoder::with_prepared_dictionary(compressed, &self.decoder)?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Without zstd, dictionaries can still be kept, but nothing can be compressed or
+/// decompressed with them.
+#[cfg(not(feature = "compression"))]
+impl Dictionary {
+    pub fn train<S: AsRef<[u8]>>(_samples: &[S], _max_size: usize) -> io::Result<Self> {
+        Err(without_zstd())
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    pub fn compress(&self, _data: &[u8]) -> io::Result<Vec<u8>> {
+        Err(without_zstd())
+    }
+
+    pub fn decompress(&self, _compressed: &[u8]) -> io::Result<Vec<u8>> {
+        Err(without_zstd())
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn without_zstd() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "minimal_storage was built without the `compression` feature",
+    )
+}
+
+impl Dictionary {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// How big some data was, and how big it is compressed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct CompressionStats {
+    pub uncompressed: u64,
+    pub compressed: u64,
+}
+
+impl CompressionStats {
+    pub fn record(&mut self, uncompressed: usize, compressed: usize) {
+        self.uncompressed += uncompressed as u64;
+        self.compressed += compressed as u64;
+    }
+
+    /// The compressed size as a share of the uncompressed size
+    pub fn ratio(&self) -> f64 {
+        match self.uncompressed {
+            0 => 1.0,
+            uncompressed => self.compressed as f64 / uncompressed as f64,
+        }
+    }
+}
+
+impl std::ops::Add for CompressionStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            uncompressed: self.uncompressed + other.uncompressed,
+            compressed: self.compressed + other.compressed,
+        }
+    }
+}
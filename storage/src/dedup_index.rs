@@ -0,0 +1,285 @@
+use std::{io, path::Path, sync::Arc};
+
+use crate::{
+    pooled_storage::{Filelike, PooledId},
+    provider::{Directory, StorageProvider},
+};
+
+const MAGIC: [u8; 8] = *b"POOLIDX\x01";
+/// The magic, then the slot count, the entry count, and the pool's value count when the
+/// index was last flushed
+const HEADER_SIZE: u64 = 8 + 8 + 8 + 8;
+/// A value's SHA-256 hash, then its id. Pooled ids are never 0, so an id of 0 is an
+/// empty slot.
+const ENTRY_SIZE: u64 = 32 + 8;
+
+const INITIAL_SLOTS: u64 = 1 << 16;
+/// Neighbouring slots are read together, since that's where a probe goes next.
+const SLOTS_PER_READ: u64 = 8;
+/// Entries are copied this many at a time when the table grows.
+const SLOTS_PER_COPY: u64 = 4096;
+
+/// Stored as the value count once the index has changed since it was flushed, so an
+/// index which wasn't flushed never matches its pool.
+const CHANGED_SINCE_FLUSH: u64 = u64::MAX;
+
+/// Every value a pool has stored, by hash, so that each distinct value is only stored
+/// once. The table lives in a file, which is read a few slots at a time, so it takes
+/// the same memory however many values there are.
+///
+/// It's an open-addressed hash table, grown into a new file once it's half full.
+pub struct DedupIndex {
+    file: Box<dyn Filelike>,
+    files: Arc<dyn StorageProvider>,
+    name: String,
+    slots: u64,
+    entries: u64,
+    value_count: u64,
+}
+
+impl DedupIndex {
+    /// Open the index at `path`, or make an empty one if there's nothing usable there.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the index needs a file name"))?;
+        let directory = Directory::new(path.parent().unwrap_or(Path::new("")));
+
+        Self::open_in(Arc::new(directory), name)
+    }
+
+    /// Like `open`, but with the file named `name` in `files`
+    pub fn open_in(files: Arc<dyn StorageProvider>, name: &str) -> io::Result<Self> {
+        let file = files.open(name)?;
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        let read = match file.read_exact_at(&mut header, 0) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(e),
+        };
+
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        let slots = u64_at(8);
+
+        if !read || header[..8] != MAGIC || !slots.is_power_of_two() {
+            return Self::create(file, files, name.to_string(), INITIAL_SLOTS);
+        }
+
+        Ok(DedupIndex {
+            file,
+            files,
+            name: name.to_string(),
+            slots,
+            entries: u64_at(16),
+            value_count: u64_at(24),
+        })
+    }
+
+    fn create(
+        file: Box<dyn Filelike>,
+        files: Arc<dyn StorageProvider>,
+        name: String,
+        slots: u64,
+    ) -> io::Result<Self> {
+        file.set_len(0)?;
+        file.set_len(HEADER_SIZE + slots * ENTRY_SIZE)?;
+
+        let index = DedupIndex {
+            file,
+            files,
+            name,
+            slots,
+            entries: 0,
+            value_count: 0,
+        };
+        index.write_header()?;
+
+        Ok(index)
+    }
+
+    /// How many values are in the index
+    pub fn len(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// The pool's value count when the index was last flushed, so that it can be thrown
+    /// away if it's out of date
+    pub fn value_count(&self) -> u64 {
+        self.value_count
+    }
+
+    /// Forget every value
+    pub fn clear(&mut self) -> io::Result<()> {
+        let file = self.files.open(&self.name)?;
+        *self = Self::create(file, Arc::clone(&self.files), self.name.clone(), INITIAL_SLOTS)?;
+        Ok(())
+    }
+
+    /// The id the value with the given hash was stored as, if it's in the index
+    pub fn get(&self, hash: &[u8; 32]) -> io::Result<Option<PooledId>> {
+        let mut slot = self.home_slot(hash);
+        let mut buf = [0u8; (SLOTS_PER_READ * ENTRY_SIZE) as usize];
+
+        //the table's never more than half full, so the probe ends at an empty slot
+        loop {
+            let count = SLOTS_PER_READ.min(self.slots - slot);
+            let buf = &mut buf[..(count * ENTRY_SIZE) as usize];
+            self.file.read_exact_at(buf, self.slot_position(slot))?;
+
+            for entry in buf.chunks_exact(ENTRY_SIZE as usize) {
+                let id = u64::from_le_bytes(entry[32..].try_into().unwrap());
+                if id == 0 {
+                    return Ok(None);
+                }
+                if entry[..32] == hash[..] {
+                    return Ok(Some(id));
+                }
+            }
+
+            slot = (slot + count) & (self.slots - 1);
+        }
+    }
+
+    /// Add a value which isn't in the index yet.
+    pub fn insert(&mut self, hash: &[u8; 32], id: PooledId) -> io::Result<()> {
+        debug_assert_ne!(id, 0);
+
+        if self.value_count != CHANGED_SINCE_FLUSH {
+            self.value_count = CHANGED_SINCE_FLUSH;
+            self.write_header()?;
+        }
+
+        if (self.entries + 1) * 2 > self.slots {
+            self.grow()?;
+        }
+
+        self.insert_entry(hash, id)?;
+        self.entries += 1;
+
+        Ok(())
+    }
+
+    /// Record the entry count and the pool's value count, which `open` gives back.
+    pub fn flush(&mut self, value_count: u64) -> io::Result<()> {
+        self.value_count = value_count;
+        self.write_header()
+    }
+
+    fn insert_entry(&self, hash: &[u8; 32], id: PooledId) -> io::Result<()> {
+        let mut slot = self.home_slot(hash);
+        let mut id_bytes = [0u8; 8];
+
+        loop {
+            self.file
+                .read_exact_at(&mut id_bytes, self.slot_position(slot) + 32)?;
+
+            if u64::from_le_bytes(id_bytes) == 0 {
+                let mut entry = [0u8; ENTRY_SIZE as usize];
+                entry[..32].copy_from_slice(hash);
+                entry[32..].copy_from_slice(&id.to_le_bytes());
+
+                return self.file.write_all_at(&entry, self.slot_position(slot));
+            }
+
+            slot = (slot + 1) & (self.slots - 1);
+        }
+    }
+
+    /// Copy every entry into a table twice the size, which then replaces this one
+    fn grow(&mut self) -> io::Result<()> {
+        let new_name = format!("{}.grow", self.name);
+        let new_file = self.files.create(&new_name)?;
+        let mut grown = Self::create(new_file, Arc::clone(&self.files), new_name, self.slots * 2)?;
+
+        let mut buf = vec![0u8; (SLOTS_PER_COPY * ENTRY_SIZE) as usize];
+        for first in (0..self.slots).step_by(SLOTS_PER_COPY as usize) {
+            let count = SLOTS_PER_COPY.min(self.slots - first);
+            let buf = &mut buf[..(count * ENTRY_SIZE) as usize];
+            self.file.read_exact_at(buf, self.slot_position(first))?;
+
+            for entry in buf.chunks_exact(ENTRY_SIZE as usize) {
+                let id = u64::from_le_bytes(entry[32..].try_into().unwrap());
+                if id != 0 {
+                    grown.insert_entry(entry[..32].try_into().unwrap(), id)?;
+                    grown.entries += 1;
+                }
+            }
+        }
+
+        grown.value_count = self.value_count;
+        grown.write_header()?;
+
+        self.files.rename(&grown.name, &self.name)?;
+        grown.name = self.name.clone();
+        *self = grown;
+
+        Ok(())
+    }
+
+    fn home_slot(&self, hash: &[u8; 32]) -> u64 {
+        u64::from_le_bytes(hash[..8].try_into().unwrap()) & (self.slots - 1)
+    }
+
+    fn slot_position(&self, slot: u64) -> u64 {
+        HEADER_SIZE + slot * ENTRY_SIZE
+    }
+
+    fn write_header(&self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&self.slots.to_le_bytes());
+        header.extend_from_slice(&self.entries.to_le_bytes());
+        header.extend_from_slice(&self.value_count.to_le_bytes());
+
+        self.file.write_all_at(&header, 0)
+    }
+}
+
+impl std::fmt::Debug for DedupIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DedupIndex")
+            .field("name", &self.name)
+            .field("slots", &self.slots)
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn hash(i: u64) -> [u8; 32] {
+        Sha256::digest(i.to_le_bytes()).into()
+    }
+
+    #[test]
+    pub fn grows_and_reopens() {
+        let path = std::env::temp_dir().join("minimal_storage-dedup_index");
+        let _ = std::fs::remove_file(&path);
+
+        let mut index = DedupIndex::open(&path).unwrap();
+        let count = INITIAL_SLOTS * 3 / 4;
+        for i in 0..count {
+            index.insert(&hash(i), i * 2 + 1).unwrap();
+        }
+        assert_eq!(index.slots, INITIAL_SLOTS * 2);
+        index.flush(count).unwrap();
+        drop(index);
+
+        let index = DedupIndex::open(&path).unwrap();
+        assert_eq!((index.len(), index.value_count()), (count, count));
+        for i in (0..count).step_by(97) {
+            assert_eq!(index.get(&hash(i)).unwrap(), Some(i * 2 + 1));
+        }
+        assert_eq!(index.get(&hash(count)).unwrap(), None);
+    }
+}
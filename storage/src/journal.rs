@@ -0,0 +1,622 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::pooled_storage::Filelike;
+
+/// Changes are tracked in blocks of this many bytes. Every page size divides it, or is a
+/// multiple of it, so a page's writes never share a block with another page's.
+const BLOCK_SIZE: u64 = 4096;
+
+/// How many changed blocks are kept in memory before they're spilled to the log.
+const MAX_DIRTY_BLOCKS: usize = 4096;
+
+const FRAME: u8 = 1;
+const TRUNCATE: u8 = 2;
+const COMMIT: u8 = 3;
+
+/// Open `files` so that their changes are only made together, when one of them is
+/// committed. Until then, changes are kept in memory and in the log at `log_path`.
+///
+/// If the log holds a complete commit, from a process which crashed while applying it,
+/// it's applied first. Changes which were never committed are thrown away, so the files
+/// are as they were after the last commit.
+pub fn open<const N: usize>(
+    log_path: impl AsRef<Path>,
+    files: [File; N],
+) -> io::Result<[JournaledFile; N]> {
+    let log = File::options()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(log_path)?;
+
+    open_files(Box::new(log), files.map(|file| Box::new(file) as Box<dyn Filelike>))
+}
+
+/// Like `open`, but for files which needn't be on disk, such as those from a
+/// `StorageProvider`
+pub fn open_files<const N: usize>(
+    log: Box<dyn Filelike>,
+    files: [Box<dyn Filelike>; N],
+) -> io::Result<[JournaledFile; N]> {
+    let mut journal = Journal {
+        log,
+        log_len: 0,
+        files: Vec::with_capacity(N),
+    };
+    for file in files {
+        let len = file.len()?;
+        journal.files.push(Target {
+            file,
+            len,
+            truncated_to: None,
+            dirty: BTreeMap::new(),
+            logged: BTreeMap::new(),
+        });
+    }
+
+    journal.recover()?;
+
+    let journal = Arc::new(Mutex::new(journal));
+
+    Ok(std::array::from_fn(|index| JournaledFile {
+        journal: Arc::clone(&journal),
+        index,
+        position: 0,
+    }))
+}
+
+/// One of a set of files opened with `journal::open`. Reads see every change, committed
+/// or not; the file on disk only changes when `commit` is called.
+pub struct JournaledFile {
+    journal: Arc<Mutex<Journal>>,
+    index: usize,
+    position: u64,
+}
+
+impl JournaledFile {
+    /// Make every change to every file in this one's set durable, all at once.
+    pub fn commit(&self) -> io::Result<()> {
+        self.journal.lock().unwrap().commit()
+    }
+}
+
+struct Journal {
+    log: Box<dyn Filelike>,
+    /// Where the next record goes. The log is empty after every commit.
+    log_len: u64,
+    files: Vec<Target>,
+}
+
+struct Target {
+    file: Box<dyn Filelike>,
+    /// The length, with uncommitted changes
+    len: u64,
+    /// The shortest the file has been since the last commit. Anything past it which
+    /// hasn't been written since reads as zeroes, like past the end of a real file.
+    truncated_to: Option<u64>,
+    /// Changed blocks which haven't been written to the log yet
+    dirty: BTreeMap<u64, Box<[u8]>>,
+    /// Where in the log each changed block's latest version starts
+    logged: BTreeMap<u64, u64>,
+}
+
+impl Journal {
+    fn read_at(&mut self, index: usize, position: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let target = &self.files[index];
+        if position >= target.len {
+            return Ok(0);
+        }
+
+        let block = position / BLOCK_SIZE;
+        let offset = position % BLOCK_SIZE;
+        let amount = (buf.len() as u64)
+            .min(BLOCK_SIZE - offset)
+            .min(target.len - position) as usize;
+        let buf = &mut buf[..amount];
+
+        if let Some(data) = target.dirty.get(&block) {
+            buf.copy_from_slice(&data[offset as usize..offset as usize + amount]);
+        } else if let Some(frame) = target.logged.get(&block) {
+            self.log.seek(SeekFrom::Start(frame_data_start(*frame) + offset))?;
+            self.log.read_exact(buf)?;
+        } else {
+            let target = &mut self.files[index];
+            read_committed(target, position, buf)?;
+        }
+
+        Ok(amount)
+    }
+
+    fn write_at(&mut self, index: usize, position: u64, buf: &[u8]) -> io::Result<usize> {
+        let block = position / BLOCK_SIZE;
+        let offset = (position % BLOCK_SIZE) as usize;
+        let amount = buf.len().min(BLOCK_SIZE as usize - offset);
+
+        let data = self.block_mut(index, block)?;
+        data[offset..offset + amount].copy_from_slice(&buf[..amount]);
+
+        let target = &mut self.files[index];
+        target.len = target.len.max(position + amount as u64);
+
+        if self.files.iter().map(|t| t.dirty.len()).sum::<usize>() > MAX_DIRTY_BLOCKS {
+            self.spill()?;
+        }
+
+        Ok(amount)
+    }
+
+    fn set_len(&mut self, index: usize, len: u64) -> io::Result<()> {
+        let target = &mut self.files[index];
+
+        if len < target.len {
+            //blocks past the end are gone, and so is the rest of the last one
+            let kept_blocks = len.div_ceil(BLOCK_SIZE);
+            target.dirty.retain(|block, _| *block < kept_blocks);
+            target.logged.retain(|block, _| *block < kept_blocks);
+            target.truncated_to = Some(target.truncated_to.map_or(len, |t| t.min(len)));
+
+            self.append_record(&truncate_record(index, len))?;
+
+            let offset = (len % BLOCK_SIZE) as usize;
+            if offset != 0 {
+                self.block_mut(index, len / BLOCK_SIZE)?[offset..].fill(0);
+            }
+        }
+
+        self.files[index].len = len;
+        Ok(())
+    }
+
+    /// The block's data, moved into memory to be changed
+    fn block_mut(&mut self, index: usize, block: u64) -> io::Result<&mut Box<[u8]>> {
+        if !self.files[index].dirty.contains_key(&block) {
+            let mut data = vec![0; BLOCK_SIZE as usize].into_boxed_slice();
+
+            if let Some(frame) = self.files[index].logged.get(&block) {
+                self.log.seek(SeekFrom::Start(frame_data_start(*frame)))?;
+                self.log.read_exact(&mut data)?;
+            } else {
+                read_committed(&mut self.files[index], block * BLOCK_SIZE, &mut data)?;
+            }
+
+            self.files[index].dirty.insert(block, data);
+        }
+
+        Ok(self.files[index].dirty.get_mut(&block).unwrap())
+    }
+
+    /// Write every dirty block to the log, so that they don't have to be kept in memory
+    fn spill(&mut self) -> io::Result<()> {
+        for index in 0..self.files.len() {
+            let dirty = std::mem::take(&mut self.files[index].dirty);
+
+            for (block, data) in dirty {
+                let start = self.log_len;
+                self.append_record(&frame_record(index, block, &data))?;
+                self.files[index].logged.insert(block, start);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn append_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.log.seek(SeekFrom::Start(self.log_len))?;
+        self.log.write_all(record)?;
+        self.log_len += record.len() as u64;
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        let unchanged = |t: &Target| {
+            t.dirty.is_empty() && t.file.len().is_ok_and(|len| len == t.len)
+        };
+        if self.log_len == 0 && self.files.iter().all(unchanged) {
+            return Ok(());
+        }
+
+        self.spill()?;
+        self.log.sync_data()?;
+
+        //once the commit record is durable, so is everything before it
+        let lens: Vec<u64> = self.files.iter().map(|t| t.len).collect();
+        self.append_record(&commit_record(&lens))?;
+        self.log.sync_data()?;
+
+        self.checkpoint()
+    }
+
+    /// Apply the logged blocks to the files, then empty the log
+    fn checkpoint(&mut self) -> io::Result<()> {
+        let mut data = vec![0; BLOCK_SIZE as usize];
+
+        for target in self.files.iter_mut() {
+            if let Some(truncated_to) = target.truncated_to.take() {
+                target.file.set_len(truncated_to)?;
+            }
+
+            for (block, frame) in std::mem::take(&mut target.logged) {
+                self.log.seek(SeekFrom::Start(frame_data_start(frame)))?;
+                self.log.read_exact(&mut data)?;
+
+                target.file.seek(SeekFrom::Start(block * BLOCK_SIZE))?;
+                target.file.write_all(&data)?;
+            }
+
+            //the last block is written whole, so it can be past the end
+            target.file.set_len(target.len)?;
+            target.file.sync_data()?;
+        }
+
+        self.log.set_len(0)?;
+        self.log.sync_data()?;
+        self.log_len = 0;
+
+        Ok(())
+    }
+
+    /// Find the last commit in the log, if there is one, and apply it. Anything after it
+    /// was never committed, and a torn record can only be after it.
+    fn recover(&mut self) -> io::Result<()> {
+        let mut reader = io::BufReader::new(&mut self.log);
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut logged = vec![BTreeMap::new(); self.files.len()];
+        let mut truncated_to: Vec<Option<u64>> = vec![None; self.files.len()];
+        let mut committed = None;
+        let mut position = 0;
+
+        while let Some((record, len)) = read_record(&mut reader, self.files.len())? {
+            match record {
+                Record::Frame { index, block } => {
+                    logged[index].insert(block, position);
+                }
+                Record::Truncate { index, len } => {
+                    let kept_blocks = len.div_ceil(BLOCK_SIZE);
+                    logged[index].retain(|block, _| *block < kept_blocks);
+                    truncated_to[index] = Some(truncated_to[index].map_or(len, |t: u64| t.min(len)));
+                }
+                Record::Commit { lens } => {
+                    committed = Some((logged.clone(), truncated_to.clone(), lens));
+                }
+            }
+            position += len;
+        }
+        drop(reader);
+
+        match committed {
+            Some((logged, truncated_to, lens)) => {
+                for (((target, logged), truncated_to), len) in
+                    self.files.iter_mut().zip(logged).zip(truncated_to).zip(lens)
+                {
+                    target.logged = logged;
+                    target.truncated_to = truncated_to;
+                    target.len = len;
+                }
+
+                self.checkpoint()
+            }
+            None => {
+                self.log.set_len(0)?;
+                self.log_len = 0;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Read from the file as of the last commit, with zeroes past its end
+fn read_committed(target: &mut Target, position: u64, buf: &mut [u8]) -> io::Result<()> {
+    buf.fill(0);
+
+    let end = target.truncated_to.unwrap_or(u64::MAX);
+    if position >= end {
+        return Ok(());
+    }
+    let readable = ((end - position).min(buf.len() as u64)) as usize;
+
+    target.file.seek(SeekFrom::Start(position))?;
+    let mut read = 0;
+    while read < readable {
+        match target.file.read(&mut buf[read..readable])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(())
+}
+
+enum Record {
+    Frame { index: usize, block: u64 },
+    Truncate { index: usize, len: u64 },
+    Commit { lens: Vec<u64> },
+}
+
+const FRAME_HEADER_SIZE: u64 = 1 + 4 + 8 + 4;
+
+fn frame_data_start(frame: u64) -> u64 {
+    frame + FRAME_HEADER_SIZE
+}
+
+/// `[FRAME][file u32][block u64][crc32c u32][data]`, with the crc covering the file,
+/// block and data
+fn frame_record(index: usize, block: u64, data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(FRAME_HEADER_SIZE as usize + data.len());
+    record.push(FRAME);
+    record.extend_from_slice(&(index as u32).to_le_bytes());
+    record.extend_from_slice(&block.to_le_bytes());
+    let crc = crc32c::crc32c_append(crc32c::crc32c(&record[1..]), data);
+    record.extend_from_slice(&crc.to_le_bytes());
+    record.extend_from_slice(data);
+
+    record
+}
+
+/// `[TRUNCATE][file u32][len u64][crc32c u32]`
+fn truncate_record(index: usize, len: u64) -> Vec<u8> {
+    let mut record = vec![TRUNCATE];
+    record.extend_from_slice(&(index as u32).to_le_bytes());
+    record.extend_from_slice(&len.to_le_bytes());
+    let crc = crc32c::crc32c(&record[1..]);
+    record.extend_from_slice(&crc.to_le_bytes());
+
+    record
+}
+
+/// `[COMMIT][each file's len u64][crc32c u32]`
+fn commit_record(lens: &[u64]) -> Vec<u8> {
+    let mut record = vec![COMMIT];
+    for len in lens {
+        record.extend_from_slice(&len.to_le_bytes());
+    }
+    let crc = crc32c::crc32c(&record[1..]);
+    record.extend_from_slice(&crc.to_le_bytes());
+
+    record
+}
+
+/// The next record and its length, or None at the end of the log or at a record which
+/// was only partly written.
+fn read_record(from: &mut impl Read, file_count: usize) -> io::Result<Option<(Record, u64)>> {
+    let mut tag = [0u8];
+    if from.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+
+    let body_len = match tag[0] {
+        FRAME => FRAME_HEADER_SIZE as usize - 1 + BLOCK_SIZE as usize,
+        TRUNCATE => 4 + 8 + 4,
+        COMMIT => file_count * 8 + 4,
+        _ => return Ok(None),
+    };
+
+    let mut body = vec![0; body_len];
+    match from.read_exact(&mut body) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let u32_at = |at: usize| u32::from_le_bytes(body[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(body[at..at + 8].try_into().unwrap());
+
+    let (record, intact) = match tag[0] {
+        FRAME => {
+            let crc = crc32c::crc32c_append(crc32c::crc32c(&body[..12]), &body[16..]);
+            let index = u32_at(0) as usize;
+            (
+                Record::Frame {
+                    index,
+                    block: u64_at(4),
+                },
+                crc == u32_at(12) && index < file_count,
+            )
+        }
+        TRUNCATE => {
+            let index = u32_at(0) as usize;
+            (
+                Record::Truncate {
+                    index,
+                    len: u64_at(4),
+                },
+                crc32c::crc32c(&body[..12]) == u32_at(12) && index < file_count,
+            )
+        }
+        _ => {
+            let lens = (0..file_count).map(|i| u64_at(i * 8)).collect();
+            let crc_at = file_count * 8;
+            (
+                Record::Commit { lens },
+                crc32c::crc32c(&body[..crc_at]) == u32_at(crc_at),
+            )
+        }
+    };
+
+    if !intact {
+        return Ok(None);
+    }
+
+    Ok(Some((record, 1 + body_len as u64)))
+}
+
+impl Read for JournaledFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self
+            .journal
+            .lock()
+            .unwrap()
+            .read_at(self.index, self.position, buf)?;
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Write for JournaledFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self
+            .journal
+            .lock()
+            .unwrap()
+            .write_at(self.index, self.position, buf)?;
+        self.position += written as u64;
+
+        Ok(written)
+    }
+
+    /// Changes only reach the disk when they're committed, so there's nothing to do here.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for JournaledFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len()?.checked_add_signed(d),
+            SeekFrom::Current(d) => self.position.checked_add_signed(d),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+impl Filelike for JournaledFile {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.journal.lock().unwrap().files[self.index].len)
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        self.journal.lock().unwrap().set_len(self.index, size)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.journal.lock().unwrap().read_at(self.index, offset, buf)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.journal.lock().unwrap().write_at(self.index, offset, buf)
+    }
+}
+
+impl std::fmt::Debug for JournaledFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JournaledFile")
+            .field("index", &self.index)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn open_files<const N: usize>(name: &str) -> [JournaledFile; N] {
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let files = std::array::from_fn(|i| {
+            File::options()
+                .create(true)
+                .truncate(false)
+                .read(true)
+                .write(true)
+                .open(dir.join(i.to_string()))
+                .unwrap()
+        });
+
+        open(dir.join("log"), files).unwrap()
+    }
+
+    fn contents(file: &mut JournaledFile) -> Vec<u8> {
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn uncommitted_changes_are_discarded() {
+        let _ = std::fs::remove_dir_all(std::env::temp_dir().join("journal_discard"));
+
+        let [mut a, mut b] = open_files::<2>("journal_discard");
+        a.write_all(&[1; 10_000]).unwrap();
+        b.write_all(b"structure").unwrap();
+        a.commit().unwrap();
+
+        //enough to spill to the log, then shrink a file, without committing
+        a.seek(SeekFrom::Start(0)).unwrap();
+        a.write_all(&vec![2; BLOCK_SIZE as usize * (MAX_DIRTY_BLOCKS + 10)])
+            .unwrap();
+        b.set_len(3).unwrap();
+        assert_eq!(contents(&mut b), b"str");
+        drop((a, b));
+
+        let [mut a, mut b] = open_files::<2>("journal_discard");
+        assert_eq!(contents(&mut a), vec![1; 10_000]);
+        assert_eq!(contents(&mut b), b"structure");
+    }
+
+    #[test]
+    fn truncation_and_regrowth() {
+        let _ = std::fs::remove_dir_all(std::env::temp_dir().join("journal_truncate"));
+
+        let [mut a] = open_files::<1>("journal_truncate");
+        a.write_all(&[7; 3 * BLOCK_SIZE as usize]).unwrap();
+        a.commit().unwrap();
+
+        //shrinking then growing reads zeroes, not the old data
+        a.set_len(100).unwrap();
+        a.set_len(2 * BLOCK_SIZE).unwrap();
+        let mut expected = vec![7; 100];
+        expected.resize(2 * BLOCK_SIZE as usize, 0);
+        assert_eq!(contents(&mut a), expected);
+
+        a.commit().unwrap();
+        drop(a);
+
+        let [mut a] = open_files::<1>("journal_truncate");
+        assert_eq!(contents(&mut a), expected);
+    }
+
+    #[test]
+    fn committed_log_is_replayed() {
+        let dir = std::env::temp_dir().join("journal_replay");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let [mut a] = open_files::<1>("journal_replay");
+        a.write_all(b"old").unwrap();
+        a.commit().unwrap();
+        drop(a);
+
+        //as if the process crashed after the commit record was written, but before the
+        // file was updated
+        let mut log = File::options().append(true).open(dir.join("log")).unwrap();
+        let mut block = vec![0; BLOCK_SIZE as usize];
+        block[..3].copy_from_slice(b"new");
+        log.write_all(&frame_record(0, 0, &block)).unwrap();
+        log.write_all(&commit_record(&[3])).unwrap();
+        //and a torn record after it, which must be ignored
+        log.write_all(&frame_record(0, 0, &block)[..100]).unwrap();
+        drop(log);
+
+        let [mut a] = open_files::<1>("journal_replay");
+        assert_eq!(contents(&mut a), b"new");
+        assert_eq!(std::fs::metadata(dir.join("log")).unwrap().len(), 0);
+    }
+}
@@ -9,6 +9,12 @@ pub mod serialize_fast;
 pub mod serialize_min;
 pub mod varint;
 
+pub mod dedup_index;
+pub mod journal;
+pub mod mapped_file;
+pub mod memory_file;
+pub mod packed_map;
+pub mod provider;
 pub mod multitype_paged_storage;
 pub mod paged_storage;
 pub mod pooled_storage;
@@ -18,5 +24,4 @@ pub mod bit_sections;
 
 pub use storage::*;
 
-#[cfg(feature = "compression")]
 pub mod compression;
\ No newline at end of file
@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
+
+use memmap2::Mmap;
+
+use crate::pooled_storage::Filelike;
+
+/// A file mapped into memory, for serving a map which is never written to. Reads are
+/// copies out of the mapping, or borrow it through `Filelike::mapped`, so nothing needs a
+/// lock to read and the OS decides which parts of the file stay in memory.
+///
+/// Every write fails. Clones share the mapping, but each has its own position.
+#[derive(Clone)]
+pub struct MappedFile {
+    map: Arc<Mmap>,
+    /// The part of the mapping which this is, so that a section of a file can be read
+    /// like a whole one
+    range: Range<usize>,
+    position: u64,
+}
+
+impl MappedFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::map(&File::open(path)?)
+    }
+
+    /// Map all of `file`. The file mustn't be changed or truncated while it's mapped, by
+    /// this process or any other.
+    pub fn map(file: &File) -> io::Result<Self> {
+        //safety: the mapping is only read, and callers promise not to change the file
+        let map = unsafe { Mmap::map(file)? };
+
+        Ok(Self {
+            range: 0..map.len(),
+            map: Arc::new(map),
+            position: 0,
+        })
+    }
+
+    /// The bytes in `range` of this file, as a file of their own which shares the
+    /// mapping. Panics if they aren't all in the file.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.range.len());
+
+        Self {
+            map: Arc::clone(&self.map),
+            range: self.range.start + range.start..self.range.start + range.end,
+            position: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "the file is mapped read-only")
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for MappedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => (self.range.len() as u64).checked_add_signed(d),
+            SeekFrom::Current(d) => self.position.checked_add_signed(d),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+impl Write for MappedFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Filelike for MappedFile {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.range.len() as u64)
+    }
+
+    fn set_len(&self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let rest = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.as_bytes().get(offset..))
+            .unwrap_or_default();
+
+        let read = buf.len().min(rest.len());
+        buf[..read].copy_from_slice(&rest[..read]);
+
+        Ok(read)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn mapped(&self) -> Option<&[u8]> {
+        Some(self.as_bytes())
+    }
+}
+
+impl std::fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedFile")
+            .field("range", &self.range)
+            .field("position", &self.position)
+            .finish()
+    }
+}
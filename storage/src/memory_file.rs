@@ -0,0 +1,119 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::Arc,
+};
+
+use parking_lot::RwLock;
+
+use crate::pooled_storage::Filelike;
+
+/// A file which is only ever in memory, for tests and for maps which are built to be
+/// sent somewhere else. Clones share the contents, like handles to the same file, but
+/// each has its own position.
+#[derive(Clone, Default)]
+pub struct MemoryFile {
+    bytes: Arc<RwLock<Vec<u8>>>,
+    position: u64,
+}
+
+impl MemoryFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: Arc::new(RwLock::new(bytes)),
+            position: 0,
+        }
+    }
+
+    /// A copy of everything in the file
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.bytes.read().clone()
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write_at(buf, self.position)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => (self.bytes.read().len() as u64).checked_add_signed(d),
+            SeekFrom::Current(d) => self.position.checked_add_signed(d),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+impl Filelike for MemoryFile {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.bytes.read().len() as u64)
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        let size = usize::try_from(size).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        self.bytes.write().resize(size, 0);
+        Ok(())
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let bytes = self.bytes.read();
+        let rest = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| bytes.get(offset..))
+            .unwrap_or_default();
+
+        let read = buf.len().min(rest.len());
+        buf[..read].copy_from_slice(&rest[..read]);
+
+        Ok(read)
+    }
+
+    /// Like a file, writing past the end fills the gap with zeroes
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let start = usize::try_from(offset).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        let end = start + buf.len();
+
+        let mut bytes = self.bytes.write();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(buf);
+
+        Ok(buf.len())
+    }
+}
+
+impl std::fmt::Debug for MemoryFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryFile")
+            .field("len", &self.bytes.read().len())
+            .field("position", &self.position)
+            .finish()
+    }
+}
@@ -1,18 +1,17 @@
 use std::{
-    any::Any, cmp::min, collections::BinaryHeap, io::{self, BufReader, BufWriter, Read, Write}, marker::PhantomData, ops::{Deref, DerefMut}, sync::{
-        atomic::{AtomicBool, AtomicUsize},
-        Arc, Mutex,
-    }, thread::panicking
+    any::Any,
+    io::{BufReader, BufWriter, Read, Write},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
 };
 
 use debug_logs::debug_print;
-use parking_lot::lock_api::RawRwLock;
-use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use parking_lot::RwLock;
 
 use crate::{
     cache::Cache,
     paged_storage::{
-        Page, PageId, PageReader, PageUse, PageWriter, PagedStorage, WriterState, ALLOWED_CACHE_PHYSICAL_PAGES, PAGE_HEADER_SIZE
+        Page, PageId, PageReader, PageUse, PageWriter, WriterState, ALLOWED_CACHE_PHYSICAL_PAGES
     },
     pooled_storage::Filelike,
     serialize_min::{DeserializeFromMinimal, SerializeMinimal},
@@ -25,32 +24,33 @@ pub struct MultitypePagedStorage<const PAGE_SIZE_K: usize, File: Filelike = std:
 }
 
 impl<const K: usize, File: Filelike + 'static> MultitypePagedStorage<K, File> {
-    pub fn open(mut file: File) -> Self {
-        let lowest_unallocated_id = match file.metadata().unwrap().len() {
-            0 => 1, //if it's a blank file, default to 1 as the lowest unallocated ID (0 is reserved)
-            _ => {
-                file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))
-                    .unwrap();
-                let mut bytes = [0u8; (usize::BITS / 8) as usize];
-                debug_assert!(bytes.len() <= PageId::<K>::data_size());
-                file.read_exact(&mut bytes).unwrap();
-
-                usize::from_le_bytes(bytes)
-            }
-        };
-
-        let pageuse = PageUse {
-            lowest_unallocated_id,
-            freed_pages: BinaryHeap::new(),
-            file,
-        };
-
-        let pageuse = Arc::new(Mutex::new(pageuse));
+    /// Open a file of pages. A blank file is set up without checksums; an existing one
+    /// keeps whichever format it was made with. A header which can't be read is an error.
+    pub fn open(file: File) -> std::io::Result<Self> {
+        Self::open_with_format(file, false)
+    }
 
-        Self {
+    /// Like `open`, but a blank file is set up to checksum each page's data.
+    pub fn open_checksummed(file: File) -> std::io::Result<Self> {
+        Self::open_with_format(file, true)
+    }
+
+    /// Like `open`, but nothing is ever written, not even a blank file's header, so the
+    /// file can be read-only or mapped. No page can be allocated.
+    pub fn open_read_only(file: File) -> std::io::Result<Self> {
+        Ok(Self {
+            pageuse: Arc::new(Mutex::new(PageUse::open_read_only(file)?)),
+            cache: Cache::new(ALLOWED_CACHE_PHYSICAL_PAGES * PageId::<K>::byte_size()),
+        })
+    }
+
+    fn open_with_format(file: File, checksum_new_file: bool) -> std::io::Result<Self> {
+        let pageuse = Arc::new(Mutex::new(PageUse::open(file, checksum_new_file)?));
+
+        Ok(Self {
             pageuse,
             cache: Cache::new(ALLOWED_CACHE_PHYSICAL_PAGES * PageId::<K>::byte_size()),
-        }
+        })
     }
 
     pub fn single_type_view<
@@ -84,7 +84,7 @@ impl<
     type Page = Page<K, T, File>;
 
     fn new_page_with(&self, f: impl FnOnce() -> T) -> Self::PageId {
-        let id = self.pageuse.lock().unwrap().alloc_new();
+        let id = self.pageuse.lock().unwrap().alloc_new().expect("a page couldn't be allocated");
 
         let item = f();
 
@@ -113,24 +113,23 @@ impl<
         id
     }
 
-    fn get<'a, 'b>(
+    fn try_get<'a, 'b>(
         &'a self,
         page_id: &Self::PageId,
         deserialize_data: <T as DeserializeFromMinimal>::ExternalData<'b>,
-    ) -> Option<Arc<Self::Page>> {
+    ) -> std::io::Result<Option<Arc<Self::Page>>> {
         if !self.pageuse.lock().unwrap().is_valid(page_id) {
-            return None;
+            return Ok(None);
         }
 
-        let page = self.cache.get_or_insert(*page_id, || {
-            let p =
-                Arc::new(Page::<_, T, _>::open(&self.pageuse, page_id, deserialize_data).unwrap());
+        let page = self.cache.try_get_or_insert(*page_id, || {
+            let p = Arc::new(Page::<_, T, _>::open(&self.pageuse, page_id, deserialize_data)?);
             let b = p.component_pages.len() * PageId::<K>::byte_size();
-            (b, p)
-        });
+            Ok::<_, std::io::Error>((b, p))
+        })?;
 
         let page = page.downcast().unwrap();
-        Some(page)
+        Ok(Some(page))
     }
 
     type SubView = SingleTypeView<K, File, T>;
@@ -140,11 +139,12 @@ impl<
     }
 
     fn truncate_unused(&mut self) {
-        self.pageuse.lock().unwrap().truncate_unused();
+        self.pageuse.lock().unwrap().truncate_unused().unwrap();
     }
 
-    fn flush(&self) {
+    fn flush(&self) -> std::io::Result<()> {
         self.cache.evict_all_possible();
+        self.pageuse.lock().unwrap().save_free_pages()
     }
     
     fn condense(&mut self, page_id: &Self::PageId) -> Option<Self::PageId> {
@@ -156,11 +156,11 @@ impl<
 
         let mut pageuse = self.pageuse.lock().unwrap();
 
-        let new_page = pageuse.alloc_new();
+        let new_page = pageuse.alloc_new().unwrap();
 
         //if we couldn't allocate a lower number, then bail
         if new_page.0 >= page_id.0 {
-            pageuse.free_page(new_page);
+            pageuse.free_page(new_page).unwrap();
             return Some(*page_id);
         }
 
@@ -176,7 +176,7 @@ impl<
         p_read.read_to_end(&mut buffer).unwrap();
 
         for old_page_id in p_read.into_inner().page_ids_acc {
-            pageuse.free_page(old_page_id);
+            pageuse.free_page(old_page_id).unwrap();
         }
 
         let pages_to_write = [new_page];
@@ -220,7 +220,7 @@ impl<
     type Page = Page<K, T, File>;
 
     fn new_page_with(&self, f: impl FnOnce() -> T) -> PageId<K> {
-        let id = self.pageuse.lock().unwrap().alloc_new();
+        let id = self.pageuse.lock().unwrap().alloc_new().expect("a page couldn't be allocated");
         let item = f();
 
         //this set will always `insert`, never `get`,
@@ -246,27 +246,27 @@ impl<
         id
     }
 
-    fn get<'a, 'b>(
+    fn try_get<'a, 'b>(
         &'a self,
         page_id: &PageId<K>,
         deserialize_data: <T as DeserializeFromMinimal>::ExternalData<'b>,
-    ) -> Option<Arc<Page<K, T, File>>> {
+    ) -> std::io::Result<Option<Arc<Page<K, T, File>>>> {
         if !self.pageuse.lock().unwrap().is_valid(page_id) {
-            return None;
+            return Ok(None);
         }
 
-        let page = self.cache.get_or_insert(*page_id, || {
-            let p =
-                Arc::new(Page::<_, T, _>::open(&self.pageuse, page_id, deserialize_data).expect("Pages must be formatted correctly"));
+        let page = self.cache.try_get_or_insert(*page_id, || {
+            let p = Arc::new(Page::<_, T, _>::open(&self.pageuse, page_id, deserialize_data)?);
             let b = p.component_pages.len() * PageId::<K>::byte_size();
-            (b, p)
-        });
+            Ok::<_, std::io::Error>((b, p))
+        })?;
 
-        Some(page)
+        Ok(Some(page))
     }
 
-    fn flush(&self) {
+    fn flush(&self) -> std::io::Result<()> {
         self.cache.evict_all_possible();
+        self.pageuse.lock().unwrap().save_free_pages()
     }
 
     type SubView = Self;
@@ -332,14 +332,29 @@ pub trait StoreByPage<Item: SerializeMinimal + DeserializeFromMinimal + 'static>
         &'a self,
         page_id: &Self::PageId,
         deserialize_data: <Item as DeserializeFromMinimal>::ExternalData<'b>,
-    ) -> Option<Arc<Self::Page>>;
+    ) -> Option<Arc<Self::Page>> {
+        self.try_get(page_id, deserialize_data)
+            .expect("Pages must be formatted correctly")
+    }
+
+    /// Like `get`, but a page which can't be read, such as one whose checksum doesn't
+    /// match, is an error instead of a panic.
+    fn try_get<'a, 'b>(
+        &'a self,
+        page_id: &Self::PageId,
+        deserialize_data: <Item as DeserializeFromMinimal>::ExternalData<'b>,
+    ) -> std::io::Result<Option<Arc<Self::Page>>>;
 
     type SubView: StoreByPage<Item, PageId = Self::PageId, Page = Self::Page>;
 
     ///Make another page storer which shares the same backing as `self`, but uses
     /// a separate cache and can be flushed seperately.
     fn sub_view(&self) -> Self::SubView;
-    fn flush(&self);
+
+    /// Write every page which isn't in use, and the list of free pages, so that they're
+    /// reused after the file is reopened. Pages freed since the last flush are leaked if
+    /// the file is closed without one.
+    fn flush(&self) -> std::io::Result<()>;
 
     /// Move the data in a given page_id to a lower one, if possible. 
     /// Returns the new page_id, or None if the page cannot be moved.
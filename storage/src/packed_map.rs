@@ -0,0 +1,501 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    mapped_file::MappedFile, memory_file::MemoryFile, pooled_storage::Filelike,
+    provider::StorageProvider,
+};
+
+/// Starts every packed map, so that anything else is refused before it's read
+pub const PACKED_MAP_MAGIC: [u8; 8] = *b"TINYMAP\0";
+/// The newest layout of the header and section table which can be read. A packed map
+/// with a newer one is refused, instead of being misread.
+pub const PACKED_MAP_VERSION: u32 = 1;
+
+/// The magic number, the version, the section count, the section table's length and
+/// its checksum, then padding
+const HEADER_SIZE: usize = 32;
+/// Each section starts at a multiple of this, so that its pages line up with the OS's
+/// when it's mapped
+const SECTION_ALIGNMENT: u64 = 4096;
+/// How much of a file is read at once while it's packed
+const COPY_CHUNK: usize = 1 << 20;
+
+/// What a section of a packed map holds, so that tools can tell without knowing where
+/// the map keeps each of its files
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SectionKind {
+    /// A dense tree's nodes
+    TreeStructure = 0,
+    /// A dense tree's pages of values
+    TreePages = 1,
+    /// A sparse tree, whose nodes and pages are in one file
+    Tree = 2,
+    Pool = 3,
+    /// An index over a pool, such as its dedup index
+    Index = 4,
+    /// A dense tree's journal, which is empty unless the map was packed part of the
+    /// way through a commit
+    Journal = 5,
+    Manifest = 6,
+    Config = 7,
+    /// Anything else, such as the compressor's notes
+    Other = 8,
+}
+
+impl SectionKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        use SectionKind::*;
+
+        [TreeStructure, TreePages, Tree, Pool, Index, Journal, Manifest, Config, Other]
+            .into_iter()
+            .find(|k| *k as u8 == kind)
+    }
+}
+
+/// Where one of a packed map's files is, in the section table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub offset: u64,
+    pub len: u64,
+    /// The crc32c of the section's bytes
+    pub checksum: u32,
+}
+
+/// A map's files packed into one, so that it can be shipped and read as it is instead
+/// of as a directory. It's made by `pack`, and is mapped into memory to be read.
+///
+/// The file is a 32 byte header (`PACKED_MAP_MAGIC`, the version, the number of
+/// sections, and the section table's length and crc32c), then the section table, then
+/// the sections. Each entry in the table is the section's kind, its name's length as a
+/// u16, the name, its offset and length as u64s and its crc32c, all little-endian.
+///
+/// Opening only checks the header and the section table; `verify` or `unpack` check
+/// the sections too. As a `StorageProvider`, it serves the sections as files named like
+/// the map's files were. It can't be changed: anything written to a section is kept
+/// in memory, and thrown away with it.
+#[derive(Clone, Debug)]
+pub struct PackedMap {
+    file: MappedFile,
+    version: u32,
+    sections: Arc<BTreeMap<String, Section>>,
+    /// What's before the names of this directory's sections, ending in `/` unless it's
+    /// empty
+    prefix: String,
+}
+
+impl PackedMap {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_mapped(MappedFile::open(path)?)
+    }
+
+    pub fn from_mapped(file: MappedFile) -> io::Result<Self> {
+        let bytes = file.as_bytes();
+
+        let header = bytes
+            .get(..HEADER_SIZE)
+            .filter(|header| header.starts_with(&PACKED_MAP_MAGIC))
+            .ok_or_else(|| not_a_packed_map("it doesn't start with a packed map's header"))?;
+
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version > PACKED_MAP_VERSION {
+            return Err(not_a_packed_map(&format!(
+                "it was packed with format version {version}, and only versions up to {PACKED_MAP_VERSION} can be read"
+            )));
+        }
+        let section_count = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let table_len = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let table_checksum = u32::from_le_bytes(header[24..28].try_into().unwrap());
+
+        let mut table = usize::try_from(table_len)
+            .ok()
+            .and_then(|len| bytes[HEADER_SIZE..].get(..len))
+            .ok_or_else(|| not_a_packed_map("its section table is cut off"))?;
+        if crc32c::crc32c(table) != table_checksum {
+            return Err(not_a_packed_map("its section table doesn't match its checksum"));
+        }
+
+        let mut sections = BTreeMap::new();
+        for _ in 0..section_count {
+            let (name, section) = read_entry(&mut table)?;
+
+            let end = section.offset.checked_add(section.len);
+            if end.is_none_or(|end| end > bytes.len() as u64) {
+                return Err(not_a_packed_map(&format!("its section {name} is cut off")));
+            }
+            if sections.insert(name.clone(), section).is_some() {
+                return Err(not_a_packed_map(&format!("it has two sections named {name}")));
+            }
+        }
+
+        Ok(Self {
+            file,
+            version,
+            sections: Arc::new(sections),
+            prefix: String::new(),
+        })
+    }
+
+    /// The version of the header and section table which the map was packed with
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The name and place of each section in this directory and its subdirectories
+    pub fn sections(&self) -> impl Iterator<Item = (&str, &Section)> {
+        self.sections
+            .iter()
+            .filter_map(|(name, section)| Some((name.strip_prefix(&self.prefix)?, section)))
+    }
+
+    /// Check every section against its checksum
+    pub fn verify(&self) -> io::Result<()> {
+        for (name, section) in self.sections() {
+            self.checked_bytes(name, section)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write each section to `to` as a file with the section's name, once it's been
+    /// checked against its checksum
+    pub fn unpack(&self, to: &dyn StorageProvider) -> io::Result<()> {
+        for (name, section) in self.sections() {
+            let bytes = self.checked_bytes(name, section)?;
+
+            let file = to.create(name)?;
+            file.write_all_at(bytes, 0)?;
+            file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    fn bytes(&self, section: &Section) -> &[u8] {
+        //every section was checked to be in the file when it was opened
+        &self.file.as_bytes()[section.offset as usize..(section.offset + section.len) as usize]
+    }
+
+    fn checked_bytes(&self, name: &str, section: &Section) -> io::Result<&[u8]> {
+        let bytes = self.bytes(section);
+
+        if crc32c::crc32c(bytes) != section.checksum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the packed map's section {name} doesn't match its checksum"),
+            ));
+        }
+
+        Ok(bytes)
+    }
+
+    fn read_only() -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, "a packed map can't be changed")
+    }
+}
+
+fn not_a_packed_map(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("not a packed map: {reason}"))
+}
+
+/// The first `len` bytes of `from`, which is left with what's after them
+fn take<'a>(from: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    let (taken, rest) = from
+        .split_at_checked(len)
+        .ok_or_else(|| not_a_packed_map("its section table is cut off"))?;
+    *from = rest;
+    Ok(taken)
+}
+
+fn read_entry(table: &mut &[u8]) -> io::Result<(String, Section)> {
+    let kind = take(table, 1)?[0];
+    let kind = SectionKind::from_u8(kind)
+        .ok_or_else(|| not_a_packed_map(&format!("it has a section of unknown kind {kind}")))?;
+
+    let name_len = u16::from_le_bytes(take(table, 2)?.try_into().unwrap());
+    let name = std::str::from_utf8(take(table, name_len as usize)?)
+        .ok()
+        .filter(|name| is_relative_name(name))
+        .ok_or_else(|| not_a_packed_map("it has a section whose name isn't a relative path"))?;
+
+    let offset = u64::from_le_bytes(take(table, 8)?.try_into().unwrap());
+    let len = u64::from_le_bytes(take(table, 8)?.try_into().unwrap());
+    let checksum = u32::from_le_bytes(take(table, 4)?.try_into().unwrap());
+
+    Ok((name.to_string(), Section { kind, offset, len, checksum }))
+}
+
+/// Whether `name` stays inside the directory it's unpacked to
+fn is_relative_name(name: &str) -> bool {
+    name.split('/')
+        .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'))
+}
+
+/// Pack `sections`, each a kind, a name and the file it's read from, into `to`, which is
+/// left with nothing else in it. Names are relative paths with `/` between directories.
+/// Returns the packed map's length.
+pub fn pack(sections: &[(SectionKind, &str, &dyn Filelike)], to: &dyn Filelike) -> io::Result<u64> {
+    if let Some((_, name, _)) = sections.iter().find(|(_, name, _)| !is_relative_name(name)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} can't be packed, since it isn't a relative path"),
+        ));
+    }
+
+    let table_len: usize = sections.iter().map(|(_, name, _)| 1 + 2 + name.len() + 8 + 8 + 4).sum();
+
+    //the sections are written first, since the table needs their checksums
+    let mut table = Vec::with_capacity(table_len);
+    let mut end = (HEADER_SIZE + table_len) as u64;
+    let mut chunk = vec![0; COPY_CHUNK];
+
+    for (kind, name, file) in sections {
+        let offset = end.next_multiple_of(SECTION_ALIGNMENT);
+        let len = file.len()?;
+        let name_len = u16::try_from(name.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{name} is too long to be packed"))
+        })?;
+
+        let mut checksum = 0;
+        let mut copied = 0;
+        while copied < len {
+            let chunk = &mut chunk[..(len - copied).min(COPY_CHUNK as u64) as usize];
+            file.read_exact_at(chunk, copied)?;
+            to.write_all_at(chunk, offset + copied)?;
+
+            checksum = crc32c::crc32c_append(checksum, chunk);
+            copied += chunk.len() as u64;
+        }
+
+        table.push(*kind as u8);
+        table.extend_from_slice(&name_len.to_le_bytes());
+        table.extend_from_slice(name.as_bytes());
+        table.extend_from_slice(&offset.to_le_bytes());
+        table.extend_from_slice(&len.to_le_bytes());
+        table.extend_from_slice(&checksum.to_le_bytes());
+
+        end = offset + len;
+    }
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(&PACKED_MAP_MAGIC);
+    header.extend_from_slice(&PACKED_MAP_VERSION.to_le_bytes());
+    header.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    header.extend_from_slice(&(table.len() as u64).to_le_bytes());
+    header.extend_from_slice(&crc32c::crc32c(&table).to_le_bytes());
+    header.resize(HEADER_SIZE, 0);
+
+    to.write_all_at(&header, 0)?;
+    to.write_all_at(&table, HEADER_SIZE as u64)?;
+    to.set_len(end)?;
+    to.sync_data()?;
+
+    Ok(end)
+}
+
+impl StorageProvider for PackedMap {
+    /// The section, or an empty file if there's no section with the name
+    fn open(&self, name: &str) -> io::Result<Box<dyn Filelike>> {
+        let mapped = match self.sections.get(&format!("{}{name}", self.prefix)) {
+            Some(section) => {
+                self.file.slice(section.offset as usize..(section.offset + section.len) as usize)
+            }
+            None => self.file.slice(0..0),
+        };
+
+        Ok(Box::new(SectionFile {
+            mapped,
+            written: OnceLock::new(),
+            position: 0,
+        }))
+    }
+
+    fn open_existing(&self, name: &str) -> io::Result<Option<Box<dyn Filelike>>> {
+        if !self.sections.contains_key(&format!("{}{name}", self.prefix)) {
+            return Ok(None);
+        }
+
+        self.open(name).map(Some)
+    }
+
+    fn create(&self, _name: &str) -> io::Result<Box<dyn Filelike>> {
+        Err(Self::read_only())
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> io::Result<()> {
+        Err(Self::read_only())
+    }
+
+    fn subdirectory(&self, name: &str) -> Arc<dyn StorageProvider> {
+        Arc::new(PackedMap {
+            file: self.file.clone(),
+            version: self.version,
+            sections: Arc::clone(&self.sections),
+            prefix: format!("{}{name}/", self.prefix),
+        })
+    }
+
+    fn open_mapped(&self, name: &str) -> io::Result<Option<MappedFile>> {
+        Ok(self.sections.get(&format!("{}{name}", self.prefix)).map(|section| {
+            self.file.slice(section.offset as usize..(section.offset + section.len) as usize)
+        }))
+    }
+}
+
+/// A section of a packed map, opened as a file. It's read from the mapping until it's
+/// first written to, which copies it into memory, since a tree writes to its files as
+/// it's opened even if nothing in it changes.
+#[derive(Debug)]
+pub struct SectionFile {
+    mapped: MappedFile,
+    written: OnceLock<MemoryFile>,
+    position: u64,
+}
+
+impl SectionFile {
+    fn copied(&self) -> &MemoryFile {
+        self.written
+            .get_or_init(|| MemoryFile::from_bytes(self.mapped.as_bytes().to_vec()))
+    }
+}
+
+impl Read for SectionFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for SectionFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write_at(buf, self.position)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SectionFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len()?.checked_add_signed(d),
+            SeekFrom::Current(d) => self.position.checked_add_signed(d),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+impl Filelike for SectionFile {
+    fn len(&self) -> io::Result<u64> {
+        match self.written.get() {
+            Some(written) => written.len(),
+            None => self.mapped.len(),
+        }
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        self.copied().set_len(size)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        match self.written.get() {
+            Some(written) => written.read_at(buf, offset),
+            None => self.mapped.read_at(buf, offset),
+        }
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.copied().write_at(buf, offset)
+    }
+
+    /// The section's bytes in the mapping, until it's been written to
+    fn mapped(&self) -> Option<&[u8]> {
+        match self.written.get() {
+            Some(_) => None,
+            None => self.mapped.mapped(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::provider::MemoryDirectory;
+
+    use super::*;
+
+    #[test]
+    pub fn pack_and_unpack() {
+        let path = std::env::temp_dir().join("packed_map_pack_and_unpack.tmap");
+
+        let directory = MemoryDirectory::new();
+        directory.open("values").unwrap().write_all_at(b"some values", 0).unwrap();
+        directory.open("geography/structure").unwrap().write_all_at(b"nodes", 0).unwrap();
+        directory.open("geography/journal").unwrap();
+
+        let files: Vec<_> = directory.files();
+        let opened: Vec<_> = files.iter().map(|(name, _)| directory.open(name).unwrap()).collect();
+        let kinds = [SectionKind::Journal, SectionKind::TreeStructure, SectionKind::Pool];
+        let sections: Vec<_> = files
+            .iter()
+            .zip(&opened)
+            .zip(kinds)
+            .map(|(((name, _), file), kind)| (kind, name.as_str(), &**file as &dyn Filelike))
+            .collect();
+
+        let out = std::fs::File::create(&path).unwrap();
+        pack(&sections, &out).unwrap();
+
+        let packed = PackedMap::open(&path).unwrap();
+        packed.verify().unwrap();
+        assert_eq!(packed.sections().count(), 3);
+
+        let geography = packed.subdirectory("geography");
+        let mut structure = geography.open("structure").unwrap();
+        let mut read = String::new();
+        structure.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "nodes");
+
+        //writes stay in memory, and the packed map is read the same afterwards
+        structure.write_all_at(b"changed", 0).unwrap();
+        assert_eq!(structure.len().unwrap(), 7);
+        assert!(geography.open("missing").unwrap().is_empty().unwrap());
+        assert!(geography.create("structure").is_err());
+
+        let unpacked = MemoryDirectory::new();
+        packed.unpack(&unpacked).unwrap();
+        assert_eq!(unpacked.files(), files);
+
+        //a changed byte in a section is found, but the map still opens
+        let values = packed.sections().find(|(name, _)| *name == "values").unwrap().1.clone();
+        drop((packed, geography, structure));
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[values.offset as usize] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let corrupted = PackedMap::open(&path).unwrap();
+        assert!(corrupted.verify().is_err());
+        assert!(corrupted.unpack(&MemoryDirectory::new()).is_err());
+        drop(corrupted);
+
+        //as does a changed byte in the table, which can't be read past
+        bytes[HEADER_SIZE] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(PackedMap::open(&path).is_err());
+    }
+}
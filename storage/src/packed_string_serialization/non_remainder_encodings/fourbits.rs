@@ -88,6 +88,12 @@ macro_rules! impl_fourbit_encoding {
             value.push((nibble_a << 4) | nibble_b);
         }
 
+        //odd lengths end with the padding's END nibble, but even ones need a whole byte
+        // of END, or the reader carries on into whatever comes after the string
+        if bytes.len() % 2 == 0 {
+            value.push(0xFF);
+        }
+
         Some(value.into_boxed_slice())
     }
 
@@ -150,3 +156,21 @@ impl_fourbit_encoding! {
     ' ' => 13,
     '.' => 14
 }}
+
+#[cfg(test)]
+mod tests {
+    use crate::serialize_min::{DeserializeFromMinimal, SerializeMinimal};
+
+    #[test]
+    fn terminated() {
+        for value in ["1", "12", "123", "1234", "12-14", "(555) 0100"] {
+            let mut bytes = Vec::new();
+            value.minimally_serialize(&mut bytes, 0.into()).unwrap();
+            bytes.push(0x12);
+
+            let mut read = &bytes[..];
+            assert_eq!(String::deserialize_minimal(&mut read, None).unwrap(), value);
+            assert_eq!(read, &[0x12]);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
-    cmp::min, collections::BinaryHeap, io::{self, BufReader, BufWriter, Read, Write}, ops::{Deref, DerefMut}, sync::{
-        atomic::{AtomicBool, AtomicU8, AtomicUsize},
+    cmp::min, collections::{BTreeSet, BinaryHeap}, io::{self, BufReader, BufWriter, Read, Write}, ops::DerefMut, path::Path, sync::{
+        atomic::{AtomicBool, AtomicU8},
         Arc, Mutex,
     }, thread::panicking
 };
@@ -14,8 +14,26 @@ pub(super) const PAGE_HEADER_SIZE: usize = 16;
 
 pub(super) const ALLOWED_CACHE_PHYSICAL_PAGES: usize = 30_000;
 
+/// A format flag in the file header: each page's data starts with its length and CRC32C.
+const FORMAT_CHECKSUMS: u64 = 1;
+/// A format flag in the file header: each page's data is compressed with the zstd
+/// dictionary which the header points to, and framed by its length (and CRC32C, if the
+/// file has checksums).
+const FORMAT_DICTIONARY: u64 = 2;
+const KNOWN_FORMAT_FLAGS: u64 = FORMAT_CHECKSUMS | FORMAT_DICTIONARY;
+
+/// Where the first page of the free-page list is, in the file header. Files from before
+/// it existed have a 0 there, which is an empty list.
+const FREE_LIST_HEAD_OFFSET: u64 = PAGE_HEADER_SIZE as u64 + 16;
+/// Where the first page of the dictionary's chain is, in the file header, if the file
+/// has `FORMAT_DICTIONARY`
+const DICTIONARY_HEAD_OFFSET: u64 = PAGE_HEADER_SIZE as u64 + 24;
+
 use crate::{
     cache::Cache,
+    compression::{CompressionStats, Dictionary},
+    journal::JournaledFile,
+    mapped_file::MappedFile,
     multitype_paged_storage::{StoragePage, StoreByPage},
     pooled_storage::Filelike,
     serialize_fast::MinimalSerdeFast,
@@ -139,6 +157,11 @@ impl<const K: usize> PageId<K> {
         Self(i.get())
     }
 
+    /// The page's position in the file, counting the header page as 0.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
     pub(super) fn byte_offset(&self) -> u64 {
         (self.0 * K * THOUSAND) as u64
     }
@@ -176,6 +199,18 @@ where
 {
     pageuse: Arc<Mutex<PageUse<PAGE_SIZE_K, File>>>,
     cache: Cache<PageId<PAGE_SIZE_K>, Page<PAGE_SIZE_K, T, File>>,
+    /// The file's pages, if it was opened with `open_mapped`
+    mapped: Option<MappedPages>,
+}
+
+/// A mapped file, with what's needed to read its pages without locking the `PageUse`.
+/// The file can't be written to, so none of this changes.
+#[derive(Clone, Debug)]
+struct MappedPages {
+    file: MappedFile,
+    checksums: bool,
+    dictionary: Option<Arc<Dictionary>>,
+    lowest_unallocated_id: usize,
 }
 
 #[derive(Debug)]
@@ -183,38 +218,291 @@ pub(super) struct PageUse<const PAGE_SIZE_K: usize, File: Filelike> {
     pub(super) lowest_unallocated_id: usize,
     pub(super) freed_pages: BinaryHeap<std::cmp::Reverse<PageId<PAGE_SIZE_K>>>,
     pub(super) file: File,
+    /// Whether each page's data is framed by its length and checksum
+    pub(super) checksums: bool,
+    /// Whether `freed_pages` has changed since it was last saved
+    pub(super) freed_pages_changed: bool,
+    /// Whether the header points to a saved free-page list. Its pages are in
+    /// `freed_pages`, so it has to be unlinked before any free page is reused.
+    pub(super) free_list_linked: bool,
+    /// What each page's data is compressed with, if it is
+    pub(super) dictionary: Option<Arc<Dictionary>>,
 }
 
 impl<const K: usize, File: Filelike> PageUse<K, File> {
-    pub fn alloc_new(&mut self) -> PageId<K> {
-        if let Some(p) = self.freed_pages.pop() {
-            return p.0;
+    /// Read the file's header, or write one if the file is blank. The header is page 0:
+    /// after its page header come the lowest unallocated id, the format flags and the first
+    /// page of the free-page list.
+    pub(super) fn open(mut file: File, checksum_new_file: bool) -> io::Result<Self> {
+        let (lowest_unallocated_id, flags) = match file.len()? {
+            0 => {
+                //0 is reserved for the header, so 1 is the first id to allocate
+                let flags = if checksum_new_file { FORMAT_CHECKSUMS } else { 0 };
+
+                file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))?;
+                file.write_all(&1usize.to_le_bytes())?;
+                file.write_all(&flags.to_le_bytes())?;
+                //and an empty free-page list
+                file.write_all(&0usize.to_le_bytes())?;
+
+                (1, flags)
+            }
+            _ => Self::read_header(&mut file)?,
+        };
+
+        let mut pageuse = Self::with_header(file, lowest_unallocated_id, flags)?;
+        pageuse.load_free_pages()?;
+
+        Ok(pageuse)
+    }
+
+    /// Like `open`, but for a file which is only going to be read, such as a mapped one.
+    /// Nothing is written, not even to a blank file, and the free-page list isn't read,
+    /// since no page will be allocated.
+    pub(super) fn open_read_only(mut file: File) -> io::Result<Self> {
+        let (lowest_unallocated_id, flags) = Self::read_header(&mut file)?;
+
+        Self::with_header(file, lowest_unallocated_id, flags)
+    }
+
+    /// The lowest unallocated id and the format flags
+    fn read_header(file: &mut File) -> io::Result<(usize, u64)> {
+        file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))?;
+        let mut bytes = [0u8; (usize::BITS / 8) as usize];
+        debug_assert!(bytes.len() + 8 <= PageId::<K>::data_size());
+        file.read_exact(&mut bytes)?;
+
+        //files from before the flags existed have zeroes here
+        let mut flags = [0u8; 8];
+        file.read_exact(&mut flags)?;
+
+        let flags = u64::from_le_bytes(flags);
+        if flags & !KNOWN_FORMAT_FLAGS != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the page file was written in a newer format",
+            ));
+        }
+
+        Ok((usize::from_le_bytes(bytes), flags))
+    }
+
+    fn with_header(file: File, lowest_unallocated_id: usize, flags: u64) -> io::Result<Self> {
+        let mut pageuse = PageUse {
+            lowest_unallocated_id,
+            freed_pages: BinaryHeap::new(),
+            file,
+            checksums: flags & FORMAT_CHECKSUMS != 0,
+            freed_pages_changed: false,
+            free_list_linked: false,
+            dictionary: None,
+        };
+
+        if flags & FORMAT_DICTIONARY != 0 {
+            pageuse.dictionary = Some(Arc::new(pageuse.load_dictionary()?));
+        }
+
+        Ok(pageuse)
+    }
+
+    /// Read the dictionary which the header points to. Its chain is framed like a page's
+    /// data, but isn't compressed.
+    fn load_dictionary(&mut self) -> io::Result<Dictionary> {
+        self.file.seek(io::SeekFrom::Start(DICTIONARY_HEAD_OFFSET))?;
+        let mut bytes = [0u8; (usize::BITS / 8) as usize];
+        self.file.read_exact(&mut bytes)?;
+
+        let head = PageId::<K>(usize::from_le_bytes(bytes));
+        if head.0 == 0 || !self.is_valid(&head) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the page file's dictionary is missing",
+            ));
+        }
+
+        let checksums = self.checksums;
+        let mut reader = PageReader::<K, false, File> {
+            file: &mut self.file,
+            page_ids_acc: Vec::new(),
+            current_page_id: head,
+            current_page_read_amount: 0,
+        };
+
+        read_framed(&mut reader, &head, checksums).map(Dictionary::from_bytes)
+    }
+
+    /// Write `data` over the chain of `pages`, taking more pages or freeing the extra
+    /// ones as it needs, like a page is written when it's flushed. The first page is
+    /// always kept, so that the chain's id stays the same.
+    fn rewrite_chain(&mut self, pages: &[PageId<K>], data: &[u8]) -> io::Result<()> {
+        let mut writer = BufWriter::new(PageWriter {
+            storage: self,
+            added_pages: vec![],
+            state: WriterState::Begin { to_write: pages },
+        });
+        writer.write_all(data)?;
+        writer.flush()?;
+
+        let writer = writer
+            .into_inner()
+            .map_err(|_| Into::<std::io::Error>::into(std::io::ErrorKind::BrokenPipe))?;
+
+        let unused = match writer.state {
+            WriterState::Begin { to_write } => to_write.len(),
+            WriterState::WritingAllocated { to_write, .. } => to_write.len(),
+            _ => 0,
+        };
+
+        for page in &pages[pages.len() - unused..] {
+            self.free_page(*page)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the free-page list which was saved in the file. Nothing is written: the
+    /// header keeps pointing to the list until a free page is reused, so a file which is
+    /// only read is left as it was.
+    fn load_free_pages(&mut self) -> io::Result<()> {
+        self.file.seek(io::SeekFrom::Start(FREE_LIST_HEAD_OFFSET))?;
+        let mut bytes = [0u8; (usize::BITS / 8) as usize];
+        match self.file.read_exact(&mut bytes) {
+            Ok(()) => {}
+            //a file which never had a page allocated is only as long as its header's start
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        let mut next_list_page = PageId::<K>(usize::from_le_bytes(bytes)).as_valid();
+        if next_list_page.is_none() {
+            return Ok(());
+        }
+
+        while let Some(list_page) = next_list_page {
+            if !self.is_valid(&list_page) || self.freed_pages.iter().any(|p| p.0 == list_page) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the free-page list is corrupt",
+                ));
+            }
+
+            next_list_page = read_page_header(&mut self.file, &list_page)?;
+
+            self.file.seek(io::SeekFrom::Start(list_page.data_byte_offset()))?;
+            self.file.read_exact(&mut bytes)?;
+            let count = usize::from_le_bytes(bytes).min(free_list_page_capacity::<K>());
+
+            for _ in 0..count {
+                let free = PageId::<K>::deserialize_minimal(&mut self.file, ())?;
+                if free.0 != 0 && self.is_valid(&free) {
+                    self.freed_pages.push(std::cmp::Reverse(free));
+                }
+            }
+
+            //the list's own pages are free too, now that it's been read
+            self.freed_pages.push(std::cmp::Reverse(list_page));
+        }
+
+        self.free_list_linked = true;
+        Ok(())
+    }
+
+    /// Stop the header pointing to the saved free-page list, before one of its pages is
+    /// reused or truncated. Until the list is saved again, the free pages are only
+    /// leaked if the file isn't flushed, instead of being handed out twice.
+    fn unlink_free_list(&mut self) -> io::Result<()> {
+        if !self.free_list_linked {
+            return Ok(());
+        }
+
+        self.file.seek(io::SeekFrom::Start(FREE_LIST_HEAD_OFFSET))?;
+        self.file.write_all(&0usize.to_le_bytes())?;
+
+        self.free_list_linked = false;
+        self.freed_pages_changed = true;
+        Ok(())
+    }
+
+    /// Save the free pages in the file, so that they can be reused after it's reopened.
+    /// The list is kept in some of the free pages themselves: the highest ones, so that
+    /// the lowest are reused first. This is only done when the storage is flushed.
+    pub(super) fn save_free_pages(&mut self) -> io::Result<()> {
+        if !self.freed_pages_changed {
+            return Ok(());
+        }
+
+        let mut free: Vec<PageId<K>> = self.freed_pages.iter().map(|p| p.0).collect();
+        free.sort();
+
+        let mut list_pages = Vec::new();
+        while free.len() > list_pages.len() * free_list_page_capacity::<K>() {
+            list_pages.push(free.pop().unwrap());
+        }
+
+        let mut remaining = &free[..];
+        for (i, list_page) in list_pages.iter().enumerate() {
+            let next = list_pages.get(i + 1).map_or(0, |p| p.0);
+            let (listed, rest) =
+                remaining.split_at(remaining.len().min(free_list_page_capacity::<K>()));
+            remaining = rest;
+
+            //a page header pointing to the next page of the list, then the count and ids
+            let mut data = Vec::with_capacity(PageId::<K>::byte_size());
+            PageId::<K>(next).minimally_serialize(&mut data, ())?;
+            PageId::<K>(0).minimally_serialize(&mut data, ())?;
+            data.extend_from_slice(&listed.len().to_le_bytes());
+            for free in listed {
+                free.minimally_serialize(&mut data, ())?;
+            }
+
+            self.file.seek(io::SeekFrom::Start(list_page.byte_offset()))?;
+            self.file.write_all(&data)?;
+        }
+
+        let head = list_pages.first().map_or(0, |p| p.0);
+        self.file.seek(io::SeekFrom::Start(FREE_LIST_HEAD_OFFSET))?;
+        self.file.write_all(&head.to_le_bytes())?;
+
+        self.free_list_linked = head != 0;
+        self.freed_pages_changed = false;
+        Ok(())
+    }
+
+    pub fn alloc_new(&mut self) -> io::Result<PageId<K>> {
+        if let Some(p) = self.freed_pages.peek().copied() {
+            self.unlink_free_list()?;
+            self.freed_pages.pop();
+            self.freed_pages_changed = true;
+
+            //pages which held the free-page list still have its header
+            self.file.seek(io::SeekFrom::Start(p.0.byte_offset()))?;
+            self.file.write_all(&[0; PAGE_HEADER_SIZE])?;
+
+            return Ok(p.0);
         }
 
         let file = &mut self.file;
 
         let new_id_num = self.lowest_unallocated_id;
-        self.lowest_unallocated_id += 1;
         debug_assert_ne!(new_id_num, usize::MAX);
         let id = PageId(new_id_num);
 
         let newer_lowest_unallocated_id = new_id_num + 1;
 
-        file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))
-            .unwrap();
-        file.write_all(&newer_lowest_unallocated_id.to_le_bytes())
-            .unwrap();
+        file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))?;
+        file.write_all(&newer_lowest_unallocated_id.to_le_bytes())?;
+        self.lowest_unallocated_id = newer_lowest_unallocated_id;
 
-        if file.metadata().unwrap().len() < id.end_byte_offset() {
-            file.set_len(id.end_byte_offset()).unwrap();
+        if file.len()? < id.end_byte_offset() {
+            file.set_len(id.end_byte_offset())?;
         }
-        file.seek(io::SeekFrom::Start(id.byte_offset())).unwrap();
-        file.write_all(&[0; PAGE_HEADER_SIZE]).unwrap();
+        file.seek(io::SeekFrom::Start(id.byte_offset()))?;
+        file.write_all(&[0; PAGE_HEADER_SIZE])?;
 
-        id
+        Ok(id)
     }
 
-    pub fn truncate_unused(&mut self) {
+    pub fn truncate_unused(&mut self) -> io::Result<()> {
         let mut max_truncatable = self.lowest_unallocated_id;
         let mut freed_max = BinaryHeap::from_iter(self.freed_pages.iter().map(|x| x.0));
 
@@ -226,48 +514,50 @@ impl<const K: usize, File: Filelike> PageUse<K, File> {
             }
         }
 
-        self.file.set_len(PageId::<K>(max_truncatable).byte_offset()).unwrap();
+        //the truncated pages are unallocated now, rather than free
+        if max_truncatable < self.lowest_unallocated_id {
+            self.unlink_free_list()?;
+            self.freed_pages.retain(|p| p.0 .0 < max_truncatable);
+            self.freed_pages_changed = true;
+            self.lowest_unallocated_id = max_truncatable;
+
+            self.file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))?;
+            self.file.write_all(&max_truncatable.to_le_bytes())?;
+        }
+
+        self.file.set_len(PageId::<K>(max_truncatable).byte_offset())
     }
 
-    pub fn alloc_new_after(&mut self, old_page: PageId<K>) -> PageId<K> {
-        let new_page = self.alloc_new();
+    pub fn alloc_new_after(&mut self, old_page: PageId<K>) -> io::Result<PageId<K>> {
+        let new_page = self.alloc_new()?;
 
         let file = &mut self.file;
 
-        file.seek(io::SeekFrom::Start(old_page.byte_offset()))
-            .unwrap();
-        new_page.minimally_serialize(&mut *file, ()).unwrap();
+        file.seek(io::SeekFrom::Start(old_page.byte_offset()))?;
+        new_page.minimally_serialize(&mut *file, ())?;
 
-        file.seek(io::SeekFrom::Start(new_page.byte_offset() + 8))
-            .unwrap();
-        old_page.minimally_serialize(&mut *file, ()).unwrap();
+        file.seek(io::SeekFrom::Start(new_page.byte_offset() + 8))?;
+        old_page.minimally_serialize(&mut *file, ())?;
 
-        new_page
+        Ok(new_page)
     }
 
-    pub fn free_page(&mut self, free: PageId<K>) {
+    pub fn free_page(&mut self, free: PageId<K>) -> io::Result<()> {
         let previous_page = {
-            self.file
-                .seek(io::SeekFrom::Start(free.byte_offset() + 8))
-                .unwrap();
-            PageId::<K>::deserialize_minimal(&mut self.file, ())
-                .unwrap()
-                .as_valid()
+            self.file.seek(io::SeekFrom::Start(free.byte_offset() + 8))?;
+            PageId::<K>::deserialize_minimal(&mut self.file, ())?.as_valid()
         };
 
         self.freed_pages.push(std::cmp::Reverse(free));
+        self.freed_pages_changed = true;
 
         if let Some(prev) = previous_page {
-            self.file
-                .seek(io::SeekFrom::Start(prev.byte_offset()))
-                .unwrap();
-            self.file.write_all(&vec![0; 8]).unwrap();
+            self.file.seek(io::SeekFrom::Start(prev.byte_offset()))?;
+            self.file.write_all(&[0; 8])?;
         }
 
-        self.file
-            .seek(io::SeekFrom::Start(free.byte_offset()))
-            .unwrap();
-        self.file.write_all(&[0; PAGE_HEADER_SIZE]).unwrap();
+        self.file.seek(io::SeekFrom::Start(free.byte_offset()))?;
+        self.file.write_all(&[0; PAGE_HEADER_SIZE])
     }
 
     pub fn is_valid(&self, id: &PageId<K>) -> bool {
@@ -275,6 +565,12 @@ impl<const K: usize, File: Filelike> PageUse<K, File> {
     }
 }
 
+/// How many free pages' ids fit in a page of the free-page list, after the count
+const fn free_list_page_capacity<const K: usize>() -> usize {
+    let id_size = (usize::BITS / 8) as usize;
+    (PageId::<K>::data_size() - id_size) / id_size
+}
+
 impl<const K: usize, T: 'static, File: Filelike + 'static> StoreByPage<T>
     for PagedStorage<K, T, File>
 where
@@ -285,7 +581,7 @@ where
     type Page = Page<K, T, File>;
 
     fn new_page_with(&self, f: impl FnOnce() -> T) -> Self::PageId {
-        let id = self.pageuse.lock().unwrap().alloc_new();
+        let id = self.pageuse.lock().unwrap().alloc_new().expect("a page couldn't be allocated");
 
         let item = f();
 
@@ -314,24 +610,36 @@ where
         id
     }
 
-    fn get<'slf, 'b>(
+    fn try_get<'slf, 'b>(
         &'slf self,
         page_id: &Self::PageId,
         deserialize_data: <T as DeserializeFromMinimal>::ExternalData<'b>,
-    ) -> Option<Arc<Self::Page>> {
+    ) -> std::io::Result<Option<Arc<Self::Page>>> {
+        //mapped pages aren't cached; the OS keeps the ones which are used in memory
+        if let Some(mapped) = &self.mapped {
+            if page_id.0 >= mapped.lowest_unallocated_id {
+                return Ok(None);
+            }
+
+            let page = Page::open_mapped(&self.pageuse, mapped, page_id, deserialize_data)?;
+            return Ok(Some(Arc::new(page)));
+        }
+
         if !self.pageuse.lock().unwrap().is_valid(page_id) {
-            return None;
+            return Ok(None);
         }
 
-        Some(self.cache.get_or_insert(*page_id, || {
-            let p = Arc::new(Page::open(&self.pageuse, page_id, deserialize_data).unwrap());
+        let page = self.cache.try_get_or_insert(*page_id, || {
+            let p = Arc::new(Page::open(&self.pageuse, page_id, deserialize_data)?);
             let len = p.component_pages.len() * PageId::<K>::byte_size();
-            (len, p)
-        }))
+            Ok::<_, std::io::Error>((len, p))
+        })?;
+
+        Ok(Some(page))
     }
 
     fn truncate_unused(&mut self) {
-        self.pageuse.lock().unwrap().truncate_unused();
+        self.pageuse.lock().unwrap().truncate_unused().unwrap();
     }
 
     fn condense(&mut self, page_id: &Self::PageId) -> Option<Self::PageId> {
@@ -343,11 +651,11 @@ where
 
         let mut pageuse = self.pageuse.lock().unwrap();
 
-        let new_page = pageuse.alloc_new();
+        let new_page = pageuse.alloc_new().unwrap();
 
         //if we couldn't allocate a lower number, then bail
         if new_page.0 >= page_id.0 {
-            pageuse.free_page(new_page);
+            pageuse.free_page(new_page).unwrap();
             return Some(*page_id);
         }
 
@@ -363,7 +671,7 @@ where
         p_read.read_to_end(&mut buffer).unwrap();
 
         for old_page_id in p_read.into_inner().page_ids_acc {
-            pageuse.free_page(old_page_id);
+            pageuse.free_page(old_page_id).unwrap();
         }
 
         let pages_to_write = [new_page];
@@ -383,8 +691,9 @@ where
         Some(new_page)        
     }
 
-    fn flush(&self) {
+    fn flush(&self) -> io::Result<()> {
         self.cache.evict_all_possible();
+        self.pageuse.lock().unwrap().save_free_pages()
     }
 
     type SubView = Self;
@@ -393,6 +702,7 @@ where
         Self {
             pageuse: Arc::clone(&self.pageuse),
             cache: Cache::new(ALLOWED_CACHE_PHYSICAL_PAGES * PageId::<K>::byte_size()),
+            mapped: self.mapped.clone(),
         }
     }
 }
@@ -401,31 +711,269 @@ impl<const K: usize, T, File: Filelike> PagedStorage<K, T, File>
 where
     T: SerializeMinimal<ExternalData<'static> = ()> + DeserializeFromMinimal,
 {
-    pub fn open(mut file: File) -> Self {
-        let lowest_unallocated_id = match file.metadata().unwrap().len() {
-            0 => 1, //if it's a blank file, default to 1 as the lowest unallocated ID (0 is reserved)
-            _ => {
-                file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64))
-                    .unwrap();
-                let mut bytes = [0u8; (usize::BITS / 8) as usize];
-                debug_assert!(bytes.len() <= PageId::<K>::data_size());
-                file.read_exact(&mut bytes).unwrap();
-
-                usize::from_le_bytes(bytes)
-            }
-        };
+    /// Open a file of pages. A blank file is set up without checksums; an existing one
+    /// keeps whichever format it was made with. A header which can't be read is an error.
+    pub fn open(file: File) -> io::Result<Self> {
+        Self::open_with_format(file, false)
+    }
 
-        let pageuse = PageUse {
-            lowest_unallocated_id,
-            freed_pages: BinaryHeap::new(),
-            file,
-        };
+    /// Like `open`, but a blank file is set up to checksum each page's data, so that
+    /// corruption is found when the page is read instead of being deserialized as garbage.
+    pub fn open_checksummed(file: File) -> io::Result<Self> {
+        Self::open_with_format(file, true)
+    }
 
-        let pageuse = Arc::new(Mutex::new(pageuse));
+    fn open_with_format(file: File, checksum_new_file: bool) -> io::Result<Self> {
+        let pageuse = Arc::new(Mutex::new(PageUse::open(file, checksum_new_file)?));
 
-        Self {
+        Ok(Self {
             pageuse,
             cache: Cache::new(ALLOWED_CACHE_PHYSICAL_PAGES * PageId::<K>::byte_size()),
+            mapped: None,
+        })
+    }
+}
+
+impl<const K: usize, T, File: Filelike> PagedStorage<K, T, File>
+where
+    T: SerializeMinimal<ExternalData<'static> = ()> + DeserializeFromMinimal,
+{
+    /// Like `open_mapped`, but with the header read through `file`, which holds the same
+    /// bytes as `mapped`, such as a journaled file over the mapping. Only the header is
+    /// read, and nothing is written, so `file` can be read-only too.
+    pub fn open_mapped_as(file: File, mapped: MappedFile) -> io::Result<Self> {
+        if mapped.is_empty()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a page file can't be mapped before it's written",
+            ));
+        }
+
+        let pageuse = PageUse::open_read_only(file)?;
+        let mapped = MappedPages {
+            file: mapped,
+            checksums: pageuse.checksums,
+            dictionary: pageuse.dictionary.clone(),
+            lowest_unallocated_id: pageuse.lowest_unallocated_id,
+        };
+
+        Ok(Self {
+            pageuse: Arc::new(Mutex::new(pageuse)),
+            cache: Cache::new(ALLOWED_CACHE_PHYSICAL_PAGES * PageId::<K>::byte_size()),
+            mapped: Some(mapped),
+        })
+    }
+}
+
+impl<const K: usize, T> PagedStorage<K, T, MappedFile>
+where
+    T: SerializeMinimal<ExternalData<'static> = ()> + DeserializeFromMinimal,
+{
+    /// Open a file of pages read-only, mapped into memory. Pages are deserialized
+    /// straight from the mapping, so getting them never waits on a lock, and they aren't
+    /// cached. Nothing can be written, so the file has to have been written already.
+    pub fn open_mapped(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = MappedFile::open(path)?;
+        Self::open_mapped_as(file.clone(), file)
+    }
+}
+
+impl<const K: usize, T: 'static> PagedStorage<K, T, JournaledFile>
+where
+    T: SerializeMinimal<ExternalData<'static> = ()> + DeserializeFromMinimal,
+{
+    /// Write every page which isn't in use, then commit the file along with the rest of
+    /// its journal. Pages which are in use are left to a later commit.
+    pub fn commit(&self) -> io::Result<()> {
+        StoreByPage::flush(self)?;
+        self.pageuse.lock().unwrap().file.commit()
+    }
+}
+
+impl<const K: usize, T, File: Filelike> PagedStorage<K, T, File>
+where
+    T: SerializeMinimal<ExternalData<'static> = ()> + DeserializeFromMinimal,
+{
+    /// Follow the chain of pages which starts at `first` by their headers alone, without
+    /// reading or caching their data.
+    pub fn page_chain(&self, first: &PageId<K>) -> Result<Vec<PageId<K>>, ChainProblem<K>> {
+        let mut pageuse = self.pageuse.lock().unwrap();
+        let unreadable = |e: io::Error| ChainProblem::Unreadable(e.kind());
+
+        let mut chain = vec![*first];
+        let mut seen = BTreeSet::new();
+        let mut previous = None;
+        let mut current = *first;
+
+        loop {
+            if current.0 == 0 || !pageuse.is_valid(&current) {
+                return Err(ChainProblem::OutOfBounds {
+                    page: current,
+                    from: previous,
+                });
+            }
+            if !seen.insert(current) {
+                return Err(ChainProblem::Cycle { page: current });
+            }
+
+            let next = read_page_header(&mut pageuse.file, &current).map_err(unreadable)?;
+            let back = PageId::<K>::deserialize_minimal(&mut pageuse.file, ())
+                .map_err(unreadable)?
+                .as_valid();
+
+            if back != previous {
+                return Err(ChainProblem::BadBackPointer {
+                    page: current,
+                    expected: previous,
+                    found: back,
+                });
+            }
+
+            let Some(next) = next else {
+                return Ok(chain);
+            };
+            chain.push(next);
+            previous = Some(current);
+            current = next;
+        }
+    }
+
+    /// The data in the chain of pages which starts at `first`, straight from the file,
+    /// checked against its checksum if the file has them and decompressed if it has a
+    /// dictionary. The chain is followed blindly, so check it with `page_chain` first.
+    pub fn read_chain_data(&self, first: &PageId<K>) -> io::Result<Vec<u8>> {
+        let mut pageuse = self.pageuse.lock().unwrap();
+        let checksums = pageuse.checksums;
+        let dictionary = pageuse.dictionary.clone();
+
+        let mut reader = PageReader::<K, false, File> {
+            file: &mut pageuse.file,
+            page_ids_acc: Vec::new(),
+            current_page_id: *first,
+            current_page_read_amount: 0,
+        };
+
+        if let Some(dictionary) = dictionary {
+            return dictionary.decompress(&read_framed(&mut reader, first, checksums)?);
+        }
+        if checksums {
+            return read_checksummed(&mut reader, first);
+        }
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Compress the data of each chain which starts at one of `chains` with `dictionary`,
+    /// then keep the dictionary in the file, so that every page is written compressed
+    /// from now on. The chains have to be all of the file's pages which are in use, and
+    /// none of them can be loaded. Returns the chains' size before and after.
+    ///
+    /// If the file is journaled, nothing is committed until `commit`, so a file which is
+    /// only partly compressed is never left behind.
+    pub fn compress_with(
+        &mut self,
+        chains: &[PageId<K>],
+        dictionary: Dictionary,
+    ) -> io::Result<CompressionStats> {
+        self.cache.evict_all_possible();
+        if !self.cache.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pages are in use, so they can't be compressed",
+            ));
+        }
+        if self.pageuse.lock().unwrap().dictionary.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the page file is already compressed",
+            ));
+        }
+
+        let mut stats = CompressionStats::default();
+
+        for first in chains {
+            let pages = self
+                .page_chain(first)
+                .map_err(|problem| io::Error::new(io::ErrorKind::InvalidData, problem.to_string()))?;
+            let data = self.read_chain_data(first)?;
+
+            let compressed = dictionary.compress(&data)?;
+            stats.record(data.len(), compressed.len());
+
+            let mut pageuse = self.pageuse.lock().unwrap();
+            let mut framed = Vec::new();
+            write_framed(&mut framed, &compressed, pageuse.checksums)?;
+            pageuse.rewrite_chain(&pages, &framed)?;
+        }
+
+        let mut pageuse = self.pageuse.lock().unwrap();
+
+        //the dictionary itself can't be compressed
+        let mut framed = Vec::new();
+        write_framed(&mut framed, dictionary.as_bytes(), pageuse.checksums)?;
+        let head = pageuse.alloc_new()?;
+        pageuse.rewrite_chain(&[head], &framed)?;
+
+        let flags = FORMAT_DICTIONARY | if pageuse.checksums { FORMAT_CHECKSUMS } else { 0 };
+        pageuse.file.seek(io::SeekFrom::Start(PAGE_HEADER_SIZE as u64 + 8))?;
+        pageuse.file.write_all(&flags.to_le_bytes())?;
+        pageuse.file.seek(io::SeekFrom::Start(DICTIONARY_HEAD_OFFSET))?;
+        pageuse.file.write_all(&head.0.to_le_bytes())?;
+
+        pageuse.dictionary = Some(Arc::new(dictionary));
+
+        Ok(stats)
+    }
+}
+
+/// Something wrong with a chain of pages, as `PagedStorage::page_chain` finds it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChainProblem<const PAGE_SIZE_K: usize> {
+    /// The page is the file's header, or hasn't been allocated. `from` is the page which
+    /// pointed to it, if it isn't the first.
+    OutOfBounds {
+        page: PageId<PAGE_SIZE_K>,
+        from: Option<PageId<PAGE_SIZE_K>>,
+    },
+    /// The chain comes back around to a page it already went through
+    Cycle { page: PageId<PAGE_SIZE_K> },
+    /// The page's header doesn't point back to the page before it
+    BadBackPointer {
+        page: PageId<PAGE_SIZE_K>,
+        expected: Option<PageId<PAGE_SIZE_K>>,
+        found: Option<PageId<PAGE_SIZE_K>>,
+    },
+    Unreadable(io::ErrorKind),
+}
+
+impl<const K: usize> std::fmt::Display for ChainProblem<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let index = |p: &Option<PageId<K>>| p.map_or("none".to_string(), |p| p.0.to_string());
+
+        match self {
+            ChainProblem::OutOfBounds { page, from: None } => {
+                write!(f, "page {} isn't an allocated page", page.0)
+            }
+            ChainProblem::OutOfBounds {
+                page,
+                from: Some(from),
+            } => write!(f, "page {} points to page {}, which isn't allocated", from.0, page.0),
+            ChainProblem::Cycle { page } => write!(f, "the chain loops back to page {}", page.0),
+            ChainProblem::BadBackPointer {
+                page,
+                expected,
+                found,
+            } => write!(
+                f,
+                "page {} points back to page {} instead of page {}",
+                page.0,
+                index(found),
+                index(expected)
+            ),
+            ChainProblem::Unreadable(kind) => write!(f, "the page headers couldn't be read: {kind}"),
         }
     }
 }
@@ -628,12 +1176,20 @@ impl<const K: usize, T, File: Filelike> Page<K, T, File>
 where
     T: SerializeMinimal<ExternalData<'static> = ()> + DeserializeFromMinimal,
 {
+    /// The physical pages which this page's data is spread over, in order. The first is
+    /// the page's own id.
+    pub fn component_pages(&self) -> &[PageId<K>] {
+        &self.component_pages
+    }
+
     pub(super) fn open<'a>(
         pageuse: &Arc<Mutex<PageUse<K, File>>>,
         page_id: &PageId<K>,
         deserialize: <T as DeserializeFromMinimal>::ExternalData<'a>,
     ) -> std::io::Result<Self> {
         let pg = &mut pageuse.lock().unwrap();
+        let checksums = pg.checksums;
+        let dictionary = pg.dictionary.clone();
 
         let mut reader = BufReader::with_capacity(
             PageId::<K>::data_size(),
@@ -645,7 +1201,7 @@ where
             },
         );
 
-        let item = T::deserialize_minimal(&mut reader, deserialize)?;
+        let item = read_item(&mut reader, page_id, checksums, dictionary.as_deref(), deserialize)?;
 
         let reader = reader.into_inner();
 
@@ -658,6 +1214,38 @@ where
         })
     }
 
+    /// Like `open`, but reading straight from the mapped file instead of locking the
+    /// `PageUse`
+    fn open_mapped<'a>(
+        pageuse: &Arc<Mutex<PageUse<K, File>>>,
+        mapped: &MappedPages,
+        page_id: &PageId<K>,
+        deserialize: <T as DeserializeFromMinimal>::ExternalData<'a>,
+    ) -> std::io::Result<Self> {
+        let mut reader = MappedPageReader {
+            bytes: mapped.file.as_bytes(),
+            page_ids_acc: vec![*page_id],
+            current_page_id: *page_id,
+            current_page_read_amount: 0,
+        };
+
+        let item = read_item(
+            &mut reader,
+            page_id,
+            mapped.checksums,
+            mapped.dictionary.as_deref(),
+            deserialize,
+        )?;
+
+        Ok(Self {
+            pageuse: Arc::clone(pageuse),
+            item: RwLock::new(item),
+            dirty: false.into(),
+            free_state: 0.into(),
+            component_pages: reader.page_ids_acc,
+        })
+    }
+
     ///
     /// ONLY CALL if this Page is not currently accessible in the cache
     /// Page may not be loaded from disk before this method has completed.
@@ -670,10 +1258,12 @@ where
             let skip_serializing_always_free = (free_state & 0b10) != 0;
 
             let mut storage = self.pageuse.lock().unwrap();
+            let checksums = storage.checksums;
+            let dictionary = storage.dictionary.clone();
 
             if skip_serializing_always_free {
                 for page_to_free in self.component_pages.iter() {
-                    storage.free_page(*page_to_free);
+                    storage.free_page(*page_to_free)?;
                 }
                 return Ok(());
             }
@@ -686,7 +1276,17 @@ where
                 },
             });
 
-            self.item.get_mut().minimally_serialize(&mut writer, ())?;
+            if let Some(dictionary) = dictionary {
+                let mut data = Vec::new();
+                self.item.get_mut().minimally_serialize(&mut data, ())?;
+                write_framed(&mut writer, &dictionary.compress(&data)?, checksums)?;
+            } else if checksums {
+                let mut data = Vec::new();
+                self.item.get_mut().minimally_serialize(&mut data, ())?;
+                write_checksummed(&mut writer, &data)?;
+            } else {
+                self.item.get_mut().minimally_serialize(&mut writer, ())?;
+            }
             writer.flush()?;
 
             let writer = writer
@@ -716,7 +1316,7 @@ where
             self.component_pages.extend_from_slice(&writer_added_pages);
 
             for page_to_free in writer_freed_pages {
-                storage.free_page(page_to_free);
+                storage.free_page(page_to_free)?;
             }
         }
 
@@ -738,6 +1338,116 @@ where
     }
 }
 
+/// A page's data didn't match the checksum which was written with it. Reads return it
+/// as the inner error of an `InvalidData` `io::Error`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChecksumMismatch<const PAGE_SIZE_K: usize> {
+    pub page: PageId<PAGE_SIZE_K>,
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl<const K: usize> std::fmt::Display for ChecksumMismatch<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "page {} is corrupt: its checksum is {:08x}, but its data's is {:08x}",
+            self.page.0, self.stored, self.computed
+        )
+    }
+}
+
+impl<const K: usize> std::error::Error for ChecksumMismatch<K> {}
+
+/// The data's length and CRC32C, then the data.
+fn write_checksummed(to: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(data.len()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+    to.write_all(&len.to_le_bytes())?;
+    to.write_all(&crc32c::crc32c(data).to_le_bytes())?;
+    to.write_all(data)
+}
+
+fn read_checksummed<const K: usize>(from: &mut impl Read, page: &PageId<K>) -> io::Result<Vec<u8>> {
+    let mut frame = [0u8; 8];
+    from.read_exact(&mut frame)?;
+    let len = u32::from_le_bytes(frame[..4].try_into().unwrap());
+    let stored = u32::from_le_bytes(frame[4..].try_into().unwrap());
+
+    //a corrupt length can be huge, so don't allocate for it up front
+    let mut data = Vec::new();
+    from.take(len as u64).read_to_end(&mut data)?;
+
+    let computed = crc32c::crc32c(&data);
+    if data.len() != len as usize || computed != stored {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            ChecksumMismatch {
+                page: *page,
+                stored,
+                computed,
+            },
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Deserialize a page's item from its data, however the file stores it
+fn read_item<'a, const K: usize, T: DeserializeFromMinimal>(
+    from: &mut impl Read,
+    page_id: &PageId<K>,
+    checksums: bool,
+    dictionary: Option<&Dictionary>,
+    deserialize: <T as DeserializeFromMinimal>::ExternalData<'a>,
+) -> io::Result<T> {
+    match dictionary {
+        Some(dictionary) => {
+            let data = dictionary.decompress(&read_framed(from, page_id, checksums)?)?;
+            T::deserialize_minimal(&mut &data[..], deserialize)
+        }
+        None if checksums => {
+            let data = read_checksummed(from, page_id)?;
+            T::deserialize_minimal(&mut &data[..], deserialize)
+        }
+        None => T::deserialize_minimal(from, deserialize),
+    }
+}
+
+/// Compressed data's length, then the data, or checksummed like uncompressed data if the
+/// file has checksums
+fn write_framed(to: &mut impl Write, data: &[u8], checksums: bool) -> io::Result<()> {
+    if checksums {
+        return write_checksummed(to, data);
+    }
+
+    let len = u32::try_from(data.len()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    to.write_all(&len.to_le_bytes())?;
+    to.write_all(data)
+}
+
+fn read_framed<const K: usize>(
+    from: &mut impl Read,
+    page: &PageId<K>,
+    checksums: bool,
+) -> io::Result<Vec<u8>> {
+    if checksums {
+        return read_checksummed(from, page);
+    }
+
+    let mut len = [0u8; 4];
+    from.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as u64;
+
+    let mut data = Vec::new();
+    from.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(data)
+}
+
 pub(super) fn read_page_header<const K: usize>(
     file: &mut impl Filelike,
     page_id: &PageId<K>,
@@ -811,7 +1521,7 @@ impl<'a, const K: usize, File: Filelike> Write for PageWriter<'a, K, File> {
                 current, written, ..
             } => (current, written),
             WriterState::NeedsNewAllocation { previous } => {
-                let current = self.storage.alloc_new_after(previous);
+                let current = self.storage.alloc_new_after(previous)?;
                 self.added_pages.push(current);
                 self.state = WriterState::WritingNew {
                     written: 0,
@@ -835,7 +1545,7 @@ impl<'a, const K: usize, File: Filelike> Write for PageWriter<'a, K, File> {
                 WriterState::WritingAllocated {
                     current, to_write, ..
                 } => {
-                    if to_write.len() == 0 {
+                    if to_write.is_empty() {
                         self.state = WriterState::NeedsNewAllocation { previous: current };
                     } else {
                         self.state = WriterState::Begin { to_write };
@@ -858,8 +1568,8 @@ impl<'a, const K: usize, File: Filelike> Write for PageWriter<'a, K, File> {
     }
 }
 
-fn slice_pop<'a, 'b, T>(slice: &'a mut &'b [T]) -> Option<&'b T> {
-    let head = slice.get(0)?;
+fn slice_pop<'b, T>(slice: &mut &'b [T]) -> Option<&'b T> {
+    let head = slice.first()?;
 
     *slice = &slice[1..];
 
@@ -906,6 +1616,42 @@ impl<'a, const K: usize, const B: bool, F: Filelike> Read for PageReader<'a, K,
     }
 }
 
+/// Reads a chain of pages like `PageReader`, but from a mapped file's bytes
+struct MappedPageReader<'a, const PAGE_SIZE_K: usize> {
+    bytes: &'a [u8],
+    page_ids_acc: Vec<PageId<PAGE_SIZE_K>>,
+    current_page_id: PageId<PAGE_SIZE_K>,
+    current_page_read_amount: usize,
+}
+
+impl<const K: usize> Read for MappedPageReader<'_, K> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.current_page_id.data_byte_offset() as usize + self.current_page_read_amount;
+        let end = min(self.current_page_id.end_byte_offset() as usize, self.bytes.len());
+        let available = self.bytes.get(start..end).unwrap_or_default();
+
+        let read_count = min(buf.len(), available.len());
+        buf[..read_count].copy_from_slice(&available[..read_count]);
+
+        self.current_page_read_amount += read_count;
+
+        if self.current_page_read_amount == PageId::<K>::data_size() {
+            let mut header = self
+                .bytes
+                .get(self.current_page_id.byte_offset() as usize..)
+                .unwrap_or_default();
+
+            if let Some(next_page_id) = PageId::<K>::deserialize_minimal(&mut header, ())?.as_valid() {
+                self.page_ids_acc.push(next_page_id);
+                self.current_page_id = next_page_id;
+                self.current_page_read_amount = 0;
+            }
+        }
+
+        Ok(read_count)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use lru_cache::TopNHeap;
@@ -931,7 +1677,7 @@ mod test {
             let blob_count = 2000;
 
             let _ = std::fs::remove_file(".test");
-            let storage = PagedStorage::<4, Vec<usize>>::open(open_file(".test"));
+            let storage = PagedStorage::<4, Vec<usize>>::open(open_file(".test")).unwrap();
             let mut ids = Vec::new();
 
             //initial population
@@ -994,7 +1740,7 @@ mod test {
     #[test]
     pub fn store_one_page() {
         let _ = std::fs::remove_file(".test");
-        let storage = PagedStorage::<4, _>::open(open_file(".test"));
+        let storage = PagedStorage::<4, _>::open(open_file(".test")).unwrap();
 
         let mut ids = Vec::new();
 
@@ -1010,7 +1756,7 @@ mod test {
             ids.push(id);
         }
 
-        storage.flush();
+        storage.flush().unwrap();
 
         dbg!(&storage);
 
@@ -1020,7 +1766,7 @@ mod test {
             page.write()[0] = start;
         }
 
-        storage.flush();
+        storage.flush().unwrap();
 
         for id in ids.iter() {
             let page = storage.get(&id, ()).unwrap();
@@ -1031,6 +1777,232 @@ mod test {
             assert_eq!(weird_vec, *page.read());
         }
 
-        storage.flush();
+        storage.flush().unwrap();
+    }
+
+    #[test]
+    pub fn checksums() {
+        let path = std::env::temp_dir().join("paged_storage_checksums");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>>::open_checksummed(open_file(path)).unwrap();
+        let long = storage.new_page((0..2000).collect());
+        let short = storage.new_page(vec![7; 4]);
+        drop(storage);
+
+        //flip a byte in the short page's data, after the checksum frame and the length
+        let mut file = open_file(path);
+        let offset = short.data_byte_offset() + 10;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(offset)).unwrap();
+        let mut byte = [0u8];
+        file.read_exact(&mut byte).unwrap();
+        io::Seek::seek(&mut file, io::SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[byte[0] ^ 0xff]).unwrap();
+        drop(file);
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        assert_eq!(
+            *storage.get(&long, ()).unwrap().read(),
+            (0..2000).collect::<Vec<_>>()
+        );
+
+        let error = storage.try_get(&short, ()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mismatch = error
+            .into_inner()
+            .unwrap()
+            .downcast::<ChecksumMismatch<4>>()
+            .unwrap();
+        assert_eq!(mismatch.page, short);
+        assert_ne!(mismatch.stored, mismatch.computed);
+
+        assert_eq!(
+            storage.read_chain_data(&short).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    pub fn unchecksummed_files_stay_readable() {
+        let path = std::env::temp_dir().join("paged_storage_unchecksummed");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        let id = storage.new_page(vec![1, 2, 3]);
+        drop(storage);
+
+        let storage = PagedStorage::<4, Vec<u32>>::open_checksummed(open_file(path)).unwrap();
+        assert_eq!(*storage.try_get(&id, ()).unwrap().unwrap().read(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    pub fn unreadable_headers() {
+        let path = std::env::temp_dir().join("paged_storage_unreadable_header");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        storage.new_page(vec![1, 2, 3]);
+        drop(storage);
+
+        //a format flag from a newer version
+        let mut file = open_file(path);
+        io::Seek::seek(&mut file, io::SeekFrom::Start(PAGE_HEADER_SIZE as u64 + 8)).unwrap();
+        file.write_all(&(1u64 << 40).to_le_bytes()).unwrap();
+        drop(file);
+
+        let error = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        //a header cut off partway through
+        open_file(path).set_len(PAGE_HEADER_SIZE as u64 + 4).unwrap();
+        let error = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    pub fn free_pages_survive_reopening() {
+        let path = std::env::temp_dir().join("paged_storage_free_pages");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        let ids: Vec<_> = (0..1200).map(|i| storage.new_page(vec![i])).collect();
+        storage.flush().unwrap();
+
+        //more than fit in one page of the free-page list
+        let (freed, kept) = ids.split_at(1000);
+        for id in freed {
+            unsafe { storage.get(id, ()).unwrap().force_free() };
+        }
+        storage.flush().unwrap();
+        drop(storage);
+
+        let file_len = std::fs::metadata(path).unwrap().len();
+        let highest = ids.iter().max().unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        let reused: Vec<_> = (0..1000).map(|i| storage.new_page(vec![i])).collect();
+        assert!(reused.iter().all(|id| id < highest));
+
+        for (i, id) in kept.iter().enumerate() {
+            assert_eq!(*storage.get(id, ()).unwrap().read(), vec![1000 + i as u32]);
+        }
+        for (i, id) in reused.iter().enumerate() {
+            assert_eq!(*storage.get(id, ()).unwrap().read(), vec![i as u32]);
+        }
+        drop(storage);
+
+        assert_eq!(std::fs::metadata(path).unwrap().len(), file_len);
+    }
+
+    #[test]
+    pub fn free_pages_are_only_saved_on_flush() {
+        let path = std::env::temp_dir().join("paged_storage_free_pages_unflushed");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        let ids: Vec<_> = (0..20).map(|i| storage.new_page(vec![i])).collect();
+        for id in &ids[..10] {
+            unsafe { storage.get(id, ()).unwrap().force_free() };
+        }
+        storage.flush().unwrap();
+        drop(storage);
+
+        //only reading leaves the file as it was
+        let written = std::fs::read(path).unwrap();
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        assert_eq!(*storage.get(&ids[15], ()).unwrap().read(), vec![15]);
+        drop(storage);
+        assert_eq!(std::fs::read(path).unwrap(), written);
+
+        //reusing a page unlinks the list, so without a flush its pages are leaked rather
+        //than handed out again
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        let reused = storage.new_page(vec![100]);
+        assert!(ids[..10].contains(&reused));
+        drop(storage);
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        let highest = ids.iter().max().unwrap();
+        assert!(storage.new_page(vec![101]) > *highest);
+        assert_eq!(*storage.get(&reused, ()).unwrap().read(), vec![100]);
+    }
+
+    #[test]
+    pub fn mapped_pages() {
+        let path = std::env::temp_dir().join("paged_storage_mapped");
+        let _ = std::fs::remove_file(&path);
+
+        let storage = PagedStorage::<4, Vec<u32>>::open_checksummed(open_file(path.to_str().unwrap())).unwrap();
+        let mut ids: Vec<_> = (0..300).map(|i| storage.new_page(vec![i; 10])).collect();
+        //and one which takes several pages
+        ids.push(storage.new_page((0..5000).collect()));
+        //a file with a free-page list is mapped without it being touched
+        for i in 0..20 {
+            let id = storage.new_page(vec![i]);
+            unsafe { storage.get(&id, ()).unwrap().force_free() };
+        }
+        storage.flush().unwrap();
+        drop(storage);
+        let written = std::fs::read(&path).unwrap();
+
+        let storage = PagedStorage::<4, Vec<u32>, MappedFile>::open_mapped(&path).unwrap();
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let (storage, ids) = (&storage, &ids);
+                s.spawn(move || {
+                    for (i, id) in ids[..300].iter().enumerate().skip(t).step_by(4) {
+                        assert_eq!(*storage.get(id, ()).unwrap().read(), vec![i as u32; 10]);
+                    }
+                });
+            }
+        });
+
+        let long = storage.get(&ids[300], ()).unwrap();
+        assert_eq!(*long.read(), (0..5000).collect::<Vec<u32>>());
+        assert!(long.component_pages().len() > 1);
+
+        assert!(storage.try_get(&PageId(400), ()).unwrap().is_none());
+
+        storage.flush().unwrap();
+        drop(long);
+        drop(storage);
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+    }
+
+    #[test]
+    pub fn dictionary_compression() {
+        let path = std::env::temp_dir().join("paged_storage_dictionary");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let page = |i: u32| -> Vec<u32> { (0..1500).map(|j| (j % 40) * 1000 + i % 9).collect() };
+
+        let mut storage = PagedStorage::<4, Vec<u32>>::open_checksummed(open_file(path)).unwrap();
+        let ids: Vec<_> = (0..300).map(|i| storage.new_page(page(i))).collect();
+        storage.flush().unwrap();
+
+        let samples: Vec<_> = ids.iter().map(|id| storage.read_chain_data(id).unwrap()).collect();
+        let dictionary = Dictionary::train(&samples, 4096).unwrap();
+
+        let stats = storage.compress_with(&ids, dictionary).unwrap();
+        assert_eq!(stats.uncompressed, samples.iter().map(|s| s.len() as u64).sum::<u64>());
+        assert!(stats.compressed * 10 < stats.uncompressed);
+
+        //pages written afterwards are compressed too
+        let added = storage.new_page(page(300));
+        drop(storage);
+
+        let storage = PagedStorage::<4, Vec<u32>>::open(open_file(path)).unwrap();
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(storage.page_chain(id).unwrap().len(), 1);
+            assert_eq!(*storage.get(id, ()).unwrap().read(), page(i as u32));
+            assert_eq!(storage.read_chain_data(id).unwrap(), samples[i]);
+        }
+        assert_eq!(*storage.get(&added, ()).unwrap().read(), page(300));
     }
 }
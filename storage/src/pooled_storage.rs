@@ -1,12 +1,17 @@
 use std::{
-    borrow::Cow,
+    collections::HashMap,
     fmt::Debug,
     fs::File,
-    io::{Read, Seek, Write},
+    io::{BufReader, Read, Seek, Write},
     marker::PhantomData,
+    sync::Arc,
 };
 
-use crate::serialize_min::{DeserializeFromMinimal, MinimalSerializedSeek, SerializeMinimal};
+use crate::{
+    compression::{CompressionStats, Dictionary},
+    dedup_index::DedupIndex,
+    serialize_min::{DeserializeFromMinimal, MinimalSerializedSeek, SerializeMinimal},
+};
 use lru_cache::TopNHeap;
 use parking_lot::Mutex;
 use sha2::{Digest, Sha256};
@@ -15,106 +20,464 @@ const INLINING_AS_ID_THRESHOLD_BYTES: usize = 4;
 
 pub type PooledId = u64;
 
-pub trait Filelike: Write + Seek + Read + Send + Debug {
-    fn metadata(&self) -> std::io::Result<std::fs::Metadata>;
+pub trait Filelike: Write + Seek + Read + Send + Sync + Debug {
+    fn len(&self) -> std::io::Result<u64>;
     fn set_len(&self, size: u64) -> std::io::Result<()>;
+
+    /// Read from `offset` without using or moving the position, so that several
+    /// threads can read at once.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize>;
+    /// Write at `offset` without using or moving the position. Nothing is buffered.
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize>;
+
+    fn is_empty(&self) -> std::io::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// The whole file, if it's mapped into memory and so can be read without copying.
+    /// Nothing can be written to a file which is mapped.
+    fn mapped(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Make everything written so far durable. Only files on disk have anything to do.
+    fn sync_data(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset)? {
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset)? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                n => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 impl Filelike for File {
-    fn metadata(&self) -> std::io::Result<std::fs::Metadata> {
-        self.metadata()
+    fn len(&self) -> std::io::Result<u64> {
+        self.metadata().map(|m| m.len())
     }
 
     fn set_len(&self, size: u64) -> std::io::Result<()> {
         self.set_len(size)
     }
+
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+
+    #[cfg(unix)]
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::write_at(self, buf, offset)
+    }
+
+    //these do move the position on windows, but nothing which shares a file relies on it
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_write(self, buf, offset)
+    }
+
+    fn sync_data(&self) -> std::io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl Filelike for Box<dyn Filelike> {
+    fn len(&self) -> std::io::Result<u64> {
+        (**self).len()
+    }
+
+    fn set_len(&self, size: u64) -> std::io::Result<()> {
+        (**self).set_len(size)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        (**self).write_at(buf, offset)
+    }
+
+    fn mapped(&self) -> Option<&[u8]> {
+        (**self).mapped()
+    }
+
+    fn sync_data(&self) -> std::io::Result<()> {
+        (**self).sync_data()
+    }
+}
+
+/// Reads a `Filelike` from a position of its own, so that readers can share it
+struct ReaderAt<'a> {
+    file: &'a dyn Filelike,
+    position: u64,
+}
+
+impl Read for ReaderAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.file.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ReaderAt<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            std::io::SeekFrom::Start(p) => Some(p),
+            std::io::SeekFrom::End(d) => self.file.len()?.checked_add_signed(d),
+            std::io::SeekFrom::Current(d) => self.position.checked_add_signed(d),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+/// Values are mostly small, so a read only takes this much from the file at once
+const READ_BUFFER_SIZE: usize = 1024;
+
+fn reader_at(file: &dyn Filelike, position: u64) -> BufReader<ReaderAt<'_>> {
+    BufReader::with_capacity(READ_BUFFER_SIZE, ReaderAt { file, position })
 }
 
 const BLOCK_WRITE: usize = 3000;
 const BLOCK_HEADER_SIZE: u64 = 8;
 
+/// Set in a finished block's header if its values are followed by an offset table: one
+/// u32 for each value, giving where it starts relative to the block's first value.
+const INDEXED_FLAG: u64 = 1 << 63;
+const OFFSET_TABLE_SIZE: u64 = (BLOCK_WRITE * size_of::<u32>()) as u64;
+/// Set in a finished block's header, along with `INDEXED_FLAG`, if its values and offset
+/// table are one zstd frame, compressed with the pool's dictionary. The header's byte
+/// count is then the frame's.
+const COMPRESSED_FLAG: u64 = 1 << 62;
+const BLOCK_FLAGS: u64 = INDEXED_FLAG | COMPRESSED_FLAG;
+
+/// After the values, `flush` writes where each finished block starts (with its header's
+/// flags), the current block's value offsets, the number of finished blocks, the current
+/// block's value count and byte count, then this. A pool can then be reopened without
+/// reading through its blocks.
+const TRAILER_MAGIC: [u8; 8] = *b"POOLEND\x02";
+const TRAILER_TAIL_SIZE: u64 = 8 + 8 + 8 + TRAILER_MAGIC.len() as u64;
+
+/// The trailer of a pool with a dictionary: the dictionary, then what's in the other
+/// trailer, but with the dictionary's length before the number of finished blocks.
+const DICTIONARY_TRAILER_MAGIC: [u8; 8] = *b"POOLEND\x03";
+const DICTIONARY_TRAILER_TAIL_SIZE: u64 = 8 + TRAILER_TAIL_SIZE;
+
+/// The trailer written before blocks were indexed: the current block's value count and
+/// byte count, then this. It's still written if the current block's offsets aren't known.
+const UNINDEXED_TRAILER_MAGIC: [u8; 8] = *b"POOLEND\x01";
+const UNINDEXED_TRAILER_SIZE: u64 = 8 + 8 + UNINDEXED_TRAILER_MAGIC.len() as u64;
+
+/// Values which have been read are cached in this many separately locked parts, chosen
+/// by index, so that threads reading different values rarely wait for each other.
+const READ_CACHE_SHARDS: usize = 16;
+const READ_CACHE_SHARD_SIZE: usize = BLOCK_WRITE / READ_CACHE_SHARDS;
+
+type ReadCacheShard<T> = Mutex<TopNHeap<READ_CACHE_SHARD_SIZE, usize, Arc<T>>>;
+
+/// How many compressed blocks are kept decompressed, for reading more of their values
+const DECOMPRESSED_BLOCKS_CACHED: usize = 4;
+
+/// Values are only ever read and written at given positions, so `destination` is shared
+/// by every reader and the writer. `inner` is only locked to find where values are.
 pub struct Pool<T> {
+    destination: Box<dyn Filelike>,
     inner: Mutex<PoolInner<T>>,
+    recent_reads: [ReadCacheShard<T>; READ_CACHE_SHARDS],
+    decompressed_blocks: Mutex<TopNHeap<DECOMPRESSED_BLOCKS_CACHED, usize, Arc<Vec<u8>>>>,
+    reference_counts: Mutex<Option<HashMap<PooledId, usize>>>,
+}
+
+/// How a value is stored, as `Pool::value_location` finds it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueLocation {
+    /// Serialized into the id itself, because it was small enough
+    Inlined { bytes: [u8; INLINING_AS_ID_THRESHOLD_BYTES] },
+    /// Written to the pool, in the given block, at `offset` bytes from the pool's start
+    Pooled {
+        index: usize,
+        block: usize,
+        offset: u64,
+        length: u64,
+    },
+    /// Written to the pool in the given block, which is compressed, at `offset` bytes
+    /// from the start of the block's values once it's decompressed
+    Compressed {
+        index: usize,
+        block: usize,
+        offset: u64,
+        length: u64,
+    },
+}
+
+/// How many inserts stored a new value, and how many found the value already stored
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DedupStats {
+    pub stored: usize,
+    pub deduplicated: usize,
+}
+
+impl DedupStats {
+    /// The share of inserts which found their value already stored
+    pub fn hit_rate(&self) -> f64 {
+        match self.stored + self.deduplicated {
+            0 => 0.0,
+            inserts => self.deduplicated as f64 / inserts as f64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FinishedBlock {
+    header: u64,
+    indexed: bool,
+    compressed: bool,
+}
+
+/// Where each of a block's values starts, for `Pool::serialized_values`
+enum Offsets {
+    /// In the offset table at the given position, if the block has one
+    Table(Option<u64>),
+    /// As the current block's offsets, if they're known, and the block's size
+    Known(Option<Vec<u64>>, u64),
+    /// In the offset table at the end of the compressed block with the given index
+    Compressed(usize),
+}
+
+/// Where a value is, as far as a pool knows without reading the file
+enum Located {
+    At(u64),
+    /// At the offset in the offset table entry at `entry`
+    Indexed { first_value_byte: u64, entry: u64 },
+    /// After `skip` other values
+    Unindexed { first_value_byte: u64, skip: usize },
+    /// In the compressed block with the given index, at the offset in its offset table
+    Compressed { block: usize, index_in_block: usize },
+}
+
+/// Where a value's bytes start
+enum Position {
+    InFile(u64),
+    /// In a compressed block's values and offset table, decompressed
+    InBlock(Arc<Vec<u8>>, usize),
 }
 
 pub struct PoolInner<T> {
     value_count: usize,
 
     pool_offset: u64,
+    blocks: Vec<FinishedBlock>,
     current_block_first_value_byte: u64,
     current_block_first_value_index: usize,
     block_value_count: usize,
+    /// Where each of the current block's values starts, relative to its first value, or
+    /// `None` if the pool was opened from a trailer which didn't have them
+    current_block_offsets: Option<Vec<u64>>,
     current_block_size_bytes: u64,
-    destination: Box<dyn Filelike>,
+    /// Whether anything (a trailer, or a value which was only partly written) is after
+    /// the last value, to be cut off before the next value is written
+    after_values: bool,
+    /// Whether values have been inserted since the trailer was last written
+    needs_trailer: bool,
 
     recent_writes: TopNHeap<BLOCK_WRITE, [u8; 32], PooledId>,
-    recent_reads: TopNHeap<BLOCK_WRITE, usize, T>,
+    /// Every value's hash, for values which are inserted again after they've left
+    /// `recent_writes`
+    dedup_index: Option<DedupIndex>,
+    dedup_stats: DedupStats,
+    /// What finished blocks are compressed with, if they are
+    dictionary: Option<Arc<Dictionary>>,
+    /// Whether blocks are compressed as they're finished. A block is compressed where it
+    /// is, which a crash could leave half done, so this is only for `write_compressed`'s
+    /// copy of a pool.
+    compress_blocks: bool,
     __phantom: PhantomData<T>,
 }
 
-impl<T: DeserializeFromMinimal + MinimalSerializedSeek + Clone> Pool<T> {
+impl<T: DeserializeFromMinimal + MinimalSerializedSeek> Pool<T> {
+    /// The value with the given id, or `None` if the pool has no such value. Any number
+    /// of threads can call this at once, and while values are being inserted.
     pub fn get(
-        &mut self,
+        &self,
         id: PooledId,
         external_data: T::ExternalData<'_>,
-    ) -> std::io::Result<Option<Cow<T>>> {
+    ) -> std::io::Result<Option<Arc<T>>> {
+        if let Some(counts) = self.reference_counts.lock().as_mut() {
+            *counts.entry(id).or_default() += 1;
+        }
+
         //if it's inlined, return the owned data serialized into the ID
         let (idx, external_data) = match Self::id_to_maybe_item(id, external_data) {
-            Ok(f) => return Ok(Some(Cow::Owned(f?))),
+            Ok(f) => return Ok(Some(Arc::new(f?))),
             Err(e) => e,
         };
 
-        let mut inner = self.inner.get_mut();
+        //if it's in the cache, return it from there
+        let shard = &self.recent_reads[idx % READ_CACHE_SHARDS];
+        if let Some(value) = shard.lock().get(&idx) {
+            return Ok(Some(Arc::clone(value)));
+        }
 
         //if the index is too high, return none
-        if idx >= inner.value_count {
+        let Some(position) = self.value_position(idx)? else {
             return Ok(None);
-        }
+        };
+
+        //and read the item (finally)
+        let value = match position {
+            Position::InFile(position) => match self.destination.mapped() {
+                Some(mapped) => {
+                    let mut value = usize::try_from(position)
+                        .ok()
+                        .and_then(|position| mapped.get(position..))
+                        .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+                    T::deserialize_minimal(&mut value, external_data)?
+                }
+                None => {
+                    T::deserialize_minimal(&mut reader_at(&*self.destination, position), external_data)?
+                }
+            },
+            Position::InBlock(block, offset) => T::deserialize_minimal(&mut &block[offset..], external_data)?,
+        };
+        let value = Arc::new(value);
+        //put it in the cache
+        shard.lock().insert_and_increase(idx, Arc::clone(&value));
+
+        Ok(Some(value))
+    }
 
-        //if it's in the cache, return a borrow from the cache
-        if inner.recent_reads.contains(&idx) {
-            return Ok(inner.recent_reads.get(&idx).map(|x| Cow::Borrowed(x)));
+    /// Where the value with the given id is stored, or `None` if the pool has no such
+    /// value. This reads the value's length, so it's as slow as an uncached `get`.
+    pub fn value_location(&self, id: PooledId) -> std::io::Result<Option<ValueLocation>> {
+        if (id & 1) == 0 {
+            let bytes = (id >> 1).to_le_bytes()[..INLINING_AS_ID_THRESHOLD_BYTES]
+                .try_into()
+                .unwrap();
+            return Ok(Some(ValueLocation::Inlined { bytes }));
         }
 
-        let (block, index_in_block) = (idx / BLOCK_WRITE, idx % BLOCK_WRITE);
+        let index = (id >> 1) as usize;
 
-        //then: seek to the current block
-        let is_in_current_block = idx >= inner.current_block_first_value_index
-            && (idx - inner.current_block_first_value_index) < BLOCK_WRITE;
+        let start = match self.value_position(index)? {
+            None => return Ok(None),
+            Some(Position::InFile(start)) => start,
+            Some(Position::InBlock(block, offset)) => {
+                let mut value = &block[offset..];
+                T::seek_past(&mut value)?;
 
-        if is_in_current_block {
-            inner.destination.seek(std::io::SeekFrom::Start(
-                inner.current_block_first_value_byte,
-            ))?;
-        } else {
-            let block_count = idx / BLOCK_WRITE;
-            inner.destination
-                .seek(std::io::SeekFrom::Start(inner.pool_offset))?;
-            for _ in 0..block_count {
-                let mut h = [0u8; size_of::<u64>()];
-                inner.destination.read_exact(&mut h)?;
+                return Ok(Some(ValueLocation::Compressed {
+                    index,
+                    block: index / BLOCK_WRITE,
+                    offset: offset as u64,
+                    length: (block.len() - offset - value.len()) as u64,
+                }));
+            }
+        };
+        let mut reader = reader_at(&*self.destination, start);
+        T::seek_past(&mut reader)?;
+        let end = reader.stream_position()?;
+
+        Ok(Some(ValueLocation::Pooled {
+            index,
+            block: index / BLOCK_WRITE,
+            offset: start - self.inner.lock().pool_offset,
+            length: end - start,
+        }))
+    }
+
+    /// Start counting how many times `get` is called for each id, inlined or not. Any
+    /// earlier counts are cleared.
+    pub fn count_references(&self) {
+        *self.reference_counts.lock() = Some(HashMap::new());
+    }
 
-                let byte_count = u64::from_le_bytes(h);
+    /// The counts since `count_references`, which stops counting.
+    pub fn take_reference_counts(&self) -> HashMap<PooledId, usize> {
+        self.reference_counts.lock().take().unwrap_or_default()
+    }
 
-                inner.destination
-                    .seek_relative(byte_count as i64 + BLOCK_HEADER_SIZE as i64)?;
+    /// Where the value with the given index starts, or `None` if there isn't one. The
+    /// pool is only locked while the value is located; its position is read afterwards.
+    fn value_position(&self, idx: usize) -> std::io::Result<Option<Position>> {
+        let located = {
+            let mut inner = self.inner.lock();
+            if idx >= inner.value_count {
+                return Ok(None);
             }
+            inner.locate(&*self.destination, idx)?
+        };
 
-            inner.destination.seek_relative(BLOCK_HEADER_SIZE as i64)?;
-        }
+        let position = match located {
+            Located::At(position) => position,
+            Located::Indexed {
+                first_value_byte,
+                entry,
+            } => {
+                let mut o = [0u8; size_of::<u32>()];
+                self.destination.read_exact_at(&mut o, entry)?;
+                first_value_byte + u32::from_le_bytes(o) as u64
+            }
+            Located::Unindexed {
+                first_value_byte,
+                skip,
+            } => {
+                let mut reader = reader_at(&*self.destination, first_value_byte);
+                for _ in 0..skip {
+                    T::seek_past(&mut reader)?;
+                }
+                reader.stream_position()?
+            }
+            Located::Compressed {
+                block,
+                index_in_block,
+            } => {
+                let values = self.decompressed_block(block)?;
+                let offset = offset_in_decompressed(&values, index_in_block)?;
+                return Ok(Some(Position::InBlock(values, offset)));
+            }
+        };
 
-        //read past every previous item
-        let index_in_block = idx % BLOCK_WRITE;
-        for _ in 0..index_in_block {
-            T::seek_past(&mut inner.destination)?;
-        }
+        Ok(Some(Position::InFile(position)))
+    }
 
-        //and read the item (finally)
-        let val = T::deserialize_minimal(&mut inner.destination, external_data)?;
-        //put it in the cache
-        inner.recent_reads.insert_and_increase(idx, val);
+}
 
-        //and return a borrow from the cache
-        Ok(inner.recent_reads.get(&idx).map(|x| Cow::Borrowed(x)))
+impl<T: DeserializeFromMinimal> Pool<T> {
+    /// The value serialized into the id itself, or `None` if the id is of a value
+    /// written to the pool
+    pub fn inlined_value(id: PooledId, external_data: T::ExternalData<'_>) -> Option<std::io::Result<T>> {
+        Self::id_to_maybe_item(id, external_data).ok()
     }
 
     fn id_to_maybe_item(
@@ -134,30 +497,426 @@ impl<T: DeserializeFromMinimal + MinimalSerializedSeek + Clone> Pool<T> {
     }
 }
 
-impl<T: SerializeMinimal> Pool<T> {
-    pub fn new(mut destination: Box<dyn Filelike>) -> std::io::Result<Self> {
+impl<T: MinimalSerializedSeek> Pool<T> {
+    /// Open a pool which an earlier `Pool` wrote, starting at the destination's current
+    /// position, so that its values can be read and more can be added. If it wasn't
+    /// flushed after its last insert, the values in its last block are counted by
+    /// reading past each of them, and a value which was only partly written is dropped.
+    pub fn open(destination: Box<dyn Filelike>) -> std::io::Result<Self> {
+        Self::open_with(destination, |inner, file, end| {
+            let mut block_value_count = 0;
+            let mut offsets = Vec::new();
+            let mut values_end = inner.current_block_first_value_byte;
+
+            let mut reader = reader_at(file, values_end);
+            while values_end < end && block_value_count < BLOCK_WRITE {
+                match T::seek_past(&mut reader) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e),
+                }
+
+                let position = reader.stream_position()?;
+                if position > end {
+                    break;
+                }
+                offsets.push(values_end - inner.current_block_first_value_byte);
+                values_end = position;
+                block_value_count += 1;
+            }
+
+            inner.block_value_count = block_value_count;
+            inner.current_block_offsets = Some(offsets);
+            inner.current_block_size_bytes = values_end - inner.current_block_first_value_byte;
+            inner.after_values = values_end < end;
+            Ok(())
+        })
+    }
+}
+
+impl<T> Pool<T> {
+    /// Like `open`, but only for a pool which was flushed after its last insert, so that
+    /// its values never need to be read. That also works for values which can't be
+    /// read past on their own.
+    pub fn open_flushed(destination: Box<dyn Filelike>) -> std::io::Result<Self> {
+        Self::open_with(destination, |inner, _, end| {
+            //a pool which nothing was ever inserted into has no trailer, and nothing to count
+            if inner.blocks.is_empty() && end <= inner.current_block_first_value_byte {
+                return Ok(());
+            }
+
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the pool wasn't flushed after its last insert, so its last block can't be counted",
+            ))
+        })
+    }
+
+    /// Use the trailer for the pool's blocks and its current block's counts. Without
+    /// one, find the blocks from their headers, then use an unindexed trailer for the
+    /// counts, or `count_block` if there isn't a trailer at all. A blank destination gets
+    /// a new pool.
+    fn open_with(
+        mut destination: Box<dyn Filelike>,
+        count_block: impl FnOnce(&mut PoolInner<T>, &dyn Filelike, u64) -> std::io::Result<()>,
+    ) -> std::io::Result<Self> {
+        let pool_offset = destination.stream_position()?;
+        let end = destination.len()?;
+
+        if end <= pool_offset {
+            return Ok(Self::started_at(destination, pool_offset));
+        }
+
+        let mut pool = Self::started_at(destination, pool_offset);
+        let file = &*pool.destination;
+        let inner = pool.inner.get_mut();
+
+        if !inner.read_trailer(file, end)? {
+            inner.read_block_headers(file)?;
+
+            match inner.read_unindexed_trailer(file, end)? {
+                Some((block_value_count, block_size_bytes)) => {
+                    inner.block_value_count = block_value_count;
+                    inner.current_block_offsets = None;
+                    inner.current_block_size_bytes = block_size_bytes;
+                    inner.after_values = true;
+                }
+                None => count_block(inner, file, end)?,
+            }
+        }
+
+        inner.value_count = inner.current_block_first_value_index + inner.block_value_count;
+
+        Ok(pool)
+    }
+
+    /// The bytes of every value written to the pool, with its id, in the order they were
+    /// inserted. They're split up by the blocks' offset tables, so the values are never
+    /// decoded; a block which doesn't have one is an error.
+    pub fn serialized_values(&self) -> impl Iterator<Item = std::io::Result<(PooledId, Vec<u8>)>> + '_ {
+        let blocks = {
+            let inner = self.inner.lock();
+
+            let mut blocks: Vec<_> = inner
+                .blocks
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    //the table is at the end of the block, just before the next block's header
+                    let offsets = match b.compressed {
+                        true => Offsets::Compressed(i),
+                        false => Offsets::Table(
+                            b.indexed.then(|| inner.block_header(i + 1) - OFFSET_TABLE_SIZE),
+                        ),
+                    };
+                    let first_value_byte = b.header + BLOCK_HEADER_SIZE;
+                    (first_value_byte, i * BLOCK_WRITE, offsets)
+                })
+                .collect();
+            blocks.push((
+                inner.current_block_first_value_byte,
+                inner.current_block_first_value_index,
+                Offsets::Known(inner.current_block_offsets.clone(), inner.current_block_size_bytes),
+            ));
+            blocks
+        };
+
+        let mut blocks = blocks.into_iter();
+        let mut block: Option<(Vec<u8>, Vec<u64>, usize, usize)> = None;
+
+        std::iter::from_fn(move || loop {
+            if let Some((bytes, offsets, first_index, next)) = &mut block {
+                if let Some(start) = offsets.get(*next) {
+                    let end = offsets.get(*next + 1).map_or(bytes.len() as u64, |o| *o);
+                    let id = as_noninlined_id(*first_index + *next);
+                    *next += 1;
+
+                    return Some(Ok((id, bytes[*start as usize..end as usize].to_vec())));
+                }
+            }
+
+            let (first_value_byte, first_index, offsets) = blocks.next()?;
+            match self.read_block_values(first_value_byte, offsets) {
+                Ok((bytes, offsets)) => block = Some((bytes, offsets, first_index, 0)),
+                Err(e) => {
+                    blocks = Vec::new().into_iter();
+                    block = None;
+                    return Some(Err(e));
+                }
+            }
+        })
+    }
+
+    /// The bytes of the value with the given id, or `None` if the pool has no such value.
+    /// Like `serialized_values`, it's found with its block's offset table, so values which
+    /// can't be read past on their own can be read this way; a block which doesn't have
+    /// one is an error. An inlined value's bytes are the id's.
+    pub fn serialized_value(&self, id: PooledId) -> std::io::Result<Option<Vec<u8>>> {
+        if (id & 1) == 0 {
+            return Ok(Some((id >> 1).to_le_bytes()[..INLINING_AS_ID_THRESHOLD_BYTES].to_vec()));
+        }
+
+        let index = (id >> 1) as usize;
+        let block = index / BLOCK_WRITE;
+        let index_in_block = index % BLOCK_WRITE;
+        let unindexed = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the pool's block {block} has no offset table"),
+            )
+        };
+
+        let located = {
+            let inner = self.inner.lock();
+            if index >= inner.value_count {
+                return Ok(None);
+            }
+
+            match inner.blocks.get(block) {
+                Some(b) if b.compressed => Located::Compressed {
+                    block,
+                    index_in_block,
+                },
+                Some(b) if b.indexed => Located::Indexed {
+                    first_value_byte: b.header + BLOCK_HEADER_SIZE,
+                    entry: inner.block_header(block + 1) - OFFSET_TABLE_SIZE
+                        + (index_in_block * size_of::<u32>()) as u64,
+                },
+                Some(_) => return Err(unindexed()),
+                //the current block's offsets are in memory, and it may be written to once
+                // the pool's unlocked
+                None => {
+                    let offsets = inner.current_block_offsets.as_ref().ok_or_else(unindexed)?;
+                    let start = offsets[index_in_block];
+                    let end = offsets.get(index_in_block + 1).map_or(inner.current_block_size_bytes, |o| *o);
+
+                    let mut bytes = vec![0u8; (end - start) as usize];
+                    self.destination.read_exact_at(&mut bytes, inner.current_block_first_value_byte + start)?;
+                    return Ok(Some(bytes));
+                }
+            }
+        };
+
+        let (start, length) = match located {
+            Located::Indexed {
+                first_value_byte,
+                entry,
+            } => {
+                //a value ends where the next one starts, and the last one where the table does
+                let mut o = [0u8; 2 * size_of::<u32>()];
+                let entries = if index_in_block + 1 < BLOCK_WRITE { 2 } else { 1 };
+                self.destination.read_exact_at(&mut o[..entries * size_of::<u32>()], entry)?;
+
+                let start = u32::from_le_bytes(o[..4].try_into().unwrap()) as u64;
+                let end = match entries {
+                    2 => u32::from_le_bytes(o[4..].try_into().unwrap()) as u64,
+                    _ => entry + size_of::<u32>() as u64 - OFFSET_TABLE_SIZE - first_value_byte,
+                };
+                (first_value_byte + start, end - start)
+            }
+            Located::Compressed {
+                block,
+                index_in_block,
+            } => {
+                let values = self.decompressed_block(block)?;
+                let start = offset_in_decompressed(&values, index_in_block)?;
+                let end = match index_in_block + 1 < BLOCK_WRITE {
+                    true => offset_in_decompressed(&values, index_in_block + 1)?,
+                    false => values.len() - OFFSET_TABLE_SIZE as usize,
+                };
+                return Ok(Some(values[start..end].to_vec()));
+            }
+            Located::At(_) | Located::Unindexed { .. } => return Err(unindexed()),
+        };
+
+        let mut bytes = vec![0u8; length as usize];
+        self.destination.read_exact_at(&mut bytes, start)?;
+        Ok(Some(bytes))
+    }
+
+    /// Write a copy of the pool to `destination`, from its current position, with each
+    /// finished block compressed with `dictionary`, which is kept in the copy's trailer.
+    /// Every value keeps its id, so the copy can replace the pool. Values can still be
+    /// inserted into the copy once it's opened, but the blocks which they finish aren't
+    /// compressed. Returns the pool's size, and the copy's.
+    pub fn write_compressed(
+        &self,
+        mut destination: Box<dyn Filelike>,
+        dictionary: Dictionary,
+    ) -> std::io::Result<CompressionStats> {
         let pool_offset = destination.stream_position()?;
         destination.write_all(&[0; 8])?;
 
-        Ok(Pool {
+        let mut copy = Self::started_at(destination, pool_offset);
+        let inner = copy.inner.get_mut();
+        inner.dictionary = Some(Arc::new(dictionary));
+        inner.compress_blocks = true;
+
+        for value in self.serialized_values() {
+            let (_, blob) = value?;
+            inner.append(&*copy.destination, &blob)?;
+        }
+
+        //even an empty copy needs the dictionary
+        inner.needs_trailer = true;
+        inner.write_trailer(&*copy.destination)?;
+
+        let mut stats = CompressionStats::default();
+        let size = |file: &dyn Filelike, offset| file.len().map(|len| (len - offset) as usize);
+        stats.record(
+            size(&*self.destination, self.inner.lock().pool_offset)?,
+            size(&*copy.destination, pool_offset)?,
+        );
+
+        Ok(stats)
+    }
+
+    /// A block's values' bytes, and where each of them starts
+    fn read_block_values(&self, first_value_byte: u64, offsets: Offsets) -> std::io::Result<(Vec<u8>, Vec<u64>)> {
+        let unindexed = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the pool's block at {first_value_byte} has no offset table"),
+            )
+        };
+
+        let (offsets, size) = match offsets {
+            Offsets::Table(Some(table)) => {
+                let mut bytes = vec![0u8; OFFSET_TABLE_SIZE as usize];
+                self.destination.read_exact_at(&mut bytes, table)?;
+                let offsets = bytes
+                    .chunks_exact(size_of::<u32>())
+                    .map(|o| u32::from_le_bytes(o.try_into().unwrap()) as u64)
+                    .collect();
+                (offsets, table - first_value_byte)
+            }
+            Offsets::Known(Some(offsets), size) => (offsets, size),
+            Offsets::Compressed(block) => {
+                let mut values = self.decompress_block(block)?;
+                let size = values.len() - OFFSET_TABLE_SIZE as usize;
+                let offsets = (0..BLOCK_WRITE)
+                    .map(|i| offset_in_decompressed(&values, i).map(|o| o as u64))
+                    .collect::<std::io::Result<_>>()?;
+
+                values.truncate(size);
+                return Ok((values, offsets));
+            }
+            Offsets::Table(None) | Offsets::Known(None, _) => return Err(unindexed()),
+        };
+
+        let mut bytes = vec![0u8; size as usize];
+        self.destination.read_exact_at(&mut bytes, first_value_byte)?;
+
+        Ok((bytes, offsets))
+    }
+
+    /// A compressed block's values and offset table
+    fn decompress_block(&self, block: usize) -> std::io::Result<Vec<u8>> {
+        let (first_value_byte, size, dictionary) = {
+            let inner = self.inner.lock();
+            let first_value_byte = inner.blocks[block].header + BLOCK_HEADER_SIZE;
+            let size = inner.block_header(block + 1) - first_value_byte;
+            (first_value_byte, size, inner.dictionary.clone())
+        };
+
+        let dictionary = dictionary.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the pool's block {block} is compressed, but the pool has no dictionary"),
+            )
+        })?;
+
+        let mut compressed = vec![0u8; size as usize];
+        self.destination.read_exact_at(&mut compressed, first_value_byte)?;
+        let values = dictionary.decompress(&compressed)?;
+
+        if values.len() < OFFSET_TABLE_SIZE as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the pool's block {block} is too short to have an offset table"),
+            ));
+        }
+
+        Ok(values)
+    }
+
+    /// Like `decompress_block`, but the most used blocks are kept decompressed
+    fn decompressed_block(&self, block: usize) -> std::io::Result<Arc<Vec<u8>>> {
+        if let Some(values) = self.decompressed_blocks.lock().get(&block) {
+            return Ok(Arc::clone(values));
+        }
+
+        let values = Arc::new(self.decompress_block(block)?);
+        self.decompressed_blocks
+            .lock()
+            .insert_and_increase(block, Arc::clone(&values));
+
+        Ok(values)
+    }
+
+    fn started_at(destination: Box<dyn Filelike>, pool_offset: u64) -> Self {
+        Pool {
+            destination,
             inner: Mutex::new(PoolInner {
-                destination,
                 value_count: 0,
                 recent_writes: TopNHeap::new(),
-                recent_reads: TopNHeap::new(),
+                dedup_index: None,
+                dedup_stats: DedupStats::default(),
+                dictionary: None,
+                compress_blocks: false,
                 __phantom: PhantomData,
 
                 pool_offset,
+                blocks: Vec::new(),
                 current_block_size_bytes: 0,
                 block_value_count: 0,
+                current_block_offsets: Some(Vec::new()),
                 current_block_first_value_index: 0,
                 current_block_first_value_byte: pool_offset + BLOCK_HEADER_SIZE,
+                after_values: false,
+                needs_trailer: false,
             }),
-        })
+            recent_reads: std::array::from_fn(|_| Mutex::new(TopNHeap::new())),
+            decompressed_blocks: Mutex::new(TopNHeap::new()),
+            reference_counts: Mutex::new(None),
+        }
     }
 
+    /// Write the trailer, if anything's been inserted since it was last written, so that
+    /// the pool can be reopened without reading its last block. Writes aren't buffered,
+    /// so that's all there is to do.
     pub fn flush(&self) -> std::io::Result<()> {
-        self.inner.lock().destination.flush()
+        self.inner.lock().write_trailer(&*self.destination)
+    }
+
+    /// Look every inserted value up in `index`, as well as in the recently written
+    /// values, so that no value is stored twice. If the index wasn't flushed along with
+    /// this pool, it can't be trusted, so it's emptied, and only values inserted from
+    /// now on are found in it.
+    pub fn use_dedup_index(&mut self, mut index: DedupIndex) -> std::io::Result<()> {
+        let inner = self.inner.get_mut();
+
+        if index.value_count() != inner.value_count as u64 {
+            index.clear()?;
+            index.flush(inner.value_count as u64)?;
+        }
+
+        inner.dedup_index = Some(index);
+        Ok(())
+    }
+
+    /// How many inserts have found their value already stored, since the pool was opened
+    pub fn dedup_stats(&self) -> DedupStats {
+        self.inner.lock().dedup_stats
+    }
+}
+
+impl<T: SerializeMinimal> Pool<T> {
+    pub fn new(mut destination: Box<dyn Filelike>) -> std::io::Result<Self> {
+        let pool_offset = destination.stream_position()?;
+        destination.write_all(&[0; 8])?;
+
+        Ok(Self::started_at(destination, pool_offset))
     }
 
     pub fn insert<'s>(&self, item: &'s T, ctx: T::ExternalData<'s>) -> std::io::Result<PooledId> {
@@ -169,6 +928,12 @@ impl<T: SerializeMinimal> Pool<T> {
         Ok(r)
     }
 
+    /// Store bytes which a value of this pool's type was serialized to, like
+    /// `insert` does once it's serialized the value.
+    pub fn insert_serialized(&self, value_blob: &[u8]) -> std::io::Result<PooledId> {
+        self.insert_blob(&value_blob.to_vec())
+    }
+
     fn insert_blob(&self, value_blob: &Vec<u8>) -> std::io::Result<PooledId> {
         //let tiny blobs be inline instead of adding them to the pool
         if value_blob.len() <= INLINING_AS_ID_THRESHOLD_BYTES {
@@ -184,65 +949,794 @@ impl<T: SerializeMinimal> Pool<T> {
             return Ok(value << 1);
         }
 
-        let hash_arr = Sha256::digest(&value_blob);
+        let hash_arr = Sha256::digest(value_blob);
         let hash = hash_arr[..].try_into().unwrap();
 
         let mut inner = self.inner.lock();
 
         if let Some(id) = inner.recent_writes.get(hash).copied() {
+            inner.dedup_stats.deduplicated += 1;
             return Ok(id);
         }
 
-        let value_index = inner.value_count;
-        inner.destination.write_all(&value_blob)?;
-        inner.value_count += 1;
+        let indexed = match &inner.dedup_index {
+            Some(index) => index.get(hash)?,
+            None => None,
+        };
+        if let Some(id) = indexed {
+            inner.dedup_stats.deduplicated += 1;
+            inner.recent_writes.insert_and_increase(*hash, id);
+            return Ok(id);
+        }
 
-        let id = as_noninlined_id(value_index);
+        let id = inner.append(&*self.destination, value_blob)?;
 
         inner.recent_writes.insert_and_increase(*hash, id);
+        inner.dedup_stats.stored += 1;
+        if let Some(index) = inner.dedup_index.as_mut() {
+            index.insert(hash, id)?;
+        }
 
-        inner.post_insert(&value_blob)?;
+        Ok(id)
+    }
+}
 
-        //LSB is 1 to indicate that this is not inlined
-        return Ok(id);
+impl<T: MinimalSerializedSeek> PoolInner<T> {
+    /// Where the value with the given index is. Blocks with an offset table take one
+    /// more read to find it; others are read through up to the value.
+    fn locate(&mut self, file: &dyn Filelike, idx: usize) -> std::io::Result<Located> {
+        let block = idx / BLOCK_WRITE;
+        let index_in_block = idx % BLOCK_WRITE;
+
+        if block >= self.blocks.len() {
+            let offset = self.current_block_offset(file, index_in_block)?;
+            return Ok(Located::At(self.current_block_first_value_byte + offset));
+        }
+
+        let FinishedBlock {
+            header,
+            indexed,
+            compressed,
+        } = self.blocks[block];
+        let first_value_byte = header + BLOCK_HEADER_SIZE;
+
+        if compressed {
+            Ok(Located::Compressed {
+                block,
+                index_in_block,
+            })
+        } else if indexed {
+            //the table is at the end of the block, just before the next block's header
+            let table = self.block_header(block + 1) - OFFSET_TABLE_SIZE;
+            Ok(Located::Indexed {
+                first_value_byte,
+                entry: table + (index_in_block * size_of::<u32>()) as u64,
+            })
+        } else {
+            Ok(Located::Unindexed {
+                first_value_byte,
+                skip: index_in_block,
+            })
+        }
+    }
+
+    /// Where the current block's value with the given index starts, relative to the
+    /// block's first value, reading through the block first if that isn't known yet
+    fn current_block_offset(
+        &mut self,
+        file: &dyn Filelike,
+        index_in_block: usize,
+    ) -> std::io::Result<u64> {
+        if self.current_block_offsets.is_none() {
+            let mut reader = reader_at(file, self.current_block_first_value_byte);
+
+            let mut offsets = Vec::with_capacity(self.block_value_count);
+            for _ in 0..self.block_value_count {
+                let position = reader.stream_position()?;
+                offsets.push(position - self.current_block_first_value_byte);
+                T::seek_past(&mut reader)?;
+            }
+
+            self.current_block_offsets = Some(offsets);
+        }
+
+        Ok(self.current_block_offsets.as_ref().unwrap()[index_in_block])
     }
 }
 
 impl<T> PoolInner<T> {
-    fn post_insert(&mut self, blob: &Vec<u8>) -> std::io::Result<()> {
+    /// Write a value after the last one, whether or not it's already stored
+    fn append(&mut self, file: &dyn Filelike, blob: &[u8]) -> std::io::Result<PooledId> {
+        //there may be a trailer where the value goes
+        let values_end = self.end_of_values(file)?;
+
+        let value_index = self.value_count;
+        file.write_all_at(blob, values_end)?;
+        self.value_count += 1;
+        self.needs_trailer = true;
+
+        self.post_insert(file, blob)?;
+
+        //LSB is 1 to indicate that this is not inlined
+        Ok(as_noninlined_id(value_index))
+    }
+
+    fn post_insert(&mut self, file: &dyn Filelike, blob: &[u8]) -> std::io::Result<()> {
+        if let Some(offsets) = self.current_block_offsets.as_mut() {
+            offsets.push(self.current_block_size_bytes);
+        }
         self.current_block_size_bytes += blob.len() as u64;
         self.block_value_count += 1;
 
         if self.block_value_count >= BLOCK_WRITE {
-            //seek back to the header, then through the header to its start
-            let current_offset_from_block =
-                0 - ((self.current_block_size_bytes + BLOCK_HEADER_SIZE) as i64);
-            self.destination
-                .seek(std::io::SeekFrom::Current(current_offset_from_block))
-                .unwrap();
+            self.finish_block(file)?;
+        }
 
-            //write the byte count into the header
-            self.destination
-                .write_all(&self.current_block_size_bytes.to_le_bytes())
-                .unwrap();
+        Ok(())
+    }
 
-            //seek back to the end of the current block
-            self.destination
-                .seek_relative(self.current_block_size_bytes as i64)
-                .unwrap();
+    /// Where the header of the block with the given index starts. The block after the
+    /// last finished one is the current block.
+    fn block_header(&self, block: usize) -> u64 {
+        match self.blocks.get(block) {
+            Some(b) => b.header,
+            None => self.current_block_first_value_byte - BLOCK_HEADER_SIZE,
+        }
+    }
+
+    /// Where the next value goes, after cutting off anything after the last value, and
+    /// finishing the current block if it's full but wasn't finished
+    fn end_of_values(&mut self, file: &dyn Filelike) -> std::io::Result<u64> {
+        if self.block_value_count >= BLOCK_WRITE {
+            self.finish_block(file)?;
+        }
+
+        let values_end = self.current_block_first_value_byte + self.current_block_size_bytes;
+
+        if self.after_values {
+            file.set_len(values_end)?;
+            self.after_values = false;
+        }
+
+        Ok(values_end)
+    }
 
-            //write the next value's header
-            self.destination.write_all(&[0; 8]).unwrap();
-            //and reset bookkeeping values
-            self.current_block_first_value_index += self.block_value_count;
-            self.current_block_size_bytes = 0;
-            self.block_value_count = 0;
+    /// Write the current block's offset table after its values, if its offsets are
+    /// known and fit, and its byte count into its header. Then start the next block.
+    fn finish_block(&mut self, file: &dyn Filelike) -> std::io::Result<()> {
+        let header = self.current_block_first_value_byte - BLOCK_HEADER_SIZE;
+        let values_end = self.current_block_first_value_byte + self.current_block_size_bytes;
+        if self.after_values {
+            file.set_len(values_end)?;
+            self.after_values = false;
         }
 
+        let table = match &self.current_block_offsets {
+            Some(offsets) if self.current_block_size_bytes <= u32::MAX as u64 => {
+                debug_assert_eq!(offsets.len(), BLOCK_WRITE);
+
+                let mut table = Vec::with_capacity(OFFSET_TABLE_SIZE as usize);
+                for offset in offsets {
+                    table.extend_from_slice(&(*offset as u32).to_le_bytes());
+                }
+                Some(table)
+            }
+            _ => None,
+        };
+        let indexed = table.is_some();
+
+        //the values and their table are replaced by them compressed, unless that isn't
+        //any smaller
+        let compressed = match (&table, &self.dictionary) {
+            (Some(table), Some(dictionary)) if self.compress_blocks => {
+                let mut block = vec![0u8; self.current_block_size_bytes as usize];
+                file.read_exact_at(&mut block, self.current_block_first_value_byte)?;
+                block.extend_from_slice(table);
+
+                Some(dictionary.compress(&block)?).filter(|c| c.len() < block.len())
+            }
+            _ => None,
+        };
+
+        let block_end = match (&compressed, &table) {
+            (Some(compressed), _) => {
+                file.write_all_at(compressed, self.current_block_first_value_byte)?;
+                //the values which were compressed went further than the block does now
+                file.set_len(self.current_block_first_value_byte + compressed.len() as u64)?;
+                self.current_block_first_value_byte + compressed.len() as u64
+            }
+            (None, Some(table)) => {
+                file.write_all_at(table, values_end)?;
+                values_end + table.len() as u64
+            }
+            (None, None) => values_end,
+        };
+
+        let mut byte_count = block_end - self.current_block_first_value_byte;
+        if indexed {
+            byte_count |= INDEXED_FLAG;
+        }
+        if compressed.is_some() {
+            byte_count |= COMPRESSED_FLAG;
+        }
+
+        //write the next block's header after the end of this one
+        file.write_all_at(&[0; 8], block_end)?;
+        file.write_all_at(&byte_count.to_le_bytes(), header)?;
+
+        //and reset bookkeeping values
+        self.blocks.push(FinishedBlock {
+            header,
+            indexed,
+            compressed: compressed.is_some(),
+        });
+        self.current_block_first_value_index += self.block_value_count;
+        self.current_block_first_value_byte = block_end + BLOCK_HEADER_SIZE;
+        self.current_block_size_bytes = 0;
+        self.block_value_count = 0;
+        self.current_block_offsets = Some(Vec::new());
+        self.needs_trailer = true;
+
         Ok(())
     }
+
+    /// Find the finished blocks by following their headers from the pool's start. Each
+    /// has its byte count in its header; the current block has 0.
+    fn read_block_headers(&mut self, file: &dyn Filelike) -> std::io::Result<()> {
+        let mut block_start = self.pool_offset;
+        self.blocks.clear();
+
+        loop {
+            let mut h = [0u8; size_of::<u64>()];
+            file.read_exact_at(&mut h, block_start)?;
+            let header = u64::from_le_bytes(h);
+
+            if header == 0 {
+                break;
+            }
+
+            self.blocks.push(FinishedBlock {
+                header: block_start,
+                indexed: header & INDEXED_FLAG != 0,
+                compressed: header & COMPRESSED_FLAG != 0,
+            });
+            block_start += BLOCK_HEADER_SIZE + (header & !BLOCK_FLAGS);
+        }
+
+        self.current_block_first_value_index = self.blocks.len() * BLOCK_WRITE;
+        self.current_block_first_value_byte = block_start + BLOCK_HEADER_SIZE;
+
+        Ok(())
+    }
+
+    /// Write the trailer, and flush the dedup index to match it
+    fn write_trailer(&mut self, file: &dyn Filelike) -> std::io::Result<()> {
+        if !self.needs_trailer {
+            return Ok(());
+        }
+
+        let values_end = self.current_block_first_value_byte + self.current_block_size_bytes;
+        let mut trailer = Vec::new();
+
+        match &self.current_block_offsets {
+            Some(offsets) => {
+                if let Some(dictionary) = &self.dictionary {
+                    trailer.extend_from_slice(dictionary.as_bytes());
+                }
+                for block in &self.blocks {
+                    let mut flags = if block.indexed { INDEXED_FLAG } else { 0 };
+                    if block.compressed {
+                        flags |= COMPRESSED_FLAG;
+                    }
+                    trailer.extend_from_slice(&(block.header | flags).to_le_bytes());
+                }
+                for offset in offsets {
+                    trailer.extend_from_slice(&offset.to_le_bytes());
+                }
+                if let Some(dictionary) = &self.dictionary {
+                    trailer.extend_from_slice(&(dictionary.as_bytes().len() as u64).to_le_bytes());
+                }
+                trailer.extend_from_slice(&(self.blocks.len() as u64).to_le_bytes());
+                trailer.extend_from_slice(&(self.block_value_count as u64).to_le_bytes());
+                trailer.extend_from_slice(&self.current_block_size_bytes.to_le_bytes());
+                trailer.extend_from_slice(match self.dictionary {
+                    Some(_) => &DICTIONARY_TRAILER_MAGIC,
+                    None => &TRAILER_MAGIC,
+                });
+            }
+            None => {
+                trailer.extend_from_slice(&(self.block_value_count as u64).to_le_bytes());
+                trailer.extend_from_slice(&self.current_block_size_bytes.to_le_bytes());
+                trailer.extend_from_slice(&UNINDEXED_TRAILER_MAGIC);
+            }
+        }
+
+        file.write_all_at(&trailer, values_end)?;
+        file.set_len(values_end + trailer.len() as u64)?;
+
+        self.after_values = true;
+        self.needs_trailer = false;
+
+        if let Some(index) = self.dedup_index.as_mut() {
+            index.flush(self.value_count as u64)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set up the blocks and the current block's counts from the trailer, if there's an
+    /// indexed one which fits the file. Returns whether there was.
+    fn read_trailer(&mut self, file: &dyn Filelike, end: u64) -> std::io::Result<bool> {
+        if end < self.pool_offset + BLOCK_HEADER_SIZE + TRAILER_TAIL_SIZE {
+            return Ok(false);
+        }
+
+        let mut magic = [0u8; TRAILER_MAGIC.len()];
+        file.read_exact_at(&mut magic, end - TRAILER_MAGIC.len() as u64)?;
+
+        let tail_size = match magic {
+            TRAILER_MAGIC => TRAILER_TAIL_SIZE,
+            DICTIONARY_TRAILER_MAGIC => DICTIONARY_TRAILER_TAIL_SIZE,
+            _ => return Ok(false),
+        };
+        if end < self.pool_offset + BLOCK_HEADER_SIZE + tail_size {
+            return Ok(false);
+        }
+
+        let mut tail = vec![0u8; tail_size as usize];
+        file.read_exact_at(&mut tail, end - tail_size)?;
+
+        let mut words = tail
+            .chunks_exact(size_of::<u64>())
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()));
+        let dictionary_len = match magic {
+            DICTIONARY_TRAILER_MAGIC => words.next().unwrap(),
+            _ => 0,
+        };
+        let block_count = words.next().unwrap();
+        let block_value_count = words.next().unwrap();
+        let block_size_bytes = words.next().unwrap();
+
+        //work out where the current block starts, giving up on anything which doesn't fit
+        let current_block_header = block_count
+            .checked_add(block_value_count)
+            .and_then(|n| n.checked_mul(size_of::<u64>() as u64))
+            .and_then(|listed| listed.checked_add(dictionary_len))
+            .and_then(|listed| listed.checked_add(block_size_bytes))
+            .and_then(|listed| listed.checked_add(tail_size))
+            .and_then(|after_header| (end - BLOCK_HEADER_SIZE).checked_sub(after_header));
+        let current_block_header = match current_block_header {
+            Some(h) if h >= self.pool_offset && block_value_count <= BLOCK_WRITE as u64 => h,
+            _ => return Ok(false),
+        };
+
+        let values_end = current_block_header + BLOCK_HEADER_SIZE + block_size_bytes;
+        let mut dictionary = vec![0u8; dictionary_len as usize];
+        file.read_exact_at(&mut dictionary, values_end)?;
+
+        let listed_start = values_end + dictionary_len;
+        let mut listed = vec![0u8; (end - tail_size - listed_start) as usize];
+        file.read_exact_at(&mut listed, listed_start)?;
+
+        let mut words = listed
+            .chunks_exact(size_of::<u64>())
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()));
+        let blocks: Vec<_> = (&mut words)
+            .take(block_count as usize)
+            .map(|w| FinishedBlock {
+                header: w & !BLOCK_FLAGS,
+                indexed: w & INDEXED_FLAG != 0,
+                compressed: w & COMPRESSED_FLAG != 0,
+            })
+            .collect();
+        let offsets: Vec<_> = words.collect();
+
+        //the first block has to start the pool, and the last has to end where the current
+        //one starts
+        let fits = match blocks.last() {
+            None => current_block_header == self.pool_offset,
+            Some(last) => {
+                let mut h = [0u8; size_of::<u64>()];
+                file.read_exact_at(&mut h, last.header)?;
+                let byte_count = u64::from_le_bytes(h) & !BLOCK_FLAGS;
+
+                blocks[0].header == self.pool_offset
+                    && last.header + BLOCK_HEADER_SIZE + byte_count == current_block_header
+            }
+        };
+
+        if !fits || offsets.iter().any(|o| *o >= block_size_bytes) {
+            return Ok(false);
+        }
+
+        self.current_block_first_value_index = blocks.len() * BLOCK_WRITE;
+        self.current_block_first_value_byte = current_block_header + BLOCK_HEADER_SIZE;
+        self.blocks = blocks;
+        self.block_value_count = block_value_count as usize;
+        self.current_block_offsets = Some(offsets);
+        self.current_block_size_bytes = block_size_bytes;
+        self.after_values = true;
+        self.dictionary = (magic == DICTIONARY_TRAILER_MAGIC)
+            .then(|| Arc::new(Dictionary::from_bytes(dictionary)));
+
+        Ok(true)
+    }
+
+    /// The current block's value count and byte count from an unindexed trailer, if
+    /// there's one which fits the block
+    fn read_unindexed_trailer(
+        &mut self,
+        file: &dyn Filelike,
+        end: u64,
+    ) -> std::io::Result<Option<(usize, u64)>> {
+        if end < self.current_block_first_value_byte + UNINDEXED_TRAILER_SIZE {
+            return Ok(None);
+        }
+
+        let mut trailer = [0u8; UNINDEXED_TRAILER_SIZE as usize];
+        file.read_exact_at(&mut trailer, end - UNINDEXED_TRAILER_SIZE)?;
+
+        if trailer[16..] != UNINDEXED_TRAILER_MAGIC {
+            return Ok(None);
+        }
+
+        let block_value_count = u64::from_le_bytes(trailer[..8].try_into().unwrap()) as usize;
+        let block_size_bytes = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+
+        let fits = self.current_block_first_value_byte + block_size_bytes + UNINDEXED_TRAILER_SIZE
+            == end
+            && block_value_count <= BLOCK_WRITE;
+
+        Ok(fits.then_some((block_value_count, block_size_bytes)))
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        let e = self.inner.get_mut().write_trailer(&*self.destination);
+        if !std::thread::panicking() {
+            e.unwrap();
+        }
+    }
 }
 
 fn as_noninlined_id(i: usize) -> PooledId {
     ((i as u64) << 1) + 1
 }
+
+/// Where the value with the given index starts in a decompressed block, which has its
+/// offset table at its end like an uncompressed block
+fn offset_in_decompressed(block: &[u8], index_in_block: usize) -> std::io::Result<usize> {
+    let values_len = block.len() - OFFSET_TABLE_SIZE as usize;
+    let entry = values_len + index_in_block * size_of::<u32>();
+    let offset = u32::from_le_bytes(block[entry..entry + size_of::<u32>()].try_into().unwrap()) as usize;
+
+    if offset >= values_len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "a compressed block's offset table points past its values",
+        ));
+    }
+
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::serialize_fast::FastMinSerde;
+
+    #[test]
+    pub fn locations_and_reference_counts() {
+        let file = File::options()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(std::env::temp_dir().join("minimal_storage-pool_locations"))
+            .unwrap();
+        let pool = Pool::<FastMinSerde<u64>>::new(Box::new(file)).unwrap();
+
+        let ids: Vec<_> = (0..BLOCK_WRITE as u64 + 10)
+            .map(|i| pool.insert(&FastMinSerde(u64::MAX - i), ()).unwrap())
+            .collect();
+        //4 bytes or fewer are put in the id instead
+        let small = Pool::<FastMinSerde<u64>>::insert_blob(&pool, &vec![1, 2]).unwrap();
+
+        assert_eq!(
+            pool.value_location(small).unwrap(),
+            Some(ValueLocation::Inlined { bytes: [1, 2, 0, 0] })
+        );
+        assert_eq!(
+            pool.value_location(ids[1]).unwrap(),
+            Some(ValueLocation::Pooled { index: 1, block: 0, offset: BLOCK_HEADER_SIZE + 8, length: 8 })
+        );
+        //past the first block's values and offset table, and the second block's header
+        let second_block =
+            BLOCK_HEADER_SIZE + 8 * BLOCK_WRITE as u64 + OFFSET_TABLE_SIZE + BLOCK_HEADER_SIZE;
+        assert_eq!(
+            pool.value_location(ids[BLOCK_WRITE + 2]).unwrap(),
+            Some(ValueLocation::Pooled {
+                index: BLOCK_WRITE + 2,
+                block: 1,
+                offset: second_block + 16,
+                length: 8
+            })
+        );
+        assert_eq!(pool.value_location(as_noninlined_id(ids.len())).unwrap(), None);
+
+        pool.count_references();
+        for id in [ids[0], ids[5], ids[0], small] {
+            pool.get(id, ()).unwrap().unwrap();
+        }
+        let counts = pool.take_reference_counts();
+        assert_eq!(counts.len(), 3);
+        assert_eq!((counts[&ids[0]], counts[&ids[5]], counts[&small]), (2, 1, 1));
+
+        //and the counting stopped
+        pool.get(ids[0], ()).unwrap();
+        assert!(pool.take_reference_counts().is_empty());
+    }
+
+    fn pool_file(name: &str, truncate: bool) -> Box<File> {
+        let file = File::options()
+            .create(true)
+            .truncate(truncate)
+            .read(true)
+            .write(true)
+            .open(std::env::temp_dir().join(name))
+            .unwrap();
+        Box::new(file)
+    }
+
+    fn check_values(pool: &Pool<FastMinSerde<u64>>, ids: &[PooledId]) {
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(pool.get(*id, ()).unwrap().unwrap().0, u64::MAX - i as u64, "{i}");
+        }
+    }
+
+    #[test]
+    pub fn reopen_and_append() {
+        let name = "minimal_storage-pool_reopen";
+        let value = |i: usize| FastMinSerde(u64::MAX - i as u64);
+
+        //a pool which was never inserted into doesn't have a trailer
+        let pool = Pool::<FastMinSerde<u64>>::new(pool_file(name, true)).unwrap();
+        pool.flush().unwrap();
+        drop(pool);
+        let pool = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        assert_eq!(pool.value_location(as_noninlined_id(0)).unwrap(), None);
+        drop(pool);
+
+        let pool = Pool::<FastMinSerde<u64>>::new(pool_file(name, true)).unwrap();
+        let mut ids: Vec<_> = (0..BLOCK_WRITE + 5).map(|i| pool.insert(&value(i), ()).unwrap()).collect();
+        pool.flush().unwrap();
+        drop(pool);
+
+        //with a trailer, the values don't need to be read
+        let pool = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        check_values(&pool, &ids);
+        //reading moves the file around, but the next value still goes after the last
+        for i in ids.len()..BLOCK_WRITE * 2 + 3 {
+            pool.get(ids[0], ()).unwrap();
+            ids.push(pool.insert(&value(i), ()).unwrap());
+        }
+        check_values(&pool, &ids);
+        assert_eq!(ids.last(), Some(&as_noninlined_id(BLOCK_WRITE * 2 + 2)));
+
+        //without flushing, only `open` can count the last block, and cuts off a partial value
+        std::mem::forget(pool);
+        let mut file = pool_file(name, false);
+        file.seek(std::io::SeekFrom::End(0)).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        drop(file);
+
+        assert!(Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).is_err());
+
+        let pool = Pool::<FastMinSerde<u64>>::open(pool_file(name, false)).unwrap();
+        check_values(&pool, &ids);
+        ids.push(pool.insert(&value(ids.len()), ()).unwrap());
+        drop(pool);
+
+        let pool = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        check_values(&pool, &ids);
+        assert_eq!(pool.value_location(as_noninlined_id(ids.len())).unwrap(), None);
+
+        //every value's bytes, from the offset tables and the trailer's offsets
+        let serialized: Vec<_> = pool.serialized_values().map(Result::unwrap).collect();
+        let expected: Vec<_> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let mut blob = Vec::new();
+                value(i).minimally_serialize(&mut blob, ()).unwrap();
+                (*id, blob)
+            })
+            .collect();
+        assert_eq!(serialized, expected);
+    }
+
+    #[test]
+    pub fn unindexed_blocks_stay_readable() {
+        let name = "minimal_storage-pool_unindexed";
+        let value = |i: usize| FastMinSerde(u64::MAX - i as u64);
+
+        //a pool written before blocks had offset tables, with one full block, then two
+        //values and a trailer which only has the current block's counts
+        let mut bytes = Vec::new();
+        let mut block = Vec::new();
+        for i in 0..BLOCK_WRITE {
+            value(i).minimally_serialize(&mut block, ()).unwrap();
+        }
+        bytes.extend_from_slice(&(block.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&block);
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        let mut current = Vec::new();
+        for i in BLOCK_WRITE..BLOCK_WRITE + 2 {
+            value(i).minimally_serialize(&mut current, ()).unwrap();
+        }
+        bytes.extend_from_slice(&current);
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&(current.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&UNINDEXED_TRAILER_MAGIC);
+
+        let mut file = pool_file(name, true);
+        file.write_all(&bytes).unwrap();
+        file.rewind().unwrap();
+
+        let pool = Pool::<FastMinSerde<u64>>::open_flushed(file).unwrap();
+        let mut ids: Vec<_> = (0..BLOCK_WRITE + 2).map(as_noninlined_id).collect();
+        check_values(&pool, &ids);
+
+        //the current block's offsets were found by reading it, so it's indexed once it's full
+        for i in ids.len()..BLOCK_WRITE * 2 + 7 {
+            ids.push(pool.insert(&value(i), ()).unwrap());
+        }
+        drop(pool);
+
+        let mut pool = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        {
+            let inner = pool.inner.get_mut();
+            let indexed: Vec<_> = inner.blocks.iter().map(|b| b.indexed).collect();
+            assert_eq!(indexed, [false, true]);
+            assert_eq!(inner.current_block_offsets.as_ref().map(Vec::len), Some(7));
+        }
+        check_values(&pool, &ids);
+
+        //and the block headers say the same, without the trailer
+        std::mem::forget(pool);
+        let file = pool_file(name, false);
+        let trailer_size = 8 * (2 + 7) + TRAILER_TAIL_SIZE;
+        file.set_len(Filelike::len(&*file).unwrap() - trailer_size).unwrap();
+        drop(file);
+
+        let pool = Pool::<FastMinSerde<u64>>::open(pool_file(name, false)).unwrap();
+        check_values(&pool, &ids);
+        assert_eq!(pool.value_location(as_noninlined_id(ids.len())).unwrap(), None);
+
+        //the serialized values can only be split up where the blocks have offset tables
+        let serialized: Vec<_> = pool.serialized_values().collect();
+        assert!(serialized[0].is_err());
+        assert_eq!(serialized.len(), 1);
+    }
+    #[test]
+    pub fn dedup_index() {
+        let name = "minimal_storage-pool_dedup";
+        let index_path = std::env::temp_dir().join("minimal_storage-pool_dedup.index");
+        let _ = std::fs::remove_file(&index_path);
+        let value = |i: usize| FastMinSerde(u64::MAX - i as u64);
+
+        let mut pool = Pool::<FastMinSerde<u64>>::new(pool_file(name, true)).unwrap();
+        pool.use_dedup_index(DedupIndex::open(&index_path).unwrap()).unwrap();
+        let ids: Vec<_> = (0..BLOCK_WRITE * 2).map(|i| pool.insert(&value(i), ()).unwrap()).collect();
+
+        //long after the first value has left the recently written values
+        assert_eq!(pool.insert(&value(0), ()).unwrap(), ids[0]);
+        assert_eq!(pool.dedup_stats(), DedupStats { stored: BLOCK_WRITE * 2, deduplicated: 1 });
+        drop(pool);
+
+        //the index is kept with the pool
+        let mut pool = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        pool.use_dedup_index(DedupIndex::open(&index_path).unwrap()).unwrap();
+        assert_eq!(pool.insert(&value(1), ()).unwrap(), ids[1]);
+        let new = pool.insert(&value(ids.len()), ()).unwrap();
+
+        //unless the pool wasn't flushed after it, in which case it's emptied
+        std::mem::forget(pool);
+        let mut pool = Pool::<FastMinSerde<u64>>::open(pool_file(name, false)).unwrap();
+        pool.use_dedup_index(DedupIndex::open(&index_path).unwrap()).unwrap();
+        assert_eq!(pool.insert(&value(2), ()).unwrap(), as_noninlined_id(ids.len() + 1));
+        assert_ne!(pool.insert(&value(ids.len()), ()).unwrap(), new);
+    }
+
+    #[test]
+    pub fn concurrent_reads() {
+        let value = |i: usize| FastMinSerde(u64::MAX - i as u64);
+
+        let pool = Pool::<FastMinSerde<u64>>::new(pool_file("minimal_storage-pool_concurrent", true)).unwrap();
+        let mut ids: Vec<_> = (0..BLOCK_WRITE * 2).map(|i| pool.insert(&value(i), ()).unwrap()).collect();
+
+        //readers share the pool with each other, and with a thread still inserting values
+        let more = std::thread::scope(|s| {
+            let (pool, ids) = (&pool, &ids);
+            for t in 0..4 {
+                s.spawn(move || {
+                    for (i, id) in ids.iter().enumerate().skip(t).step_by(3) {
+                        assert_eq!(pool.get(*id, ()).unwrap().unwrap().0, u64::MAX - i as u64);
+                    }
+                });
+            }
+
+            let writer = s.spawn(move || {
+                let more = BLOCK_WRITE * 2..BLOCK_WRITE * 3 + 5;
+                more.map(|i| pool.insert(&value(i), ()).unwrap()).collect::<Vec<_>>()
+            });
+            writer.join().unwrap()
+        });
+        ids.extend(more);
+
+        check_values(&pool, &ids);
+    }
+
+    #[test]
+    pub fn mapped_reads() {
+        let name = "minimal_storage-pool_mapped";
+        let value = |i: usize| FastMinSerde(u64::MAX - i as u64);
+
+        let pool = Pool::<FastMinSerde<u64>>::new(pool_file(name, true)).unwrap();
+        let ids: Vec<_> = (0..BLOCK_WRITE * 2 + 5).map(|i| pool.insert(&value(i), ()).unwrap()).collect();
+        pool.flush().unwrap();
+        drop(pool);
+
+        let mapped = crate::mapped_file::MappedFile::open(std::env::temp_dir().join(name)).unwrap();
+        let pool = Pool::<FastMinSerde<u64>>::open_flushed(Box::new(mapped)).unwrap();
+        check_values(&pool, &ids);
+    }
+
+    #[test]
+    pub fn compressed_copy() {
+        let value = |i: usize| FastMinSerde(u64::MAX - i as u64);
+
+        let pool = Pool::<FastMinSerde<u64>>::new(pool_file("minimal_storage-pool_uncompressed", true)).unwrap();
+        let mut ids: Vec<_> = (0..BLOCK_WRITE * 2 + 5).map(|i| pool.insert(&value(i), ()).unwrap()).collect();
+        pool.flush().unwrap();
+
+        let serialized: Vec<_> = pool.serialized_values().map(Result::unwrap).collect();
+        let samples: Vec<_> = serialized.chunks(64).map(|c| c.iter().flat_map(|v| v.1.clone()).collect::<Vec<_>>()).collect();
+        let dictionary = Dictionary::train(&samples, 1024).unwrap();
+
+        let name = "minimal_storage-pool_compressed";
+        let stats = pool.write_compressed(pool_file(name, true), dictionary).unwrap();
+        assert!(stats.compressed < stats.uncompressed);
+
+        let copy = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        check_values(&copy, &ids);
+        assert_eq!(
+            copy.value_location(ids[1]).unwrap(),
+            Some(ValueLocation::Compressed { index: 1, block: 0, offset: 8, length: 8 })
+        );
+        assert_eq!(copy.serialized_values().map(Result::unwrap).collect::<Vec<_>>(), serialized);
+
+        //the block which this finishes isn't compressed, but the earlier ones still are
+        for i in ids.len()..BLOCK_WRITE * 3 + 2 {
+            ids.push(copy.insert(&value(i), ()).unwrap());
+        }
+        copy.flush().unwrap();
+        drop(copy);
+
+        let copy = Pool::<FastMinSerde<u64>>::open_flushed(pool_file(name, false)).unwrap();
+        check_values(&copy, &ids);
+        assert!(matches!(
+            copy.value_location(ids[BLOCK_WRITE * 2]).unwrap(),
+            Some(ValueLocation::Pooled { block: 2, .. })
+        ));
+
+        //one at a time, from the compressed blocks, the uncompressed one and the current one
+        let serialized: Vec<_> = copy.serialized_values().map(Result::unwrap).collect();
+        assert_eq!(serialized.len(), ids.len());
+        for (id, bytes) in &serialized {
+            assert_eq!(copy.serialized_value(*id).unwrap().as_ref(), Some(bytes), "{id}");
+        }
+        assert_eq!(copy.serialized_value(as_noninlined_id(ids.len())).unwrap(), None);
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs::{create_dir_all, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::{mapped_file::MappedFile, memory_file::MemoryFile, pooled_storage::Filelike};
+
+/// Where a set of files is kept, so that the same code can keep a map on disk or in
+/// memory. Files are named by relative paths with `/` between directories, which are
+/// made as they're needed.
+pub trait StorageProvider: Send + Sync + Debug {
+    /// The file with the given name, which is made empty if there isn't one yet
+    fn open(&self, name: &str) -> io::Result<Box<dyn Filelike>>;
+
+    /// The file with the given name, or `None` if there isn't one. Unlike `open`, nothing
+    /// is made, and the file is opened read-only where that's possible.
+    fn open_existing(&self, name: &str) -> io::Result<Option<Box<dyn Filelike>>>;
+
+    /// Like `open`, but anything which was in the file is thrown away
+    fn create(&self, name: &str) -> io::Result<Box<dyn Filelike>>;
+
+    /// Put the file named `from` in place of the one named `to`. Either file which is
+    /// already open stays open as it was.
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// The files whose names start with `name/`, named without it
+    fn subdirectory(&self, name: &str) -> Arc<dyn StorageProvider>;
+
+    /// The file with the given name, mapped into memory read-only. `None` if there's no
+    /// such file, or these files can't be mapped. Nothing is made or written.
+    fn open_mapped(&self, _name: &str) -> io::Result<Option<MappedFile>> {
+        Ok(None)
+    }
+
+    /// The file with the given name, for a caller which only reads it: mapped if it can
+    /// be, or else opened with `open_existing`. It's `NotFound` if there's no such file.
+    fn open_for_reading(&self, name: &str) -> io::Result<Box<dyn Filelike>> {
+        if let Some(mapped) = self.open_mapped(name)? {
+            return Ok(Box::new(mapped));
+        }
+
+        self.open_existing(name)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("there's no {name} file"))
+        })
+    }
+}
+
+/// Files in a directory on disk
+#[derive(Clone, Debug)]
+pub struct Directory {
+    path: PathBuf,
+}
+
+impl Directory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open_file(&self, name: &str, truncate: bool) -> io::Result<File> {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        File::options()
+            .create(true)
+            .truncate(truncate)
+            .read(true)
+            .write(true)
+            .open(path)
+    }
+}
+
+impl StorageProvider for Directory {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Filelike>> {
+        Ok(Box::new(self.open_file(name, false)?))
+    }
+
+    fn open_existing(&self, name: &str) -> io::Result<Option<Box<dyn Filelike>>> {
+        match File::open(self.path.join(name)) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn create(&self, name: &str) -> io::Result<Box<dyn Filelike>> {
+        Ok(Box::new(self.open_file(name, true)?))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        std::fs::rename(self.path.join(from), self.path.join(to))
+    }
+
+    fn subdirectory(&self, name: &str) -> Arc<dyn StorageProvider> {
+        Arc::new(Directory::new(self.path.join(name)))
+    }
+
+    fn open_mapped(&self, name: &str) -> io::Result<Option<MappedFile>> {
+        match MappedFile::open(self.path.join(name)) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Prefixes `MemoryDirectory::to_bytes`'s buffer
+const MEMORY_DIRECTORY_MAGIC: [u8; 8] = *b"MEMDIR\x00\x01";
+
+/// Files which are only ever in memory. Clones, and subdirectories, share the files.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryDirectory {
+    files: Arc<Mutex<BTreeMap<String, MemoryFile>>>,
+    /// What's before the names of this directory's files, ending in `/` unless it's empty
+    prefix: String,
+}
+
+impl MemoryDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The name and contents of each file in this directory and its subdirectories
+    pub fn files(&self) -> Vec<(String, Vec<u8>)> {
+        self.files
+            .lock()
+            .iter()
+            .filter_map(|(name, file)| {
+                let name = name.strip_prefix(&self.prefix)?;
+                Some((name.to_string(), file.to_vec()))
+            })
+            .collect()
+    }
+
+    /// All of `files` in one buffer: a magic number, then each file's name's length as a
+    /// u32, the name, the file's length as a u64 and its contents, all little-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MEMORY_DIRECTORY_MAGIC.to_vec();
+
+        for (name, contents) in self.files() {
+            bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&contents);
+        }
+
+        bytes
+    }
+
+    /// The directory which `to_bytes` made the buffer from
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut rest = bytes
+            .strip_prefix(&MEMORY_DIRECTORY_MAGIC[..])
+            .ok_or_else(not_a_memory_directory)?;
+
+        let mut files = BTreeMap::new();
+        while !rest.is_empty() {
+            let name_len = u32::from_le_bytes(take(&mut rest, 4)?.try_into().unwrap());
+            let name = std::str::from_utf8(take(&mut rest, name_len as usize)?)
+                .map_err(|_| not_a_memory_directory())?;
+            let len = u64::from_le_bytes(take(&mut rest, 8)?.try_into().unwrap());
+            let len = usize::try_from(len).map_err(|_| not_a_memory_directory())?;
+            let contents = take(&mut rest, len)?;
+
+            files.insert(name.to_string(), MemoryFile::from_bytes(contents.to_vec()));
+        }
+
+        Ok(Self {
+            files: Arc::new(Mutex::new(files)),
+            prefix: String::new(),
+        })
+    }
+}
+
+fn not_a_memory_directory() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a memory directory's bytes")
+}
+
+/// The first `len` bytes of `from`, which is left with what's after them
+fn take<'a>(from: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    let (taken, rest) = from.split_at_checked(len).ok_or_else(not_a_memory_directory)?;
+    *from = rest;
+    Ok(taken)
+}
+
+impl StorageProvider for MemoryDirectory {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Filelike>> {
+        let mut files = self.files.lock();
+        let file = files.entry(format!("{}{name}", self.prefix)).or_default();
+
+        Ok(Box::new(file.clone()))
+    }
+
+    fn open_existing(&self, name: &str) -> io::Result<Option<Box<dyn Filelike>>> {
+        let files = self.files.lock();
+        let file = files.get(&format!("{}{name}", self.prefix));
+
+        Ok(file.map(|file| Box::new(file.clone()) as Box<dyn Filelike>))
+    }
+
+    fn create(&self, name: &str) -> io::Result<Box<dyn Filelike>> {
+        let file = self.open(name)?;
+        //like truncating a file on disk, this empties it for every handle to it
+        file.set_len(0)?;
+
+        Ok(file)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut files = self.files.lock();
+        let file = files
+            .remove(&format!("{}{from}", self.prefix))
+            .ok_or(io::ErrorKind::NotFound)?;
+        files.insert(format!("{}{to}", self.prefix), file);
+
+        Ok(())
+    }
+
+    fn subdirectory(&self, name: &str) -> Arc<dyn StorageProvider> {
+        Arc::new(MemoryDirectory {
+            files: Arc::clone(&self.files),
+            prefix: format!("{}{name}/", self.prefix),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn memory_directory_round_trip() {
+        let directory = MemoryDirectory::new();
+        directory.open("top").unwrap().write_all_at(b"top file", 0).unwrap();
+
+        let sub = directory.subdirectory("sub");
+        sub.open("inner").unwrap().write_all_at(b"inner", 4).unwrap();
+        sub.open("moved").unwrap().write_all_at(b"moved", 0).unwrap();
+        sub.rename("moved", "renamed").unwrap();
+
+        let restored = MemoryDirectory::from_bytes(&directory.to_bytes()).unwrap();
+        assert_eq!(
+            restored.files(),
+            vec![
+                ("sub/inner".to_string(), b"\0\0\0\0inner".to_vec()),
+                ("sub/renamed".to_string(), b"moved".to_vec()),
+                ("top".to_string(), b"top file".to_vec()),
+            ]
+        );
+
+        //emptied for the handle which was already open too
+        let top = restored.open("top").unwrap();
+        restored.create("top").unwrap();
+        assert_eq!(top.len().unwrap(), 0);
+
+        assert!(MemoryDirectory::from_bytes(&directory.to_bytes()[..20]).is_err());
+    }
+}
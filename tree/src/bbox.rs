@@ -574,7 +574,7 @@ pub struct DeltaBoundingBox32 {
 
 impl PartialEq for DeltaBoundingBox32 {
     fn eq(&self, other: &Self) -> bool {
-        self.xy == other.xy
+        self.cmp(other).is_eq()
     }
 }
 
@@ -588,7 +588,11 @@ impl PartialOrd for DeltaBoundingBox32 {
 
 impl Ord for DeltaBoundingBox32 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.xy.cmp(&other.xy)
+        //boxes sharing a corner are still different keys, or the tree would file them together
+        self.xy
+            .cmp(&other.xy)
+            .then(self.width.cmp(&other.width))
+            .then(self.height.cmp(&other.height))
     }
 }
 
@@ -771,4 +775,26 @@ mod test {
 
         assert_serialize_roundtrip(b, (), ());
     }
+
+    #[test]
+    pub fn delta_keys_with_shared_corner() {
+        let parent = EARTH_BBOX;
+        let point = BoundingBox {
+            x: 2460500,
+            y: 3515000,
+            x_end: 2460500,
+            y_end: 3515000,
+        };
+        let area = BoundingBox {
+            x_end: 2461200,
+            y_end: 3516000,
+            ..point
+        };
+
+        let point_key = point.delta_from_parent(&parent);
+        let area_key = area.delta_from_parent(&parent);
+        assert_ne!(point_key, area_key);
+        assert!(point_key < area_key);
+        assert_eq!(BoundingBox::apply_delta_from_parent(&area_key, &parent), area);
+    }
 }
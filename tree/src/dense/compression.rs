@@ -0,0 +1,54 @@
+use minimal_storage::{
+    compression::{CompressionStats, Dictionary},
+    paged_storage::PageId,
+};
+
+use crate::{
+    dense::structure::StoredTree,
+    tree_traits::{MultidimensionalKey, MultidimensionalValue},
+    PAGE_SIZE,
+};
+
+impl<const DIMENSION_COUNT: usize, const NODE_SATURATION_POINT: usize, Key, Value>
+    StoredTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>
+where
+    Key: MultidimensionalKey<DIMENSION_COUNT>,
+    Value: MultidimensionalValue<Key>,
+{
+    /// The data of up to `count` pages, spread across the tree, to train a dictionary on
+    pub fn page_samples(&mut self, count: usize) -> std::io::Result<Vec<Vec<u8>>> {
+        self.flush()?;
+
+        let pages = self.page_ids();
+        let step = pages.len().div_ceil(count.max(1)).max(1);
+
+        pages
+            .iter()
+            .step_by(step)
+            .map(|page| self.storage.read_chain_data(page))
+            .collect()
+    }
+
+    /// Compress every page with `dictionary`, which is kept in the data file, so that
+    /// pages are written compressed from now on. It's all committed together, so a
+    /// crash leaves the tree as it was. Returns the pages' size before and after.
+    pub fn compress_pages(&mut self, dictionary: Dictionary) -> std::io::Result<CompressionStats> {
+        self.flush()?;
+
+        let pages = self.page_ids();
+        let stats = self.storage.compress_with(&pages, dictionary)?;
+        self.storage.commit()?;
+
+        Ok(stats)
+    }
+
+    /// The first page of every node which has one
+    fn page_ids(&self) -> Vec<PageId<PAGE_SIZE>> {
+        let mut pages = Vec::new();
+        self.walk_nodes(|node, _, _| {
+            pages.extend(*node.page_id.read().unwrap());
+            true
+        });
+        pages
+    }
+}
@@ -0,0 +1,123 @@
+use btree_vec::SeparateStateIteratable;
+use minimal_storage::{
+    multitype_paged_storage::{StoragePage, StoreByPage},
+    paged_storage::{Page, PageId},
+};
+
+use crate::{
+    dense::structure::{Node, StoredTree},
+    tree_traits::{Dimension, MultidimensionalKey, MultidimensionalParent, MultidimensionalValue},
+    PAGE_SIZE,
+};
+
+/// One node of the tree's structure, as `StoredTree::nodes` describes it.
+#[derive(Clone, Debug)]
+pub struct NodeSummary<Bbox> {
+    pub id: u64,
+    /// The root is at depth 0
+    pub depth: usize,
+    pub bbox: Bbox,
+    pub is_leaf: bool,
+    pub page_id: Option<PageId<PAGE_SIZE>>,
+    /// Entries stored in this node itself, rather than in its descendants
+    pub children: usize,
+}
+
+/// What's stored in one node's page.
+#[derive(Clone, Debug)]
+pub struct NodeContents<Key, Value> {
+    /// The physical pages which the node's page is spread over, in order
+    pub component_pages: Vec<PageId<PAGE_SIZE>>,
+    pub entries: Vec<(Key, Value)>,
+}
+
+impl<const DIMENSION_COUNT: usize, const NODE_SATURATION_POINT: usize, Key, Value>
+    StoredTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>
+where
+    Key: MultidimensionalKey<DIMENSION_COUNT>,
+    Value: MultidimensionalValue<Key>,
+{
+    /// Every node in the structure, depth-first. Only the structure is read, not pages.
+    pub fn nodes(&self) -> Vec<NodeSummary<Key::Parent>> {
+        let mut summaries = Vec::new();
+
+        self.walk_nodes(|node, bbox, depth| {
+            let page_id = *node.page_id.read().unwrap();
+
+            summaries.push(NodeSummary {
+                id: node.id,
+                depth,
+                bbox: bbox.clone(),
+                is_leaf: node.left_right_split.get().is_none(),
+                page_id,
+                children: node.children_count.get_maybe_initial(&page_id),
+            });
+            true
+        });
+
+        summaries
+    }
+
+    /// Read the page of the node with the given id. `None` if there's no such node, or if
+    /// it has no page because nothing's stored in it.
+    pub fn node_contents(&self, id: u64) -> Option<NodeContents<Key, Value>> {
+        let mut found = None;
+        self.walk_nodes(|node, bbox, _| {
+            if node.id == id {
+                found = Some((node, bbox.clone()));
+            }
+            found.is_none()
+        });
+        let (node, bbox) = found?;
+
+        let page_id = node.page_id.read().unwrap();
+        let page_id = page_id.as_ref()?;
+        let page = self
+            .storage
+            .get(page_id, (page_id, &node.children_count, &bbox))?;
+
+        let component_pages = page.component_pages().to_vec();
+
+        let read = Page::read_arc(&page);
+        let mut iter_state = read.children.begin_iteration();
+        let entries = std::iter::from_fn(|| read.children.stateless_next(&mut iter_state))
+            .map(|(k, v)| (Key::apply_delta_from_parent(k, &bbox), v.to_owned()))
+            .flat_map(|(k, v)| v.into_iter().map(move |v| (k, v)))
+            .collect();
+
+        Some(NodeContents {
+            component_pages,
+            entries,
+        })
+    }
+
+    /// Call `visit` with each node, its bbox and its depth, until it returns false.
+    pub(super) fn walk_nodes<'a>(
+        &'a self,
+        mut visit: impl FnMut(
+            &'a Node<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
+            &Key::Parent,
+            usize,
+        ) -> bool,
+    ) {
+        let mut stack = vec![(
+            &self.root.node,
+            self.root.root_bbox.to_owned(),
+            <Key::Parent as MultidimensionalParent<DIMENSION_COUNT>>::DimensionEnum::arbitrary_first(),
+            0,
+        )];
+
+        while let Some((node, bbox, direction, depth)) = stack.pop() {
+            if !visit(node, &bbox, depth) {
+                return;
+            }
+
+            if let Some((left, right)) = node.left_right_split.get() {
+                let (left_bbox, right_bbox) = bbox.split_evenly_on_dimension(&direction);
+
+                stack.push((right, right_bbox, direction.next_axis(), depth + 1));
+                stack.push((left, left_bbox, direction.next_axis(), depth + 1));
+            }
+        }
+    }
+}
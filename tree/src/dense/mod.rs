@@ -1,3 +1,6 @@
+pub mod compression;
+pub mod inspect;
+pub mod validate;
 pub mod structure;
 pub mod tree;
 pub mod tree_serde;
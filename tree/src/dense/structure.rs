@@ -8,7 +8,10 @@ use std::{
 };
 
 use btree_vec::BTreeVec;
-use minimal_storage::paged_storage::{PageId, PagedStorage};
+use minimal_storage::{
+    journal::JournaledFile,
+    paged_storage::{PageId, PagedStorage},
+};
 
 use crate::{
     tree_traits::{MultidimensionalKey, MultidimensionalValue},
@@ -23,7 +26,7 @@ where
     pub(crate) storage: TreePagedStorage<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
     pub(crate) root: Root<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
     pub(crate) structure_dirty: AtomicBool,
-    pub(crate) structure_file: std::fs::File,
+    pub(crate) structure_file: JournaledFile,
 }
 
 impl<const D: usize, const N: usize, K, V> Debug for StoredTree<D, N, K, V>
@@ -45,7 +48,11 @@ pub type TreePagedStorage<
     const NODE_SATURATION_POINT: usize,
     Key,
     Value,
-> = PagedStorage<{ PAGE_SIZE }, Inner<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>>;
+> = PagedStorage<
+    { PAGE_SIZE },
+    Inner<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
+    JournaledFile,
+>;
 
 #[derive(Debug)]
 pub(crate) struct Root<const DIMENSION_COUNT: usize, const NODE_SATURATION_POINT: usize, Key, Value>
@@ -1,6 +1,5 @@
 use std::{
     collections::VecDeque,
-    fs::File,
     io::{Seek, Write},
     path::PathBuf,
     sync::{
@@ -14,16 +13,22 @@ use btree_vec::{BTreeVec, SeparateStateIteratable};
 
 use crate::{
     dense::structure::{Inner, Node, Root, StoredTree, TreePagedStorage},
+    nearest::best_first,
     tree_traits::SplitDirection,
     PAGE_SIZE,
 };
 
 use crate::tree_traits::{
-    Dimension, MultidimensionalKey, MultidimensionalParent, MultidimensionalValue,
+    Dimension, DistanceTo, MultidimensionalKey, MultidimensionalParent, MultidimensionalQuery,
+    MultidimensionalValue,
 };
 use minimal_storage::{
+    journal,
+    memory_file::MemoryFile,
     multitype_paged_storage::{StoragePage, StoreByPage},
     paged_storage::{Page, PageArcReadLock, PageId, PageReadLock, PageRwLock, PagedStorage},
+    pooled_storage::Filelike,
+    provider::{Directory, StorageProvider},
     serialize_min::{DeserializeFromMinimal, SerializeMinimal},
 };
 
@@ -34,22 +39,29 @@ where
     Value: MultidimensionalValue<Key>,
 {
     pub fn new(bbox: Key::Parent, folder: PathBuf) -> Self {
-        let storage_file = std::fs::File::options()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&folder.join("data"))
-            .unwrap();
-        let storage = PagedStorage::open(storage_file);
+        Self::open(bbox, folder).unwrap()
+    }
 
-        let mut structure_file = File::options()
-            .write(true)
-            .create(true)
-            .read(true)
-            .open(folder.join("structure"))
-            .unwrap();
+    /// Like `new`, but a structure file which can't be read is an error instead of a panic.
+    ///
+    /// The data and structure files are journaled together, so after a crash the tree
+    /// is as it was at the last `flush`. That's only this tree: a map's pools, sparse
+    /// trees (such as `tmp.bboxes`), and routing and geocoding trees aren't journaled, so
+    /// a crash can leave them out of step with it.
+    pub fn open(bbox: Key::Parent, folder: PathBuf) -> std::io::Result<Self> {
+        Self::open_in(bbox, &Directory::new(folder))
+    }
 
-        let root = if structure_file.metadata().unwrap().len() == 0 {
+    /// Like `open`, but with the tree's files in `files`, which could be in memory
+    pub fn open_in(bbox: Key::Parent, files: &dyn StorageProvider) -> std::io::Result<Self> {
+        let [storage_file, mut structure_file] = journal::open_files(
+            files.open("journal")?,
+            [files.open("data")?, files.open("structure")?],
+        )?;
+
+        let storage = PagedStorage::open_checksummed(storage_file)?;
+
+        let root = if structure_file.len()? == 0 {
             let node = Node::new(1, bbox.clone());
 
             Root {
@@ -57,28 +69,80 @@ where
                 node,
             }
         } else {
-            Root::deserialize_minimal(&mut structure_file, &folder).unwrap()
+            Root::deserialize_minimal(&mut structure_file, ())?
         };
 
-        StoredTree {
+        Ok(StoredTree {
             structure_file,
             root,
             structure_dirty: true.into(),
             storage,
+        })
+    }
+
+    /// Like `open_in`, but with the data and structure mapped read-only, so that pages
+    /// are read without a lock and nothing is ever written. Falls back to `open_in` if
+    /// `files` can't map them, but a missing file is `NotFound` instead of being made.
+    pub fn open_mapped_in(bbox: Key::Parent, files: &dyn StorageProvider) -> std::io::Result<Self> {
+        let (Some(data), Some(structure)) = (files.open_mapped("data")?, files.open_mapped("structure")?) else {
+            for name in ["data", "structure"] {
+                if files.open_existing(name)?.is_none() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("there's no {name} file"),
+                    ));
+                }
+            }
+            return Self::open_in(bbox, files);
+        };
+
+        //the journal is recovered from a copy, since recovering truncates it
+        let log = match files.open_mapped("journal")? {
+            Some(log) => MemoryFile::from_bytes(log.as_bytes().to_vec()),
+            None => MemoryFile::default(),
+        };
+        let [storage_file, mut structure_file] =
+            journal::open_files(Box::new(log), [Box::new(data.clone()), Box::new(structure)])
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::PermissionDenied => std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "the tree's journal has a commit which hasn't been applied; open it read-write once to apply it",
+                    ),
+                    _ => e,
+                })?;
+
+        if structure_file.len()? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "a tree can't be mapped before it's flushed",
+            ));
         }
+
+        let storage = PagedStorage::open_mapped_as(storage_file, data)?;
+        let root = Root::deserialize_minimal(&mut structure_file, ())?;
+
+        Ok(StoredTree {
+            structure_file,
+            root,
+            structure_dirty: false.into(),
+            storage,
+        })
     }
 
+    /// Write the structure and every page which isn't in use, and commit them together.
+    /// Nothing else in the map is part of the commit.
     pub fn flush<'s>(&'s mut self) -> std::io::Result<()> {
         if self.structure_dirty.swap(false, Relaxed) {
-            self.structure_file.rewind().unwrap();
-
             let mut buf = Vec::new();
             self.root.minimally_serialize(&mut buf, ())?;
-            self.structure_file.write_all(&buf).unwrap();
+
+            self.structure_file.rewind()?;
+            self.structure_file.write_all(&buf)?;
+            //a smaller structure than last time mustn't leave the old one's end behind
+            self.structure_file.set_len(buf.len() as u64)?;
         }
 
-        self.storage.flush();
-        Ok(())
+        self.storage.commit()
     }
     pub fn find_items_in_box<'a>(
         &'a self,
@@ -90,6 +154,26 @@ where
     pub fn find_entries_in_box<'a>(
         &'a self,
         query: &'a Key::Parent,
+    ) -> impl Iterator<Item = (Key, Value)> + 'a {
+        self.find_entries_matching(query, move |k, bbox| {
+            Key::delta_from_parent_would_be_contained(k, bbox, query)
+        })
+    }
+
+    /// Entries whose keys are in `query`, such as one of the shapes in `crate::shapes`.
+    pub fn find_entries_in_query<'a>(
+        &'a self,
+        query: &'a impl MultidimensionalQuery<DIMENSION_COUNT, Key>,
+    ) -> impl Iterator<Item = (Key, Value)> + 'a {
+        self.find_entries_matching(query, move |k, bbox| {
+            query.contains_item(&Key::apply_delta_from_parent(k, bbox))
+        })
+    }
+
+    fn find_entries_matching<'a>(
+        &'a self,
+        query: &'a impl MultidimensionalQuery<DIMENSION_COUNT, Key>,
+        keep: impl Fn(&Key::DeltaFromParent, &Key::Parent) -> bool + Clone + 'a,
     ) -> impl Iterator<Item = (Key, Value)> + 'a {
         self.root
             .search_all_nodes_touching_area(query, usize::MAX)
@@ -113,11 +197,12 @@ where
                 drop(page_id);
 
                 let mut iter_state = page_read.children.begin_iteration();
+                let keep = keep.clone();
 
                 Some(
                     std::iter::from_fn(move || loop {
                         let (k, v) = page_read.children.stateless_next(&mut iter_state)?;
-                        if Key::delta_from_parent_would_be_contained(&k, &bbox, &query) {
+                        if keep(&k, &bbox) {
                             let k = Key::apply_delta_from_parent(&k, &bbox);
                             return Some((k, v.to_owned()));
                         }
@@ -127,6 +212,68 @@ where
             })
             .flatten()
     }
+
+    /// The `k` entries closest to `point` which pass `filter`, closest first, with their
+    /// distances. Nodes are visited in order of the distance from their bbox to the
+    /// point, and only as far as the returned iterator is consumed.
+    pub fn nearest<'a, Point: 'a>(
+        &'a self,
+        point: Point,
+        k: usize,
+        filter: impl Fn(&Key, &Value) -> bool + 'a,
+    ) -> impl Iterator<Item = (Key, Value, f64)> + 'a
+    where
+        Key: DistanceTo<Point>,
+        Key::Parent: DistanceTo<Point>,
+    {
+        let root = (
+            &self.root.node,
+            self.root.root_bbox.to_owned(),
+            <Key::Parent as MultidimensionalParent<DIMENSION_COUNT>>::DimensionEnum::arbitrary_first(),
+        );
+
+        best_first(root, k, move |(node, bbox, direction)| {
+            let children = node.left_right_split.get().map(|(left, right)| {
+                let (left_bbox, right_bbox) = bbox.split_evenly_on_dimension(&direction);
+                let (left_distance, right_distance) =
+                    (left_bbox.distance_to(&point), right_bbox.distance_to(&point));
+
+                [
+                    ((&**left, left_bbox, direction.next_axis()), left_distance),
+                    ((&**right, right_bbox, direction.next_axis()), right_distance),
+                ]
+            });
+
+            let mut entries = Vec::new();
+
+            let page_id = node.page_id.read().unwrap();
+            if let Some(id) = page_id.as_ref() {
+                let page_read = Page::read_arc(
+                    &self
+                        .storage
+                        .get(id, (id, &node.children_count, &bbox))
+                        .unwrap(),
+                );
+                drop(page_id);
+
+                let mut iter_state = page_read.children.begin_iteration();
+                while let Some((k, v)) = page_read.children.stateless_next(&mut iter_state) {
+                    let k = Key::apply_delta_from_parent(k, &bbox);
+                    let distance = k.distance_to(&point);
+
+                    entries.extend(
+                        v.iter()
+                            .filter(|v| filter(&k, v))
+                            .cloned()
+                            .map(|v| (k, v, distance)),
+                    );
+                }
+            }
+
+            (children.into_iter().flatten(), entries)
+        })
+    }
+
     pub fn get<'a, 'b>(&'a self, query: &'b Key) -> Option<Value> {
         let (leaf, leaf_bbox) = self.root.search_leaf_for_key(query);
 
@@ -288,7 +435,7 @@ where
 {
     fn search_all_nodes_touching_area<'a>(
         &'a self,
-        area: &'a Key::Parent,
+        area: &'a impl MultidimensionalQuery<DIMENSION_COUNT, Key>,
         max_depth: usize,
     ) -> impl Iterator<
         Item = (
@@ -316,7 +463,7 @@ where
                     let (left_bbox_calculated, right_bbox_calculated) =
                         bbox.split_evenly_on_dimension(&direction);
 
-                    if left_bbox_calculated.overlaps(&area) {
+                    if area.overlaps_box(&left_bbox_calculated) {
                         search_stack.push_back((
                             left,
                             left_bbox_calculated,
@@ -324,7 +471,7 @@ where
                             depth,
                         ));
                     }
-                    if right_bbox_calculated.overlaps(&area) {
+                    if area.overlaps_box(&right_bbox_calculated) {
                         search_stack.push_back((
                             right,
                             right_bbox_calculated,
@@ -1,6 +1,5 @@
 use std::{
     collections::VecDeque,
-    path::PathBuf,
     sync::{atomic::AtomicUsize, Mutex, OnceLock, RwLock},
 };
 
@@ -117,17 +116,17 @@ where
     Key: MultidimensionalKey<DIMENSION_COUNT>,
     Value: MultidimensionalValue<Key>,
 {
-    type ExternalData<'d> = &'d PathBuf;
+    type ExternalData<'d> = ();
 
     fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
         from: &'a mut R,
-        external_data: Self::ExternalData<'d>,
+        _external_data: Self::ExternalData<'d>,
     ) -> Result<Self, std::io::Error> {
         let root_bbox: Key::Parent = DeserializeFromMinimal::deserialize_minimal(from, ())?;
 
         let node = DeserializeFromMinimal::deserialize_minimal(
             from,
-            (external_data, 1, root_bbox.clone(), Dimension::arbitrary_first()),
+            (1, root_bbox.clone(), Dimension::arbitrary_first()),
         )?;
 
         Ok(Self { root_bbox, node })
@@ -163,7 +162,6 @@ where
     Value: MultidimensionalValue<Key>,
 {
     type ExternalData<'d> = (
-        &'d PathBuf,
         u64,
         Key::Parent,
         <Key::Parent as MultidimensionalParent<DIMENSION_COUNT>>::DimensionEnum,
@@ -171,7 +169,7 @@ where
 
     fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(
         from: &'a mut R,
-        (root_path, id, parent, direction): Self::ExternalData<'d>,
+        (id, parent, direction): Self::ExternalData<'d>,
     ) -> Result<Self, std::io::Error> {
         let page_id = FastNullablePageId::deserialize_minimal(from, ())?;
         let children_count = usize::deserialize_minimal(from, ())?.into();
@@ -186,11 +184,11 @@ where
             OnceLock::from((
                 Box::new(Self::deserialize_minimal(
                     from,
-                    (root_path, left_id, left_bbox, next_dir),
+                    (left_id, left_bbox, next_dir),
                 )?),
                 Box::new(Self::deserialize_minimal(
                     from,
-                    (root_path, right_id, right_bbox, next_dir),
+                    (right_id, right_bbox, next_dir),
                 )?),
             ))
         } else {
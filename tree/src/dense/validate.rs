@@ -0,0 +1,173 @@
+use std::{
+    collections::BTreeMap,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+use minimal_storage::{
+    multitype_paged_storage::StoreByPage,
+    paged_storage::{ChainProblem, PageId},
+    serialize_min::DeserializeFromMinimal,
+};
+
+use crate::{
+    dense::structure::{ExternalChildrenCount, Inner, Node, StoredTree},
+    tree_traits::{MultidimensionalKey, MultidimensionalValue},
+    PAGE_SIZE,
+};
+
+/// Something wrong with a stored tree, as `StoredTree::validate` finds it.
+#[derive(Clone, Debug)]
+pub enum TreeProblem<Key> {
+    /// The node's page chain is malformed, so its page can't be read
+    BrokenChain {
+        node: u64,
+        problem: ChainProblem<PAGE_SIZE>,
+    },
+    /// A physical page is part of more than one node's page
+    SharedPage {
+        page: PageId<PAGE_SIZE>,
+        nodes: [u64; 2],
+    },
+    /// The structure's count of the node's entries doesn't match the count in its page
+    ChildrenCount {
+        node: u64,
+        structure: usize,
+        page: usize,
+    },
+    /// The node's page couldn't be decoded
+    Undecodable { node: u64, error: String },
+    /// The key isn't inside the bbox of the node which it's stored in
+    KeyOutsideNode { node: u64, key: Key },
+}
+
+impl<Key: std::fmt::Debug> std::fmt::Display for TreeProblem<Key> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeProblem::BrokenChain { node, problem } => write!(f, "node {node}: {problem}"),
+            TreeProblem::SharedPage { page, nodes } => write!(
+                f,
+                "page {} is used by both node {} and node {}",
+                page.index(),
+                nodes[0],
+                nodes[1]
+            ),
+            TreeProblem::ChildrenCount {
+                node,
+                structure,
+                page,
+            } => write!(
+                f,
+                "node {node}: the structure counts {structure} entries, but its page has {page}"
+            ),
+            TreeProblem::Undecodable { node, error } => {
+                write!(f, "node {node}: the page couldn't be decoded: {error}")
+            }
+            TreeProblem::KeyOutsideNode { node, key } => {
+                write!(f, "node {node}: {key:?} is outside of the node's bbox")
+            }
+        }
+    }
+}
+
+impl<const DIMENSION_COUNT: usize, const NODE_SATURATION_POINT: usize, Key, Value>
+    StoredTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>
+where
+    Key: MultidimensionalKey<DIMENSION_COUNT>,
+    Value: MultidimensionalValue<Key>,
+{
+    /// Check every node's page against the structure, reading them straight from the file
+    /// instead of through the cache, so that a corrupt page is reported instead of
+    /// panicking at query time. An empty list means that nothing's wrong.
+    pub fn validate(&self) -> Vec<TreeProblem<Key>> {
+        //so that the file is up to date with any changes in the cache
+        self.storage.flush().expect("the tree's pages couldn't be written");
+
+        let mut problems = Vec::new();
+        let mut page_owners = BTreeMap::new();
+
+        self.walk_nodes(|node, bbox, _| {
+            let Some(first_page) = *node.page_id.read().unwrap() else {
+                return true;
+            };
+
+            let chain = match self.storage.page_chain(&first_page) {
+                Ok(chain) => chain,
+                Err(problem) => {
+                    problems.push(TreeProblem::BrokenChain {
+                        node: node.id,
+                        problem,
+                    });
+                    return true;
+                }
+            };
+
+            for page in chain {
+                if let Some(other) = page_owners.insert(page, node.id) {
+                    problems.push(TreeProblem::SharedPage {
+                        page,
+                        nodes: [other, node.id],
+                    });
+                }
+            }
+
+            if let Err(error) = self.validate_page(node, bbox, &first_page, &mut problems) {
+                problems.push(TreeProblem::Undecodable {
+                    node: node.id,
+                    error,
+                });
+            }
+
+            true
+        });
+
+        problems
+    }
+
+    fn validate_page(
+        &self,
+        node: &Node<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
+        bbox: &Key::Parent,
+        first_page: &PageId<PAGE_SIZE>,
+        problems: &mut Vec<TreeProblem<Key>>,
+    ) -> Result<(), String> {
+        let data = self
+            .storage
+            .read_chain_data(first_page)
+            .map_err(|e| e.to_string())?;
+
+        let page_count = usize::deserialize_minimal(&mut &data[..], ()).map_err(|e| e.to_string())?;
+        let structure_count = node.children_count.get_initial(first_page);
+
+        if page_count != structure_count {
+            problems.push(TreeProblem::ChildrenCount {
+                node: node.id,
+                structure: structure_count,
+                page: page_count,
+            });
+        }
+
+        //decoding trusts the count which it's given, so give it the page's own; and it
+        // unwraps on some malformed data, so a panic is one more kind of failure
+        let count = ExternalChildrenCount::from(page_count);
+        let decoded = catch_unwind(AssertUnwindSafe(|| {
+            Inner::<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>::deserialize_minimal(
+                &mut &data[..],
+                (first_page, &count, bbox),
+            )
+        }));
+        let inner = match decoded {
+            Ok(inner) => inner.map_err(|e| e.to_string())?,
+            Err(_) => return Err("decoding it panicked".to_string()),
+        };
+
+        for (delta, _) in inner.children.iter() {
+            let key = Key::apply_delta_from_parent(delta, bbox);
+
+            if !key.is_contained_in(bbox) {
+                problems.push(TreeProblem::KeyOutsideNode { node: node.id, key });
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    bbox::{BoundingBox, EARTH_BBOX},
+    tree_traits::DistanceTo,
+};
+
+const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+const DECIMICRO_PER_DEGREE: f64 = 10_000_000.0;
+
+/// Great-circle distance in metres between two points given in decimicro degrees
+/// (`(lon, lat)`, the same units as `EARTH_BBOX`).
+pub fn distance_meters(a: (i32, i32), b: (i32, i32)) -> f64 {
+    let (lon_a, lat_a) = to_radians(a);
+    let (lon_b, lat_b) = to_radians(b);
+
+    let half_dlat = (lat_b - lat_a) / 2.0;
+    let half_dlon = (lon_b - lon_a) / 2.0;
+
+    let h = half_dlat.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_dlon.sin().powi(2);
+
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().min(1.0).asin()
+}
+
+/// Great-circle distance in metres from `point` to the closest part of `bbox`, or no
+/// more than it: each term of the haversine formula is minimised over the box on its
+/// own, which is exact for points and a lower bound for areas.
+pub fn min_distance_meters(point: (i32, i32), bbox: &BoundingBox<i32>) -> f64 {
+    let (lon, lat) = to_radians(point);
+    let (min_lon, min_lat) = to_radians((*bbox.x(), *bbox.y()));
+    let (max_lon, max_lat) = to_radians((*bbox.x_end(), *bbox.y_end()));
+
+    let dlat = if lat < min_lat {
+        min_lat - lat
+    } else if lat > max_lat {
+        lat - max_lat
+    } else {
+        0.0
+    };
+
+    //sin² of half the difference rises up to 180° and falls after, so its smallest value
+    // over the box is at one of the box's sides
+    let half_dlon_sin2 = if (min_lon..=max_lon).contains(&lon) {
+        0.0
+    } else {
+        let near = (min_lon - lon).abs().min((max_lon - lon).abs());
+        let far = (min_lon - lon).abs().max((max_lon - lon).abs());
+        (near / 2.0).sin().powi(2).min((far / 2.0).sin().powi(2))
+    };
+
+    let min_cos = min_lat.cos().min(max_lat.cos()).max(0.0);
+
+    let h = (dlat / 2.0).sin().powi(2) + lat.cos() * min_cos * half_dlon_sin2;
+
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().min(1.0).asin()
+}
+
+/// Great-circle distance in metres from `point` to the closest point of the
+/// great-circle arc from `a` to `b`.
+pub fn distance_to_segment_meters(point: (i32, i32), a: (i32, i32), b: (i32, i32)) -> f64 {
+    let to_ends = || distance_meters(point, a).min(distance_meters(point, b));
+
+    let (p, a3, b3) = (unit_vector(point), unit_vector(a), unit_vector(b));
+
+    let normal = cross(a3, b3);
+    let normal_len = dot(normal, normal).sqrt();
+    if normal_len < 1e-12 {
+        return to_ends();
+    }
+    let normal = normal.map(|c| c / normal_len);
+
+    //the closest point of the whole great circle is only on the arc if it's on the
+    // inner side of both ends
+    if dot(cross(a3, p), normal) >= 0.0 && dot(cross(p, b3), normal) >= 0.0 {
+        EARTH_RADIUS_METERS * dot(normal, p).abs().min(1.0).asin()
+    } else {
+        to_ends()
+    }
+}
+
+fn unit_vector(point: (i32, i32)) -> [f64; 3] {
+    let (lon, lat) = to_radians(point);
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+impl DistanceTo<(i32, i32)> for BoundingBox<i32> {
+    fn distance_to(&self, point: &(i32, i32)) -> f64 {
+        min_distance_meters(*point, self)
+    }
+}
+
+/// How many decimicro degrees of longitude and latitude a distance of `meters` spans
+/// at the given point. Used to turn a radius into a bounding box for tree queries.
+pub fn meters_to_decimicro(at: (i32, i32), meters: f64) -> (i32, i32) {
+    let (_, lat) = to_radians(at);
+
+    let lat_span = meters / EARTH_RADIUS_METERS;
+    let lon_span = lat_span / lat.cos().max(1e-6);
+
+    (
+        (lon_span.to_degrees() * DECIMICRO_PER_DEGREE).min(i32::MAX as f64) as i32,
+        (lat_span.to_degrees() * DECIMICRO_PER_DEGREE).min(i32::MAX as f64) as i32,
+    )
+}
+
+/// The smallest box containing everything within `meters` of `point`. Circles
+/// around a pole take in every longitude.
+pub fn bbox_around(point: (i32, i32), meters: f64) -> BoundingBox<i32> {
+    let (_, lat) = to_radians(point);
+    let angle = meters / EARTH_RADIUS_METERS;
+
+    let to_decimicro = |radians: f64| {
+        (radians.to_degrees() * DECIMICRO_PER_DEGREE).clamp(i32::MIN as f64, i32::MAX as f64) as i32
+    };
+
+    if lat.abs() + angle >= std::f64::consts::FRAC_PI_2 {
+        let y = to_decimicro((lat - angle).max(-std::f64::consts::FRAC_PI_2));
+        let y_end = to_decimicro((lat + angle).min(std::f64::consts::FRAC_PI_2));
+        return BoundingBox::new(*EARTH_BBOX.x(), y, *EARTH_BBOX.x_end(), y_end);
+    }
+
+    //the furthest longitude is where a meridian touches the circle
+    let dx = to_decimicro((angle.sin() / lat.cos()).min(1.0).asin());
+    let dy = to_decimicro(angle);
+
+    BoundingBox::new(
+        point.0.saturating_sub(dx),
+        point.1.saturating_sub(dy),
+        point.0.saturating_add(dx),
+        point.1.saturating_add(dy),
+    )
+}
+
+pub fn degrees_to_decimicro(lon: f64, lat: f64) -> (i32, i32) {
+    (
+        (lon * DECIMICRO_PER_DEGREE).round() as i32,
+        (lat * DECIMICRO_PER_DEGREE).round() as i32,
+    )
+}
+
+pub fn decimicro_to_degrees(point: (i32, i32)) -> (f64, f64) {
+    (
+        point.0 as f64 / DECIMICRO_PER_DEGREE,
+        point.1 as f64 / DECIMICRO_PER_DEGREE,
+    )
+}
+
+fn to_radians(point: (i32, i32)) -> (f64, f64) {
+    let (lon, lat) = decimicro_to_degrees(point);
+    (lon.to_radians(), lat.to_radians())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn one_degree_of_latitude() {
+        let d = distance_meters((0, 0), (0, 10_000_000));
+
+        assert!((d - 111_195.0).abs() < 10.0, "{d}");
+    }
+
+    #[test]
+    pub fn distance_to_box() {
+        let at = degrees_to_decimicro(10.0, 50.0);
+        let point = |lon, lat| {
+            let (x, y) = degrees_to_decimicro(lon, lat);
+            BoundingBox::from_point(x, y)
+        };
+
+        //exact for points
+        let other = degrees_to_decimicro(10.5, 50.5);
+        let d = min_distance_meters(at, &point(10.5, 50.5));
+        assert!((d - distance_meters(at, other)).abs() < 1e-6);
+
+        //a lower bound for every point of a box, and zero inside it
+        let (x, y) = degrees_to_decimicro(11.0, 49.0);
+        let (x_end, y_end) = degrees_to_decimicro(12.0, 51.0);
+        let area = BoundingBox::new(x, y, x_end, y_end);
+        let bound = min_distance_meters(at, &area);
+        for lon in [11.0, 11.5, 12.0] {
+            for lat in [49.0, 49.7, 50.0, 50.3, 51.0] {
+                let d = distance_meters(at, degrees_to_decimicro(lon, lat));
+                assert!(bound <= d + 1e-6, "{bound} > {d}");
+            }
+        }
+        //but not a much lower one
+        let closest = distance_meters(at, degrees_to_decimicro(11.0, 50.0));
+        assert!(bound > closest * 0.95, "{bound} {closest}");
+        assert_eq!(min_distance_meters(degrees_to_decimicro(11.5, 50.0), &area), 0.0);
+    }
+
+    #[test]
+    pub fn distance_to_segment() {
+        let a = degrees_to_decimicro(10.0, 50.0);
+        let b = degrees_to_decimicro(11.0, 50.0);
+
+        //beside the middle of the segment: less than to either end
+        let beside = degrees_to_decimicro(10.5, 50.01);
+        let d = distance_to_segment_meters(beside, a, b);
+        assert!(d < distance_meters(beside, a) && d < distance_meters(beside, b));
+        //great circles bend towards the pole, so it's closer than the latitude suggests
+        let one_hundredth_degree = distance_meters((0, 0), (0, 100_000));
+        assert!(d < one_hundredth_degree, "{d}");
+        assert!(d > one_hundredth_degree * 0.5, "{d}");
+
+        //beyond an end: the distance to that end
+        let past = degrees_to_decimicro(11.5, 50.2);
+        assert!((distance_to_segment_meters(past, a, b) - distance_meters(past, b)).abs() < 1e-6);
+
+        //on it
+        assert!(distance_to_segment_meters(a, a, b) < 1e-6);
+        assert!((distance_to_segment_meters(past, a, a) - distance_meters(past, a)).abs() < 1e-6);
+    }
+
+    #[test]
+    pub fn radius_roundtrip() {
+        let at = degrees_to_decimicro(-71.06, 42.36);
+        let (dx, dy) = meters_to_decimicro(at, 1000.0);
+
+        let east = distance_meters(at, (at.0 + dx, at.1));
+        let north = distance_meters(at, (at.0, at.1 + dy));
+
+        assert!((east - 1000.0).abs() < 1.0, "{east}");
+        assert!((north - 1000.0).abs() < 1.0, "{north}");
+    }
+}
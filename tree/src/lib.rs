@@ -1,6 +1,9 @@
 pub mod bbox;
 mod compare_by;
+pub mod geo;
+mod nearest;
 pub mod point_range;
+pub mod shapes;
 pub mod tree_traits;
 
 pub mod dense;
@@ -11,12 +14,10 @@ mod test;
 
 pub const PAGE_SIZE: usize = 8;
 
-use minimal_storage::{multitype_paged_storage::{SingleTypeView, StoreByPage}, paged_storage::PageId};
+use minimal_storage::{multitype_paged_storage::StoreByPage, paged_storage::PageId, provider::StorageProvider};
 use sparse::{SparseKey, SparseValue};
 use tree_traits::{MultidimensionalKey, MultidimensionalValue};
 
-use crate::sparse::structure::Inner;
-
 pub fn open_tree_dense<const D: usize, const S: usize, Key, Value>(
     state_path: std::path::PathBuf,
     global_area: Key::Parent,
@@ -30,24 +31,39 @@ where
     dense::structure::StoredTree::new(global_area, state_path)
 }
 
+/// Like `open_tree_dense`, but with the tree's files in `files`, which could be in memory
+pub fn open_tree_dense_in<const D: usize, const S: usize, Key, Value>(
+    files: &dyn StorageProvider,
+    global_area: Key::Parent,
+) -> dense::structure::StoredTree<D, S, Key, Value>
+where
+    Key: MultidimensionalKey<D>,
+    Value: MultidimensionalValue<Key>,
+{
+    dense::structure::StoredTree::open_in(global_area, files).unwrap()
+}
+
 pub fn open_tree_sparse<const D: usize, const S: usize, Key, Value>(
     state_path: std::path::PathBuf,
     global_area: Key::Parent,
-) -> sparse::structure::StoredTree<
-    D,
-    S,
-    Key,
-    Value,
-    minimal_storage::paged_storage::Page<
-        PAGE_SIZE,
-        sparse::structure::Root<D, S, Key, Value>,
-        std::fs::File,
-    >,
-    SingleTypeView<PAGE_SIZE, std::fs::File, Inner<D, S, Key, Value>>,
->
+) -> sparse::SingleFileTree<D, S, Key, Value>
 where
     Key: SparseKey<D>,
     Value: SparseValue,
 {
     sparse::open_file(global_area, state_path)
 }
+
+/// Like `open_tree_sparse`, but with the tree in the file named `name` in `files`, which
+/// could be in memory
+pub fn open_tree_sparse_in<const D: usize, const S: usize, Key, Value>(
+    files: &dyn StorageProvider,
+    name: &str,
+    global_area: Key::Parent,
+) -> sparse::SingleFileTree<D, S, Key, Value>
+where
+    Key: SparseKey<D>,
+    Value: SparseValue,
+{
+    sparse::open_in(global_area, files, name)
+}
@@ -0,0 +1,90 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+enum Candidate<N, K, V> {
+    Node(N),
+    Entry(K, V),
+}
+
+struct Queued<N, K, V> {
+    distance: f64,
+    /// Insertion order, so that equally distant candidates come out first-in-first-out
+    order: u64,
+    candidate: Candidate<N, K, V>,
+}
+
+impl<N, K, V> PartialEq for Queued<N, K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<N, K, V> Eq for Queued<N, K, V> {}
+
+impl<N, K, V> PartialOrd for Queued<N, K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N, K, V> Ord for Queued<N, K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        //reversed, since BinaryHeap pops the largest
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+/// Walk a tree in order of distance, yielding up to `k` entries, closest first.
+///
+/// `expand` gives a node's children and its own entries, each with its distance. A
+/// node's distance must be no more than that of anything under it: an entry is only
+/// yielded once nothing left in the queue could be closer.
+pub(crate) fn best_first<N, K, V, Children, Entries>(
+    root: N,
+    k: usize,
+    mut expand: impl FnMut(N) -> (Children, Entries),
+) -> impl Iterator<Item = (K, V, f64)>
+where
+    Children: IntoIterator<Item = (N, f64)>,
+    Entries: IntoIterator<Item = (K, V, f64)>,
+{
+    let mut queue = BinaryHeap::from([Queued {
+        distance: 0.0,
+        order: 0,
+        candidate: Candidate::Node(root),
+    }]);
+    let mut order = 0;
+
+    std::iter::from_fn(move || loop {
+        let Queued {
+            distance,
+            candidate,
+            ..
+        } = queue.pop()?;
+
+        match candidate {
+            Candidate::Entry(key, value) => return Some((key, value, distance)),
+            Candidate::Node(node) => {
+                let (children, entries) = expand(node);
+
+                let children = children
+                    .into_iter()
+                    .map(|(n, d)| (Candidate::Node(n), d));
+                let entries = entries
+                    .into_iter()
+                    .map(|(k, v, d)| (Candidate::Entry(k, v), d));
+
+                for (candidate, distance) in children.chain(entries) {
+                    order += 1;
+                    queue.push(Queued {
+                        distance,
+                        order,
+                        candidate,
+                    });
+                }
+            }
+        }
+    })
+    .take(k)
+}
@@ -4,33 +4,22 @@ use std::{
 };
 
 use minimal_storage::{
-    multitype_paged_storage::{MultitypePagedStorage, SingleTypeView, StoreByPage},
-    paged_storage::{Page, PageId},
+    multitype_paged_storage::{MultitypePagedStorage, StoreByPage},
+    paged_storage::PageId,
     serialize_fast::FastMinSerde,
     serialize_min::{DeserializeFromMinimal, SerializeMinimal},
     varint::{FromVarint, ToVarint},
 };
 
 use crate::{
-    sparse::{
-        structure::{Inner, Root},
-        SparseKey, SparseValue,
-    },
+    sparse::{SparseKey, SparseValue},
     tree_traits::{MaxValue, MinValue, SplitDirection},
-    PAGE_SIZE,
 };
 
-use super::tree_traits::{Average, MultidimensionalKey, MultidimensionalParent, Zero};
+use super::tree_traits::{Average, DistanceTo, MultidimensionalKey, MultidimensionalParent, Zero};
 
 pub type StoredBinaryTree<const NODE_SATURATION_POINT: usize, K, T> =
-    crate::sparse::structure::StoredTree<
-        1,
-        NODE_SATURATION_POINT,
-        K,
-        T,
-        Page<{ PAGE_SIZE }, Root<1, NODE_SATURATION_POINT, K, T>, std::fs::File>,
-        SingleTypeView<PAGE_SIZE, std::fs::File, Inner<1, NODE_SATURATION_POINT, K, T>>,
-    >;
+    crate::sparse::SingleFileTree<1, NODE_SATURATION_POINT, K, T>;
 
 #[repr(transparent)]
 pub struct DisregardWhenDeserializing<Disregard, T>(T, PhantomData<Disregard>);
@@ -158,7 +147,8 @@ impl<T: OneDimensionalCoord> MultidimensionalParent<1> for RangeInclusive<T> {
         let i_start = std::cmp::max(self.start(), child.start());
         let i_end = std::cmp::min(self.end(), child.end());
 
-        i_start < i_end
+        //both ends are inclusive, so a range which only shares one end still overlaps
+        i_start <= i_end
     }
 
     fn split_evenly_on_dimension(&self, _: &()) -> (Self, Self) {
@@ -170,10 +160,11 @@ impl<T: OneDimensionalCoord> MultidimensionalParent<1> for RangeInclusive<T> {
     fn split_enclosed_in_side(&self, _: &(), child: &Self) -> (SplitDirection, Self) {
         let middle = Average::avg(self.start(), self.end());
 
-        if *child.start() >= middle {
+        //keys at the middle are in the left half; see `parent_split_enclosed_in_side`
+        if *child.end() <= middle {
             return (SplitDirection::Left, (*self.start())..=middle);
         }
-        if *child.end() <= middle {
+        if *child.start() > middle {
             return (SplitDirection::Right, middle..=(*self.end()))
         }
 
@@ -238,3 +229,29 @@ impl<T: OneDimensionalCoord> MultidimensionalKey<1> for T {
         return (SplitDirection::Split, parent.to_owned());
     }
 }
+
+macro_rules! impl_distance_1d {
+    ($($typ:ident),*) => {
+        $(
+        impl DistanceTo<$typ> for $typ {
+            fn distance_to(&self, point: &$typ) -> f64 {
+                self.abs_diff(*point) as f64
+            }
+        }
+
+        impl DistanceTo<$typ> for RangeInclusive<$typ> {
+            fn distance_to(&self, point: &$typ) -> f64 {
+                if point < self.start() {
+                    self.start().abs_diff(*point) as f64
+                } else if point > self.end() {
+                    self.end().abs_diff(*point) as f64
+                } else {
+                    0.0
+                }
+            }
+        }
+        )*
+    };
+}
+
+impl_distance_1d! {u8, i8, u16, i16, u32, i32, u64, i64, usize, isize}
@@ -0,0 +1,295 @@
+//! Query shapes for trees keyed by `BoundingBox<i32>` in decimicro degrees. Distances
+//! are along great circles. A key which is an area matches a circle or a corridor if
+//! all of its corners are in it.
+
+use crate::{
+    bbox::BoundingBox,
+    geo::{bbox_around, distance_meters, distance_to_segment_meters, min_distance_meters},
+    tree_traits::MultidimensionalQuery,
+};
+
+/// Everything within `radius_meters` of `center`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Circle {
+    pub center: (i32, i32),
+    pub radius_meters: f64,
+}
+
+impl Circle {
+    pub fn new(center: (i32, i32), radius_meters: f64) -> Self {
+        Circle {
+            center,
+            radius_meters,
+        }
+    }
+}
+
+impl MultidimensionalQuery<2, BoundingBox<i32>> for Circle {
+    fn bounding_box(&self) -> BoundingBox<i32> {
+        bbox_around(self.center, self.radius_meters)
+    }
+
+    fn overlaps_box(&self, bbox: &BoundingBox<i32>) -> bool {
+        min_distance_meters(self.center, bbox) <= self.radius_meters
+    }
+
+    fn contains_item(&self, item: &BoundingBox<i32>) -> bool {
+        corners(item).all(|c| distance_meters(self.center, c) <= self.radius_meters)
+    }
+}
+
+/// A closed ring of points, with the first point repeated at the end.
+pub type Ring = Vec<(i32, i32)>;
+
+/// An area made of any number of rings. Outer and inner rings aren't told apart:
+/// containment uses the even-odd rule, so holes work out on their own. Edges are
+/// straight in degrees, as OSM draws them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Polygon {
+    pub rings: Vec<Ring>,
+}
+
+impl Polygon {
+    pub fn bbox(&self) -> BoundingBox<i32> {
+        let points = self.rings.iter().flatten();
+
+        //not `extend_with_point`, which treats a box at (0, 0) as empty
+        let (x, y, x_end, y_end) = points.fold(
+            (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+            |(x, y, x_end, y_end), p| (x.min(p.0), y.min(p.1), x_end.max(p.0), y_end.max(p.1)),
+        );
+
+        if x > x_end {
+            return BoundingBox::empty();
+        }
+
+        BoundingBox::new(x, y, x_end, y_end)
+    }
+
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        let mut inside = false;
+
+        for (a, b) in self.edges() {
+            if (a.1 > y) != (b.1 > y) {
+                let crossing_x = a.0 as f64
+                    + (y as f64 - a.1 as f64) * (b.0 as f64 - a.0 as f64)
+                        / (b.1 as f64 - a.1 as f64);
+
+                if (x as f64) < crossing_x {
+                    inside = !inside;
+                }
+            }
+        }
+
+        inside
+    }
+
+    fn edges(&self) -> impl Iterator<Item = ((i32, i32), (i32, i32))> + '_ {
+        self.rings
+            .iter()
+            .flat_map(|ring| ring.windows(2).map(|e| (e[0], e[1])))
+    }
+}
+
+impl MultidimensionalQuery<2, BoundingBox<i32>> for Polygon {
+    fn bounding_box(&self) -> BoundingBox<i32> {
+        self.bbox()
+    }
+
+    fn overlaps_box(&self, bbox: &BoundingBox<i32>) -> bool {
+        self.bbox().overlaps(bbox)
+            && (self.edges().any(|(a, b)| segment_touches_box(a, b, bbox))
+                || self.contains((*bbox.x(), *bbox.y())))
+    }
+
+    fn contains_item(&self, item: &BoundingBox<i32>) -> bool {
+        let corner = (*item.x(), *item.y());
+        if item.x() == item.x_end() && item.y() == item.y_end() {
+            return self.contains(corner);
+        }
+
+        //with no edge crossing it, an area is either all inside or all outside
+        !self
+            .edges()
+            .any(|(a, b)| segment_touches_box(a, b, item))
+            && self.contains(corner)
+    }
+}
+
+/// Everything within `radius_meters` of a line, such as a route.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Corridor {
+    pub line: Vec<(i32, i32)>,
+    pub radius_meters: f64,
+}
+
+impl Corridor {
+    pub fn new(line: Vec<(i32, i32)>, radius_meters: f64) -> Self {
+        Corridor {
+            line,
+            radius_meters,
+        }
+    }
+
+    pub fn distance_meters(&self, point: (i32, i32)) -> f64 {
+        match self.line.as_slice() {
+            [] => f64::INFINITY,
+            [only] => distance_meters(point, *only),
+            line => line
+                .windows(2)
+                .map(|s| distance_to_segment_meters(point, s[0], s[1]))
+                .fold(f64::INFINITY, f64::min),
+        }
+    }
+
+    /// The bbox of each segment, grown by the radius. Great circles bulge slightly
+    /// poleward of their ends, which is negligible for the length of a road's segment.
+    fn segment_boxes(&self) -> impl Iterator<Item = BoundingBox<i32>> + '_ {
+        let ends = self
+            .line
+            .windows(2)
+            .map(|s| (s[0], s[1]))
+            .chain(match self.line.as_slice() {
+                [only] => Some((*only, *only)),
+                _ => None,
+            });
+
+        ends.map(|(a, b)| {
+            let mut bbox = bbox_around(a, self.radius_meters);
+            let to = bbox_around(b, self.radius_meters);
+            bbox.extend_with_point(*to.x(), *to.y());
+            bbox.extend_with_point(*to.x_end(), *to.y_end());
+            bbox
+        })
+    }
+}
+
+impl MultidimensionalQuery<2, BoundingBox<i32>> for Corridor {
+    fn bounding_box(&self) -> BoundingBox<i32> {
+        self.segment_boxes()
+            .reduce(|mut all, s| {
+                all.extend_with_point(*s.x(), *s.y());
+                all.extend_with_point(*s.x_end(), *s.y_end());
+                all
+            })
+            .unwrap_or_else(BoundingBox::empty)
+    }
+
+    fn overlaps_box(&self, bbox: &BoundingBox<i32>) -> bool {
+        self.segment_boxes().any(|s| s.overlaps(bbox))
+    }
+
+    fn contains_item(&self, item: &BoundingBox<i32>) -> bool {
+        corners(item).all(|c| self.distance_meters(c) <= self.radius_meters)
+    }
+}
+
+fn corners(bbox: &BoundingBox<i32>) -> impl Iterator<Item = (i32, i32)> {
+    let (x, y, x_end, y_end) = (*bbox.x(), *bbox.y(), *bbox.x_end(), *bbox.y_end());
+
+    let mut corners = vec![(x, y), (x_end, y), (x, y_end), (x_end, y_end)];
+    corners.dedup();
+
+    corners.into_iter()
+}
+
+/// Whether any part of the straight segment from `a` to `b` is in `bbox`.
+fn segment_touches_box(a: (i32, i32), b: (i32, i32), bbox: &BoundingBox<i32>) -> bool {
+    //Liang-Barsky: narrow the part of the segment (from 0 to 1) which is between each
+    // pair of the box's sides
+    let (dx, dy) = (b.0 as f64 - a.0 as f64, b.1 as f64 - a.1 as f64);
+    let (mut t_min, mut t_max) = (0.0f64, 1.0f64);
+
+    for (delta, start, low, high) in [
+        (dx, a.0 as f64, *bbox.x() as f64, *bbox.x_end() as f64),
+        (dy, a.1 as f64, *bbox.y() as f64, *bbox.y_end() as f64),
+    ] {
+        if delta == 0.0 {
+            if start < low || start > high {
+                return false;
+            }
+            continue;
+        }
+
+        let (t0, t1) = ((low - start) / delta, (high - start) / delta);
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+
+    t_min <= t_max
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geo::degrees_to_decimicro;
+
+    //the blanket implementation for boxes makes the key ambiguous without these
+    fn contains(q: &impl MultidimensionalQuery<2, BoundingBox<i32>>, item: &BoundingBox<i32>) -> bool {
+        q.contains_item(item)
+    }
+    fn overlaps(q: &impl MultidimensionalQuery<2, BoundingBox<i32>>, bbox: &BoundingBox<i32>) -> bool {
+        q.overlaps_box(bbox)
+    }
+    fn bounds(q: &impl MultidimensionalQuery<2, BoundingBox<i32>>) -> BoundingBox<i32> {
+        q.bounding_box()
+    }
+
+    fn point(lon: f64, lat: f64) -> BoundingBox<i32> {
+        let (x, y) = degrees_to_decimicro(lon, lat);
+        BoundingBox::from_point(x, y)
+    }
+
+    #[test]
+    pub fn circle() {
+        let circle = Circle::new(degrees_to_decimicro(10.0, 60.0), 10_000.0);
+
+        //at 60°, 0.1° of longitude is only about 5.6km, but 0.1° of latitude is 11km
+        assert!(contains(&circle, &point(10.15, 60.0)));
+        assert!(!contains(&circle, &point(10.0, 60.1)));
+        assert!(contains(&circle, &point(10.0, 60.08)));
+
+        let bbox = bounds(&circle);
+        assert!(bbox.contains(&point(10.15, 60.0)) && bbox.contains(&point(10.0, 60.08)));
+
+        assert!(overlaps(&circle, &BoundingBox::new(100_500_000, 600_500_000, 110_000_000, 610_000_000)));
+        assert!(!overlaps(&circle, &BoundingBox::new(100_000_000, 601_000_000, 110_000_000, 610_000_000)));
+    }
+
+    #[test]
+    pub fn polygon() {
+        //a U shape, open to the north
+        let u = Polygon {
+            rings: vec![vec![(0, 0), (30, 0), (30, 30), (20, 30), (20, 10), (10, 10), (10, 30), (0, 30), (0, 0)]],
+        };
+
+        assert!(contains(&u, &BoundingBox::from_point(5, 20)));
+        assert!(!contains(&u, &BoundingBox::from_point(15, 20)));
+
+        assert!(contains(&u, &BoundingBox::new(1, 1, 29, 9)));
+        //the corners are all inside, but the notch cuts through it
+        assert!(!contains(&u, &BoundingBox::new(5, 5, 25, 25)));
+
+        assert!(overlaps(&u, &BoundingBox::new(12, 5, 18, 20)));
+        assert!(!overlaps(&u, &BoundingBox::new(12, 15, 18, 40)));
+        //entirely inside, with no edges
+        assert!(overlaps(&u, &BoundingBox::new(1, 1, 2, 2)));
+    }
+
+    #[test]
+    pub fn corridor() {
+        let route = Corridor::new(
+            vec![degrees_to_decimicro(10.0, 50.0), degrees_to_decimicro(10.1, 50.0), degrees_to_decimicro(10.1, 50.1)],
+            500.0,
+        );
+
+        assert!(contains(&route, &point(10.05, 50.003)));
+        assert!(contains(&route, &point(10.103, 50.05)));
+        assert!(!contains(&route, &point(10.05, 50.05)));
+        assert!(!contains(&route, &point(9.99, 50.0)));
+
+        let bbox = bounds(&route);
+        assert!(bbox.contains(&point(10.103, 50.05)) && bbox.contains(&point(10.05, 50.003)));
+        assert!(!overlaps(&route, &BoundingBox::new(100_200_000, 500_200_000, 100_800_000, 500_800_000)));
+    }
+}
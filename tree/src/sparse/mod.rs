@@ -7,7 +7,7 @@ pub mod tree;
 pub mod tree_serde;
 
 pub mod open;
-pub use open::{open_file, open_storage};
+pub use open::{open_file, open_in, open_mapped_in, open_storage, SingleFileTree};
 
 pub use structure::StoredTree;
 
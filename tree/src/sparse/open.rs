@@ -3,6 +3,8 @@ use std::{marker::PhantomData, num::NonZero, path::PathBuf};
 use minimal_storage::{
     multitype_paged_storage::{MultitypePagedStorage, SingleTypeView, StoragePage, StoreByPage},
     paged_storage::{Page, PageId},
+    pooled_storage::Filelike,
+    provider::StorageProvider,
 };
 
 use crate::{
@@ -13,6 +15,21 @@ use crate::{
     PAGE_SIZE,
 };
 
+/// A sparse tree which is kept in a single file, as `open_file` and `open_in` open it
+pub type SingleFileTree<const DIMENSION_COUNT: usize, const NODE_SATURATION_POINT: usize, Key, Value> =
+    StoredTree<
+        DIMENSION_COUNT,
+        NODE_SATURATION_POINT,
+        Key,
+        Value,
+        Page<PAGE_SIZE, Root<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>, Box<dyn Filelike>>,
+        SingleTypeView<
+            PAGE_SIZE,
+            Box<dyn Filelike>,
+            Inner<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
+        >,
+    >;
+
 pub fn open_file<
     const DIMENSION_COUNT: usize,
     const NODE_SATURATION_POINT: usize,
@@ -21,18 +38,7 @@ pub fn open_file<
 >(
     bbox: Key::Parent,
     storage_file: PathBuf,
-) -> StoredTree<
-    DIMENSION_COUNT,
-    NODE_SATURATION_POINT,
-    Key,
-    Value,
-    Page<PAGE_SIZE, Root<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>, std::fs::File>,
-    SingleTypeView<
-        PAGE_SIZE,
-        std::fs::File,
-        Inner<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>,
-    >,
-> {
+) -> SingleFileTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value> {
     let storage_file = std::fs::File::options()
         .create(true)
         .read(true)
@@ -40,7 +46,71 @@ pub fn open_file<
         .open(&storage_file)
         .unwrap();
 
-    let storage = MultitypePagedStorage::open(storage_file);
+    open_filelike(bbox, Box::new(storage_file))
+}
+
+/// Like `open_file`, but with the tree in the file named `name` in `files`, which could
+/// be in memory
+pub fn open_in<
+    const DIMENSION_COUNT: usize,
+    const NODE_SATURATION_POINT: usize,
+    Key: SparseKey<DIMENSION_COUNT>,
+    Value: SparseValue,
+>(
+    bbox: Key::Parent,
+    files: &dyn StorageProvider,
+    name: &str,
+) -> SingleFileTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value> {
+    open_filelike(bbox, files.open(name).unwrap())
+}
+
+/// Like `open_in`, but the tree is only read, from its file mapped if `files` can map
+/// it, so nothing is ever written. The tree has to have been flushed to the file.
+pub fn open_mapped_in<
+    const DIMENSION_COUNT: usize,
+    const NODE_SATURATION_POINT: usize,
+    Key: SparseKey<DIMENSION_COUNT>,
+    Value: SparseValue,
+>(
+    bbox: Key::Parent,
+    files: &dyn StorageProvider,
+    name: &str,
+) -> std::io::Result<SingleFileTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>> {
+    let storage_file = files.open_for_reading(name)?;
+    if storage_file.is_empty()? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("the tree in {name} can't be read before it's flushed"),
+        ));
+    }
+
+    let storage = MultitypePagedStorage::open_read_only(storage_file)?;
+
+    //safety: as in `open_filelike`
+    let root_page_id = unsafe { PageId::from_index(NonZero::new(1).unwrap()) };
+    //`open_storage` would make a missing root, which can't be written
+    if StoreByPage::<Root<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>>::get(&storage, &root_page_id, ())
+        .is_none()
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("the tree in {name} has no root"),
+        ));
+    }
+
+    Ok(open_storage(bbox, &storage, Some(root_page_id)))
+}
+
+fn open_filelike<
+    const DIMENSION_COUNT: usize,
+    const NODE_SATURATION_POINT: usize,
+    Key: SparseKey<DIMENSION_COUNT>,
+    Value: SparseValue,
+>(
+    bbox: Key::Parent,
+    storage_file: Box<dyn Filelike>,
+) -> SingleFileTree<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value> {
+    let storage = MultitypePagedStorage::open_checksummed(storage_file).unwrap();
 
     //safety: the file is either new or MUST have been opened with this same function before.
     //    In the first case, the root gets put in the first free page, which is 1.
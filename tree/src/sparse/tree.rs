@@ -12,9 +12,11 @@ use btree_vec::{BTreeVec, SeparateStateIteratable};
 use debug_logs::debug_print;
 
 use crate::{
+    nearest::best_first,
     sparse::structure::{Inner, Node, Root, StoredTree, TreePagedStorage},
     tree_traits::{
-        MultidimensionalKey, MultidimensionalQuery, MultidimensionalValue, SplitDirection,
+        DistanceTo, MultidimensionalKey, MultidimensionalQuery, MultidimensionalValue,
+        SplitDirection,
     },
     PAGE_SIZE,
 };
@@ -27,6 +29,24 @@ use minimal_storage::{
 
 use super::{SparseKey, SparseValue};
 
+/// An iterator over the tree's nodes, holding the root's read guard for as long as it
+/// lives.
+#[allow(dead_code)]
+struct RootGuardedIter<RAII, I> {
+    //safety: the fields MUST be in this order to prevent UB in
+    // the instant that the RAII guard is freed before the inner
+    // field.
+    inner_iter: I,
+    root_page: RAII,
+}
+impl<RAII, I: Iterator> Iterator for RootGuardedIter<RAII, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner_iter.next()
+    }
+}
+
 impl<
         const DIMENSION_COUNT: usize,
         const NODE_SATURATION_POINT: usize,
@@ -45,8 +65,7 @@ where
     RootPage: StoragePage<Root<DIMENSION_COUNT, NODE_SATURATION_POINT, Key, Value>>,
 {
     pub fn flush<'s>(&'s mut self) -> std::io::Result<()> {
-        self.storage.flush();
-        Ok(())
+        self.storage.flush()
     }
     pub fn find_items_in_box<'a>(
         &'a self,
@@ -59,24 +78,9 @@ where
         &'s self,
         query: &'a impl MultidimensionalQuery<DIMENSION_COUNT, Key>,
     ) -> impl Iterator<Item = (Key, Value)> + 'a {
-        #[allow(dead_code)]
-        struct FindEntriesInBox<RAII, I> {
-            //safety: the fields MUST be in this order to prevent UB in
-            // the instant that the RAII guard is freed before the inner
-            // field.
-            inner_iter: I,
-            root_page: RAII,
-        }
-        impl<RAII, I: Iterator> Iterator for FindEntriesInBox<RAII, I> {
-            type Item = I::Item;
-
-            fn next(&mut self) -> Option<Self::Item> {
-                self.inner_iter.next()
-            }
-        }
         let root_page = StoragePage::read_arc(&self.root);
 
-        //safety: the RAII guard is stored into the FindEntriesInBox struct, which is in charge of keeping the
+        //safety: the RAII guard is stored into the RootGuardedIter struct, which is in charge of keeping the
         // guard alive and therefore the validity of the pointer.
         let inner_iter = unsafe {
             self.root
@@ -86,7 +90,7 @@ where
                 .search_all_nodes_touching_area(query)
         };
 
-        (FindEntriesInBox {
+        (RootGuardedIter {
             inner_iter,
             root_page,
         })
@@ -109,6 +113,73 @@ where
         .flatten()
     }
 
+    /// The `k` entries closest to `point` which pass `filter`, closest first, with their
+    /// distances. See the dense tree's `nearest`.
+    pub fn nearest<'a, Point: 'a>(
+        &'a self,
+        point: Point,
+        k: usize,
+        filter: impl Fn(&Key, &Value) -> bool + 'a,
+    ) -> impl Iterator<Item = (Key, Value, f64)> + 'a
+    where
+        Key: DistanceTo<Point>,
+        Key::Parent: DistanceTo<Point>,
+    {
+        let root_page = StoragePage::read_arc(&self.root);
+
+        //safety: see find_entries_in_query
+        let root = unsafe { self.root.as_ptr().as_ref().unwrap() };
+        let start = (
+            &root.node,
+            root.root_bbox.clone(),
+            <Key::Parent as MultidimensionalParent<DIMENSION_COUNT>>::DimensionEnum::arbitrary_first(),
+        );
+
+        let inner_iter = best_first(start, k, move |(node, bbox, direction)| {
+            let children = node.left_right_split.get().map(|(left, right)| {
+                let (mut left_bbox, mut right_bbox) = bbox.split_evenly_on_dimension(&direction);
+
+                //see search_all_nodes_touching_area
+                if left_bbox == bbox || right_bbox == bbox {
+                    left_bbox = bbox.clone();
+                    right_bbox = bbox.clone();
+                }
+                let (left_distance, right_distance) =
+                    (left_bbox.distance_to(&point), right_bbox.distance_to(&point));
+
+                [
+                    ((&**left, left_bbox, direction.next_axis()), left_distance),
+                    ((&**right, right_bbox, direction.next_axis()), right_distance),
+                ]
+            });
+
+            let mut entries = Vec::new();
+
+            if let Some(page_id) = node.page_id.get() {
+                let page_read = Storage::Page::read_arc(&self.storage.get(page_id, ()).unwrap());
+
+                let mut iter_state = page_read.children.begin_iteration();
+                while let Some((k, v)) = page_read.children.stateless_next(&mut iter_state) {
+                    let distance = k.distance_to(&point);
+
+                    entries.extend(
+                        v.iter()
+                            .filter(|v| filter(k, v))
+                            .cloned()
+                            .map(|v| (*k, v, distance)),
+                    );
+                }
+            }
+
+            (children.into_iter().flatten(), entries)
+        });
+
+        RootGuardedIter {
+            inner_iter,
+            root_page,
+        }
+    }
+
     pub fn find_entries_in_box<'a, 's: 'a>(
         &'s self,
         query: &'a Key::Parent,
@@ -207,6 +278,28 @@ where
         item
     }
 
+    /// Like `get_owned`, but returns every value stored at the key instead of only the first.
+    /// The same caveat as `search_leaf_for_key` applies: this assumes that all of a key's
+    /// values fit into one node.
+    pub fn get_all_owned<'a, 'b>(&'a self, query: &'b Key) -> Vec<Value> {
+        let root = self.root.read();
+        let (leaf, _leaf_bbox) = root.search_leaf_for_key(query);
+
+        let Some(page_id) = leaf.page_id.get() else {
+            return Vec::new();
+        };
+
+        let page: std::sync::Arc<<Storage as StoreByPage<_>>::Page> =
+            self.storage.get(page_id, ()).unwrap();
+
+        let items = match page.read().children.get(&query) {
+            Some(values) => values.iter().cloned().collect(),
+            None => Vec::new(),
+        };
+
+        items
+    }
+
     pub fn get_readref<'a, 'b>(&'a self, query: &'b Key) -> Option<impl AsRef<Value> + 'a> {
         let root = self.root.read();
         let (leaf, _leaf_bbox) = root.search_leaf_for_key(query);
@@ -393,7 +486,12 @@ where
                     //      if the area spans the two sides, but since this will result in the same
                     //      behaviour (i.e. the return statement being reached), no need to complicate
                     //      the code with additional checks. That being said, note this possibility; it WILL happen!
-                    let rounding_limit_reached = child_bbox == bbox;
+                    let rounding_limit_reached = child_bbox == bbox || {
+                        //a split at the rounding limit gives both children the whole box, even
+                        //when the area itself is enclosed in one of the halves
+                        let (left_bbox, right_bbox) = bbox.split_evenly_on_dimension(&direction);
+                        left_bbox == bbox || right_bbox == bbox
+                    };
 
                     if !rounding_limit_reached {
                         if split_leftright == SplitDirection::Left {
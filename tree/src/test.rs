@@ -1,19 +1,12 @@
 use std::fs::File;
 
-use minimal_storage::{multitype_paged_storage::{MultitypePagedStorage, StoragePage, StoreByPage}, paged_storage::PageId};
+use minimal_storage::{multitype_paged_storage::{StoragePage, StoreByPage}, paged_storage::PageId, provider::{MemoryDirectory, StorageProvider}, serialize_fast::MinimalSerdeFast, serialize_min::{DeserializeFromMinimal, SerializeMinimal}};
 
-use crate::{point_range::StoredBinaryTree, sparse::{open_file, structure::{Root, StoredTree}, SparseKey, SparseValue}, tree_traits::{MultidimensionalKey, MultidimensionalParent}};
+use crate::{bbox::{BoundingBox, EARTH_BBOX}, geo::{degrees_to_decimicro, distance_meters}, open_tree_dense, open_tree_dense_in, open_tree_sparse_in, shapes::{Circle, Corridor, Polygon}, sparse::{open_in, structure::{Root, StoredTree}, SparseKey, SparseValue}, tree_traits::{MultidimensionalKey, MultidimensionalParent, MultidimensionalQuery}};
 
 fn open_test_tree<const D: usize, const SATURATION: usize, K: SparseKey<D>, V: SparseValue>(testname: &'static str, parent: K::Parent) -> StoredTree<D, SATURATION, K, V, impl StoragePage<Root<D, SATURATION, K, V>>, impl StoreByPage<crate::sparse::structure::Inner<D, SATURATION, K, V>, PageId = PageId<{ crate::PAGE_SIZE }>>> {
-    let folder = std::env::current_dir().unwrap().join(".test");
-    std::fs::create_dir_all(&folder).unwrap();
-
-    let filename = folder.join(testname);
-    //remove the file if it exists to ensure a clean test; otherwise, ignore it
-    let _ = std::fs::remove_file(&filename);
-
-
-    open_file(parent, filename)
+    //in memory, so that tests never share a file
+    open_in(parent, &MemoryDirectory::new(), testname)
 }
 
 macro_rules! funcname {
@@ -64,7 +57,7 @@ pub fn overlapping_keys() {
     let t = open_test_tree::<1, SATURATION, usize, usize>(funcname!(), 0usize..=10usize);
 
     for value in 0..RANGE_END_VALUES {
-        for v in 0..NUMBER_INSERT {
+        for _ in 0..NUMBER_INSERT {
             t.insert(5, value);
         }
         eprintln!("Finished inserting value {value}");
@@ -90,7 +83,469 @@ pub fn overlapping_keys() {
     dbg!(num_values_encountered);
 
     //check that all the values read are equal
-    for (value, num_encountered) in num_values_encountered.iter().enumerate() {
+    for num_encountered in num_values_encountered.iter() {
         assert_eq!(*num_encountered, NUMBER_INSERT);
     }
-}
\ No newline at end of file
+}
+#[test]
+pub fn range_queries() {
+    let t = open_test_tree::<1, 8000, u64, u64>(funcname!(), 0..=u64::MAX);
+
+    for i in 0..1000u64 {
+        t.insert(i * 1_000_000_007, i);
+    }
+
+    let found = |range: std::ops::RangeInclusive<u64>| {
+        let mut v: Vec<_> = t.find_items_in_box(&range).collect();
+        v.sort();
+        v
+    };
+
+    assert_eq!(found(0..=u64::MAX).len(), 1000);
+    assert_eq!(found(1_000_000_007..=3_000_000_021), vec![1, 2, 3]);
+    //a range of a single key
+    assert_eq!(found(500 * 1_000_000_007..=500 * 1_000_000_007), vec![500]);
+    assert!(found(1..=1_000_000_006).is_empty());
+}
+
+#[test]
+pub fn touching_ranges() {
+    //ranges are inclusive at both ends, so sharing an end or a single key is an overlap
+    assert!((0u64..=5).overlaps(&(5..=10)));
+    assert!((5u64..=10).overlaps(&(0..=5)));
+    assert!((3u64..=3).overlaps(&(0..=10)));
+    assert!(!(0u64..=4).overlaps(&(5..=10)));
+}
+
+#[test]
+pub fn ranges_in_one_half() {
+    use crate::tree_traits::SplitDirection;
+
+    //keys at the middle are inserted on the left, so ranges ending there are searched there
+    let parent = 0u64..=1023;
+    assert_eq!(parent.split_enclosed_in_side(&(), &(500..=511)), (SplitDirection::Left, 0..=511));
+    assert_eq!(parent.split_enclosed_in_side(&(), &(512..=520)), (SplitDirection::Right, 511..=1023));
+    assert_eq!(parent.split_enclosed_in_side(&(), &(511..=520)), (SplitDirection::Split, 0..=1023));
+
+    //small nodes, so that keys sit on the ends of many nodes' ranges
+    let t = open_test_tree::<1, 8, u64, u64>(funcname!(), 0..=1023);
+    for i in 0..1024u64 {
+        t.insert(i, i);
+    }
+
+    let found = |range: std::ops::RangeInclusive<u64>| {
+        let mut v: Vec<_> = t.find_items_in_box(&range).collect();
+        v.sort();
+        v
+    };
+
+    for i in 0..1024u64 {
+        assert_eq!(found(i..=i), vec![i]);
+    }
+    //the root splits at 511
+    assert_eq!(found(500..=511), (500..=511).collect::<Vec<_>>());
+    assert_eq!(found(511..=520), (511..=520).collect::<Vec<_>>());
+    assert_eq!(found(0..=1023).len(), 1024);
+}
+
+#[test]
+pub fn nearest_in_range() {
+    let t = open_test_tree::<1, 8000, u64, u64>(funcname!(), 0..=u64::MAX);
+
+    for i in 0..10_000u64 {
+        t.insert(i * 1_000, i);
+    }
+
+    let nearest: Vec<_> = t.nearest(5_123_400u64, 3, |_, _| true).collect();
+    assert_eq!(
+        nearest,
+        vec![(5_123_000, 5123, 400.0), (5_124_000, 5124, 600.0), (5_122_000, 5122, 1400.0)]
+    );
+
+    //the filter is applied before counting
+    let odd: Vec<_> = t.nearest(0u64, 2, |_, v| v % 2 == 1).map(|x| x.1).collect();
+    assert_eq!(odd, vec![1, 3]);
+
+    //lazy, and in order of distance all the way through
+    let mut last = 0.0;
+    for (_, _, d) in t.nearest(u64::MAX, usize::MAX, |_, _| true).take(500) {
+        assert!(d >= last);
+        last = d;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Id(u64);
+
+impl SerializeMinimal for Id {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(&'a self, write_to: &mut W, _external_data: ()) -> std::io::Result<()> {
+        self.0.minimally_serialize(write_to, ())
+    }
+}
+
+impl DeserializeFromMinimal for Id {
+    type ExternalData<'d> = &'d BoundingBox<i32>;
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(from: &'a mut R, _external_data: &'d BoundingBox<i32>) -> Result<Self, std::io::Error> {
+        u64::deserialize_minimal(from, ()).map(Id)
+    }
+}
+
+#[test]
+pub fn nearest_points() {
+    let mut t = open_tree_dense_in::<2, 200, BoundingBox<i32>, Id>(&MemoryDirectory::new(), EARTH_BBOX);
+    t.expand_to_depth(5);
+
+    let points = grid();
+    for (i, p) in points.iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+    }
+
+    let at = degrees_to_decimicro(8.2345, 47.1234);
+    let mut expected: Vec<_> = points.iter().enumerate().map(|(i, p)| (distance_meters(at, *p), i as u64)).collect();
+    expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let found: Vec<_> = t.nearest(at, 10, |_, _| true).collect();
+    assert_eq!(found.len(), 10);
+    for ((bbox, id, d), (expected_d, expected_id)) in found.iter().zip(&expected) {
+        assert_eq!(*id, Id(*expected_id));
+        assert_eq!((*bbox.x(), *bbox.y()), points[*expected_id as usize]);
+        assert!((d - expected_d).abs() < 1e-6);
+    }
+
+    let even: Vec<_> = t.nearest(at, 3, |_, id| id.0 % 2 == 0).map(|x| x.1 .0).collect();
+    let expected_even: Vec<_> = expected.iter().map(|x| x.1).filter(|id| id % 2 == 0).take(3).collect();
+    assert_eq!(even, expected_even);
+}
+
+/// A grid of points 0.01 degrees apart
+fn grid() -> Vec<(i32, i32)> {
+    (0..50).flat_map(|x| (0..50).map(move |y| degrees_to_decimicro(8.0 + x as f64 / 100.0, 47.0 + y as f64 / 100.0))).collect()
+}
+
+fn expect_query(query: &impl MultidimensionalQuery<2, BoundingBox<i32>>, points: &[(i32, i32)], mut found: Vec<u64>) {
+    let mut expected: Vec<_> = (0..points.len() as u64).filter(|i| query.contains_item(&BoundingBox::from_point(points[*i as usize].0, points[*i as usize].1))).collect();
+
+    found.sort();
+    expected.sort();
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+}
+
+/// A box as a sparse tree's key. The sparse tree keeps each node's keys sorted, and
+/// boxes have no order of their own, so these are sorted by their corners.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct SortedBox(BoundingBox<i32>);
+
+impl SortedBox {
+    fn corners(&self) -> (i32, i32, i32, i32) {
+        (*self.0.x(), *self.0.y(), *self.0.x_end(), *self.0.y_end())
+    }
+}
+
+impl PartialOrd for SortedBox {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortedBox {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.corners().cmp(&other.corners())
+    }
+}
+
+impl MultidimensionalKey<2> for SortedBox {
+    type Parent = BoundingBox<i32>;
+
+    type DeltaFromParent = <BoundingBox<i32> as MultidimensionalKey<2>>::DeltaFromParent;
+    type DeltaFromSelfAsChild = <BoundingBox<i32> as MultidimensionalKey<2>>::DeltaFromSelfAsChild;
+
+    fn is_contained_in(&self, parent: &BoundingBox<i32>) -> bool {
+        self.0.is_contained_in(parent)
+    }
+
+    fn delta_from_parent(&self, parent: &BoundingBox<i32>) -> Self::DeltaFromParent {
+        self.0.delta_from_parent(parent)
+    }
+
+    fn apply_delta_from_parent(delta: &Self::DeltaFromParent, parent: &BoundingBox<i32>) -> Self {
+        SortedBox(BoundingBox::apply_delta_from_parent(delta, parent))
+    }
+
+    fn smallest_key_in(parent: &BoundingBox<i32>) -> Self {
+        SortedBox(BoundingBox::smallest_key_in(parent))
+    }
+
+    fn largest_key_in(parent: &BoundingBox<i32>) -> Self {
+        SortedBox(BoundingBox::largest_key_in(parent))
+    }
+
+    fn delta_from_self(finl: &Self::DeltaFromParent, initil: &Self::DeltaFromParent) -> Self::DeltaFromSelfAsChild {
+        BoundingBox::delta_from_self(finl, initil)
+    }
+
+    fn apply_delta_from_self(delta: &Self::DeltaFromSelfAsChild, initial: &Self::DeltaFromParent) -> Self::DeltaFromParent {
+        BoundingBox::apply_delta_from_self(delta, initial)
+    }
+
+    fn parent_split_enclosed_in_side(
+        &self,
+        parent: &BoundingBox<i32>,
+        dimension: &<BoundingBox<i32> as MultidimensionalParent<2>>::DimensionEnum,
+    ) -> (crate::tree_traits::SplitDirection, BoundingBox<i32>) {
+        self.0.parent_split_enclosed_in_side(parent, dimension)
+    }
+}
+
+impl SerializeMinimal for SortedBox {
+    type ExternalData<'s> = ();
+
+    fn minimally_serialize<'a, 's: 'a, W: std::io::Write>(&'a self, write_to: &mut W, _external_data: ()) -> std::io::Result<()> {
+        self.0.minimally_serialize(write_to, ())
+    }
+}
+
+impl DeserializeFromMinimal for SortedBox {
+    type ExternalData<'d> = ();
+
+    fn deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(from: &'a mut R, _external_data: ()) -> Result<Self, std::io::Error> {
+        BoundingBox::deserialize_minimal(from, ()).map(SortedBox)
+    }
+}
+
+impl MinimalSerdeFast for SortedBox {
+    fn fast_minimally_serialize<'a, 's: 'a, W: std::io::Write>(&'a self, write_to: &mut W, _external_data: ()) -> std::io::Result<()> {
+        self.0.fast_minimally_serialize(write_to, ())
+    }
+
+    fn fast_deserialize_minimal<'a, 'd: 'a, R: std::io::Read>(from: &'a mut R, _external_data: ()) -> Result<Self, std::io::Error> {
+        BoundingBox::fast_deserialize_minimal(from, ()).map(SortedBox)
+    }
+
+    fn fast_seek_after<R: std::io::Read>(from: &mut R) -> std::io::Result<()> {
+        BoundingBox::<i32>::fast_seek_after(from)
+    }
+}
+
+/// A query on boxes, asked of a tree keyed by `SortedBox`es
+struct OnSorted<'a, Q>(&'a Q);
+
+impl<Q: MultidimensionalQuery<2, BoundingBox<i32>>> MultidimensionalQuery<2, SortedBox> for OnSorted<'_, Q> {
+    fn bounding_box(&self) -> BoundingBox<i32> {
+        self.0.bounding_box()
+    }
+
+    fn overlaps_box(&self, bbox: &BoundingBox<i32>) -> bool {
+        self.0.overlaps_box(bbox)
+    }
+
+    fn contains_item(&self, item: &SortedBox) -> bool {
+        self.0.contains_item(&item.0)
+    }
+}
+
+#[test]
+pub fn shape_queries() {
+    let mut dense = open_tree_dense_in::<2, 200, BoundingBox<i32>, Id>(&MemoryDirectory::new(), EARTH_BBOX);
+    dense.expand_to_depth(5);
+    let sparse = open_test_tree::<2, 200, SortedBox, u64>(funcname!(), EARTH_BBOX);
+
+    let points = grid();
+    for (i, p) in points.iter().enumerate() {
+        dense.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+        sparse.insert(SortedBox(BoundingBox::from_point(p.0, p.1)), i as u64);
+    }
+
+    let circle = Circle::new(degrees_to_decimicro(8.2345, 47.1234), 3_000.0);
+    let route = Corridor::new(vec![degrees_to_decimicro(8.0, 47.0), degrees_to_decimicro(8.3, 47.2), degrees_to_decimicro(8.3, 47.45)], 800.0);
+    let triangle = Polygon { rings: vec![vec![degrees_to_decimicro(8.1, 47.1), degrees_to_decimicro(8.4, 47.1), degrees_to_decimicro(8.1, 47.4), degrees_to_decimicro(8.1, 47.1)]] };
+
+    expect_query(&circle, &points, dense.find_entries_in_query(&circle).map(|x| x.1 .0).collect());
+    expect_query(&circle, &points, sparse.find_entries_in_query(&OnSorted(&circle)).map(|x| x.1).collect());
+    expect_query(&route, &points, dense.find_entries_in_query(&route).map(|x| x.1 .0).collect());
+    expect_query(&route, &points, sparse.find_entries_in_query(&OnSorted(&route)).map(|x| x.1).collect());
+    expect_query(&triangle, &points, dense.find_entries_in_query(&triangle).map(|x| x.1 .0).collect());
+    expect_query(&triangle, &points, sparse.find_entries_in_query(&OnSorted(&triangle)).map(|x| x.1).collect());
+}
+
+#[test]
+pub fn inspect_nodes() {
+    let folder = std::env::current_dir().unwrap().join(".test").join(funcname!());
+    let _ = std::fs::remove_dir_all(&folder);
+
+    let mut t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder, EARTH_BBOX);
+    t.expand_to_depth(3);
+
+    let points = grid();
+    for (i, p) in points.iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+    }
+
+    let nodes = t.nodes();
+    assert_eq!((nodes[0].depth, nodes[0].bbox), (0, EARTH_BBOX));
+    //too many points for one node, so it split
+    assert!(!nodes[0].is_leaf && nodes.iter().any(|n| n.is_leaf && n.depth > 1));
+    assert_eq!(nodes.iter().map(|n| n.children).sum::<usize>(), points.len());
+
+    let mut ids = Vec::new();
+    for node in nodes.iter().filter(|n| n.page_id.is_some()) {
+        let contents = t.node_contents(node.id).unwrap();
+        assert_eq!(contents.component_pages[0], node.page_id.unwrap());
+        assert_eq!(contents.entries.len(), node.children);
+
+        for (bbox, id) in contents.entries {
+            assert_eq!((*bbox.x(), *bbox.y()), points[id.0 as usize]);
+            assert!(node.bbox.contains(&bbox));
+            ids.push(id.0);
+        }
+    }
+    ids.sort();
+    assert_eq!(ids, (0..points.len() as u64).collect::<Vec<_>>());
+
+    assert!(t.node_contents(u64::MAX).is_none());
+}
+
+#[test]
+pub fn validate_corruption() {
+    let folder = std::env::current_dir().unwrap().join(".test").join(funcname!());
+    let _ = std::fs::remove_dir_all(&folder);
+
+    let mut t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder.clone(), EARTH_BBOX);
+    for (i, p) in grid().iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+    }
+    t.flush().unwrap();
+    assert!(t.validate().is_empty());
+
+    let stored: Vec<_> = t.nodes().into_iter().filter(|n| n.page_id.is_some()).collect();
+    drop(t);
+
+    //point one node's page past the end of the file, and another's back to itself
+    let mut data = File::options().write(true).open(folder.join("data")).unwrap();
+    let point_to = |data: &mut File, page: usize, next: usize| {
+        use std::io::{Seek, Write};
+        data.seek(std::io::SeekFrom::Start((page * crate::PAGE_SIZE * 1024) as u64)).unwrap();
+        data.write_all(&next.to_be_bytes()).unwrap();
+    };
+    let (first, second) = (stored[0].page_id.unwrap(), stored[1].page_id.unwrap());
+    point_to(&mut data, first.index(), 1_000_000);
+    point_to(&mut data, second.index(), second.index());
+    drop(data);
+
+    let t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder, EARTH_BBOX);
+    let problems: Vec<_> = t.validate().iter().map(|p| p.to_string()).collect();
+    assert_eq!(
+        problems,
+        [
+            format!("node {}: page {} points to page 1000000, which isn't allocated", stored[0].id, first.index()),
+            format!("node {}: the chain loops back to page {}", stored[1].id, second.index()),
+        ]
+    );
+}
+
+#[test]
+pub fn unflushed_changes_are_discarded() {
+    let folder = std::env::current_dir().unwrap().join(".test").join(funcname!());
+    let _ = std::fs::remove_dir_all(&folder);
+
+    let points = grid();
+    let (flushed, unflushed) = points.split_at(points.len() / 2);
+
+    let mut t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder.clone(), EARTH_BBOX);
+    for (i, p) in flushed.iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+    }
+    t.flush().unwrap();
+
+    //as if the process stopped before the next flush
+    for (i, p) in unflushed.iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id((flushed.len() + i) as u64));
+    }
+    drop(t);
+
+    let t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder, EARTH_BBOX);
+    let mut found: Vec<_> = t.find_items_in_box(&EARTH_BBOX).map(|id| id.0).collect();
+    found.sort();
+    assert_eq!(found, (0..flushed.len() as u64).collect::<Vec<_>>());
+    assert!(t.validate().is_empty());
+}
+
+#[test]
+pub fn compressed_pages() {
+    let folder = std::env::current_dir().unwrap().join(".test").join(funcname!());
+    let _ = std::fs::remove_dir_all(&folder);
+
+    let points = grid();
+    let (before, after) = points.split_at(points.len() / 2);
+
+    let mut t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder.clone(), EARTH_BBOX);
+    for (i, p) in before.iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+    }
+
+    let samples = t.page_samples(1000).unwrap();
+    let dictionary = minimal_storage::compression::Dictionary::train(&samples, 1024).unwrap();
+    let stats = t.compress_pages(dictionary).unwrap();
+    assert!(stats.compressed < stats.uncompressed);
+    assert!(t.validate().is_empty());
+
+    //pages written from now on are compressed too
+    for (i, p) in after.iter().enumerate() {
+        t.insert(&BoundingBox::from_point(p.0, p.1), Id((before.len() + i) as u64));
+    }
+    t.flush().unwrap();
+    drop(t);
+
+    let t = open_tree_dense::<2, 200, BoundingBox<i32>, Id>(folder, EARTH_BBOX);
+    let mut found: Vec<_> = t.find_items_in_box(&EARTH_BBOX).map(|id| id.0).collect();
+    found.sort();
+    assert_eq!(found, (0..points.len() as u64).collect::<Vec<_>>());
+    assert!(t.validate().is_empty());
+}
+
+#[test]
+pub fn in_memory_map() {
+    let files = MemoryDirectory::new();
+    let points = grid();
+
+    let mut dense = open_tree_dense_in::<2, 200, BoundingBox<i32>, Id>(&*files.subdirectory("dense"), EARTH_BBOX);
+    let sparse = open_tree_sparse_in::<1, 200, u64, u64>(&files, "sparse", 0..=u64::MAX);
+    for (i, p) in points.iter().enumerate() {
+        dense.insert(&BoundingBox::from_point(p.0, p.1), Id(i as u64));
+        sparse.insert(i as u64 * 7, i as u64);
+    }
+    dense.flush().unwrap();
+    drop(dense);
+    drop(sparse);
+
+    //everything's in the one buffer
+    let files = MemoryDirectory::from_bytes(&files.to_bytes()).unwrap();
+
+    let dense = open_tree_dense_in::<2, 200, BoundingBox<i32>, Id>(&*files.subdirectory("dense"), EARTH_BBOX);
+    let mut found: Vec<_> = dense.find_items_in_box(&EARTH_BBOX).map(|id| id.0).collect();
+    found.sort();
+    assert_eq!(found, (0..points.len() as u64).collect::<Vec<_>>());
+    assert!(dense.validate().is_empty());
+
+    let sparse = open_tree_sparse_in::<1, 200, u64, u64>(&files, "sparse", 0..=u64::MAX);
+    for i in 0..points.len() as u64 {
+        assert_eq!(sparse.get_owned(&(i * 7)), Some(i));
+    }
+}
+
+/// More values at one key than fit in a node, next to keys which share its smallest box
+#[test]
+pub fn busy_key() {
+    let t = open_test_tree::<1, 200, u64, u64>(funcname!(), 0..=u64::MAX);
+    for i in 1..=500 {
+        t.insert(0, i);
+        t.insert(i, 0);
+    }
+
+    let mut found: Vec<_> = t.find_items_in_box(&(0..=0)).collect();
+    found.sort();
+    assert_eq!(found, (1..=500).collect::<Vec<_>>());
+}
@@ -27,6 +27,13 @@ impl<const DIMENSION_COUNT: usize, K: MultidimensionalKey<DIMENSION_COUNT>>
     }
 }
 
+/// How far something is from a point, for nearest-neighbour searches. A parent's
+/// distance must never be more than the distance of anything contained in it, since
+/// whole nodes are skipped based on it.
+pub trait DistanceTo<Point> {
+    fn distance_to(&self, point: &Point) -> f64;
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum SplitDirection {
     Left,